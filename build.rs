@@ -0,0 +1,42 @@
+/// Record the locked versions of the main dependencies for `versions.yml`.
+use std::{env, fs};
+
+// Dependencies reported next to the tool version
+const REPORTED: [&str; 5] = ["bio", "flate2", "polars", "rayon", "duckdb"];
+
+// Optional dependencies, with the feature enabling them
+const OPTIONAL: [(&str, &str); 2] =
+    [("polars", "PARQUET"), ("duckdb", "DUCKDB")];
+
+// Whether `name` is built in, an optional dependency only with its feature
+fn is_built(name: &str) -> bool {
+    OPTIONAL
+        .iter()
+        .find(|(dependency, _)| *dependency == name)
+        .is_none_or(|(_, feature)| {
+            env::var_os(format!("CARGO_FEATURE_{feature}")).is_some()
+        })
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=Cargo.lock");
+
+    let lock = fs::read_to_string("Cargo.lock").unwrap_or_default();
+    let mut versions = Vec::new();
+    let mut name = None;
+
+    for line in lock.lines() {
+        if let Some(value) = line.strip_prefix("name = ") {
+            name = Some(value.trim_matches('"').to_string());
+        } else if let Some(value) = line.strip_prefix("version = ") {
+            match name.take() {
+                Some(n) if REPORTED.contains(&n.as_str()) && is_built(&n) => {
+                    versions.push(format!("{}={}", n, value.trim_matches('"')))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    println!("cargo:rustc-env=DEPENDENCY_VERSIONS={}", versions.join(";"));
+}
@@ -0,0 +1,3 @@
+ALTER TABLE uniprot_entries DROP COLUMN gene_name;
+
+ALTER TABLE uniprot_entries DROP COLUMN protein_name;
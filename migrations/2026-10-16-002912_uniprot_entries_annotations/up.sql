@@ -0,0 +1,3 @@
+ALTER TABLE uniprot_entries ADD COLUMN protein_name VARCHAR(500);
+
+ALTER TABLE uniprot_entries ADD COLUMN gene_name VARCHAR(50);
@@ -0,0 +1,11 @@
+CREATE TABLE uniprot_sequences (
+  accession_number VARCHAR(50) NOT NULL PRIMARY KEY,
+
+  -- FASTA header, without the leading >
+  header TEXT NOT NULL,
+
+  -- Canonical sequence
+  sequence TEXT NOT NULL,
+
+  FOREIGN KEY (accession_number) REFERENCES uniprot_entries(accession_number)
+)
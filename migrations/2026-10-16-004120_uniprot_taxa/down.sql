@@ -0,0 +1,3 @@
+ALTER TABLE uniprot_entries DROP COLUMN taxon;
+
+DROP TABLE uniprot_taxa
@@ -0,0 +1,19 @@
+CREATE TABLE uniprot_taxa (
+  -- Organism mnemonic, suffix of the entry names
+  code VARCHAR(5) NOT NULL PRIMARY KEY,
+
+  -- NCBI taxonomy identifier
+  taxid INTEGER NOT NULL,
+
+  scientific_name VARCHAR(500) NOT NULL,
+  common_name VARCHAR(500),
+
+  -- A archaea, B bacteria, E eukaryota, V viruses, O others
+  kingdom CHAR(1) NOT NULL
+);
+
+CREATE INDEX uniprot_taxa_taxid ON uniprot_taxa(taxid);
+
+-- Code of the organism in uniprot_taxa. Not declared as a foreign key,
+-- SQLite can't drop such a column.
+ALTER TABLE uniprot_entries ADD COLUMN taxon VARCHAR(5);
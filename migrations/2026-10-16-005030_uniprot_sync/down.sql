@@ -0,0 +1,3 @@
+DROP TABLE import_runs;
+
+ALTER TABLE belongs_to_uniprot_sequence_similarity_family DROP COLUMN obsolete
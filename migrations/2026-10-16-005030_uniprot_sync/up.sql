@@ -0,0 +1,14 @@
+-- Memberships gone from the latest release are kept, flagged obsolete
+ALTER TABLE belongs_to_uniprot_sequence_similarity_family ADD COLUMN obsolete BOOLEAN NOT NULL DEFAULT 0;
+
+CREATE TABLE import_runs (
+  id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+
+  -- URL or path of the imported file
+  source TEXT NOT NULL,
+
+  -- UniProt release, e.g. 2024_01
+  release_version VARCHAR(50),
+
+  imported_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+)
@@ -0,0 +1,3 @@
+DROP TABLE uniprot_entry_keywords;
+
+DROP TABLE uniprot_keywords
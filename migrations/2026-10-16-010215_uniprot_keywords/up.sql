@@ -0,0 +1,20 @@
+CREATE TABLE uniprot_keywords (
+  -- Keyword accession, e.g. KW-0804
+  accession VARCHAR(10) NOT NULL PRIMARY KEY,
+
+  -- Keyword identifier, e.g. Transcription regulation
+  name VARCHAR(300) NOT NULL,
+
+  -- Biological process, Ligand, Molecular function...
+  category VARCHAR(100),
+
+  definition TEXT
+);
+
+CREATE TABLE uniprot_entry_keywords (
+  entry VARCHAR(50) NOT NULL,
+  keyword VARCHAR(10) NOT NULL,
+  PRIMARY KEY (entry, keyword),
+  FOREIGN KEY (entry) REFERENCES uniprot_entries(accession_number),
+  FOREIGN KEY (keyword) REFERENCES uniprot_keywords(accession)
+)
@@ -0,0 +1,3 @@
+DROP TABLE uniprot_entry_enzymes;
+
+DROP TABLE uniprot_enzymes
@@ -0,0 +1,18 @@
+CREATE TABLE uniprot_enzymes (
+  -- EC number, e.g. 2.7.11.1
+  ec_number VARCHAR(20) NOT NULL PRIMARY KEY,
+
+  -- Recommended name
+  name VARCHAR(500) NOT NULL,
+
+  -- Alternative names, separated by semicolons
+  alternative_names TEXT
+);
+
+CREATE TABLE uniprot_entry_enzymes (
+  entry VARCHAR(50) NOT NULL,
+  ec_number VARCHAR(20) NOT NULL,
+  PRIMARY KEY (entry, ec_number),
+  FOREIGN KEY (entry) REFERENCES uniprot_entries(accession_number),
+  FOREIGN KEY (ec_number) REFERENCES uniprot_enzymes(ec_number)
+)
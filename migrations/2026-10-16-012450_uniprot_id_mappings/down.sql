@@ -0,0 +1 @@
+DROP TABLE uniprot_id_mappings
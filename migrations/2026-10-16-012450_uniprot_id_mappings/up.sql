@@ -0,0 +1,12 @@
+CREATE TABLE uniprot_id_mappings (
+  entry VARCHAR(50) NOT NULL,
+
+  -- Target of the mapping: gene_name, ensembl or refseq
+  database VARCHAR(20) NOT NULL,
+
+  -- Identifier in the target database, e.g. ENSG00000142192.22
+  identifier VARCHAR(100) NOT NULL,
+
+  PRIMARY KEY (entry, database, identifier),
+  FOREIGN KEY (entry) REFERENCES uniprot_entries(accession_number)
+)
@@ -0,0 +1,5 @@
+DROP TABLE uniprot_entry_go_terms;
+
+DROP TABLE go_term_parents;
+
+DROP TABLE go_terms
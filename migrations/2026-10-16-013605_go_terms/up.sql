@@ -0,0 +1,26 @@
+CREATE TABLE go_terms (
+  -- GO identifier, e.g. GO:0003700
+  id VARCHAR(10) NOT NULL PRIMARY KEY,
+
+  name VARCHAR(500) NOT NULL,
+
+  -- biological_process, molecular_function or cellular_component
+  namespace VARCHAR(50) NOT NULL
+);
+
+-- is_a and part_of relations of go-basic.obo
+CREATE TABLE go_term_parents (
+  term VARCHAR(10) NOT NULL,
+  parent VARCHAR(10) NOT NULL,
+  PRIMARY KEY (term, parent),
+  FOREIGN KEY (term) REFERENCES go_terms(id),
+  FOREIGN KEY (parent) REFERENCES go_terms(id)
+);
+
+CREATE TABLE uniprot_entry_go_terms (
+  entry VARCHAR(50) NOT NULL,
+  term VARCHAR(10) NOT NULL,
+  PRIMARY KEY (entry, term),
+  FOREIGN KEY (entry) REFERENCES uniprot_entries(accession_number),
+  FOREIGN KEY (term) REFERENCES go_terms(id)
+)
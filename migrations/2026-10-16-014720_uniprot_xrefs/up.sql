@@ -0,0 +1,12 @@
+CREATE TABLE uniprot_xrefs (
+  accession VARCHAR(50) NOT NULL,
+
+  -- Cross-referenced database: PDB, Pfam or InterPro
+  database VARCHAR(20) NOT NULL,
+
+  -- Identifier in that database, e.g. 1AAP, PF00014 or IPR002223
+  xref_id VARCHAR(50) NOT NULL,
+
+  PRIMARY KEY (accession, database, xref_id),
+  FOREIGN KEY (accession) REFERENCES uniprot_entries(accession_number)
+)
@@ -0,0 +1,21 @@
+CREATE TABLE uniprot_variants (
+  accession VARCHAR(50) NOT NULL,
+
+  -- Feature identifier, e.g. VAR_018369
+  variant VARCHAR(20) NOT NULL,
+
+  -- Amino acid change in HGVS notation, e.g. p.His52Arg
+  aa_change VARCHAR(100) NOT NULL,
+
+  -- LP/P (likely) pathogenic, LB/B (likely) benign or US uncertain
+  category VARCHAR(10) NOT NULL,
+
+  -- dbSNP identifier, e.g. rs893184
+  dbsnp VARCHAR(20),
+
+  -- Associated disease, with its MIM number when known
+  disease TEXT,
+
+  PRIMARY KEY (accession, variant),
+  FOREIGN KEY (accession) REFERENCES uniprot_entries(accession_number)
+)
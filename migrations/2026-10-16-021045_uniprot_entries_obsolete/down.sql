@@ -0,0 +1,3 @@
+ALTER TABLE uniprot_entries DROP COLUMN replaced_by;
+
+ALTER TABLE uniprot_entries DROP COLUMN obsolete;
@@ -0,0 +1,5 @@
+-- Accession deleted from UniProtKB or merged into another entry
+ALTER TABLE uniprot_entries ADD COLUMN obsolete BOOLEAN NOT NULL DEFAULT 0;
+
+-- Primary accession of the entry it was merged into
+ALTER TABLE uniprot_entries ADD COLUMN replaced_by VARCHAR(50);
@@ -0,0 +1,3 @@
+DROP TABLE uniprot_entry_proteomes;
+
+DROP TABLE uniprot_proteomes
@@ -0,0 +1,22 @@
+CREATE TABLE uniprot_proteomes (
+  -- Proteome identifier, e.g. UP000005640
+  id VARCHAR(20) NOT NULL PRIMARY KEY,
+
+  -- NCBI taxonomy identifier and scientific name of the organism
+  taxid INTEGER,
+  organism VARCHAR(200),
+
+  -- e.g. Reference and representative proteome, Other proteome
+  proteome_type VARCHAR(100) NOT NULL,
+
+  -- Reference proteome of its species
+  reference BOOLEAN NOT NULL DEFAULT 0
+);
+
+-- Proteomes are filled after the links, from the identifiers linked
+CREATE TABLE uniprot_entry_proteomes (
+  entry VARCHAR(50) NOT NULL,
+  proteome VARCHAR(20) NOT NULL,
+  PRIMARY KEY (entry, proteome),
+  FOREIGN KEY (entry) REFERENCES uniprot_entries(accession_number)
+)
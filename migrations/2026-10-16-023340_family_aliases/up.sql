@@ -0,0 +1,19 @@
+-- Former names of the families, detected when synchronizing a release by
+-- the members the old and new names share
+CREATE TABLE family_aliases (
+  -- Name the family had in an earlier release
+  alias VARCHAR(300) NOT NULL,
+  family VARCHAR(300) NOT NULL,
+
+  -- rename, or merge when several former families went into it
+  kind VARCHAR(10) NOT NULL,
+
+  -- Share of the former members of the alias found in the family
+  overlap REAL NOT NULL,
+
+  -- Release the change was detected in
+  release_version VARCHAR(50),
+
+  PRIMARY KEY (alias, family),
+  FOREIGN KEY (family) REFERENCES uniprot_sequence_similarity_families(name)
+)
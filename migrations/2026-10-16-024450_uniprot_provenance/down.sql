@@ -0,0 +1,5 @@
+DROP TABLE uniprot_entry_history;
+
+ALTER TABLE belongs_to_uniprot_sequence_similarity_family DROP COLUMN import_run;
+
+ALTER TABLE uniprot_entries DROP COLUMN import_run
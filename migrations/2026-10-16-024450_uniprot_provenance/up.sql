@@ -0,0 +1,23 @@
+-- Import run that last changed the entry or the membership, see
+-- uniprot_entry_history. Not declared as foreign keys, SQLite can't drop
+-- such columns.
+ALTER TABLE uniprot_entries ADD COLUMN import_run INTEGER;
+
+ALTER TABLE belongs_to_uniprot_sequence_similarity_family ADD COLUMN import_run INTEGER;
+
+-- Changes of the entries, by import run
+CREATE TABLE uniprot_entry_history (
+  id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+  accession VARCHAR(50) NOT NULL,
+  import_run INTEGER NOT NULL,
+
+  -- inserted, renamed, joined, left, annotated or retired
+  change VARCHAR(20) NOT NULL,
+
+  -- Family joined or left, former values of what changed...
+  detail TEXT,
+
+  FOREIGN KEY (import_run) REFERENCES import_runs(id)
+);
+
+CREATE INDEX uniprot_entry_history_accession ON uniprot_entry_history(accession)
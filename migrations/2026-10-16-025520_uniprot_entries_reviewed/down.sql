@@ -0,0 +1 @@
+ALTER TABLE uniprot_entries DROP COLUMN reviewed;
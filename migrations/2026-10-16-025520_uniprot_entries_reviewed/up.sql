@@ -0,0 +1,3 @@
+-- Swiss-Prot (reviewed) or TrEMBL (unreviewed) entry, unknown until
+-- enriched
+ALTER TABLE uniprot_entries ADD COLUMN reviewed BOOLEAN;
@@ -0,0 +1,49 @@
+use clap::Parser;
+
+use biology_ru::logging::{init_logging, LogArgs};
+use biology_ru::uaspire::count::count_fastq;
+use biology_ru::uaspire::reader::STDIN_PATH;
+
+#[derive(Parser)]
+#[command(
+    name = "uaspire-count",
+    author = "Biology CLI",
+    version = "1.0.0",
+    about = "Count uASPIre barcode pairs and RBSs into a gzipped TSV"
+)]
+struct Args {
+    // Input FASTQ files, `-` reads an interleaved stream from stdin
+    #[arg()]
+    read1: std::path::PathBuf,
+    #[arg()]
+    read2: Option<std::path::PathBuf>,
+
+    // Output TSV
+    #[arg(long, short, default_value = "counts.tsv.gz")]
+    output: std::path::PathBuf,
+
+    // Chunk size
+    #[arg(long, short, default_value = "10000")]
+    chunk_size: usize,
+
+    #[command(flatten)]
+    log: LogArgs,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let _guard = init_logging(&args.log);
+
+    let read2 = match (args.read2, args.read1.as_os_str() == STDIN_PATH) {
+        (Some(read2), _) => read2,
+        (None, true) => std::path::PathBuf::from(STDIN_PATH),
+        (None, false) => panic!("READ2 is required unless READ1 is -"),
+    };
+
+    if let Err(e) =
+        count_fastq(&args.read1, &read2, &args.output, args.chunk_size)
+    {
+        panic!("Counting failed: {e}");
+    }
+}
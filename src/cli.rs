@@ -1,6 +1,7 @@
 use clap::{Parser, Subcommand};
 
 use crate::commands;
+use crate::logging::LogArgs;
 
 #[derive(Subcommand, Debug)]
 pub enum Commands {
@@ -8,6 +9,10 @@ pub enum Commands {
     Uniprot(commands::uniprot::Commands),
     #[command(subcommand)]
     Uaspire(commands::uaspire::Commands),
+    #[command(subcommand)]
+    Crispr(commands::crispr::Commands),
+    #[command(subcommand)]
+    Mpra(commands::mpra::Commands),
 }
 
 #[derive(Parser)]
@@ -21,4 +26,7 @@ pub enum Commands {
 pub struct Cli {
     #[command(subcommand)]
     pub command: Commands,
+
+    #[command(flatten)]
+    pub log: LogArgs,
 }
@@ -0,0 +1,83 @@
+use clap::{Parser, Subcommand};
+
+use crate::commands::uaspire::{init_processing, run_sample, ProcessArgs};
+use crate::uaspire::assay::Assay;
+use crate::uaspire::crispr::GuideOptions;
+use crate::uaspire::processor::UaspireProcessor;
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Commands {
+    Count(CountCommand),
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct CountCommand {
+    // Input FASTQ files
+    #[arg()]
+    read1: std::path::PathBuf,
+    #[arg()]
+    read2: std::path::PathBuf,
+
+    // Sample name
+    #[arg(long, short)]
+    sample_name: String,
+
+    // Output directory
+    #[arg(long, short, default_value = "./output")]
+    output_dir: std::path::PathBuf,
+
+    // Guide library, FASTA or `name,sequence` CSV
+    #[arg(long, short)]
+    guides: std::path::PathBuf,
+
+    // Sequence right before the guide in read 1
+    #[arg(long, default_value = "CACCG")]
+    guide_anchor: String,
+
+    // Match guides within one substitution
+    #[arg(long)]
+    one_mismatch: bool,
+
+    // Sample barcode starts in read 1 and read 2
+    #[arg(long, default_value = "0")]
+    barcode1_offset: usize,
+    #[arg(long, default_value = "0")]
+    barcode2_offset: usize,
+
+    // Print the run summary as JSON
+    #[arg(long)]
+    json_summary: bool,
+
+    // Validate inputs and print the plan without writing anything
+    #[arg(long)]
+    dry_run: bool,
+
+    #[command(flatten)]
+    process: ProcessArgs,
+}
+
+/// Run a CRISPR command and return the process exit code.
+pub fn command(cmds: Commands) -> i32 {
+    match cmds {
+        Commands::Count(cmd) => {
+            init_processing(cmd.process.threads);
+
+            let mut opts = cmd.process.into_options();
+            opts.assay = Assay::Crispr;
+            opts.guides = Some(GuideOptions {
+                library: cmd.guides,
+                anchor: cmd.guide_anchor.to_ascii_uppercase(),
+                max_mismatches: usize::from(cmd.one_mismatch),
+                barcode_offsets: (cmd.barcode1_offset, cmd.barcode2_offset),
+            });
+            let csv_stdout = opts.csv_stdout;
+
+            let processor = UaspireProcessor::new(&cmd.sample_name)
+                .inputs(&cmd.read1, &cmd.read2)
+                .output_dir(&cmd.output_dir)
+                .options(opts);
+
+            run_sample(processor, cmd.dry_run, cmd.json_summary, csv_stdout)
+        }
+    }
+}
@@ -1,2 +1,4 @@
+pub mod crispr;
+pub mod mpra;
 pub mod uaspire;
 pub mod uniprot;
@@ -0,0 +1,95 @@
+use clap::{Parser, Subcommand};
+
+use crate::commands::uaspire::{init_processing, run_sample, ProcessArgs};
+use crate::uaspire::assay::Assay;
+use crate::uaspire::mpra::MpraOptions;
+use crate::uaspire::processor::UaspireProcessor;
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Commands {
+    Count(CountCommand),
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct CountCommand {
+    // Input FASTQ files
+    #[arg()]
+    read1: std::path::PathBuf,
+    #[arg()]
+    read2: std::path::PathBuf,
+
+    // Sample name
+    #[arg(long, short)]
+    sample_name: String,
+
+    // Output directory
+    #[arg(long, short, default_value = "./output")]
+    output_dir: std::path::PathBuf,
+
+    // Constant sequence next to the random barcode
+    #[arg(long, short)]
+    anchor: String,
+
+    // Random barcode length, and whether it comes before the anchor
+    #[arg(long, default_value = "20")]
+    barcode_len: usize,
+    #[arg(long)]
+    upstream: bool,
+
+    // Read holding the anchor and the random barcode
+    #[arg(
+        long,
+        default_value = "2",
+        value_parser = clap::value_parser!(u8).range(1..=2)
+    )]
+    read: u8,
+
+    // Sample barcode starts in read 1 and read 2
+    #[arg(long, default_value = "0")]
+    barcode1_offset: usize,
+    #[arg(long, default_value = "0")]
+    barcode2_offset: usize,
+
+    // `barcode,element` CSV or TSV for element-level counts
+    #[arg(long)]
+    association: Option<std::path::PathBuf>,
+
+    // Print the run summary as JSON
+    #[arg(long)]
+    json_summary: bool,
+
+    // Validate inputs and print the plan without writing anything
+    #[arg(long)]
+    dry_run: bool,
+
+    #[command(flatten)]
+    process: ProcessArgs,
+}
+
+/// Run an MPRA command and return the process exit code.
+pub fn command(cmds: Commands) -> i32 {
+    match cmds {
+        Commands::Count(cmd) => {
+            init_processing(cmd.process.threads);
+
+            let mut opts = cmd.process.into_options();
+            opts.assay = Assay::Mpra;
+            opts.mpra = Some(MpraOptions {
+                anchor: cmd.anchor.to_ascii_uppercase(),
+                barcode_len: cmd.barcode_len,
+                upstream: cmd.upstream,
+                read: cmd.read,
+                barcode_offsets: (cmd.barcode1_offset, cmd.barcode2_offset),
+                association: cmd.association,
+            });
+            let csv_stdout = opts.csv_stdout;
+
+            let processor = UaspireProcessor::new(&cmd.sample_name)
+                .inputs(&cmd.read1, &cmd.read2)
+                .output_dir(&cmd.output_dir)
+                .options(opts);
+
+            run_sample(processor, cmd.dry_run, cmd.json_summary, csv_stdout)
+        }
+    }
+}
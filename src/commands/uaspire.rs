@@ -1,22 +1,88 @@
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 use rayon::ThreadPoolBuilder;
-use tracing_subscriber;
 
-use crate::uaspire::fastq::process_fastq;
+use strum::IntoEnumIterator;
+
+use crate::uaspire::assay::Assay;
+use crate::uaspire::batch::{read_batch_manifest, run_batch};
+use crate::uaspire::bench::{run_bench, run_whitelist_bench};
+use crate::uaspire::check::check_pair;
+use crate::uaspire::classify::{FailReason, Geometry, NLimits};
+use crate::uaspire::compare::compare_runs;
+use crate::uaspire::composition::RestrictionSite;
+use crate::uaspire::downsample::DownsampleOptions;
+use crate::uaspire::duplicates::DuplicateOptions;
+use crate::uaspire::export::DbKind;
+use crate::uaspire::fastq::{OverwritePolicy, ProcessOptions};
+use crate::uaspire::h5ad::write_h5ad;
+use crate::uaspire::inspect::inspect;
+use crate::uaspire::interrupt::install_handlers;
+use crate::uaspire::longread::LongReadOptions;
+use crate::uaspire::manifest::{CountsShape, OutputLayout};
+use crate::uaspire::matrix::{
+    build_matrix, load_counts, rbs_metadata, write_matrix, MatrixColumns,
+    MatrixValue,
+};
+use crate::uaspire::metadata::print_schemas;
+use crate::uaspire::notification::NotifyOptions;
+use crate::uaspire::overlap::OverlapOptions;
+use crate::uaspire::probe::probe_pair;
+use crate::uaspire::processor::{RunError, UaspireProcessor};
+use crate::uaspire::reader::STDIN_PATH;
+use crate::uaspire::retention::{OutputKind, RetentionPolicy};
+use crate::uaspire::samplesheet::{check_samplesheet, read_samplesheet};
+use crate::uaspire::twopass::{aggregate_assignments, classify_to_assignments};
+use crate::uaspire::variants::VariantOptions;
+use crate::uaspire::watch::{watch_folder, WatchOptions};
 
 #[derive(Subcommand, Debug, Clone)]
 pub enum Commands {
     #[command(name = "process-sample")]
     ParseFastq(ParseFastqCommand),
+    Explain(ExplainCommand),
+    Check(CheckCommand),
+    Probe(ProbeCommand),
+    // Output schemas and versions embedded in the Parquet metadata
+    Schema,
+    Batch(BatchCommand),
+    Watch(WatchCommand),
+    Bench(BenchCommand),
+    Matrix(MatrixCommand),
+    Compare(CompareCommand),
+    Inspect(InspectCommand),
+    Classify(ClassifyCommand),
+    Aggregate(AggregateCommand),
+    #[command(subcommand)]
+    Samplesheet(SamplesheetCommands),
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum SamplesheetCommands {
+    Check(SamplesheetCheckCommand),
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct SamplesheetCheckCommand {
+    // CSV with sample,barcode1,barcode2 columns
+    #[arg()]
+    samplesheet: std::path::PathBuf,
+
+    // Smallest Hamming distance allowed between barcodes of a read
+    #[arg(long, default_value = "3")]
+    min_distance: usize,
+
+    // Expected barcode length
+    #[arg(long)]
+    barcode_len: Option<usize>,
 }
 
 #[derive(Parser, Debug, Clone)]
 pub struct ParseFastqCommand {
-    // Input FASTQ files
+    // Input FASTQ files, `-` reads an interleaved stream from stdin
     #[arg()]
     read1: std::path::PathBuf,
     #[arg()]
-    read2: std::path::PathBuf,
+    read2: Option<std::path::PathBuf>,
 
     // Sample name
     #[arg(long, short)]
@@ -26,34 +92,1001 @@ pub struct ParseFastqCommand {
     #[arg(long, short, default_value = "./output")]
     output_dir: std::path::PathBuf,
 
+    // Print the run summary as JSON
+    #[arg(long)]
+    json_summary: bool,
+
+    // Validate inputs and print the plan without writing anything
+    #[arg(long)]
+    dry_run: bool,
+
+    #[command(flatten)]
+    process: ProcessArgs,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct ProcessArgs {
+    // Worker threads for classification
+    #[arg(long, default_value = "10")]
+    pub(crate) threads: usize,
+
     // Chunk and parquet sizes
     #[arg(long, short, default_value = "10000")]
     chunk_size: usize,
     #[arg(long, short, default_value = "10000")]
     parquet_size: usize,
+
+    // Approximate memory budget for counts before spilling to disk
+    #[arg(long, default_value = "4G", value_parser = parse_size)]
+    max_memory: usize,
+
+    // Compact the chunk files in tmp/parquet once they grow over this size
+    #[arg(long, value_parser = parse_size)]
+    max_tmp_size: Option<usize>,
+
+    // Merge chunk files with the Polars streaming engine
+    #[arg(long)]
+    merge_streaming: bool,
+
+    // Columns used to partition the counts output
+    #[arg(long, value_delimiter = ',', default_value = "barcode1,barcode2")]
+    partition_by: Vec<String>,
+
+    // Optional SQL database export of the merged counts
+    #[arg(long)]
+    export_db: Option<std::path::PathBuf>,
+    #[arg(long, value_enum, default_value = "sqlite")]
+    export_db_kind: DbKind,
+
+    // Write the merged counts as CSV to stdout
+    #[arg(long)]
+    csv_stdout: bool,
+
+    // Write the reads of each barcode pair to data/fastq
+    #[arg(long)]
+    write_fastq: bool,
+
+    // Write read pairs failing classification to data/rejects
+    #[arg(long)]
+    write_rejects: bool,
+    #[arg(long, requires = "write_rejects")]
+    annotate_rejects: bool,
+
+    // Require identical read IDs, including /1 and /2 suffixes
+    #[arg(long)]
+    strict_ids: bool,
+
+    // Stop reading after this many malformed records
+    #[arg(long)]
+    max_errors: Option<u64>,
+
+    // Output retention, qc, counts and elements kept unless dropped
+    #[arg(long, value_delimiter = ',')]
+    keep: Option<Vec<OutputKind>>,
+    #[arg(long, value_delimiter = ',')]
+    drop: Vec<OutputKind>,
+    #[arg(long)]
+    max_output_gb: Option<f64>,
+
+    // Write per-chunk timings to trace.tsv
+    #[arg(long)]
+    trace: bool,
+
+    // Add CPM and flipped fraction columns to the counts
+    #[arg(long)]
+    normalize: bool,
+
+    // Drop RBS rows with fewer reads, flipped and unflipped together
+    #[arg(long, default_value = "0")]
+    min_count: u64,
+
+    // Control RBSs as FASTA or name,sequence CSV, tallied in QC and left
+    // out of the counts
+    #[arg(long)]
+    spike_ins: Option<std::path::PathBuf>,
+    // Scale counts to a million spike-in reads of their barcode pair
+    #[arg(long, requires = "spike_ins")]
+    spike_in_normalize: bool,
+
+    // Sort rows before every write so reruns give identical files
+    #[arg(long)]
+    deterministic: bool,
+
+    // Skip chunk files unless the memory budget is exceeded
+    #[arg(long)]
+    in_memory: bool,
+
+    // Existing output directory: replace it, or refuse when not empty
+    #[arg(long, visible_alias = "force")]
+    overwrite: bool,
+    #[arg(long, conflicts_with = "overwrite")]
+    no_clobber: bool,
+
+    // Add the counts of new reads to an existing run of the sample
+    #[arg(long, conflicts_with_all = ["overwrite", "no_clobber"])]
+    append: bool,
+
+    // Carry on with an interrupted run of the sample, on the same inputs
+    #[arg(long, conflicts_with_all = ["overwrite", "no_clobber", "append"])]
+    resume: bool,
+
+    // Hive-partitioned directories or one file per sample
+    #[arg(long, value_enum, default_value = "hive")]
+    layout: OutputLayout,
+
+    // Flip states as unflipped/flipped columns, or as state/count rows
+    #[arg(long, value_enum, default_value = "wide")]
+    counts_shape: CountsShape,
+
+    // Amplicon assay the read pairs come from
+    #[arg(long, value_enum, default_value = "uaspire")]
+    assay: Assay,
+
+    #[command(flatten)]
+    geometry: GeometryArgs,
+
+    // Report barcode corrections in qc/barcode_corrections.tsv
+    #[arg(long)]
+    report_corrections: bool,
+
+    // Add Shine-Dalgarno free energy and predicted strength columns
+    #[arg(long)]
+    predict_strength: bool,
+
+    // Add GC content and longest homopolymer columns
+    #[arg(long)]
+    composition: bool,
+
+    // Flag RBSs holding these sites on either strand, as NAME=SEQUENCE
+    #[arg(long, value_delimiter = ',')]
+    restriction_sites: Vec<RestrictionSite>,
+
+    // Merge overlapping mates and classify the whole fragment
+    #[arg(long)]
+    merge_overlaps: bool,
+    #[arg(long, default_value = "12", requires = "merge_overlaps")]
+    min_overlap: usize,
+    #[arg(long, default_value = "0.1", requires = "merge_overlaps")]
+    max_overlap_mismatch: f64,
+
+    // Scan single long reads (Nanopore) for any number of constructs
+    #[arg(
+        long,
+        conflicts_with_all = ["merge_overlaps", "write_fastq", "write_rejects"]
+    )]
+    long_reads: bool,
+    #[arg(long, default_value = "0.15", requires = "long_reads")]
+    max_edit_rate: f64,
+
+    // Estimate the PCR duplicate rate on a subsample of the valid pairs
+    #[arg(long, conflicts_with = "long_reads")]
+    estimate_duplicates: bool,
+    #[arg(long, default_value = "100", requires = "estimate_duplicates")]
+    duplicate_sample_every: u64,
+    #[arg(long, default_value = "20", requires = "estimate_duplicates")]
+    duplicate_prefix_len: usize,
+
+    // Align a subsample of reads to this reference construct and report
+    // per-position variant frequencies in qc/variants.parquet
+    #[arg(long)]
+    variants_reference: Option<std::path::PathBuf>,
+    #[arg(
+        long,
+        default_value = "2",
+        requires = "variants_reference",
+        value_parser = clap::value_parser!(u8).range(1..=2)
+    )]
+    variants_read: u8,
+    #[arg(long, default_value = "10", requires = "variants_reference")]
+    variants_sample_every: u64,
+
+    // Post the run summary as JSON to this URL, and/or pipe it to this
+    // shell command, once the run completes or fails
+    #[arg(long)]
+    notify_webhook: Option<String>,
+    #[arg(long)]
+    notify_cmd: Option<String>,
+
+    // Keep at most this many reads per barcode pair, drawn at random
+    #[arg(long)]
+    downsample: Option<u64>,
+    #[arg(long, default_value = "0", requires = "downsample")]
+    downsample_seed: u64,
+
+    // Serve live counters in the Prometheus format on this port, of the
+    // loopback interface unless another address is given
+    #[arg(long)]
+    metrics_port: Option<u16>,
+    #[arg(long, default_value = "127.0.0.1", requires = "metrics_port")]
+    metrics_addr: std::net::IpAddr,
+}
+
+// Read geometry shared by the single-pass and classification commands
+#[derive(Args, Debug, Clone)]
+pub struct GeometryArgs {
+    // Variable length RBS ending before this anchor sequence
+    #[arg(long)]
+    rbs_anchor: Option<String>,
+    #[arg(long, default_value = "15", requires = "rbs_anchor")]
+    rbs_min_len: usize,
+    #[arg(long, default_value = "25", requires = "rbs_anchor")]
+    rbs_max_len: usize,
+
+    /// Constant region window in read 2, 1-based LO:HI, or auto to learn it
+    /// once from the first chunk and keep it for the whole run
+    #[arg(long, value_parser = parse_window)]
+    window: Option<WindowArg>,
+
+    // Correct barcodes within this many mismatches of a single whitelist
+    // barcode
+    #[arg(long, default_value = "0")]
+    barcode_mismatches: usize,
+
+    // Settings file with a [uaspire.max_n] section
+    #[arg(long)]
+    config: Option<std::path::PathBuf>,
+
+    // Maximum N base calls, overriding the settings file. The pair limit
+    // is ignored once a read or element limit is set
+    #[arg(long)]
+    max_n: Option<usize>,
+    #[arg(long)]
+    max_n_read1: Option<usize>,
+    #[arg(long)]
+    max_n_read2: Option<usize>,
+    #[arg(long)]
+    max_n_barcode: Option<usize>,
+    #[arg(long)]
+    max_n_rbs: Option<usize>,
+}
+
+/// N limits of a settings file, defaults when the section is missing.
+fn load_n_limits(
+    path: &std::path::Path,
+) -> Result<NLimits, config::ConfigError> {
+    let settings = config::Config::builder()
+        .add_source(config::File::from(path))
+        .build()?;
+
+    match settings.get::<NLimits>("uaspire.max_n") {
+        Err(config::ConfigError::NotFound(_)) => Ok(NLimits::default()),
+        limits => limits,
+    }
+}
+
+impl GeometryArgs {
+    fn n_limits(&self) -> NLimits {
+        let limits = match &self.config {
+            Some(path) => load_n_limits(path).unwrap_or_else(|e| {
+                panic!("Invalid settings file {}: {e}", path.display())
+            }),
+            None => NLimits::default(),
+        };
+
+        NLimits {
+            pair: self.max_n.unwrap_or(limits.pair),
+            read1: self.max_n_read1.or(limits.read1),
+            read2: self.max_n_read2.or(limits.read2),
+            barcode: self.max_n_barcode.or(limits.barcode),
+            rbs: self.max_n_rbs.or(limits.rbs),
+        }
+    }
+
+    pub(crate) fn geometry(&self) -> Geometry {
+        Geometry {
+            max_n: self.n_limits(),
+            rbs_anchor: self
+                .rbs_anchor
+                .as_ref()
+                .map(|a| a.to_ascii_uppercase()),
+            rbs_len_range: (self.rbs_min_len, self.rbs_max_len),
+            barcode_mismatches: self.barcode_mismatches,
+            window: match self.window {
+                Some(WindowArg::Fixed(lo, hi)) => (lo, hi),
+                _ => Geometry::default().window,
+            },
+            ..Geometry::default()
+        }
+    }
+}
+
+impl ProcessArgs {
+    pub(crate) fn into_options(self) -> ProcessOptions {
+        let retention =
+            RetentionPolicy::new(self.keep, self.drop, self.max_output_gb)
+                .unwrap_or_else(|e| panic!("Invalid retention policy: {e}"));
+
+        ProcessOptions {
+            chunk_size: self.chunk_size,
+            parquet_size: self.parquet_size,
+            max_memory: self.max_memory,
+            max_tmp_bytes: self.max_tmp_size,
+            merge_streaming: self.merge_streaming,
+            partition_by: self.partition_by,
+            export_db: self.export_db.map(|p| (p, self.export_db_kind)),
+            csv_stdout: self.csv_stdout,
+            write_fastq: self.write_fastq,
+            write_rejects: self.write_rejects,
+            annotate_rejects: self.annotate_rejects,
+            strict_ids: self.strict_ids,
+            max_errors: self.max_errors,
+            retention,
+            trace: self.trace,
+            normalize: self.normalize,
+            min_count: self.min_count,
+            spike_ins: self.spike_ins,
+            spike_in_normalize: self.spike_in_normalize,
+            deterministic: self.deterministic,
+            in_memory: self.in_memory,
+            layout: self.layout,
+            assay: self.assay,
+            predict_strength: self.predict_strength,
+            composition: self.composition,
+            restriction_sites: self.restriction_sites,
+            merge_overlap: self.merge_overlaps.then_some(OverlapOptions {
+                min_overlap: self.min_overlap,
+                max_mismatch_rate: self.max_overlap_mismatch,
+            }),
+            append: self.append,
+            resume: self.resume,
+            long_reads: self.long_reads.then_some(LongReadOptions {
+                max_edit_rate: self.max_edit_rate,
+            }),
+            barcode_corrections: self.report_corrections,
+            variants: self.variants_reference.map(|reference| VariantOptions {
+                reference,
+                read: self.variants_read,
+                sample_every: self.variants_sample_every,
+            }),
+            metrics_port: self.metrics_port,
+            metrics_addr: self.metrics_addr,
+            auto_window: self.geometry.window == Some(WindowArg::Auto),
+            counts_shape: self.counts_shape,
+            downsample: self.downsample.map(|depth| DownsampleOptions {
+                depth,
+                seed: self.downsample_seed,
+            }),
+            notify: NotifyOptions {
+                webhook: self.notify_webhook,
+                command: self.notify_cmd,
+            },
+            duplicates: self.estimate_duplicates.then_some(DuplicateOptions {
+                sample_every: self.duplicate_sample_every,
+                prefix_len: self.duplicate_prefix_len,
+            }),
+            geometry: self.geometry.geometry(),
+            overwrite: match (self.overwrite, self.no_clobber) {
+                (true, _) => OverwritePolicy::Overwrite,
+                (false, true) => OverwritePolicy::NoClobber,
+                (false, false) => OverwritePolicy::Merge,
+            },
+            ..ProcessOptions::default()
+        }
+    }
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct BatchCommand {
+    // CSV manifest with sample, read1, read2 and optional output columns
+    #[arg()]
+    manifest: std::path::PathBuf,
+
+    // Root directory of the per-sample outputs
+    #[arg(long, short, default_value = "./output")]
+    output_dir: std::path::PathBuf,
+
+    // Number of samples processed at the same time
+    #[arg(long, short, default_value = "1")]
+    jobs: usize,
+
+    #[command(flatten)]
+    process: ProcessArgs,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct WatchCommand {
+    // Run folder to monitor
+    #[arg()]
+    dir: std::path::PathBuf,
+
+    // Root directory of the per-sample outputs
+    #[arg(long, short, default_value = "./output")]
+    output_dir: std::path::PathBuf,
+
+    // Glob selecting R1 files, and the tags telling R1 and R2 apart
+    #[arg(long, default_value = "*_R1*.fastq.gz")]
+    pattern: glob::Pattern,
+    #[arg(long, default_value = "_R1")]
+    read1_tag: String,
+    #[arg(long, default_value = "_R2")]
+    read2_tag: String,
+
+    // Seconds without size change before a file counts as complete
+    #[arg(long, default_value = "60")]
+    settle_secs: u64,
+
+    #[command(flatten)]
+    process: ProcessArgs,
 }
 
-pub fn command(cmds: Commands) {
+#[derive(Parser, Debug, Clone)]
+pub struct BenchCommand {
+    // Number of synthetic read pairs
+    #[arg(long, default_value = "1000000")]
+    reads: usize,
+
+    // Thread counts and chunk sizes to compare
+    #[arg(long, value_delimiter = ',', default_value = "1,2,4,8")]
+    threads: Vec<usize>,
+    #[arg(long, value_delimiter = ',', default_value = "1000,10000,100000")]
+    chunk_sizes: Vec<usize>,
+
+    // Whitelist sizes to compare scanned and hashed barcode lookups on
+    #[arg(long, value_delimiter = ',')]
+    whitelist_sizes: Vec<usize>,
+    #[arg(long, default_value = "1000000")]
+    lookups: usize,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct MatrixCommand {
+    // Output directories of the runs, or their counts directories
+    #[arg(required = true)]
+    inputs: Vec<std::path::PathBuf>,
+
+    // Matrix file, CSV when ending in .csv, AnnData when ending in .h5ad
+    // and Parquet otherwise
+    #[arg(long, short, default_value = "matrix.parquet")]
+    output: std::path::PathBuf,
+
+    // One column per sample or per barcode pair
+    #[arg(long, value_enum, default_value = "sample")]
+    columns: MatrixColumns,
+
+    // Cell values
+    #[arg(long, value_enum, default_value = "flip-fraction")]
+    value: MatrixValue,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct ClassifyCommand {
+    // Input FASTQ files
+    #[arg()]
+    read1: std::path::PathBuf,
+    #[arg()]
+    read2: std::path::PathBuf,
+
+    // Sample name
+    #[arg(long, short)]
+    sample_name: String,
+
+    // Directory of the per-read assignments
+    #[arg(long, short)]
+    output_dir: std::path::PathBuf,
+
+    #[arg(long, default_value = "10")]
+    threads: usize,
+    #[arg(long, default_value = "1000000")]
+    chunk_size: usize,
+
+    #[command(flatten)]
+    geometry: GeometryArgs,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct AggregateCommand {
+    // Directory written by `classify`
+    #[arg()]
+    assignments: std::path::PathBuf,
+
+    // Output directory
+    #[arg(long, short, default_value = "./output")]
+    output_dir: std::path::PathBuf,
+
+    // Drop RBSs seen fewer times in a barcode pair
+    #[arg(long, default_value = "0")]
+    min_count: u64,
+
+    // Add counts per million and flipped fraction columns
+    #[arg(long)]
+    normalize: bool,
+
+    // Add Shine-Dalgarno free energy and predicted strength columns
+    #[arg(long)]
+    predict_strength: bool,
+
+    // Add GC content and longest homopolymer columns
+    #[arg(long)]
+    composition: bool,
+
+    // Sort rows so that identical inputs give identical files
+    #[arg(long)]
+    deterministic: bool,
+
+    // Hive-partitioned directories or one file per sample
+    #[arg(long, value_enum, default_value = "hive")]
+    layout: OutputLayout,
+
+    #[arg(long, value_delimiter = ',', default_value = "barcode1,barcode2")]
+    partition_by: Vec<String>,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct InspectCommand {
+    // Output directory of a run, or its counts directory
+    #[arg()]
+    output_dir: std::path::PathBuf,
+
+    // Number of most frequent RBSs to list
+    #[arg(long, short = 'n', default_value = "10")]
+    top: usize,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct CompareCommand {
+    // Output directories of the runs to compare
+    #[arg(required = true, num_args = 2..)]
+    runs: Vec<std::path::PathBuf>,
+
+    // Comparison table
+    #[arg(long, short, default_value = "comparison.tsv")]
+    output: std::path::PathBuf,
+
+    // Directory of the scatter plots, none when omitted
+    #[arg(long)]
+    plots: Option<std::path::PathBuf>,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct ExplainCommand {
+    // Failure reason name or code, all reasons when omitted
+    #[arg()]
+    reason: Option<String>,
+
+    // Print a Markdown table for the documentation
+    #[arg(long)]
+    markdown: bool,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct CheckCommand {
+    // Input FASTQ files
+    #[arg()]
+    read1: std::path::PathBuf,
+    #[arg()]
+    read2: std::path::PathBuf,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct ProbeCommand {
+    // Input FASTQ files
+    #[arg()]
+    read1: std::path::PathBuf,
+    #[arg()]
+    read2: std::path::PathBuf,
+
+    // Read pairs scanned from the start of the files
+    #[arg(long, default_value = "100000")]
+    reads: u64,
+}
+
+/// Constant region window given on the command line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum WindowArg {
+    Fixed(usize, usize),
+    Auto,
+}
+
+/// Parse a window such as `7:24`, or `auto`.
+fn parse_window(s: &str) -> Result<WindowArg, String> {
+    if s.eq_ignore_ascii_case("auto") {
+        return Ok(WindowArg::Auto);
+    }
+
+    let bound = |b: &str| {
+        b.trim().parse::<usize>().map_err(|_| {
+            format!("invalid window '{s}', expected LO:HI or auto")
+        })
+    };
+    match s.split_once(':') {
+        Some((lo, hi)) => Ok(WindowArg::Fixed(bound(lo)?, bound(hi)?)),
+        None => Err(format!("invalid window '{s}', expected LO:HI or auto")),
+    }
+}
+
+/// Parse a size such as `512M` or `4G` into bytes.
+fn parse_size(s: &str) -> Result<usize, String> {
+    let s = s.trim();
+    let (number, unit) = match s.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(pos) => s.split_at(pos),
+        None => (s, ""),
+    };
+
+    let multiplier: f64 = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1.0,
+        "K" | "KB" => 1e3,
+        "M" | "MB" => 1e6,
+        "G" | "GB" => 1e9,
+        "T" | "TB" => 1e12,
+        _ => return Err(format!("unknown size unit '{unit}'")),
+    };
+
+    let value: f64 = number
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid size '{s}'"))?;
+
+    Ok((value * multiplier) as usize)
+}
+
+/// Global thread pool and signal handlers shared by all processing runs.
+pub(crate) fn init_processing(threads: usize) {
+    ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build_global()
+        .expect("Failed to build thread pool");
+
+    // SIGINT and SIGTERM stop the run after the chunk in flight
+    if let Err(err) = install_handlers() {
+        eprintln!("Couldn't install signal handlers: {err}");
+    }
+}
+
+/// Run a single sample, or print its plan, and return the exit code.
+pub(crate) fn run_sample(
+    processor: UaspireProcessor,
+    dry_run: bool,
+    json_summary: bool,
+    csv_stdout: bool,
+) -> i32 {
+    // The summary must not end up in the middle of streamed CSV
+    let mut out: Box<dyn std::io::Write> = if csv_stdout {
+        Box::new(std::io::stderr())
+    } else {
+        Box::new(std::io::stdout())
+    };
+
+    if dry_run {
+        return match processor.plan() {
+            Ok(plan) => {
+                plan.print(&mut out).expect("Failed to print plan");
+                0
+            }
+            Err(e) => {
+                eprintln!("{e}");
+                e.exit_code()
+            }
+        };
+    }
+
+    let result = processor.run();
+
+    let summary = match result {
+        Ok(summary) => summary,
+        Err(e) => {
+            eprintln!("{e}");
+            return e.exit_code();
+        }
+    };
+
+    let printed = if json_summary {
+        summary.print_json(&mut out)
+    } else {
+        summary.print(&mut out)
+    };
+    printed.expect("Failed to print run summary");
+
+    summary.exit_code()
+}
+
+/// Run a uASPIre command and return the process exit code.
+pub fn command(cmds: Commands) -> i32 {
     match cmds {
         Commands::ParseFastq(cmd) => {
-            tracing_subscriber::fmt()
-                .compact()
-                .with_max_level(tracing::Level::INFO)
-                .init();
-
-            ThreadPoolBuilder::new()
-                .num_threads(10)
-                .build_global()
-                .expect("Failed to build thread pool");
-
-            process_fastq(
-                &cmd.read1.to_string_lossy(),
-                &cmd.read2.to_string_lossy(),
+            init_processing(cmd.process.threads);
+
+            let read2 = match (&cmd.read2, cmd.read1.as_os_str() == STDIN_PATH)
+            {
+                (Some(read2), _) => read2.clone(),
+                (None, _) if cmd.process.long_reads => Default::default(),
+                (None, true) => std::path::PathBuf::from(STDIN_PATH),
+                (None, false) => panic!(
+                    "READ2 is required unless READ1 is - or with --long-reads"
+                ),
+            };
+
+            let opts = cmd.process.into_options();
+            let csv_stdout = opts.csv_stdout;
+
+            let processor = UaspireProcessor::new(&cmd.sample_name)
+                .inputs(&cmd.read1, &read2)
+                .output_dir(&cmd.output_dir)
+                .options(opts);
+
+            run_sample(processor, cmd.dry_run, cmd.json_summary, csv_stdout)
+        }
+        Commands::Batch(cmd) => {
+            init_processing(cmd.process.threads);
+
+            let entries = read_batch_manifest(&cmd.manifest)
+                .unwrap_or_else(|e| panic!("Invalid batch manifest: {e}"));
+            let opts = cmd.process.into_options();
+
+            let failed = run_batch(&entries, &cmd.output_dir, &opts, cmd.jobs);
+            if failed.is_empty() {
+                0
+            } else {
+                eprintln!("Failed samples: {}", failed.join(", "));
+                1
+            }
+        }
+        Commands::Watch(cmd) => {
+            init_processing(cmd.process.threads);
+
+            let watch = WatchOptions {
+                pattern: cmd.pattern,
+                read1_tag: cmd.read1_tag,
+                read2_tag: cmd.read2_tag,
+                settle: std::time::Duration::from_secs(cmd.settle_secs),
+            };
+            let opts = cmd.process.into_options();
+
+            if let Err(e) =
+                watch_folder(&cmd.dir, &cmd.output_dir, &watch, &opts)
+            {
+                panic!("Watching {} failed: {e}", cmd.dir.display());
+            }
+            0
+        }
+        Commands::Bench(cmd) => {
+            let results = run_bench(cmd.reads, &cmd.threads, &cmd.chunk_sizes)
+                .unwrap_or_else(|e| panic!("Couldn't build thread pool: {e}"));
+
+            println!("{:>8} {:>10} {:>14}", "threads", "chunk", "reads/s");
+            for r in results {
+                println!(
+                    "{:>8} {:>10} {:>14.0}",
+                    r.threads,
+                    r.chunk_size,
+                    r.reads_per_sec()
+                );
+            }
+
+            if !cmd.whitelist_sizes.is_empty() {
+                let results = run_whitelist_bench(
+                    &cmd.whitelist_sizes,
+                    cmd.lookups,
+                    cmd.reads,
+                );
+
+                println!();
+                println!(
+                    "{:>10} {:>12} {:>12} {:>8} {:>14}",
+                    "whitelist", "scan s", "hashed s", "speedup", "reads/s"
+                );
+                for r in results {
+                    println!(
+                        "{:>10} {:>12.4} {:>12.4} {:>8.1} {:>14.0}",
+                        r.size,
+                        r.scan_seconds,
+                        r.hashed_seconds,
+                        r.speedup(),
+                        r.classify_reads_per_sec
+                    );
+                }
+            }
+            0
+        }
+        Commands::Matrix(cmd) => {
+            let counts = load_counts(&cmd.inputs)
+                .unwrap_or_else(|e| panic!("Couldn't load counts: {e}"));
+            let mut matrix = build_matrix(&counts, cmd.columns, cmd.value)
+                .unwrap_or_else(|e| panic!("Couldn't build matrix: {e}"));
+
+            if cmd.output.extension().is_some_and(|ext| ext == "h5ad") {
+                let obs = rbs_metadata(&counts)
+                    .unwrap_or_else(|e| panic!("Couldn't build metadata: {e}"));
+
+                return match write_h5ad(&matrix, &obs, &cmd.output) {
+                    Ok(_) => 0,
+                    Err(err) => panic!("Couldn't write AnnData file: {err}"),
+                };
+            }
+
+            match write_matrix(&mut matrix, &cmd.output) {
+                Ok(_) => 0,
+                Err(err) => panic!("Couldn't write matrix: {err}"),
+            }
+        }
+        Commands::Compare(cmd) => {
+            let comparisons =
+                compare_runs(&cmd.runs, &cmd.output, cmd.plots.as_deref())
+                    .unwrap_or_else(|e| panic!("Couldn't compare runs: {e}"));
+
+            for c in comparisons {
+                println!(
+                    "{} vs {}: {} shared, pearson {:.3}, spearman {:.3}",
+                    c.run_a,
+                    c.run_b,
+                    c.shared,
+                    c.pearson_log_counts,
+                    c.spearman_counts
+                );
+            }
+            0
+        }
+        Commands::Classify(cmd) => {
+            init_processing(cmd.threads);
+
+            // The window is learnt by single-pass runs only
+            if cmd.geometry.window == Some(WindowArg::Auto) {
+                let e = RunError::Config(
+                    "--window auto is not supported by classify".into(),
+                );
+                eprintln!("{e}");
+                return e.exit_code();
+            }
+
+            let result = classify_to_assignments(
+                &cmd.read1,
+                &cmd.read2,
                 &cmd.sample_name,
-                &cmd.output_dir.to_string_lossy(),
+                &cmd.output_dir,
+                &cmd.geometry.geometry(),
                 cmd.chunk_size,
-                cmd.parquet_size,
             );
+
+            match result {
+                Ok(valid) => {
+                    println!("{valid} read pairs assigned");
+                    0
+                }
+                Err(e) => {
+                    eprintln!("{e}");
+                    e.exit_code()
+                }
+            }
+        }
+        Commands::Aggregate(cmd) => {
+            let opts = ProcessOptions {
+                min_count: cmd.min_count,
+                normalize: cmd.normalize,
+                predict_strength: cmd.predict_strength,
+                composition: cmd.composition,
+                deterministic: cmd.deterministic,
+                layout: cmd.layout,
+                partition_by: cmd.partition_by,
+                ..ProcessOptions::default()
+            };
+
+            match aggregate_assignments(
+                &cmd.assignments,
+                &cmd.output_dir,
+                &opts,
+            ) {
+                Ok(valid) => {
+                    println!("{valid} read pairs aggregated");
+                    0
+                }
+                Err(e) => {
+                    eprintln!("{e}");
+                    e.exit_code()
+                }
+            }
+        }
+        Commands::Inspect(cmd) => {
+            let report = inspect(&cmd.output_dir, cmd.top)
+                .unwrap_or_else(|e| panic!("Couldn't inspect output: {e}"));
+
+            report
+                .print(&mut std::io::stdout())
+                .expect("Failed to print report");
+            0
+        }
+        Commands::Explain(cmd) => {
+            explain(&cmd);
+            0
+        }
+        Commands::Samplesheet(SamplesheetCommands::Check(cmd)) => {
+            let entries = match read_samplesheet(&cmd.samplesheet) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    eprintln!("{}: {e}", cmd.samplesheet.display());
+                    return 1;
+                }
+            };
+
+            let report =
+                check_samplesheet(&entries, cmd.min_distance, cmd.barcode_len);
+            report.print();
+
+            if report.is_ok() {
+                0
+            } else {
+                1
+            }
+        }
+        Commands::Check(cmd) => {
+            let report = check_pair(&cmd.read1, &cmd.read2);
+            report.print();
+
+            if report.is_ok() {
+                0
+            } else {
+                1
+            }
+        }
+        Commands::Schema => {
+            print_schemas(&mut std::io::stdout())
+                .expect("Failed to print schemas");
+            0
+        }
+        Commands::Probe(cmd) => {
+            match probe_pair(&cmd.read1, &cmd.read2, cmd.reads) {
+                Ok(report) => report.print(),
+                Err(err) => panic!("Couldn't probe read pairs: {err}"),
+            }
+            0
+        }
+    }
+}
+
+fn explain(cmd: &ExplainCommand) {
+    let reasons: Vec<FailReason> = match &cmd.reason {
+        Some(key) => match FailReason::lookup(key) {
+            Some(reason) => vec![reason],
+            None => panic!("Unknown failure reason: {key}"),
+        },
+        None => FailReason::iter().collect(),
+    };
+
+    if cmd.markdown {
+        println!("| Code | Name | Description |");
+        println!("|------|------|-------------|");
+    }
+
+    for r in reasons {
+        if cmd.markdown {
+            println!("| {} | `{}` | {} |", r.code(), r.name(), r.description());
+        } else {
+            println!("{:>3}  {:<16} {}", r.code(), r.name(), r.description());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_or_auto_window() {
+        assert_eq!(parse_window("7:24"), Ok(WindowArg::Fixed(7, 24)));
+        assert_eq!(parse_window(" 7 : 24 "), Ok(WindowArg::Fixed(7, 24)));
+        assert_eq!(parse_window("auto"), Ok(WindowArg::Auto));
+        assert_eq!(parse_window("AUTO"), Ok(WindowArg::Auto));
+    }
+
+    #[test]
+    fn malformed_window() {
+        for window in ["7", "7-24", "7:", ":24", "a:b", "-1:24", ""] {
+            assert!(parse_window(window).is_err(), "{window}");
+        }
+    }
+
+    #[test]
+    fn sizes_with_units() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("512B"), Ok(512));
+        assert_eq!(parse_size("4k"), Ok(4_000));
+        assert_eq!(parse_size("512M"), Ok(512_000_000));
+        assert_eq!(parse_size("1.5GB"), Ok(1_500_000_000));
+        assert_eq!(parse_size(" 2T "), Ok(2_000_000_000_000));
+    }
+
+    #[test]
+    fn malformed_size() {
+        for size in ["", "G", "4X", "4 GiB", "four"] {
+            assert!(parse_size(size).is_err(), "{size}");
         }
     }
 }
@@ -1,76 +1,1643 @@
-use clap::{Parser, Subcommand};
-use config::builder::DefaultState;
-use config::{Config, ConfigBuilder, Environment, File};
-use diesel::prelude::*;
-use dotenvy::dotenv;
-use env_logger;
-use std::path::PathBuf;
+use clap::{Args, Parser, Subcommand};
+use diesel::Connection;
+use log::{info, warn};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
+use crate::uniprot::cache::{
+    clean, stale_entries, write_cache_entries, CleanOptions, RawCache,
+};
+use crate::uniprot::config::{RateLimits, UniprotConfig};
+use crate::uniprot::db::{
+    applied_migrations, run_migrations, Database, DbConnection,
+};
+use crate::uniprot::download::{download_text, DownloadOptions};
+use crate::uniprot::enrich::{
+    enrich_entries, EnrichOptions, UNIPROTKB_REST_URL,
+};
+use crate::uniprot::enzyme::{get_enzymes, insert_enzymes, read_enzymes};
+use crate::uniprot::export::{export_tables, ExportFormat};
+use crate::uniprot::family::{identity_matrix, write_matrix, write_orthologs};
+use crate::uniprot::go::{
+    expand_go_terms, fetch_go_annotations, get_ontology, insert_ontology,
+    read_ontology, GoAnnotationOptions,
+};
+use crate::uniprot::graph::{build_graph, write_graph, GraphFormat};
+use crate::uniprot::history::{entry_history, write_history};
+use crate::uniprot::idmap::{
+    map_entries, write_mappings, IdMapOptions, MappingTarget,
+};
+use crate::uniprot::keywords::{
+    fetch_entry_keywords, get_keywords, insert_keywords, read_keywords,
+    EntryKeywordsOptions,
+};
+use crate::uniprot::orthologs::{
+    ortholog_pairs, write_ortholog_pairs, Relationship,
+};
+use crate::uniprot::output::OutputFormat;
+use crate::uniprot::proteomes::{fetch_proteomes, ProteomeOptions};
+use crate::uniprot::query::{query_entries, write_entries, EntryFilter};
+use crate::uniprot::representatives::{
+    select_representatives, write_representatives, write_representatives_fasta,
+    Policy,
+};
+use crate::uniprot::retired::{
+    mark_retired, open_list, prune_entries, read_deleted, read_merged,
+    retired_entries, stored_accessions, write_report,
+};
+use crate::uniprot::seqsearch::{
+    entry_families, search_fasta, write_hits, KmerIndex, SeqSearchOptions,
+    MAX_K,
+};
+use crate::uniprot::sequences::{
+    fetch_sequences, select_sequences, write_fasta, FastaSelection,
+    FetchSequencesOptions,
+};
+use crate::uniprot::server::serve;
 use crate::uniprot::similar::{
-    filter_by_species, get_similar_entries, insert_entries,
+    filter_by_species, get_similar_entries, insert_entries, preview_entries,
+    read_similar_entries, SimilarEntries,
+};
+use crate::uniprot::stats::{family_stats, write_stats, StatsFormat};
+use crate::uniprot::sync::{
+    family_aliases, last_release, record_import, resolve_family, sync_entries,
+    write_family_aliases,
+};
+use crate::uniprot::taxonomy::{
+    get_taxa, insert_taxa, read_taxa, resolve_species,
+};
+use crate::uniprot::variants::{
+    family_variants, get_variants, insert_variants, read_variants,
+    write_variants, VariantFilter,
 };
 
 ///////////////////////////////////////////////////////////////////////////////
 
 #[derive(Subcommand, Debug)]
 pub enum Commands {
-    Uniprot(Args),
+    #[command(name = "fetch-similar")]
+    FetchSimilar(FetchSimilarArgs),
+    Sync(SyncArgs),
+    #[command(name = "fetch-taxonomy")]
+    FetchTaxonomy(FetchTaxonomyArgs),
+    Enrich(EnrichArgs),
+    #[command(name = "fetch-keywords")]
+    FetchKeywords(FetchKeywordsArgs),
+    #[command(name = "fetch-enzymes")]
+    FetchEnzymes(FetchEnzymesArgs),
+    #[command(name = "fetch-go")]
+    FetchGo(FetchGoArgs),
+    #[command(name = "fetch-proteomes")]
+    FetchProteomes(FetchProteomesArgs),
+    #[command(name = "fetch-variants")]
+    FetchVariants(FetchVariantsArgs),
+    #[command(name = "fetch-retired")]
+    FetchRetired(FetchRetiredArgs),
+    Prune(PruneArgs),
+    Idmap(IdmapArgs),
+    #[command(name = "fetch-sequences")]
+    FetchSequences(FetchSequencesArgs),
+    #[command(subcommand)]
+    Export(ExportCommands),
+    Query(QueryArgs),
+    Orthologs(OrthologsArgs),
+    Representatives(RepresentativesArgs),
+    Seqsearch(SeqsearchArgs),
+    Stats(StatsArgs),
+    Variants(VariantsArgs),
+    History(HistoryArgs),
+    Serve(ServeArgs),
+    #[command(subcommand)]
+    Family(FamilyCommands),
+    #[command(subcommand)]
+    Db(DbCommands),
+    #[command(subcommand)]
+    Cache(CacheCommands),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum DbCommands {
+    // Create the database and its schema
+    Init(DbArgs),
+    // Apply the migrations the database lacks
+    Migrate(DbArgs),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CacheCommands {
+    // Downloaded files kept in the cache, by URL and release
+    List(CacheListArgs),
+    // Remove the older or corrupt copies
+    Clean(CacheCleanArgs),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum FamilyCommands {
+    // Pairwise identities and closest orthologs of the members
+    Analyze(FamilyAnalyzeArgs),
+    // Former names of the families, recorded by `uniprot sync`
+    Aliases(FamilyAliasesArgs),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ExportCommands {
+    Fasta(ExportFastaArgs),
+    Tables(ExportTablesArgs),
+    Graph(ExportGraphArgs),
+}
+
+#[derive(Parser, Debug)]
+pub struct FetchSimilarArgs {
+    #[arg(short, long, default_value = "assets/config")]
+    config: PathBuf,
+
+    // Downloaded similar.txt read instead of the URL, `-` for stdin
+    #[arg(short, long)]
+    input: Option<PathBuf>,
+
+    #[command(flatten)]
+    download: DownloadArgs,
+
+    // Count what would be inserted or updated, without writing
+    #[arg(long)]
+    dry_run: bool,
 }
 
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
-pub struct Args {
+pub struct SyncArgs {
     #[arg(short, long, default_value = "assets/config")]
     config: PathBuf,
+
+    // Downloaded similar.txt read instead of the URL, `-` for stdin
+    #[arg(short, long)]
+    input: Option<PathBuf>,
+
+    #[command(flatten)]
+    download: DownloadArgs,
+
+    // Synchronize even when the release was already imported
+    #[arg(long)]
+    force: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct FetchTaxonomyArgs {
+    #[arg(short, long, default_value = "assets/config")]
+    config: PathBuf,
+
+    // Downloaded speclist.txt read instead of the URL, `-` for stdin
+    #[arg(short, long)]
+    input: Option<PathBuf>,
+
+    #[command(flatten)]
+    download: DownloadArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct DownloadArgs {
+    // uniprot.cache_dir of the config when unset
+    #[arg(long)]
+    cache_dir: Option<PathBuf>,
+    #[arg(long, default_value_t = 3)]
+    retries: u32,
+    #[arg(long)]
+    force_refresh: bool,
+    // Copy of this release in the cache, such as 2024_01, read without
+    // any request
+    #[arg(long, conflicts_with = "force_refresh")]
+    release: Option<String>,
+}
+
+impl DownloadArgs {
+    fn to_options(&self, config: &UniprotConfig) -> DownloadOptions {
+        DownloadOptions {
+            cache_dir: self
+                .cache_dir
+                .clone()
+                .unwrap_or_else(|| config.cache_dir.clone()),
+            retries: self.retries,
+            force_refresh: self.force_refresh,
+            release: self.release.clone(),
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+pub struct EnrichArgs {
+    #[arg(short, long, default_value = "assets/config")]
+    config: PathBuf,
+
+    #[command(flatten)]
+    rest: RestArgs,
+
+    // Request entries already annotated as well
+    #[arg(long)]
+    all: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct FetchKeywordsArgs {
+    #[arg(short, long, default_value = "assets/config")]
+    config: PathBuf,
+
+    // Downloaded keywlist.txt read instead of the URL, `-` for stdin
+    #[arg(short, long)]
+    input: Option<PathBuf>,
+
+    #[command(flatten)]
+    download: DownloadArgs,
+    #[command(flatten)]
+    rest: RestArgs,
+
+    // Request entries with keywords already as well
+    #[arg(long)]
+    all: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct FetchEnzymesArgs {
+    #[arg(short, long, default_value = "assets/config")]
+    config: PathBuf,
+
+    // Downloaded enzyme.dat read instead of the URL, `-` for stdin
+    #[arg(short, long)]
+    input: Option<PathBuf>,
+
+    #[command(flatten)]
+    download: DownloadArgs,
+}
+
+#[derive(Parser, Debug)]
+pub struct FetchVariantsArgs {
+    #[arg(short, long, default_value = "assets/config")]
+    config: PathBuf,
+
+    // Downloaded humsavar.txt read instead of the URL, `-` for stdin
+    #[arg(short, long)]
+    input: Option<PathBuf>,
+
+    #[command(flatten)]
+    download: DownloadArgs,
+}
+
+#[derive(Parser, Debug)]
+pub struct FetchRetiredArgs {
+    #[arg(short, long, default_value = "assets/config")]
+    config: PathBuf,
+
+    // delac_sp.txt, delac_tr.txt or their gzipped copies read instead of
+    // the URL, `-` for stdin
+    #[arg(long)]
+    deleted: Vec<PathBuf>,
+    // sec_ac.txt read instead of the URL
+    #[arg(long)]
+    merged: Option<PathBuf>,
+
+    #[command(flatten)]
+    download: DownloadArgs,
+}
+
+#[derive(Parser, Debug)]
+pub struct PruneArgs {
+    #[arg(short, long, default_value = "assets/config")]
+    config: PathBuf,
+
+    // Report the obsolete entries without deleting them
+    #[arg(long)]
+    dry_run: bool,
+    // Report of the pruned entries, standard output when unset
+    #[arg(short, long)]
+    report: Option<PathBuf>,
+    #[arg(long, value_enum, default_value = "tsv")]
+    format: OutputFormat,
+}
+
+#[derive(Parser, Debug)]
+pub struct FetchGoArgs {
+    #[arg(short, long, default_value = "assets/config")]
+    config: PathBuf,
+
+    // Downloaded go-basic.obo read instead of the URL, `-` for stdin
+    #[arg(short, long)]
+    input: Option<PathBuf>,
+
+    #[command(flatten)]
+    download: DownloadArgs,
+    #[command(flatten)]
+    rest: RestArgs,
+
+    // Request entries with annotations already as well
+    #[arg(long)]
+    all: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct FetchProteomesArgs {
+    #[arg(short, long, default_value = "assets/config")]
+    config: PathBuf,
+
+    #[command(flatten)]
+    rest: RestArgs,
+
+    // Request entries linked to proteomes already as well
+    #[arg(long)]
+    all: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct IdmapArgs {
+    #[arg(short, long, default_value = "assets/config")]
+    config: PathBuf,
+
+    // Target databases, all of them when not given
+    #[arg(long, value_enum, value_delimiter = ',')]
+    to: Vec<MappingTarget>,
+
+    #[command(flatten)]
+    rest: RestArgs,
+
+    // Map entries with mappings already as well
+    #[arg(long)]
+    all: bool,
+
+    // Write the stored mappings to this file, `-` for stdout
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    #[arg(long, value_enum, default_value = "tsv")]
+    format: OutputFormat,
+}
+
+#[derive(Args, Debug)]
+pub struct RestArgs {
+    // Accessions per request
+    #[arg(long, default_value_t = 100)]
+    batch_size: usize,
+    // The limits of uniprot.rest in the config apply to those unset
+    //
+    // Requests in flight at once
+    #[arg(long)]
+    concurrency: Option<usize>,
+    // Rate limit of the UniProtKB REST API
+    #[arg(long)]
+    requests_per_second: Option<f64>,
+    #[arg(long)]
+    retries: Option<u32>,
+}
+
+impl RestArgs {
+    fn concurrency(&self, limits: &RateLimits) -> usize {
+        self.concurrency.unwrap_or(limits.concurrency)
+    }
+
+    fn retries(&self, limits: &RateLimits) -> u32 {
+        self.retries.unwrap_or(limits.retries)
+    }
+
+    // Minimum delay between two requests
+    fn interval(&self, limits: &RateLimits) -> Result<Duration, String> {
+        let requests_per_second = self
+            .requests_per_second
+            .unwrap_or(limits.requests_per_second);
+        if requests_per_second <= 0.0 {
+            return Err("--requests-per-second must be positive".to_string());
+        }
+        Ok(Duration::from_secs_f64(1.0 / requests_per_second))
+    }
+}
+
+#[derive(Parser, Debug)]
+pub struct FetchSequencesArgs {
+    #[arg(short, long, default_value = "assets/config")]
+    config: PathBuf,
+
+    #[command(flatten)]
+    rest: RestArgs,
+
+    // Download sequences already stored as well
+    #[arg(long)]
+    all: bool,
+    // Members of these families only, every entry when not given
+    #[arg(long = "family")]
+    families: Vec<String>,
+}
+
+#[derive(Parser, Debug)]
+pub struct VariantsArgs {
+    #[arg(short, long, default_value = "assets/config")]
+    config: PathBuf,
+
+    // Substring of the family name
+    #[arg(long)]
+    family: Option<String>,
+    // Substring of the disease name
+    #[arg(long)]
+    disease: Option<String>,
+    // LP/P, LB/B or US, any when unset
+    #[arg(long = "category", value_delimiter = ',')]
+    categories: Vec<String>,
+
+    #[arg(long, value_enum, default_value = "table")]
+    format: OutputFormat,
+}
+
+#[derive(Parser, Debug)]
+pub struct HistoryArgs {
+    #[arg(short, long, default_value = "assets/config")]
+    config: PathBuf,
+
+    accession: String,
+
+    #[arg(long, value_enum, default_value = "table")]
+    format: OutputFormat,
+}
+
+#[derive(Parser, Debug)]
+pub struct OrthologsArgs {
+    #[arg(short, long, default_value = "assets/config")]
+    config: PathBuf,
+
+    // Mnemonic, NCBI taxid or scientific name of each species
+    #[arg(long)]
+    species_a: String,
+    #[arg(long)]
+    species_b: String,
+
+    // Substring of the family name
+    #[arg(long)]
+    family: Option<String>,
+    // Leave out the families with paralogs in either species
+    #[arg(long)]
+    one_to_one: bool,
+
+    #[arg(long, value_enum, default_value = "table")]
+    format: OutputFormat,
+    // Standard output when not given
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Parser, Debug)]
+pub struct SeqsearchArgs {
+    #[arg(short, long, default_value = "assets/config")]
+    config: PathBuf,
+
+    // Query sequences, `-` for stdin
+    fasta: PathBuf,
+
+    // Stored sequences searched, all when unset
+    #[arg(long = "family")]
+    families: Vec<String>,
+    #[arg(long, value_delimiter = ',')]
+    species: Vec<String>,
+
+    // Length of the indexed words
+    #[arg(
+        short,
+        long,
+        default_value = "5",
+        value_parser = clap::value_parser!(u8).range(3..=MAX_K as i64)
+    )]
+    k: u8,
+    // Distinct query words a stored sequence must share to be reported
+    #[arg(long, default_value_t = 4)]
+    min_shared: usize,
+    // Best hits reported per query
+    #[arg(long, default_value_t = 10)]
+    max_hits: usize,
+
+    #[arg(long, value_enum, default_value = "table")]
+    format: OutputFormat,
+}
+
+#[derive(Parser, Debug)]
+pub struct ServeArgs {
+    #[arg(short, long, default_value = "assets/config")]
+    config: PathBuf,
+
+    // Address and port the JSON API listens on
+    #[arg(short, long, default_value = "127.0.0.1:8080")]
+    bind: SocketAddr,
+}
+
+#[derive(Parser, Debug)]
+pub struct FamilyAnalyzeArgs {
+    #[arg(short, long, default_value = "assets/config")]
+    config: PathBuf,
+
+    // Name of the family, as stored
+    family: String,
+
+    // Receives identity.tsv and orthologs.tsv
+    #[arg(short, long, default_value = "family-analysis")]
+    output_dir: PathBuf,
+
+    #[command(flatten)]
+    rest: RestArgs,
+}
+
+#[derive(Parser, Debug)]
+pub struct FamilyAliasesArgs {
+    #[arg(short, long, default_value = "assets/config")]
+    config: PathBuf,
+
+    // Substring of the current or former family name
+    #[arg(long)]
+    family: Option<String>,
+
+    #[arg(long, value_enum, default_value = "table")]
+    format: OutputFormat,
+}
+
+#[derive(Parser, Debug)]
+pub struct ExportFastaArgs {
+    #[arg(short, long, default_value = "assets/config")]
+    config: PathBuf,
+
+    // Selection, everything when unset
+    #[arg(long = "family")]
+    families: Vec<String>,
+    #[arg(long, value_delimiter = ',')]
+    species: Vec<String>,
+
+    #[arg(short, long, default_value = "entries.fasta")]
+    output: PathBuf,
+}
+
+#[derive(Parser, Debug)]
+pub struct QueryArgs {
+    #[arg(short, long, default_value = "assets/config")]
+    config: PathBuf,
+
+    // Substring of the family name
+    #[arg(long)]
+    family: Option<String>,
+    #[arg(long = "accession", value_delimiter = ',')]
+    accessions: Vec<String>,
+    // Mnemonics, NCBI taxids or scientific names
+    #[arg(long, value_delimiter = ',')]
+    species: Vec<String>,
+    // Keyword names or accessions, such as "Transcription regulation"
+    #[arg(long = "keyword")]
+    keywords: Vec<String>,
+    // EC number or class, such as 2.7.11.1, 2.7.* or 2.7.-.-
+    #[arg(long)]
+    ec: Option<String>,
+    // GO identifiers, such as GO:0003700, descendant terms match too
+    #[arg(long = "go")]
+    go_terms: Vec<String>,
+    // Match the given GO terms only, not their descendants
+    #[arg(long)]
+    go_exact: bool,
+    // Entries cross-referenced in these databases, such as PDB or Pfam
+    #[arg(long = "has-xref", value_delimiter = ',')]
+    xref_databases: Vec<String>,
+    // Proteome identifiers, such as UP000005640
+    #[arg(long = "proteome", value_delimiter = ',')]
+    proteomes: Vec<String>,
+    // Entries of a reference proteome only
+    #[arg(long)]
+    reference_proteome: bool,
+
+    // Inclusive ranges
+    #[arg(long)]
+    min_mass: Option<i32>,
+    #[arg(long)]
+    max_mass: Option<i32>,
+    #[arg(long)]
+    min_length: Option<i32>,
+    #[arg(long)]
+    max_length: Option<i32>,
+
+    #[arg(long, value_enum, default_value = "table")]
+    format: OutputFormat,
+}
+
+#[derive(Parser, Debug)]
+pub struct StatsArgs {
+    #[arg(short, long, default_value = "assets/config")]
+    config: PathBuf,
+
+    // Substring of the family name
+    #[arg(long)]
+    family: Option<String>,
+    // Mnemonics, NCBI taxids or scientific names, members of other
+    // species are left out of the statistics
+    #[arg(long, value_delimiter = ',')]
+    species: Vec<String>,
+
+    // Inclusive range of the member counts
+    #[arg(long)]
+    min_members: Option<usize>,
+    #[arg(long)]
+    max_members: Option<usize>,
+
+    #[arg(long, value_enum, default_value = "table")]
+    format: StatsFormat,
+    // Standard output when not given
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Parser, Debug)]
+pub struct ExportTablesArgs {
+    #[arg(short, long, default_value = "assets/config")]
+    config: PathBuf,
+
+    #[arg(long, value_enum, default_value = "parquet")]
+    format: ExportFormat,
+    // Also write one row per family member with its entry columns
+    #[arg(long)]
+    denormalized: bool,
+
+    #[arg(short, long, default_value = "uniprot")]
+    output_dir: PathBuf,
+}
+
+#[derive(Parser, Debug)]
+pub struct ExportGraphArgs {
+    #[arg(short, long, default_value = "assets/config")]
+    config: PathBuf,
+
+    // Substring of the family name
+    #[arg(long)]
+    family: Option<String>,
+    // Mnemonics, NCBI taxids or scientific names, members of other
+    // species are left out
+    #[arg(long, value_delimiter = ',')]
+    species: Vec<String>,
+    // Cross-referenced databases merged as nodes, such as Pfam,InterPro
+    #[arg(long, value_delimiter = ',')]
+    xrefs: Vec<String>,
+
+    #[arg(long, value_enum, default_value = "graphml")]
+    format: GraphFormat,
+    // Standard output when unset
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Parser, Debug)]
+pub struct DbArgs {
+    #[arg(short, long, default_value = "assets/config")]
+    config: PathBuf,
+}
+
+#[derive(Parser, Debug)]
+pub struct CacheListArgs {
+    #[arg(short, long, default_value = "assets/config")]
+    config: PathBuf,
+
+    // uniprot.cache_dir of the config when unset
+    #[arg(long)]
+    cache_dir: Option<PathBuf>,
+
+    #[arg(long, value_enum, default_value = "table")]
+    format: OutputFormat,
+}
+
+#[derive(Parser, Debug)]
+pub struct CacheCleanArgs {
+    #[arg(short, long, default_value = "assets/config")]
+    config: PathBuf,
+
+    // uniprot.cache_dir of the config when unset
+    #[arg(long)]
+    cache_dir: Option<PathBuf>,
+
+    // Latest copies of every URL kept
+    #[arg(long, default_value_t = 1)]
+    keep: usize,
+    // Remove every copy, the latest too
+    #[arg(long, conflicts_with = "keep")]
+    all: bool,
+    // Only consider the copies of this release
+    #[arg(long)]
+    release: Option<String>,
+    // List the copies to remove without removing them
+    #[arg(long)]
+    dry_run: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct RepresentativesArgs {
+    #[arg(short, long, default_value = "assets/config")]
+    config: PathBuf,
+
+    // Selection policy
+    #[arg(long, value_enum, default_value = "longest")]
+    policy: Policy,
+
+    // Output files
+    #[arg(short, long, default_value = "representatives.tsv")]
+    output: PathBuf,
+    #[arg(long)]
+    fasta: Option<PathBuf>,
+
+    #[arg(long, value_enum, default_value = "tsv")]
+    format: OutputFormat,
 }
 
 ///////////////////////////////////////////////////////////////////////////////
 
-#[allow(dead_code)]
+fn open_database(
+    config: &UniprotConfig,
+) -> Result<Database, Box<dyn std::error::Error>> {
+    Ok(Database::open(&config.database_url)?)
+}
+
+fn connect(
+    config: &UniprotConfig,
+) -> Result<DbConnection, Box<dyn std::error::Error>> {
+    Ok(open_database(config)?.get()?)
+}
+
 fn establish_connection(
-    settings: &Config,
-) -> Result<SqliteConnection, Box<dyn std::error::Error>> {
-    let database_url: String = settings.get("DATABASE_URL")?;
-    let connection = SqliteConnection::establish(&database_url)
-        .map_err(|e| format!("Error connecting to {}: {}", database_url, e))?;
+    config: &UniprotConfig,
+) -> Result<DbConnection, Box<dyn std::error::Error>> {
+    let mut connection = connect(config)?;
+
+    // Creates or upgrades the schema before anything is read or written
+    run_migrations(&mut connection).map_err(|e| e.to_string())?;
+
     Ok(connection)
 }
 
-#[allow(dead_code)]
-fn load_settings(args: &Args) -> Result<Config, Box<dyn std::error::Error>> {
-    dotenv().ok();
-    let config_file = args.config.to_str().ok_or("Invalid config path")?;
-    let settings = ConfigBuilder::<DefaultState>::default()
-        .add_source(File::with_name(config_file))
-        .add_source(Environment::default())
-        .build()?;
+pub fn command(cmds: Commands) -> i32 {
+    let (context, result) = match cmds {
+        Commands::FetchSimilar(args) => {
+            ("Couldn't load similar entries", fetch_similar(&args))
+        }
+        Commands::Sync(args) => ("Couldn't synchronize entries", sync(&args)),
+        Commands::FetchTaxonomy(args) => {
+            ("Couldn't load the taxonomy", fetch_taxonomy(&args))
+        }
+        Commands::Enrich(args) => ("Couldn't enrich entries", enrich(&args)),
+        Commands::FetchKeywords(args) => {
+            ("Couldn't load keywords", fetch_keywords(&args))
+        }
+        Commands::FetchEnzymes(args) => {
+            ("Couldn't load enzymes", fetch_enzymes(&args))
+        }
+        Commands::FetchGo(args) => {
+            ("Couldn't load GO annotations", fetch_go(&args))
+        }
+        Commands::FetchProteomes(args) => {
+            ("Couldn't load proteomes", fetch_proteomes_command(&args))
+        }
+        Commands::FetchVariants(args) => {
+            ("Couldn't load variants", fetch_variants(&args))
+        }
+        Commands::FetchRetired(args) => {
+            ("Couldn't load retired accessions", fetch_retired(&args))
+        }
+        Commands::Prune(args) => ("Couldn't prune entries", prune(&args)),
+        Commands::Idmap(args) => ("Couldn't map identifiers", idmap(&args)),
+        Commands::FetchSequences(args) => {
+            ("Couldn't fetch sequences", fetch_sequences_command(&args))
+        }
+        Commands::Query(args) => ("Couldn't query entries", query(&args)),
+        Commands::Orthologs(args) => {
+            ("Couldn't pair orthologs", orthologs(&args))
+        }
+        Commands::Export(ExportCommands::Fasta(args)) => {
+            ("Couldn't export sequences", export_fasta(&args))
+        }
+        Commands::Export(ExportCommands::Tables(args)) => {
+            ("Couldn't export tables", export_tables_command(&args))
+        }
+        Commands::Export(ExportCommands::Graph(args)) => {
+            ("Couldn't export the graph", export_graph(&args))
+        }
+        Commands::Representatives(args) => {
+            ("Couldn't select representatives", representatives(&args))
+        }
+        Commands::Stats(args) => ("Couldn't compute statistics", stats(&args)),
+        Commands::Seqsearch(args) => {
+            ("Couldn't search sequences", seqsearch(&args))
+        }
+        Commands::Variants(args) => {
+            ("Couldn't query variants", variants(&args))
+        }
+        Commands::History(args) => {
+            ("Couldn't list the entry history", history(&args))
+        }
+        Commands::Serve(args) => {
+            ("Couldn't serve the database", serve_command(&args))
+        }
+        Commands::Family(FamilyCommands::Analyze(args)) => {
+            ("Couldn't analyze the family", family_analyze(&args))
+        }
+        Commands::Family(FamilyCommands::Aliases(args)) => (
+            "Couldn't list the family aliases",
+            list_family_aliases(&args),
+        ),
+        Commands::Db(DbCommands::Init(args)) => {
+            ("Couldn't initialize the database", db_init(&args))
+        }
+        Commands::Db(DbCommands::Migrate(args)) => {
+            ("Couldn't migrate the database", db_migrate(&args))
+        }
+        Commands::Cache(CacheCommands::List(args)) => {
+            ("Couldn't list the cache", cache_list(&args))
+        }
+        Commands::Cache(CacheCommands::Clean(args)) => {
+            ("Couldn't clean the cache", cache_clean(&args))
+        }
+    };
+
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            report(context, e.as_ref());
+            1
+        }
+    }
+}
+
+// The error, then the causes its message doesn't already include, such as
+// the underlying network or decoding failure
+fn report(context: &str, err: &dyn std::error::Error) {
+    let mut message = format!("{context}: {err}");
+    eprintln!("{message}");
+
+    let mut source = err.source();
+    while let Some(cause) = source {
+        let cause_message = cause.to_string();
+        if !message.contains(&cause_message) {
+            eprintln!("  caused by: {cause_message}");
+            message.push_str(&cause_message);
+        }
+        source = cause.source();
+    }
+}
+
+fn enrich(args: &EnrichArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let config = UniprotConfig::load(&args.config)?;
+    let opts = EnrichOptions {
+        batch_size: args.rest.batch_size,
+        concurrency: args.rest.concurrency(&config.rest),
+        interval: args.rest.interval(&config.rest)?,
+        retries: args.rest.retries(&config.rest),
+        all: args.all,
+    };
+
+    let mut connection = establish_connection(&config)?;
+
+    let run = record_import(&mut connection, UNIPROTKB_REST_URL, None)?;
+    let enriched = enrich_entries(&mut connection, run, &opts)?;
+    info!("Enriched {enriched} entries");
+
+    Ok(())
+}
+
+fn fetch_sequences_command(
+    args: &FetchSequencesArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = UniprotConfig::load(&args.config)?;
+    let opts = FetchSequencesOptions {
+        batch_size: args.rest.batch_size,
+        concurrency: args.rest.concurrency(&config.rest),
+        interval: args.rest.interval(&config.rest)?,
+        retries: args.rest.retries(&config.rest),
+        all: args.all,
+        families: args.families.clone(),
+    };
+
+    let mut connection = establish_connection(&config)?;
+
+    let fetched = fetch_sequences(&mut connection, &opts)?;
+    info!("Stored {fetched} sequences");
+
+    Ok(())
+}
+
+fn fetch_keywords(
+    args: &FetchKeywordsArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = UniprotConfig::load(&args.config)?;
+    let opts = EntryKeywordsOptions {
+        batch_size: args.rest.batch_size,
+        concurrency: args.rest.concurrency(&config.rest),
+        interval: args.rest.interval(&config.rest)?,
+        retries: args.rest.retries(&config.rest),
+        all: args.all,
+    };
+
+    let mut connection = establish_connection(&config)?;
+
+    let keywords = match &args.input {
+        Some(input) => read_keywords(input)?,
+        None => get_keywords(
+            &config.keywlist.url,
+            &args.download.to_options(&config),
+        )?,
+    };
+    insert_keywords(&keywords, &mut connection)?;
+
+    let stored = fetch_entry_keywords(&mut connection, &opts)?;
+    info!("Stored {stored} entry keywords");
+
+    Ok(())
+}
+
+fn fetch_enzymes(
+    args: &FetchEnzymesArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = UniprotConfig::load(&args.config)?;
+    let mut connection = establish_connection(&config)?;
+
+    let enzymes = match &args.input {
+        Some(input) => read_enzymes(input)?,
+        None => {
+            get_enzymes(&config.enzyme.url, &args.download.to_options(&config))?
+        }
+    };
+    let linked = insert_enzymes(&enzymes, &mut connection)?;
+    info!("Linked {linked} entries to their EC numbers");
+
+    Ok(())
+}
+
+fn fetch_variants(
+    args: &FetchVariantsArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = UniprotConfig::load(&args.config)?;
+    let mut connection = establish_connection(&config)?;
+
+    let variants = match &args.input {
+        Some(input) => read_variants(input)?,
+        None => get_variants(
+            &config.humsavar.url,
+            &args.download.to_options(&config),
+        )?,
+    };
+    let stored = insert_variants(&variants, &mut connection)?;
+    info!("Stored {stored} variants of {} listed", variants.len());
+
+    Ok(())
+}
+
+fn fetch_retired(
+    args: &FetchRetiredArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = UniprotConfig::load(&args.config)?;
+    let mut connection = establish_connection(&config)?;
+    let known = stored_accessions(&mut connection)?;
+
+    let mut deleted = Vec::new();
+    let mut sources = Vec::new();
+    if args.deleted.is_empty() {
+        sources.push(config.delac.url.clone());
+        let text = download_text(
+            &config.delac.url,
+            &args.download.to_options(&config),
+        )?;
+        deleted.extend(read_deleted(text.as_bytes(), &known)?);
+    }
+    for path in &args.deleted {
+        sources.push(path.display().to_string());
+        deleted.extend(read_deleted(open_list(path)?, &known)?);
+    }
+
+    let merged = match &args.merged {
+        Some(path) => {
+            sources.push(path.display().to_string());
+            read_merged(open_list(path)?, &known)?
+        }
+        None => {
+            sources.push(config.sec_ac.url.clone());
+            let text = download_text(
+                &config.sec_ac.url,
+                &args.download.to_options(&config),
+            )?;
+            read_merged(text.as_bytes(), &known)?
+        }
+    };
+
+    let flagged = connection.transaction(|connection| {
+        let run = record_import(connection, &sources.join(" "), None)?;
+        mark_retired(connection, &deleted, &merged, run)
+    })?;
+    info!("Flagged {flagged} entries obsolete");
+
+    Ok(())
+}
+
+fn prune(args: &PruneArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let config = UniprotConfig::load(&args.config)?;
+    let mut connection = establish_connection(&config)?;
+
+    let retired = retired_entries(&mut connection)?;
+    match &args.report {
+        Some(path) => {
+            write_report(&retired, args.format, std::fs::File::create(path)?)?
+        }
+        None => write_report(&retired, args.format, std::io::stdout().lock())?,
+    }
+
+    let replaced = retired.iter().filter(|e| e.replaced_by.is_some()).count();
+    if args.dry_run {
+        info!(
+            "{} obsolete entries, {replaced} of them merged, none deleted",
+            retired.len()
+        );
+        return Ok(());
+    }
+
+    let pruned = prune_entries(&mut connection, &retired)?;
+    info!("Pruned {pruned} obsolete entries, {replaced} of them merged");
+
+    Ok(())
+}
+
+fn fetch_go(args: &FetchGoArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let config = UniprotConfig::load(&args.config)?;
+    let opts = GoAnnotationOptions {
+        batch_size: args.rest.batch_size,
+        concurrency: args.rest.concurrency(&config.rest),
+        interval: args.rest.interval(&config.rest)?,
+        retries: args.rest.retries(&config.rest),
+        all: args.all,
+    };
+
+    let mut connection = establish_connection(&config)?;
+
+    let ontology = match &args.input {
+        Some(input) => read_ontology(input)?,
+        None => {
+            get_ontology(&config.go.url, &args.download.to_options(&config))?
+        }
+    };
+    insert_ontology(&ontology, &mut connection)?;
+
+    let stored = fetch_go_annotations(&mut connection, &opts)?;
+    info!("Stored {stored} GO annotations");
+
+    Ok(())
+}
+
+fn fetch_proteomes_command(
+    args: &FetchProteomesArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = UniprotConfig::load(&args.config)?;
+    let opts = ProteomeOptions {
+        batch_size: args.rest.batch_size,
+        concurrency: args.rest.concurrency(&config.rest),
+        interval: args.rest.interval(&config.rest)?,
+        retries: args.rest.retries(&config.rest),
+        all: args.all,
+    };
+
+    let mut connection = establish_connection(&config)?;
+    let (linked, described) = fetch_proteomes(&mut connection, &opts)?;
+    info!("Stored {linked} proteome links and {described} proteomes");
+
+    Ok(())
+}
+
+fn idmap(args: &IdmapArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let config = UniprotConfig::load(&args.config)?;
+    let targets = if args.to.is_empty() {
+        MappingTarget::ALL.to_vec()
+    } else {
+        args.to.clone()
+    };
+    let opts = IdMapOptions {
+        targets: targets.clone(),
+        batch_size: args.rest.batch_size,
+        concurrency: args.rest.concurrency(&config.rest),
+        interval: args.rest.interval(&config.rest)?,
+        retries: args.rest.retries(&config.rest),
+        all: args.all,
+    };
+
+    let mut connection = establish_connection(&config)?;
+
+    let stored = map_entries(&mut connection, &opts)?;
+    info!("Stored {stored} mappings");
+
+    match args.output.as_deref() {
+        None => {}
+        Some(path) if path == Path::new("-") => {
+            write_mappings(
+                &mut connection,
+                &targets,
+                args.format,
+                std::io::stdout(),
+            )?;
+        }
+        Some(path) => {
+            let written = write_mappings(
+                &mut connection,
+                &targets,
+                args.format,
+                std::fs::File::create(path)?,
+            )?;
+            info!("Wrote {written} mappings to {}", path.display());
+        }
+    }
+
+    Ok(())
+}
+
+fn export_fasta(
+    args: &ExportFastaArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = UniprotConfig::load(&args.config)?;
+    let mut connection = establish_connection(&config)?;
+
+    let selection = FastaSelection {
+        families: args.families.clone(),
+        species: args.species.clone(),
+    };
+    let sequences = select_sequences(&mut connection, &selection)?;
+    write_fasta(&sequences, &args.output)?;
+    info!(
+        "Wrote {} sequences to {}",
+        sequences.len(),
+        args.output.display()
+    );
+
+    Ok(())
+}
+
+fn query(args: &QueryArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let config = UniprotConfig::load(&args.config)?;
+    let mut connection = establish_connection(&config)?;
+
+    let go_terms = if args.go_exact || args.go_terms.is_empty() {
+        args.go_terms.clone()
+    } else {
+        expand_go_terms(&args.go_terms, &mut connection)?
+    };
+    let filter = EntryFilter {
+        family: args.family.clone(),
+        accessions: args.accessions.clone(),
+        species: resolve_species(&args.species, &mut connection)?,
+        keywords: args.keywords.clone(),
+        ec: args.ec.clone(),
+        go_terms,
+        xref_databases: args.xref_databases.clone(),
+        proteomes: args.proteomes.clone(),
+        reference_proteome: args.reference_proteome,
+        mass: (args.min_mass, args.max_mass),
+        seq_length: (args.min_length, args.max_length),
+    };
+    let entries = query_entries(&mut connection, &filter)?;
+    write_entries(&entries, args.format, std::io::stdout().lock())?;
+
+    Ok(())
+}
+
+fn stats(args: &StatsArgs) -> Result<(), Box<dyn std::error::Error>> {
+    if matches!(args.format, StatsFormat::Parquet) && args.output.is_none() {
+        return Err("Parquet output needs --output".into());
+    }
+
+    let config = UniprotConfig::load(&args.config)?;
+    let mut connection = establish_connection(&config)?;
+
+    let filter = EntryFilter {
+        family: args.family.clone(),
+        species: resolve_species(&args.species, &mut connection)?,
+        ..EntryFilter::default()
+    };
+    let entries = query_entries(&mut connection, &filter)?;
+
+    let mut stats = family_stats(&entries);
+    stats.retain(|s| {
+        args.min_members.is_none_or(|min| s.members >= min)
+            && args.max_members.is_none_or(|max| s.members <= max)
+    });
+    if stats.iter().all(|s| s.mean_length.is_none()) {
+        warn!("No length nor mass stored, run `uniprot enrich` first");
+    }
+
+    match &args.output {
+        Some(path) => {
+            write_stats(&stats, args.format, std::fs::File::create(path)?)?;
+            info!("Wrote {} families to {}", stats.len(), path.display());
+        }
+        None => write_stats(&stats, args.format, std::io::stdout().lock())?,
+    }
+
+    Ok(())
+}
+
+fn orthologs(args: &OrthologsArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let config = UniprotConfig::load(&args.config)?;
+    let mut connection = establish_connection(&config)?;
+
+    let species_a =
+        resolve_species(&[args.species_a.clone()], &mut connection)?;
+    let species_b =
+        resolve_species(&[args.species_b.clone()], &mut connection)?;
+    if species_a.iter().any(|code| species_b.contains(code)) {
+        return Err(format!(
+            "{} and {} are the same species",
+            args.species_a, args.species_b
+        )
+        .into());
+    }
+
+    let filter = EntryFilter {
+        family: args.family.clone(),
+        species: [species_a.as_slice(), species_b.as_slice()].concat(),
+        ..EntryFilter::default()
+    };
+    let entries = query_entries(&mut connection, &filter)?;
+
+    let mut pairs = ortholog_pairs(&entries, &species_a, &species_b);
+    let one_to_one = pairs
+        .iter()
+        .filter(|p| p.relationship == Relationship::OneToOne)
+        .count();
+    info!("{} ortholog pairs, {one_to_one} of them 1:1", pairs.len());
+    if args.one_to_one {
+        pairs.retain(|p| p.relationship == Relationship::OneToOne);
+    }
+
+    match &args.output {
+        Some(path) => write_ortholog_pairs(
+            &pairs,
+            args.format,
+            std::fs::File::create(path)?,
+        )?,
+        None => {
+            write_ortholog_pairs(&pairs, args.format, std::io::stdout().lock())?
+        }
+    }
+
+    Ok(())
+}
+
+fn seqsearch(args: &SeqsearchArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let config = UniprotConfig::load(&args.config)?;
+    let mut connection = establish_connection(&config)?;
+
+    let selection = FastaSelection {
+        families: args.families.clone(),
+        species: args.species.clone(),
+    };
+    let sequences = select_sequences(&mut connection, &selection)?;
+    let families = entry_families(&mut connection)?;
+
+    let index = KmerIndex::build(sequences, families, args.k.into());
+    info!("Indexed {} stored sequences", index.len());
+
+    let opts = SeqSearchOptions {
+        min_shared: args.min_shared,
+        max_hits: args.max_hits,
+    };
+    let hits = search_fasta(&index, &args.fasta, &opts)?;
+    write_hits(&hits, args.format, std::io::stdout().lock())?;
+
+    Ok(())
+}
+
+fn variants(args: &VariantsArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let config = UniprotConfig::load(&args.config)?;
+    let mut connection = establish_connection(&config)?;
+
+    let filter = VariantFilter {
+        family: args.family.clone(),
+        disease: args.disease.clone(),
+        categories: args.categories.clone(),
+    };
+    let variants = family_variants(&mut connection, &filter)?;
+    write_variants(&variants, args.format, std::io::stdout().lock())?;
+
+    Ok(())
+}
+
+fn history(args: &HistoryArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let config = UniprotConfig::load(&args.config)?;
+    let mut connection = establish_connection(&config)?;
+
+    let history = entry_history(&mut connection, &args.accession)?;
+    let Some(last) = history.last() else {
+        return Err(
+            format!("No history recorded for {}", args.accession).into()
+        );
+    };
+    info!(
+        "{} last changed on {} by run {} ({})",
+        args.accession, last.imported_at, last.run, last.source
+    );
+    write_history(&history, args.format, std::io::stdout().lock())?;
+
+    Ok(())
+}
+
+fn export_graph(
+    args: &ExportGraphArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = UniprotConfig::load(&args.config)?;
+    let mut connection = establish_connection(&config)?;
+
+    let filter = EntryFilter {
+        family: args.family.clone(),
+        species: resolve_species(&args.species, &mut connection)?,
+        ..EntryFilter::default()
+    };
+    let graph = build_graph(&mut connection, &filter, &args.xrefs)?;
+
+    match &args.output {
+        Some(path) => {
+            write_graph(&graph, args.format, std::fs::File::create(path)?)?;
+            info!(
+                "Wrote {} nodes and {} edges to {}",
+                graph.nodes.len(),
+                graph.edges.len(),
+                path.display()
+            );
+        }
+        None => write_graph(&graph, args.format, std::io::stdout().lock())?,
+    }
+
+    Ok(())
+}
+
+fn serve_command(args: &ServeArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let config = UniprotConfig::load(&args.config)?;
+    let database = open_database(&config)?;
+    run_migrations(&mut database.get()?).map_err(|e| e.to_string())?;
+
+    serve(database, args.bind)?;
+    Ok(())
+}
+
+fn family_analyze(
+    args: &FamilyAnalyzeArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = UniprotConfig::load(&args.config)?;
+    let mut connection = establish_connection(&config)?;
+
+    let family = resolve_family(&mut connection, &args.family)?;
+    if family != args.family {
+        info!("{} is now named {family}", args.family);
+    }
+
+    let opts = FetchSequencesOptions {
+        batch_size: args.rest.batch_size,
+        concurrency: args.rest.concurrency(&config.rest),
+        interval: args.rest.interval(&config.rest)?,
+        retries: args.rest.retries(&config.rest),
+        all: false,
+        families: vec![family.clone()],
+    };
+    fetch_sequences(&mut connection, &opts)?;
+
+    let selection = FastaSelection {
+        families: vec![family.clone()],
+        species: Vec::new(),
+    };
+    let sequences = select_sequences(&mut connection, &selection)?;
+    if sequences.len() < 2 {
+        return Err(format!(
+            "{} members with a sequence in {family}, two are needed",
+            sequences.len()
+        )
+        .into());
+    }
+
+    info!("Aligning the {} members of {family}", sequences.len());
+    let matrix = identity_matrix(sequences);
+
+    std::fs::create_dir_all(&args.output_dir)?;
+    let path = args.output_dir.join("identity.tsv");
+    write_matrix(&matrix, std::fs::File::create(&path)?)?;
+    info!("Wrote the identity matrix to {}", path.display());
+
+    let orthologs = matrix.closest_orthologs();
+    let path = args.output_dir.join("orthologs.tsv");
+    write_orthologs(&orthologs, std::fs::File::create(&path)?)?;
+    info!(
+        "Wrote {} closest orthologs to {}",
+        orthologs.len(),
+        path.display()
+    );
+
+    Ok(())
+}
+
+fn list_family_aliases(
+    args: &FamilyAliasesArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = UniprotConfig::load(&args.config)?;
+    let mut connection = establish_connection(&config)?;
+
+    let aliases = family_aliases(&mut connection, args.family.as_deref())?;
+    write_family_aliases(&aliases, args.format, std::io::stdout().lock())?;
 
-    Ok(settings)
+    Ok(())
 }
 
-pub fn command(_cmds: Commands) {
-    println!("Running Uniprot command");
+fn export_tables_command(
+    args: &ExportTablesArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = UniprotConfig::load(&args.config)?;
+    let mut connection = establish_connection(&config)?;
+
+    export_tables(
+        &mut connection,
+        &args.output_dir,
+        args.format,
+        args.denormalized,
+    )
+}
+
+fn db_init(args: &DbArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let config = UniprotConfig::load(&args.config)?;
+    let mut connection = connect(&config)?;
+    let database_url = &config.database_url;
+
+    if !applied_migrations(&mut connection)
+        .map_err(|e| e.to_string())?
+        .is_empty()
+    {
+        return Err(format!(
+            "{database_url} is already initialized, use `db migrate`"
+        )
+        .into());
+    }
+
+    let applied = run_migrations(&mut connection).map_err(|e| e.to_string())?;
+    println!(
+        "Initialized {database_url} with {} migrations",
+        applied.len()
+    );
+
+    Ok(())
+}
+
+fn db_migrate(args: &DbArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let config = UniprotConfig::load(&args.config)?;
+    let mut connection = connect(&config)?;
+    let database_url = &config.database_url;
+
+    let applied = run_migrations(&mut connection).map_err(|e| e.to_string())?;
+    if applied.is_empty() {
+        println!("{database_url} is up to date");
+    }
+    for version in applied {
+        println!("Applied {version}");
+    }
+
+    Ok(())
+}
+
+fn cache_list(args: &CacheListArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let config = UniprotConfig::load(&args.config)?;
+    let cache_dir = args.cache_dir.as_ref().unwrap_or(&config.cache_dir);
+
+    let entries = RawCache::new(cache_dir).entries()?;
+    write_cache_entries(&entries, args.format, std::io::stdout().lock())?;
+
+    Ok(())
 }
 
-fn run(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+fn cache_clean(
+    args: &CacheCleanArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = UniprotConfig::load(&args.config)?;
+    let cache =
+        RawCache::new(args.cache_dir.as_ref().unwrap_or(&config.cache_dir));
+    let opts = CleanOptions {
+        keep: if args.all { 0 } else { args.keep },
+        release: args.release.clone(),
+    };
+
+    if args.dry_run {
+        let stale = stale_entries(&cache, &opts)?;
+        write_cache_entries(
+            &stale,
+            OutputFormat::Table,
+            std::io::stdout().lock(),
+        )?;
+        return Ok(());
+    }
+
+    let removed = clean(&cache, &opts)?;
+    let size: u64 = removed.iter().map(|entry| entry.size).sum();
+    info!("Removed {} copies, {size} bytes", removed.len());
+
+    Ok(())
+}
+
+fn representatives(
+    args: &RepresentativesArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = UniprotConfig::load(&args.config)?;
+    let mut connection = establish_connection(&config)?;
+
+    let representatives = select_representatives(&mut connection, args.policy)?;
+    write_representatives(&representatives, args.format, &args.output)?;
+
+    if let Some(fasta) = &args.fasta {
+        write_representatives_fasta(&representatives, fasta)?;
+    }
+
+    Ok(())
+}
+
+fn fetch_similar(
+    args: &FetchSimilarArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
     // Configuration
-    let settings = load_settings(&args)?;
-    let url: String = settings.get("uniprot.similar.url")?;
-    let species: Vec<String> = settings.get("uniprot.similar.species")?;
-    let mut connection = establish_connection(&settings)?;
+    let config = UniprotConfig::load(&args.config)?;
+    // Migrations are writes too, a dry run expects an up to date schema
+    let mut connection = if args.dry_run {
+        connect(&config)?
+    } else {
+        establish_connection(&config)?
+    };
 
     // Process entries
-    let all_entries = get_similar_entries(&url)?;
-    let entries = filter_by_species(&all_entries, &species)?;
-    insert_entries(&entries, &mut connection)?;
+    let (source, similar) = load_similar(&config, &args.input, &args.download)?;
+    let codes = resolve_species(&config.similar.species, &mut connection)?;
+    let entries = filter_by_species(&similar.entries, &codes)?;
+
+    if args.dry_run {
+        preview_entries(&entries, &mut connection)?.print();
+        return Ok(());
+    }
+    connection.transaction(|connection| {
+        let run =
+            record_import(connection, &source, similar.release.as_deref())?;
+        insert_entries(&entries, run, connection)
+    })?;
 
     Ok(())
 }
 
-#[allow(dead_code)]
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    env_logger::init();
-    let args = Args::parse();
-    run(&args)?;
+// similar.txt from `input` or from the configured URL, with its source
+fn load_similar(
+    config: &UniprotConfig,
+    input: &Option<PathBuf>,
+    download: &DownloadArgs,
+) -> Result<(String, SimilarEntries), Box<dyn std::error::Error>> {
+    match input {
+        Some(input) => {
+            Ok((input.display().to_string(), read_similar_entries(input)?))
+        }
+        None => {
+            let url = &config.similar.url;
+            let similar =
+                get_similar_entries(url, &download.to_options(config))?;
+            Ok((url.clone(), similar))
+        }
+    }
+}
+
+fn sync(args: &SyncArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let config = UniprotConfig::load(&args.config)?;
+    let mut connection = establish_connection(&config)?;
+
+    let (source, similar) = load_similar(&config, &args.input, &args.download)?;
+    let previous_release = last_release(&mut connection)?;
+
+    if similar.release.is_some()
+        && similar.release == previous_release
+        && !args.force
+    {
+        println!(
+            "Already at release {}, nothing to do",
+            similar.release.unwrap_or_default()
+        );
+        return Ok(());
+    }
+
+    let codes = resolve_species(&config.similar.species, &mut connection)?;
+    let entries = filter_by_species(&similar.entries, &codes)?;
+
+    let release = similar.release.as_deref();
+    let mut summary = connection.transaction(|connection| {
+        let run = record_import(connection, &source, release)?;
+        sync_entries(&entries, release, run, connection)
+    })?;
+
+    summary.previous_release = previous_release;
+    summary.release = similar.release;
+    summary.print();
+
+    Ok(())
+}
+
+fn fetch_taxonomy(
+    args: &FetchTaxonomyArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = UniprotConfig::load(&args.config)?;
+    let mut connection = establish_connection(&config)?;
+
+    let taxa = match &args.input {
+        Some(input) => read_taxa(input)?,
+        None => {
+            get_taxa(&config.speclist.url, &args.download.to_options(&config))?
+        }
+    };
+    insert_taxa(&taxa, &mut connection)?;
+
     Ok(())
 }
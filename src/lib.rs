@@ -1,5 +1,10 @@
+#[cfg(all(feature = "parquet", feature = "uniprot"))]
 pub mod cli;
+#[cfg(all(feature = "parquet", feature = "uniprot"))]
 pub mod commands;
+pub mod logging;
+#[cfg(feature = "uniprot")]
 pub mod schema;
 pub mod uaspire;
+#[cfg(feature = "uniprot")]
 pub mod uniprot;
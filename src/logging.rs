@@ -0,0 +1,74 @@
+/// Logger setup shared by all commands and binaries.
+use clap::{ArgAction, Args, ValueEnum};
+use tracing::Level;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum LogFormat {
+    Compact,
+    Json,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct LogArgs {
+    // More logs with -v (debug) or -vv (trace)
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    verbose: u8,
+
+    // Only log warnings and errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    // Log line format, JSON for ingestion by a pipeline orchestrator
+    #[arg(long, value_enum, default_value = "compact", global = true)]
+    log_format: LogFormat,
+
+    // Write logs to this file instead of stderr
+    #[arg(long, global = true)]
+    log_file: Option<std::path::PathBuf>,
+}
+
+impl LogArgs {
+    pub fn level(&self) -> Level {
+        match (self.quiet, self.verbose) {
+            (true, _) => Level::WARN,
+            (false, 0) => Level::INFO,
+            (false, 1) => Level::DEBUG,
+            (false, _) => Level::TRACE,
+        }
+    }
+}
+
+/// Install the global logger, `log` records included.
+///
+/// The returned guard flushes the log file when dropped, it must be kept
+/// alive until the program exits.
+pub fn init_logging(args: &LogArgs) -> Option<WorkerGuard> {
+    // Logs go to stderr so stdout stays free for streamed output
+    let (writer, guard) = match &args.log_file {
+        Some(path) => {
+            let file = std::fs::File::create(path).unwrap_or_else(|e| {
+                panic!("Couldn't create log file {}: {e}", path.display())
+            });
+            let (writer, guard) = tracing_appender::non_blocking(file);
+            (BoxMakeWriter::new(writer), Some(guard))
+        }
+        None => (BoxMakeWriter::new(std::io::stderr), None),
+    };
+
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(args.level())
+        .with_writer(writer);
+
+    match args.log_format {
+        LogFormat::Compact => builder.compact().init(),
+        LogFormat::Json => builder
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .init(),
+    }
+
+    guard
+}
@@ -2,16 +2,22 @@ use clap::Parser;
 
 use biology_ru::cli::{Cli, Commands};
 use biology_ru::commands;
+use biology_ru::logging::init_logging;
 
 fn main() {
     let cli = Cli::parse();
 
-    match cli.command {
-        Commands::Uniprot(cmd) => {
-            commands::uniprot::command(cmd);
-        }
-        Commands::Uaspire(cmd) => {
-            commands::uaspire::command(cmd);
-        }
-    }
+    // Flushes the log file on exit
+    let guard = init_logging(&cli.log);
+
+    let code = match cli.command {
+        Commands::Uniprot(cmd) => commands::uniprot::command(cmd),
+        Commands::Uaspire(cmd) => commands::uaspire::command(cmd),
+        Commands::Crispr(cmd) => commands::crispr::command(cmd),
+        Commands::Mpra(cmd) => commands::mpra::command(cmd),
+    };
+
+    // Exiting skips destructors, flush the logs first
+    drop(guard);
+    std::process::exit(code);
 }
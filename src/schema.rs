@@ -4,6 +4,42 @@ diesel::table! {
     belongs_to_uniprot_sequence_similarity_family (entry, family) {
         entry -> Text,
         family -> Text,
+        obsolete -> Bool,
+        import_run -> Nullable<Integer>,
+    }
+}
+
+diesel::table! {
+    family_aliases (alias, family) {
+        alias -> Text,
+        family -> Text,
+        kind -> Text,
+        overlap -> Float,
+        release_version -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    go_term_parents (term, parent) {
+        term -> Text,
+        parent -> Text,
+    }
+}
+
+diesel::table! {
+    go_terms (id) {
+        id -> Text,
+        name -> Text,
+        namespace -> Text,
+    }
+}
+
+diesel::table! {
+    import_runs (id) {
+        id -> Integer,
+        source -> Text,
+        release_version -> Nullable<Text>,
+        imported_at -> Text,
     }
 }
 
@@ -13,6 +49,86 @@ diesel::table! {
         entry_name -> Text,
         mass -> Nullable<Integer>,
         seq_length -> Nullable<Integer>,
+        protein_name -> Nullable<Text>,
+        gene_name -> Nullable<Text>,
+        taxon -> Nullable<Text>,
+        obsolete -> Bool,
+        replaced_by -> Nullable<Text>,
+        import_run -> Nullable<Integer>,
+        reviewed -> Nullable<Bool>,
+    }
+}
+
+diesel::table! {
+    uniprot_entry_history (id) {
+        id -> Integer,
+        accession -> Text,
+        import_run -> Integer,
+        change -> Text,
+        detail -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    uniprot_entry_enzymes (entry, ec_number) {
+        entry -> Text,
+        ec_number -> Text,
+    }
+}
+
+diesel::table! {
+    uniprot_entry_proteomes (entry, proteome) {
+        entry -> Text,
+        proteome -> Text,
+    }
+}
+
+diesel::table! {
+    uniprot_enzymes (ec_number) {
+        ec_number -> Text,
+        name -> Text,
+        alternative_names -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    uniprot_id_mappings (entry, database, identifier) {
+        entry -> Text,
+        database -> Text,
+        identifier -> Text,
+    }
+}
+
+diesel::table! {
+    uniprot_entry_go_terms (entry, term) {
+        entry -> Text,
+        term -> Text,
+    }
+}
+
+diesel::table! {
+    uniprot_entry_keywords (entry, keyword) {
+        entry -> Text,
+        keyword -> Text,
+    }
+}
+
+diesel::table! {
+    uniprot_keywords (accession) {
+        accession -> Text,
+        name -> Text,
+        category -> Nullable<Text>,
+        definition -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    uniprot_proteomes (id) {
+        id -> Text,
+        taxid -> Nullable<Integer>,
+        organism -> Nullable<Text>,
+        proteome_type -> Text,
+        reference -> Bool,
     }
 }
 
@@ -22,11 +138,79 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    uniprot_taxa (code) {
+        code -> Text,
+        taxid -> Integer,
+        scientific_name -> Text,
+        common_name -> Nullable<Text>,
+        kingdom -> Text,
+    }
+}
+
+diesel::table! {
+    uniprot_sequences (accession_number) {
+        accession_number -> Text,
+        header -> Text,
+        sequence -> Text,
+    }
+}
+
+diesel::table! {
+    uniprot_variants (accession, variant) {
+        accession -> Text,
+        variant -> Text,
+        aa_change -> Text,
+        category -> Text,
+        dbsnp -> Nullable<Text>,
+        disease -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    uniprot_xrefs (accession, database, xref_id) {
+        accession -> Text,
+        database -> Text,
+        xref_id -> Text,
+    }
+}
+
 diesel::joinable!(belongs_to_uniprot_sequence_similarity_family -> uniprot_entries (entry));
 diesel::joinable!(belongs_to_uniprot_sequence_similarity_family -> uniprot_sequence_similarity_families (family));
+diesel::joinable!(family_aliases -> uniprot_sequence_similarity_families (family));
+diesel::joinable!(uniprot_entries -> uniprot_taxa (taxon));
+diesel::joinable!(uniprot_entry_enzymes -> uniprot_entries (entry));
+diesel::joinable!(uniprot_entry_enzymes -> uniprot_enzymes (ec_number));
+diesel::joinable!(uniprot_entry_go_terms -> go_terms (term));
+diesel::joinable!(uniprot_entry_go_terms -> uniprot_entries (entry));
+diesel::joinable!(uniprot_entry_history -> import_runs (import_run));
+diesel::joinable!(uniprot_entry_keywords -> uniprot_entries (entry));
+diesel::joinable!(uniprot_entry_keywords -> uniprot_keywords (keyword));
+diesel::joinable!(uniprot_entry_proteomes -> uniprot_entries (entry));
+diesel::joinable!(uniprot_id_mappings -> uniprot_entries (entry));
+diesel::joinable!(uniprot_sequences -> uniprot_entries (accession_number));
+diesel::joinable!(uniprot_variants -> uniprot_entries (accession));
+diesel::joinable!(uniprot_xrefs -> uniprot_entries (accession));
 
 diesel::allow_tables_to_appear_in_same_query!(
     belongs_to_uniprot_sequence_similarity_family,
+    family_aliases,
+    go_term_parents,
+    go_terms,
+    import_runs,
     uniprot_entries,
+    uniprot_entry_enzymes,
+    uniprot_entry_go_terms,
+    uniprot_entry_history,
+    uniprot_entry_keywords,
+    uniprot_entry_proteomes,
+    uniprot_enzymes,
+    uniprot_id_mappings,
+    uniprot_keywords,
+    uniprot_proteomes,
     uniprot_sequence_similarity_families,
+    uniprot_sequences,
+    uniprot_taxa,
+    uniprot_variants,
+    uniprot_xrefs,
 );
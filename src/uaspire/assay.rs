@@ -0,0 +1,103 @@
+/// Read classifiers of the amplicon assays sharing the counting pipeline.
+///
+/// A classifier turns a read pair into a sample key (the barcode pair), a
+/// feature (the counted sequence or its name) and a state (flip status for
+/// uASPIre, always non-flipped for single-state assays). Chunking, counting,
+/// spilling and the Parquet outputs are the same whatever the assay.
+use bio::io::fastq;
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::uaspire::classify::{
+    classify_merged, classify_pair, Config, FailReason, Flip, Sample,
+};
+use crate::uaspire::overlap::MergedRead;
+
+// ---------- Assays ----------
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, ValueEnum, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Assay {
+    // Recombinase-based RBS strength measurement
+    #[default]
+    Uaspire,
+    // Guide counting of pooled CRISPR screens
+    Crispr,
+    // Random barcode counting of massively parallel reporter assays
+    Mpra,
+}
+
+// ---------- Classifier ----------
+
+/// Sample key, feature and state of a read pair, or why it was rejected.
+pub(crate) type Classification<'s> =
+    Result<Result<(Sample, &'s str, Flip), FailReason>, std::str::Utf8Error>;
+
+pub(crate) trait ReadClassifier: Sync {
+    /// Classify the mates of a read pair.
+    fn classify_pair<'s>(
+        &'s self,
+        rec1: &'s fastq::Record,
+        rec2: &'s fastq::Record,
+    ) -> Classification<'s>;
+
+    /// Classify a pair merged into a single fragment. Assays without a
+    /// fragment-level geometry classify the mates instead.
+    fn classify_merged<'s>(
+        &'s self,
+        rec1: &'s fastq::Record,
+        rec2: &'s fastq::Record,
+        _merged: &'s MergedRead,
+    ) -> Classification<'s> {
+        self.classify_pair(rec1, rec2)
+    }
+}
+
+impl ReadClassifier for Config<'_> {
+    fn classify_pair<'s>(
+        &'s self,
+        rec1: &'s fastq::Record,
+        rec2: &'s fastq::Record,
+    ) -> Classification<'s> {
+        classify_pair(self, rec1, rec2)
+    }
+
+    fn classify_merged<'s>(
+        &'s self,
+        rec1: &'s fastq::Record,
+        rec2: &'s fastq::Record,
+        merged: &'s MergedRead,
+    ) -> Classification<'s> {
+        classify_merged(self, rec1, rec2, merged)
+    }
+}
+
+// =========================================================
+// Helper functions
+// =========================================================
+
+/// Observed and whitelist barcodes of a pair carrying its sample barcodes
+/// inline, at fixed offsets from the start of both reads.
+pub(crate) fn inline_barcodes<'s, 'c>(
+    cfg: &Config<'c>,
+    seq1: &'s str,
+    seq2: &'s str,
+    offsets: (usize, usize),
+) -> Result<[(&'s str, &'c str); 2], FailReason> {
+    let len = cfg.barcode_len;
+    let (Some(observed1), Some(observed2)) = (
+        seq1.get(offsets.0..offsets.0 + len),
+        seq2.get(offsets.1..offsets.1 + len),
+    ) else {
+        return Err(FailReason::ReadTooShort);
+    };
+
+    let Some(barcode1) = cfg.match_barcode1(observed1) else {
+        return Err(FailReason::Barcode1);
+    };
+    let Some(barcode2) = cfg.match_barcode2(observed2) else {
+        return Err(FailReason::Barcode2);
+    };
+
+    Ok([(observed1, barcode1), (observed2, barcode2)])
+}
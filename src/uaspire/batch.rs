@@ -0,0 +1,102 @@
+/// Sequential or bounded-concurrency processing of a multi-sample manifest.
+use serde::Deserialize;
+use tracing::{error, info};
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+    thread,
+};
+
+use crate::uaspire::fastq::ProcessOptions;
+use crate::uaspire::interrupt::is_interrupted;
+use crate::uaspire::processor::UaspireProcessor;
+
+// ---------- Manifest entries ----------
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BatchEntry {
+    pub sample: String,
+    pub read1: PathBuf,
+    pub read2: PathBuf,
+    #[serde(default)]
+    pub output: Option<PathBuf>,
+}
+
+impl BatchEntry {
+    /// Output directory of the sample, its name when not given.
+    fn output_dir(&self, root: &Path) -> PathBuf {
+        match &self.output {
+            Some(output) => root.join(output),
+            None => root.join(&self.sample),
+        }
+    }
+}
+
+pub fn read_batch_manifest(
+    path: impl AsRef<Path>,
+) -> Result<Vec<BatchEntry>, csv::Error> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+    reader.deserialize().collect()
+}
+
+// =========================================================
+// Batch processing
+// =========================================================
+
+pub(crate) fn process_entry(
+    entry: &BatchEntry,
+    root: &Path,
+    opts: &ProcessOptions,
+) -> bool {
+    info!("Processing sample {}", entry.sample);
+
+    let result = UaspireProcessor::new(&entry.sample)
+        .inputs(&entry.read1, &entry.read2)
+        .output_dir(entry.output_dir(root))
+        .options(opts.clone())
+        .run();
+
+    if let Err(e) = &result {
+        error!("Sample {}: {}", entry.sample, e);
+    }
+
+    result.is_ok()
+}
+
+/// Process all samples with at most `jobs` running at the same time, and
+/// return the names of the samples that failed.
+///
+/// Every sample runs its parallel work on the global rayon pool, so the
+/// thread count stays bounded whatever the number of jobs.
+pub fn run_batch(
+    entries: &[BatchEntry],
+    root: &Path,
+    opts: &ProcessOptions,
+    jobs: usize,
+) -> Vec<String> {
+    let queue = Mutex::new(entries.iter());
+    let failed = Mutex::new(Vec::new());
+
+    thread::scope(|scope| {
+        for _ in 0..jobs.max(1) {
+            scope.spawn(|| loop {
+                // Samples not started yet are left alone once interrupted
+                if is_interrupted() {
+                    break;
+                }
+                let Some(entry) = queue.lock().unwrap().next() else {
+                    break;
+                };
+
+                if !process_entry(entry, root, opts) {
+                    failed.lock().unwrap().push(entry.sample.clone());
+                }
+            });
+        }
+    });
+
+    failed.into_inner().unwrap()
+}
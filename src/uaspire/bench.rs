@@ -0,0 +1,299 @@
+/// Classification throughput on a synthetic in-memory read stream.
+use bio::io::fastq;
+use dashmap::DashMap;
+use rayon::prelude::*;
+
+use std::{collections::HashSet, hint::black_box, time::Instant};
+
+use crate::uaspire::classify::{
+    add_to_table, classify_pair, Config, SampleTable,
+};
+use crate::uaspire::constants;
+
+// Fraction of the synthetic pairs made of random bases
+const NOISE_FRACTION: f64 = 0.1;
+
+// Read length of both mates
+const READ_LEN: usize = 75;
+
+// ---------- Results ----------
+
+#[derive(Debug, Clone)]
+pub struct BenchResult {
+    pub threads: usize,
+    pub chunk_size: usize,
+    pub reads: usize,
+    pub seconds: f64,
+}
+
+impl BenchResult {
+    pub fn reads_per_sec(&self) -> f64 {
+        self.reads as f64 / self.seconds
+    }
+}
+
+/// Barcode lookups against a whitelist, scanned or hashed.
+#[derive(Debug, Clone)]
+pub struct WhitelistBenchResult {
+    pub size: usize,
+    pub lookups: usize,
+    pub scan_seconds: f64,
+    pub hashed_seconds: f64,
+    pub classify_reads_per_sec: f64,
+}
+
+impl WhitelistBenchResult {
+    pub fn speedup(&self) -> f64 {
+        self.scan_seconds / self.hashed_seconds
+    }
+}
+
+// =========================================================
+// Synthetic reads
+// =========================================================
+
+/// Small xorshift generator, reproducible without extra dependencies.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+        items[self.next() as usize % items.len()]
+    }
+
+    fn bases(&mut self, n: usize) -> String {
+        (0..n)
+            .map(|_| b"ACGT"[self.next() as usize % 4] as char)
+            .collect()
+    }
+}
+
+/// Pad a read with random bases up to the read length.
+fn pad(rng: &mut XorShift, mut seq: String) -> String {
+    if seq.len() < READ_LEN {
+        let tail = rng.bases(READ_LEN - seq.len());
+        seq.push_str(&tail);
+    }
+    seq
+}
+
+/// Generate read pairs laid out like uASPIre constructs, with a share of
+/// random pairs so that rejection paths are exercised too.
+pub fn synthetic_pairs(
+    n: usize,
+    seed: u64,
+) -> (Vec<fastq::Record>, Vec<fastq::Record>) {
+    let mut rng = XorShift(seed.max(1));
+    let qual = vec![b'I'; READ_LEN];
+
+    let mut reads1 = Vec::with_capacity(n);
+    let mut reads2 = Vec::with_capacity(n);
+
+    for i in 0..n {
+        let noise = (rng.next() % 1000) as f64 / 1000.0 < NOISE_FRACTION;
+
+        let (seq1, seq2) = if noise {
+            (rng.bases(READ_LEN), rng.bases(READ_LEN))
+        } else {
+            let disc = if rng.next() % 2 == 0 {
+                constants::NON_FLIPPED_SEQ
+            } else {
+                constants::FLIPPED_SEQ
+            };
+
+            // Barcode 1 then the discriminator on R1
+            let seq1 = format!(
+                "{}{}{}{}",
+                rng.bases(10),
+                rng.pick(&constants::BARCODES_1),
+                rng.bases(constants::DISCRIMINATOR_OFFSET),
+                disc
+            );
+
+            // Barcode 2, constant region and RBS on R2
+            let seq2 = format!(
+                "{}{}{}{}",
+                rng.bases(4),
+                rng.pick(&constants::BARCODES_2),
+                constants::CONSTANT_REGION,
+                rng.bases(constants::RBS_LEN)
+            );
+
+            (seq1, seq2)
+        };
+
+        let id = format!("bench.{i}");
+        let seq1 = pad(&mut rng, seq1);
+        let seq2 = pad(&mut rng, seq2);
+
+        reads1.push(fastq::Record::with_attrs(
+            &id,
+            None,
+            seq1.as_bytes(),
+            &qual,
+        ));
+        reads2.push(fastq::Record::with_attrs(
+            &id,
+            None,
+            seq2.as_bytes(),
+            &qual,
+        ));
+    }
+
+    (reads1, reads2)
+}
+
+// =========================================================
+// Benchmark
+// =========================================================
+
+fn classify_all(
+    cfg: &Config,
+    reads1: &[fastq::Record],
+    reads2: &[fastq::Record],
+    chunk_size: usize,
+) {
+    let table: SampleTable = DashMap::new();
+
+    for (chunk1, chunk2) in
+        reads1.chunks(chunk_size).zip(reads2.chunks(chunk_size))
+    {
+        chunk1
+            .par_iter()
+            .zip(chunk2.par_iter())
+            .for_each(|(rec1, rec2)| {
+                if let Ok(Ok((sample, rbs, flipped))) =
+                    classify_pair(cfg, rec1, rec2)
+                {
+                    add_to_table(&table, sample, rbs, flipped);
+                }
+            });
+    }
+}
+
+/// Time the classification of `reads` synthetic pairs for every
+/// combination of thread count and chunk size.
+pub fn run_bench(
+    reads: usize,
+    threads: &[usize],
+    chunk_sizes: &[usize],
+) -> Result<Vec<BenchResult>, rayon::ThreadPoolBuildError> {
+    let cfg = Config::from_constants();
+    let (reads1, reads2) = synthetic_pairs(reads, 42);
+
+    let mut results = Vec::new();
+
+    for &n_threads in threads {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(n_threads)
+            .build()?;
+
+        for &chunk_size in chunk_sizes {
+            let start = Instant::now();
+            pool.install(|| {
+                classify_all(&cfg, &reads1, &reads2, chunk_size.max(1))
+            });
+
+            results.push(BenchResult {
+                threads: n_threads,
+                chunk_size,
+                reads,
+                seconds: start.elapsed().as_secs_f64(),
+            });
+        }
+    }
+
+    Ok(results)
+}
+
+// =========================================================
+// Whitelist benchmark
+// =========================================================
+
+/// Random distinct barcodes, the uASPIre ones first.
+fn synthetic_whitelist(
+    rng: &mut XorShift,
+    size: usize,
+    base: &[&str],
+) -> Vec<String> {
+    let mut barcodes: Vec<String> =
+        base.iter().take(size).map(|b| b.to_string()).collect();
+    let mut seen: HashSet<String> = barcodes.iter().cloned().collect();
+
+    while barcodes.len() < size {
+        let barcode = rng.bases(constants::BARCODE_LEN);
+        if seen.insert(barcode.clone()) {
+            barcodes.push(barcode);
+        }
+    }
+
+    barcodes
+}
+
+/// Compare a linear scan with the hashed lookup used by the classifier for
+/// whitelists of every size, and time a whole classification with them.
+///
+/// Half of the queried barcodes are in the whitelist, so that both hits and
+/// misses are measured.
+pub fn run_whitelist_bench(
+    sizes: &[usize],
+    lookups: usize,
+    reads: usize,
+) -> Vec<WhitelistBenchResult> {
+    let mut rng = XorShift(7);
+    let (reads1, reads2) = synthetic_pairs(reads, 42);
+
+    let mut results = Vec::new();
+
+    for &size in sizes {
+        let owned1 =
+            synthetic_whitelist(&mut rng, size, &constants::BARCODES_1);
+        let owned2 =
+            synthetic_whitelist(&mut rng, size, &constants::BARCODES_2);
+        let barcodes1: Vec<&str> = owned1.iter().map(|b| b.as_str()).collect();
+        let barcodes2: Vec<&str> = owned2.iter().map(|b| b.as_str()).collect();
+
+        let queries: Vec<String> = (0..lookups)
+            .map(|i| match i % 2 {
+                0 => rng.pick(&barcodes1).to_string(),
+                _ => rng.bases(constants::BARCODE_LEN),
+            })
+            .collect();
+
+        let start = Instant::now();
+        for query in &queries {
+            black_box(barcodes1.contains(&query.as_str()));
+        }
+        let scan_seconds = start.elapsed().as_secs_f64();
+
+        let lookup: HashSet<&str> = barcodes1.iter().copied().collect();
+        let start = Instant::now();
+        for query in &queries {
+            black_box(lookup.contains(query.as_str()));
+        }
+        let hashed_seconds = start.elapsed().as_secs_f64();
+
+        let cfg =
+            Config::from_constants().with_whitelists(&barcodes1, &barcodes2);
+        let start = Instant::now();
+        classify_all(&cfg, &reads1, &reads2, 10_000);
+        let classify_reads_per_sec =
+            reads as f64 / start.elapsed().as_secs_f64();
+
+        results.push(WhitelistBenchResult {
+            size,
+            lookups,
+            scan_seconds,
+            hashed_seconds,
+            classify_reads_per_sec,
+        });
+    }
+
+    results
+}
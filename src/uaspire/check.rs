@@ -0,0 +1,131 @@
+/// Integrity checks of a FASTQ pair before processing.
+use std::path::Path;
+
+use crate::uaspire::classify::pair_id;
+use crate::uaspire::reader::open_fastq;
+
+// ---------- Report ----------
+
+#[derive(Debug, Default)]
+pub struct CheckReport {
+    pub records1: u64,
+    pub records2: u64,
+    pub mismatched_ids: u64,
+    pub first_mismatch: Option<(u64, String, String)>,
+    pub errors: Vec<String>,
+}
+
+impl CheckReport {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+            && self.records1 == self.records2
+            && self.mismatched_ids == 0
+    }
+
+    pub fn print(&self) {
+        println!("R1 records: {}", self.records1);
+        println!("R2 records: {}", self.records2);
+        println!("Mismatched IDs: {}", self.mismatched_ids);
+
+        if let Some((index, id1, id2)) = &self.first_mismatch {
+            println!("First mismatch at record {}: {} vs {}", index, id1, id2);
+        }
+
+        for error in &self.errors {
+            println!("Error: {}", error);
+        }
+
+        println!("Status: {}", if self.is_ok() { "OK" } else { "FAILED" });
+    }
+}
+
+// =========================================================
+// Check
+// =========================================================
+
+/// Read both files to the end, verifying that they parse, hold the same
+/// number of records and that the read IDs pair up.
+pub fn check_pair(
+    path1: impl AsRef<Path>,
+    path2: impl AsRef<Path>,
+) -> CheckReport {
+    let mut report = CheckReport::default();
+
+    let (reader1, reader2) = match (open_fastq(&path1), open_fastq(&path2)) {
+        (Ok(r1), Ok(r2)) => (r1, r2),
+        (r1, r2) => {
+            for (path, r) in
+                [(path1.as_ref(), r1.err()), (path2.as_ref(), r2.err())]
+            {
+                if let Some(e) = r {
+                    report.errors.push(format!(
+                        "{}: cannot open: {}",
+                        path.display(),
+                        e
+                    ));
+                }
+            }
+            return report;
+        }
+    };
+
+    let mut records1 = Some(reader1.records());
+    let mut records2 = Some(reader2.records());
+
+    // Reading stops on the first error of a file, a truncated gzip stream
+    // surfacing as an unexpected end of file.
+    loop {
+        let rec1 = records1.as_mut().and_then(|r| r.next());
+        let rec2 = records2.as_mut().and_then(|r| r.next());
+
+        if rec1.is_none() && rec2.is_none() {
+            break;
+        }
+
+        let rec1 = match rec1 {
+            Some(Ok(rec)) => Some(rec),
+            Some(Err(e)) => {
+                report.errors.push(format!(
+                    "{}: record {}: {}",
+                    path1.as_ref().display(),
+                    report.records1 + 1,
+                    e
+                ));
+                records1 = None;
+                None
+            }
+            None => None,
+        };
+
+        let rec2 = match rec2 {
+            Some(Ok(rec)) => Some(rec),
+            Some(Err(e)) => {
+                report.errors.push(format!(
+                    "{}: record {}: {}",
+                    path2.as_ref().display(),
+                    report.records2 + 1,
+                    e
+                ));
+                records2 = None;
+                None
+            }
+            None => None,
+        };
+
+        report.records1 += rec1.is_some() as u64;
+        report.records2 += rec2.is_some() as u64;
+
+        if let (Some(rec1), Some(rec2)) = (&rec1, &rec2) {
+            if pair_id(rec1.id()) != pair_id(rec2.id()) {
+                report.mismatched_ids += 1;
+                report.first_mismatch.get_or_insert((
+                    report.records1,
+                    rec1.id().to_string(),
+                    rec2.id().to_string(),
+                ));
+            }
+        }
+    }
+
+    report
+}
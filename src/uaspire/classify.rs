@@ -0,0 +1,822 @@
+/// Classification of read pairs into barcode pairs, RBSs and flip states.
+use bio::io::fastq;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+
+use strum::{EnumCount, IntoEnumIterator};
+use strum_macros::{EnumCount, EnumIter};
+
+use std::{
+    collections::HashSet,
+    hash::Hash,
+    sync::atomic::{AtomicU64, Ordering},
+    sync::Arc,
+};
+
+use crate::uaspire::constants;
+use crate::uaspire::correction::{nearest_barcode, BarcodeCorrections};
+use crate::uaspire::overlap::MergedRead;
+
+// ---------- Sample table ----------
+
+pub(crate) type SampleTable = DashMap<Sample, DashMap<String, [AtomicU64; 2]>>;
+
+// ---------- Base call quality ----------
+
+/// Maximum numbers of N base calls. The others apply when set, and the pair
+/// limit only when none is, so that Ns in flanks that are never extracted
+/// need not reject a read.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NLimits {
+    pub pair: usize,
+    pub read1: Option<usize>,
+    pub read2: Option<usize>,
+    pub barcode: Option<usize>,
+    pub rbs: Option<usize>,
+}
+
+impl NLimits {
+    /// Whether only the whole pair is limited.
+    pub(crate) fn pair_only(&self) -> bool {
+        self.read1.is_none()
+            && self.read2.is_none()
+            && self.barcode.is_none()
+            && self.rbs.is_none()
+    }
+}
+
+impl Default for NLimits {
+    fn default() -> Self {
+        NLimits {
+            pair: constants::MAX_N_COUNT,
+            read1: None,
+            read2: None,
+            barcode: None,
+            rbs: None,
+        }
+    }
+}
+
+// ---------- Read geometry ----------
+
+/// Positions and lengths of the construct elements within the reads.
+///
+/// With an `rbs_anchor`, the RBS runs from the end of the constant region
+/// to the anchor and its length may vary within `rbs_len_range`, instead of
+/// being a fixed `rbs_len` slice. Barcodes within `barcode_mismatches` of a
+/// single whitelist barcode are corrected to it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Geometry {
+    pub window: (usize, usize),
+    pub rbs_len: usize,
+    pub rbs_anchor: Option<String>,
+    pub rbs_len_range: (usize, usize),
+    pub barcode_len: usize,
+    pub barcode_mismatches: usize,
+    pub max_n: NLimits,
+    pub disc_offset: usize,
+}
+
+impl Default for Geometry {
+    fn default() -> Self {
+        Geometry {
+            window: constants::CONSTANT_REGION_WINDOW,
+            rbs_len: constants::RBS_LEN,
+            rbs_anchor: None,
+            rbs_len_range: (constants::RBS_LEN, constants::RBS_LEN),
+            barcode_len: constants::BARCODE_LEN,
+            barcode_mismatches: 0,
+            max_n: NLimits::default(),
+            disc_offset: constants::DISCRIMINATOR_OFFSET,
+        }
+    }
+}
+
+// ---------- Configuration ----------
+
+#[derive(Clone, Debug)]
+pub(crate) struct Config<'a> {
+    pub(crate) barcodes1: &'a [&'a str],
+    pub(crate) barcodes2: &'a [&'a str],
+    // Hashed whitelists, a linear scan gets slow with large custom ones
+    pub(crate) lookup1: HashSet<&'a str>,
+    pub(crate) lookup2: HashSet<&'a str>,
+    pub(crate) const_region: &'a str,
+    pub(crate) window: (usize, usize),
+    pub(crate) rbs_len: usize,
+    pub(crate) rbs_anchor: Option<&'a str>,
+    pub(crate) rbs_len_range: (usize, usize),
+    pub(crate) barcode_len: usize,
+    pub(crate) barcode_mismatches: usize,
+    // Tally of corrected barcodes, when reported
+    pub(crate) corrections: Option<Arc<BarcodeCorrections>>,
+    pub(crate) max_n: NLimits,
+    pub(crate) non_flipped: &'a str,
+    pub(crate) flipped: &'a str,
+    pub(crate) disc_offset: usize,
+    pub(crate) cross_check: bool,
+    pub(crate) strict_ids: bool,
+}
+
+impl Config<'static> {
+    /// Geometry and whitelists of the uASPIre constructs.
+    pub(crate) fn from_constants() -> Self {
+        Config {
+            barcodes1: &constants::BARCODES_1,
+            barcodes2: &constants::BARCODES_2,
+            lookup1: constants::BARCODES_1.iter().copied().collect(),
+            lookup2: constants::BARCODES_2.iter().copied().collect(),
+            const_region: constants::CONSTANT_REGION,
+            window: constants::CONSTANT_REGION_WINDOW,
+            rbs_len: constants::RBS_LEN,
+            rbs_anchor: None,
+            rbs_len_range: (constants::RBS_LEN, constants::RBS_LEN),
+            barcode_len: constants::BARCODE_LEN,
+            barcode_mismatches: 0,
+            corrections: None,
+            max_n: NLimits::default(),
+            non_flipped: constants::NON_FLIPPED_SEQ,
+            flipped: constants::FLIPPED_SEQ,
+            disc_offset: constants::DISCRIMINATOR_OFFSET,
+            cross_check: whitelists_differ(
+                &constants::BARCODES_1,
+                &constants::BARCODES_2,
+            ),
+            strict_ids: false,
+        }
+    }
+}
+
+impl<'a> Config<'a> {
+    /// Check that the geometry and the whitelists are consistent.
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        let (lo, hi) = self.window;
+        if lo == 0 || lo > hi {
+            return Err(format!("invalid constant region window {lo}..{hi}"));
+        }
+        if hi - lo + 1 < self.const_region.len() {
+            return Err(format!(
+                "constant region window {lo}..{hi} is shorter than {}",
+                self.const_region
+            ));
+        }
+
+        let (min, max) = self.rbs_len_range;
+        if self.rbs_anchor.is_some() && (min == 0 || min > max) {
+            return Err(format!("invalid RBS length range {min}..{max}"));
+        }
+        if self.rbs_anchor.is_some_and(|a| a.is_empty()) {
+            return Err("empty RBS anchor".to_string());
+        }
+        if self.barcode_mismatches >= self.barcode_len {
+            return Err(format!(
+                "{} barcode mismatches leave nothing of {} base barcodes",
+                self.barcode_mismatches, self.barcode_len
+            ));
+        }
+
+        for (name, whitelist) in
+            [("barcode1", self.barcodes1), ("barcode2", self.barcodes2)]
+        {
+            for (i, barcode) in whitelist.iter().enumerate() {
+                if barcode.len() != self.barcode_len {
+                    return Err(format!(
+                        "{name} {barcode} is not {} bases long",
+                        self.barcode_len
+                    ));
+                }
+                if !barcode.bytes().all(|b| b"ACGT".contains(&b)) {
+                    return Err(format!("{name} {barcode} is not ACGT only"));
+                }
+                if whitelist[..i].contains(barcode) {
+                    return Err(format!("{name} {barcode} is duplicated"));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Shortest read 1 and read 2 that the geometry can classify.
+    ///
+    /// Read 1 must hold barcode 1, the discriminator offset and the shorter
+    /// discriminator. Read 2 must hold the shortest RBS after a constant
+    /// region ending as late as the window allows.
+    pub(crate) fn min_read_lengths(&self) -> (usize, usize) {
+        let disc_len = self.non_flipped.len().min(self.flipped.len());
+        let read1 = self.barcode_len + self.disc_offset + disc_len;
+
+        let rbs_len = match self.rbs_anchor {
+            Some(anchor) => self.rbs_len_range.0 + anchor.len(),
+            None => self.rbs_len,
+        };
+        let read2 = self.window.1 + rbs_len;
+
+        (read1, read2)
+    }
+
+    /// Replace the barcode whitelists.
+    pub(crate) fn with_whitelists(
+        mut self,
+        barcodes1: &'a [&'a str],
+        barcodes2: &'a [&'a str],
+    ) -> Self {
+        self.barcodes1 = barcodes1;
+        self.barcodes2 = barcodes2;
+        self.lookup1 = barcodes1.iter().copied().collect();
+        self.lookup2 = barcodes2.iter().copied().collect();
+        self.cross_check = self.lookup1 != self.lookup2;
+        self
+    }
+
+    pub(crate) fn is_barcode1(&self, barcode: &str) -> bool {
+        self.lookup1.contains(barcode)
+    }
+
+    pub(crate) fn is_barcode2(&self, barcode: &str) -> bool {
+        self.lookup2.contains(barcode)
+    }
+
+    /// Whitelist barcode 1 an observed barcode stands for, corrected within
+    /// the allowed mismatches.
+    pub(crate) fn match_barcode1(&self, observed: &str) -> Option<&'a str> {
+        nearest_barcode(
+            &self.lookup1,
+            self.barcodes1,
+            observed,
+            self.barcode_mismatches,
+        )
+    }
+
+    /// Whitelist barcode 2 an observed barcode stands for, corrected within
+    /// the allowed mismatches.
+    pub(crate) fn match_barcode2(&self, observed: &str) -> Option<&'a str> {
+        nearest_barcode(
+            &self.lookup2,
+            self.barcodes2,
+            observed,
+            self.barcode_mismatches,
+        )
+    }
+
+    /// Tally the corrections of a classified construct, given as observed
+    /// and whitelist barcodes.
+    pub(crate) fn record_corrections(
+        &self,
+        barcode1: (&str, &str),
+        barcode2: (&str, &str),
+    ) {
+        let Some(corrections) = &self.corrections else {
+            return;
+        };
+
+        for (read, (observed, barcode)) in [(1, barcode1), (2, barcode2)] {
+            if observed != barcode {
+                corrections.record(read, observed, barcode);
+            }
+        }
+    }
+
+    /// Report corrected barcodes into `corrections`.
+    pub(crate) fn with_corrections(
+        mut self,
+        corrections: Arc<BarcodeCorrections>,
+    ) -> Self {
+        self.corrections = Some(corrections);
+        self
+    }
+
+    /// Replace the construct geometry, keeping the whitelists.
+    pub(crate) fn with_geometry(mut self, geometry: &'a Geometry) -> Self {
+        self.window = geometry.window;
+        self.rbs_len = geometry.rbs_len;
+        self.rbs_anchor = geometry.rbs_anchor.as_deref();
+        self.rbs_len_range = geometry.rbs_len_range;
+        self.barcode_len = geometry.barcode_len;
+        self.barcode_mismatches = geometry.barcode_mismatches;
+        self.max_n = geometry.max_n;
+        self.disc_offset = geometry.disc_offset;
+        self
+    }
+}
+
+// ---------- Reads classication ---------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumCount, EnumIter)]
+pub enum FailReason {
+    BaseCalls,
+    ConstantSeq,
+    ConstantPos,
+    Barcode1,
+    Barcode2,
+    Barcode1Cross,
+    Barcode2Cross,
+    DiscSeq,
+    DiscPos,
+    IdMismatch,
+    MalformedRecord,
+    RbsLength,
+    BaseCallsRead1,
+    BaseCallsRead2,
+    BaseCallsBarcode,
+    BaseCallsRbs,
+    ReadTooShort,
+    Guide,
+    MpraBarcode,
+}
+
+impl FailReason {
+    /// Stable numeric code, never reassigned once released.
+    pub fn code(self) -> u16 {
+        match self {
+            FailReason::BaseCalls => 1,
+            FailReason::ConstantSeq => 2,
+            FailReason::ConstantPos => 3,
+            FailReason::Barcode1 => 4,
+            FailReason::Barcode2 => 5,
+            FailReason::Barcode1Cross => 6,
+            FailReason::Barcode2Cross => 7,
+            FailReason::DiscSeq => 8,
+            FailReason::DiscPos => 9,
+            FailReason::IdMismatch => 10,
+            FailReason::MalformedRecord => 11,
+            FailReason::RbsLength => 12,
+            FailReason::BaseCallsRead1 => 13,
+            FailReason::BaseCallsRead2 => 14,
+            FailReason::BaseCallsBarcode => 15,
+            FailReason::BaseCallsRbs => 16,
+            FailReason::ReadTooShort => 17,
+            FailReason::Guide => 18,
+            FailReason::MpraBarcode => 19,
+        }
+    }
+
+    /// Short name used in QC tables and on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            FailReason::BaseCalls => "base_calls",
+            FailReason::ConstantSeq => "constant_seq",
+            FailReason::ConstantPos => "constant_pos",
+            FailReason::Barcode1 => "barcode_1",
+            FailReason::Barcode2 => "barcode_2",
+            FailReason::Barcode1Cross => "barcode_1_cross",
+            FailReason::Barcode2Cross => "barcode_2_cross",
+            FailReason::DiscSeq => "disc_seq",
+            FailReason::DiscPos => "disc_pos",
+            FailReason::IdMismatch => "id_mismatch",
+            FailReason::MalformedRecord => "malformed_record",
+            FailReason::RbsLength => "rbs_length",
+            FailReason::BaseCallsRead1 => "base_calls_read1",
+            FailReason::BaseCallsRead2 => "base_calls_read2",
+            FailReason::BaseCallsBarcode => "base_calls_barcode",
+            FailReason::BaseCallsRbs => "base_calls_rbs",
+            FailReason::ReadTooShort => "read_too_short",
+            FailReason::Guide => "guide",
+            FailReason::MpraBarcode => "mpra_barcode",
+        }
+    }
+
+    /// User-facing explanation of the failure.
+    pub fn description(self) -> &'static str {
+        match self {
+            FailReason::BaseCalls => "Too many N base calls in the read pair",
+            FailReason::ConstantSeq => {
+                "Constant region not found in the expected window of read 2"
+            }
+            FailReason::ConstantPos => {
+                "Constant region too close to the read ends to extract the \
+                 barcode and RBS"
+            }
+            FailReason::Barcode1 => "Barcode 1 not in the whitelist",
+            FailReason::Barcode2 => "Barcode 2 not in the whitelist",
+            FailReason::Barcode1Cross => {
+                "Barcode 1 only found in the barcode 2 whitelist, check the \
+                 read layout and sample sheet"
+            }
+            FailReason::Barcode2Cross => {
+                "Barcode 2 only found in the barcode 1 whitelist, check the \
+                 read layout and sample sheet"
+            }
+            FailReason::DiscSeq => {
+                "Neither the flipped nor the non-flipped discriminator was \
+                 found in read 1"
+            }
+            FailReason::DiscPos => {
+                "Discriminator too close to the start of read 1 to extract \
+                 barcode 1"
+            }
+            FailReason::IdMismatch => {
+                "Read IDs of the pair differ, the files are out of sync"
+            }
+            FailReason::MalformedRecord => {
+                "Record could not be parsed, e.g. truncated or corrupt gzip \
+                 stream or non-UTF-8 sequence"
+            }
+            FailReason::RbsLength => {
+                "RBS anchor not found within the allowed RBS length range"
+            }
+            FailReason::BaseCallsRead1 => "Too many N base calls in read 1",
+            FailReason::BaseCallsRead2 => "Too many N base calls in read 2",
+            FailReason::BaseCallsBarcode => {
+                "Too many N base calls in an extracted barcode"
+            }
+            FailReason::BaseCallsRbs => {
+                "Too many N base calls in the extracted RBS"
+            }
+            FailReason::ReadTooShort => {
+                "A read is shorter than the construct geometry requires"
+            }
+            FailReason::Guide => {
+                "Guide anchor missing or no library guide after it (CRISPR)"
+            }
+            FailReason::MpraBarcode => {
+                "MPRA anchor missing or barcode incomplete or with Ns (MPRA)"
+            }
+        }
+    }
+
+    /// Copy of a record with the reason appended to its description.
+    pub fn annotate(self, rec: &fastq::Record) -> fastq::Record {
+        let tag = format!("fail={} code={}", self.name(), self.code());
+        let desc = match rec.desc() {
+            Some(desc) => format!("{desc} {tag}"),
+            None => tag,
+        };
+
+        fastq::Record::with_attrs(rec.id(), Some(&desc), rec.seq(), rec.qual())
+    }
+
+    /// Look a reason up by its short name or numeric code.
+    pub fn lookup(key: &str) -> Option<Self> {
+        FailReason::iter()
+            .find(|r| r.name() == key || r.code().to_string() == key)
+    }
+}
+
+// ---------- Discriminator status ----------
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Flip {
+    NonFlipped,
+    Flipped,
+}
+
+// ---------- Sample is a barcode pair ----------
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub(crate) struct Sample {
+    pub(crate) barcode1: String,
+    pub(crate) barcode2: String,
+}
+
+// ---------- Read types counter ----------
+
+#[derive(Default)]
+pub(crate) struct Counters {
+    total: AtomicU64,
+    valid: AtomicU64,
+    fails: [AtomicU64; FailReason::COUNT],
+    filtered_rows: AtomicU64,
+    merged: AtomicU64,
+    // Valid constructs, a long read holding several
+    constructs: AtomicU64,
+}
+
+impl Counters {
+    pub(crate) fn inc_total(&self) {
+        self.total.fetch_add(1, Ordering::Relaxed);
+    }
+    pub(crate) fn inc_valid(&self) {
+        self.valid.fetch_add(1, Ordering::Relaxed);
+    }
+    pub(crate) fn inc_fail(&self, r: FailReason) {
+        self.fails[r as usize].fetch_add(1, Ordering::Relaxed);
+    }
+    pub(crate) fn inc_construct(&self) {
+        self.constructs.fetch_add(1, Ordering::Relaxed);
+    }
+    pub(crate) fn inc_merged(&self) {
+        self.merged.fetch_add(1, Ordering::Relaxed);
+    }
+    /// Add a counter of an earlier run, given by its QC name.
+    pub(crate) fn add_named(&self, name: &str, value: u64) {
+        let counter = match name {
+            "total" => &self.total,
+            "valid" => &self.valid,
+            "filtered_rows" => &self.filtered_rows,
+            "merged_pairs" => &self.merged,
+            "constructs" => &self.constructs,
+            _ => match FailReason::iter().find(|r| r.name() == name) {
+                Some(r) => &self.fails[r as usize],
+                None => return,
+            },
+        };
+        counter.fetch_add(value, Ordering::Relaxed);
+    }
+    pub(crate) fn add_filtered_rows(&self, n: u64) {
+        self.filtered_rows.fetch_add(n, Ordering::Relaxed);
+    }
+    pub(crate) fn filtered_count(&self) -> u64 {
+        self.filtered_rows.load(Ordering::Relaxed)
+    }
+    pub(crate) fn total_count(&self) -> u64 {
+        self.total.load(Ordering::Relaxed)
+    }
+    pub(crate) fn valid_count(&self) -> u64 {
+        self.valid.load(Ordering::Relaxed)
+    }
+    pub(crate) fn fail_count(&self, r: FailReason) -> u64 {
+        self.fails[r as usize].load(Ordering::Relaxed)
+    }
+
+    /// QC counter names and values, in output order.
+    pub(crate) fn qc_rows(&self) -> Vec<(&'static str, u64)> {
+        let mut rows = vec![
+            ("total", self.total.load(Ordering::Relaxed)),
+            ("valid", self.valid.load(Ordering::Relaxed)),
+        ];
+        rows.extend(FailReason::iter().map(|r| (r.name(), self.fail_count(r))));
+        rows.push(("filtered_rows", self.filtered_count()));
+        rows.push(("merged_pairs", self.merged.load(Ordering::Relaxed)));
+        rows.push(("constructs", self.constructs.load(Ordering::Relaxed)));
+
+        rows
+    }
+}
+
+// =========================================================
+// Helper functions
+// =========================================================
+
+/// Read ID without the `/1` or `/2` mate suffix.
+pub fn pair_id(id: &str) -> &str {
+    id.strip_suffix("/1")
+        .or_else(|| id.strip_suffix("/2"))
+        .unwrap_or(id)
+}
+
+/// Validates that the IDs of two FASTQ records match.
+///
+/// Comments are never part of the ID; unless `strict`, the `/1` and `/2`
+/// mate suffixes are ignored as well.
+pub(crate) fn validate_pairs(
+    rec1: &fastq::Record,
+    rec2: &fastq::Record,
+    strict: bool,
+) -> bool {
+    let id1 = rec1.id();
+    let id2 = rec2.id();
+
+    if strict {
+        id1 == id2
+    } else {
+        pair_id(id1) == pair_id(id2)
+    }
+}
+
+/// Whether the two whitelists differ, i.e. whether a barcode found in the
+/// other read's whitelist points to a layout or configuration mistake.
+pub(crate) fn whitelists_differ(
+    barcodes1: &[&str],
+    barcodes2: &[&str],
+) -> bool {
+    barcodes1.iter().any(|b| !barcodes2.contains(b))
+        || barcodes2.iter().any(|b| !barcodes1.contains(b))
+}
+
+/// Number of N base calls in a sequence.
+fn count_n(seq: &str) -> usize {
+    seq.bytes().filter(|&b| b == b'N').count()
+}
+
+/// RBS between `start` and the first anchor occurrence giving a length in
+/// the allowed range.
+pub(crate) fn find_anchored<'s>(
+    seq: &'s str,
+    start: usize,
+    anchor: &str,
+    cfg: &Config,
+) -> Option<&'s str> {
+    let (min, max) = cfg.rbs_len_range;
+    let end = (start + max + anchor.len()).min(seq.len());
+    let search = seq.get(start + min..end)?;
+
+    let len = min + search.find(anchor)?;
+    Some(&seq[start..start + len])
+}
+
+/// Add a valid read pair to the counts table.
+pub(crate) fn add_to_table(
+    table: &SampleTable,
+    sample: Sample,
+    rbs: &str,
+    flipped: Flip,
+) {
+    let inner = table.entry(sample).or_insert_with(DashMap::new);
+    let cell = inner.entry(rbs.to_owned()).or_default();
+
+    let idx = match flipped {
+        Flip::NonFlipped => 0,
+        Flip::Flipped => 1,
+    };
+
+    cell[idx].fetch_add(1, Ordering::Relaxed);
+}
+
+// =========================================================
+// Core logic
+// =========================================================
+
+pub(crate) fn classify_pair<'a>(
+    cfg: &Config<'a>,
+    rec1: &'a fastq::Record,
+    rec2: &'a fastq::Record,
+) -> Result<Result<(Sample, &'a str, Flip), FailReason>, std::str::Utf8Error> {
+    if !validate_pairs(rec1, rec2, cfg.strict_ids) {
+        return Ok(Err(FailReason::IdMismatch));
+    }
+
+    let seq1 = std::str::from_utf8(rec1.seq())?;
+    let seq2 = std::str::from_utf8(rec2.seq())?;
+
+    Ok(classify_seqs(cfg, seq1, seq2))
+}
+
+/// Classify a pair merged into a single fragment, its reverse complement
+/// standing for read 2.
+pub(crate) fn classify_merged<'s>(
+    cfg: &Config,
+    rec1: &fastq::Record,
+    rec2: &fastq::Record,
+    merged: &'s MergedRead,
+) -> Result<Result<(Sample, &'s str, Flip), FailReason>, std::str::Utf8Error> {
+    if !validate_pairs(rec1, rec2, cfg.strict_ids) {
+        return Ok(Err(FailReason::IdMismatch));
+    }
+
+    let seq1 = std::str::from_utf8(&merged.forward)?;
+    let seq2 = std::str::from_utf8(&merged.reverse)?;
+
+    Ok(classify_seqs(cfg, seq1, seq2))
+}
+
+/// Classify the sequences of read 1 and read 2.
+fn classify_seqs<'s>(
+    cfg: &Config,
+    seq1: &'s str,
+    seq2: &'s str,
+) -> Result<(Sample, &'s str, Flip), FailReason> {
+    // Slicing below assumes the reads cover the whole geometry
+    let (min1, min2) = cfg.min_read_lengths();
+    if seq1.len() < min1 || seq2.len() < min2 {
+        return Err(FailReason::ReadTooShort);
+    }
+
+    // -----------------------------------------------------
+    // 1. Fast rejection for base call
+    // -----------------------------------------------------
+    let (n1, n2) = (count_n(seq1), count_n(seq2));
+    if cfg.max_n.pair_only() && n1 + n2 > cfg.max_n.pair {
+        return Err(FailReason::BaseCalls);
+    }
+    if cfg.max_n.read1.is_some_and(|max| n1 > max) {
+        return Err(FailReason::BaseCallsRead1);
+    }
+    if cfg.max_n.read2.is_some_and(|max| n2 > max) {
+        return Err(FailReason::BaseCallsRead2);
+    }
+
+    // -----------------------------------------------------
+    // 2. Reject when missing constant region
+    // -----------------------------------------------------
+    let (win_lo, win_hi) = cfg.window;
+    let window = &seq2[win_lo - 1..win_hi];
+    let const_offset = match window.find(cfg.const_region) {
+        Some(local) => local + win_lo - 1,
+        None => return Err(FailReason::ConstantSeq),
+    };
+
+    // -----------------------------------------------------
+    // 3. Reject when constant region is too skewed
+    // -----------------------------------------------------
+    let rbs_len = match cfg.rbs_anchor {
+        Some(_) => cfg.rbs_len_range.0,
+        None => cfg.rbs_len,
+    };
+    if const_offset < cfg.barcode_len
+        || const_offset + cfg.const_region.len() + rbs_len > seq2.len()
+    {
+        return Err(FailReason::ConstantPos);
+    }
+
+    // -----------------------------------------------------
+    // 4. Extract RBS
+    // -----------------------------------------------------
+    let rbs_start = const_offset + cfg.const_region.len();
+    let rbs = match cfg.rbs_anchor {
+        Some(anchor) => match find_anchored(seq2, rbs_start, anchor, cfg) {
+            Some(rbs) => rbs,
+            None => return Err(FailReason::RbsLength),
+        },
+        None => &seq2[rbs_start..rbs_start + cfg.rbs_len],
+    };
+    if cfg.max_n.rbs.is_some_and(|max| count_n(rbs) > max) {
+        return Err(FailReason::BaseCallsRbs);
+    }
+
+    // -----------------------------------------------------
+    // 5. Extract barcode 2
+    // -----------------------------------------------------
+    let observed2 = &seq2[const_offset - cfg.barcode_len..const_offset];
+    if cfg
+        .max_n
+        .barcode
+        .is_some_and(|max| count_n(observed2) > max)
+    {
+        return Err(FailReason::BaseCallsBarcode);
+    }
+    let Some(barcode2) = cfg.match_barcode2(observed2) else {
+        if cfg.cross_check && cfg.is_barcode1(observed2) {
+            return Err(FailReason::Barcode2Cross);
+        }
+        return Err(FailReason::Barcode2);
+    };
+
+    // -----------------------------------------------------
+    // 6. Extract discriminator
+    // -----------------------------------------------------
+    let (disc_pos, flipped) =
+        match (seq1.find(cfg.non_flipped), seq1.find(cfg.flipped)) {
+            (Some(p), _) => (p, Flip::NonFlipped),
+            (None, Some(p)) => (p, Flip::Flipped),
+            _ => return Err(FailReason::DiscSeq),
+        };
+    if disc_pos < cfg.disc_offset + cfg.barcode_len {
+        return Err(FailReason::DiscPos);
+    }
+
+    // -----------------------------------------------------
+    // 6. Extract barcode 1
+    // -----------------------------------------------------
+    let barcode1_start = disc_pos - cfg.disc_offset - cfg.barcode_len;
+    let observed1 = &seq1[barcode1_start..barcode1_start + cfg.barcode_len];
+    if cfg
+        .max_n
+        .barcode
+        .is_some_and(|max| count_n(observed1) > max)
+    {
+        return Err(FailReason::BaseCallsBarcode);
+    }
+    let Some(barcode1) = cfg.match_barcode1(observed1) else {
+        if cfg.cross_check && cfg.is_barcode2(observed1) {
+            return Err(FailReason::Barcode1Cross);
+        }
+        return Err(FailReason::Barcode1);
+    };
+
+    // -----------------------------------------------------
+    // 7. End
+    // -----------------------------------------------------
+    cfg.record_corrections((observed1, barcode1), (observed2, barcode2));
+
+    Ok((
+        Sample {
+            barcode1: barcode1.to_owned(),
+            barcode2: barcode2.to_owned(),
+        },
+        rbs,
+        flipped,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(id: &str) -> fastq::Record {
+        fastq::Record::with_attrs(id, None, b"ACGT", b"IIII")
+    }
+
+    #[test]
+    fn mate_suffixes_are_stripped() {
+        assert_eq!(pair_id("read7/1"), "read7");
+        assert_eq!(pair_id("read7/2"), "read7");
+        assert_eq!(pair_id("read7"), "read7");
+    }
+
+    #[test]
+    fn other_suffixes_are_kept() {
+        assert_eq!(pair_id("read7/3"), "read7/3");
+        assert_eq!(pair_id("read7/1/2"), "read7/1");
+        assert_eq!(pair_id("/1"), "");
+    }
+
+    #[test]
+    fn pairs_match_on_their_ids() {
+        let (rec1, rec2) = (record("read7/1"), record("read7/2"));
+        assert!(validate_pairs(&rec1, &rec2, false));
+        assert!(!validate_pairs(&rec1, &rec2, true));
+        assert!(!validate_pairs(&rec1, &record("read8/2"), false));
+    }
+}
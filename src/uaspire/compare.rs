@@ -0,0 +1,265 @@
+/// Replicate comparison of the counts of two or more runs.
+use polars::prelude::*;
+use serde::Serialize;
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::uaspire::matrix::load_counts;
+
+// Side of the square scatter plots, in pixels
+const PLOT_SIZE: f64 = 400.0;
+
+type Key = (String, String, String);
+
+// ---------- Run counts ----------
+
+/// Total reads and flipped reads of each (barcode1, barcode2, gre) row.
+struct RunCounts {
+    label: String,
+    rows: HashMap<Key, (u64, u64)>,
+}
+
+// ---------- Report ----------
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PairComparison {
+    pub run_a: String,
+    pub run_b: String,
+    pub shared: usize,
+    pub only_a: usize,
+    pub only_b: usize,
+    pub pearson_log_counts: f64,
+    pub spearman_counts: f64,
+    pub pearson_flip_fraction: f64,
+    pub mean_abs_log2_ratio: f64,
+}
+
+// =========================================================
+// Statistics
+// =========================================================
+
+fn pearson(x: &[f64], y: &[f64]) -> f64 {
+    let n = x.len() as f64;
+    if x.len() < 2 {
+        return f64::NAN;
+    }
+
+    let mean_x = x.iter().sum::<f64>() / n;
+    let mean_y = y.iter().sum::<f64>() / n;
+
+    let (mut cov, mut var_x, mut var_y) = (0.0, 0.0, 0.0);
+    for (a, b) in x.iter().zip(y) {
+        cov += (a - mean_x) * (b - mean_y);
+        var_x += (a - mean_x).powi(2);
+        var_y += (b - mean_y).powi(2);
+    }
+
+    cov / (var_x.sqrt() * var_y.sqrt())
+}
+
+/// Ranks starting at 1, ties sharing their average rank.
+fn ranks(values: &[f64]) -> Vec<f64> {
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by(|&a, &b| values[a].total_cmp(&values[b]));
+
+    let mut ranks = vec![0.0; values.len()];
+    let mut i = 0;
+    while i < order.len() {
+        let mut j = i;
+        while j + 1 < order.len() && values[order[j + 1]] == values[order[i]] {
+            j += 1;
+        }
+
+        let rank = (i + j) as f64 / 2.0 + 1.0;
+        for &k in &order[i..=j] {
+            ranks[k] = rank;
+        }
+        i = j + 1;
+    }
+
+    ranks
+}
+
+fn spearman(x: &[f64], y: &[f64]) -> f64 {
+    pearson(&ranks(x), &ranks(y))
+}
+
+// =========================================================
+// Helper functions
+// =========================================================
+
+fn load_run(dir: &Path) -> PolarsResult<RunCounts> {
+    let df = load_counts(&[dir.to_path_buf()])?;
+
+    let barcode1 = df.column("barcode1")?.str()?.clone();
+    let barcode2 = df.column("barcode2")?.str()?.clone();
+    let gre = df.column("gre")?.str()?.clone();
+    let unflipped = df.column("unflipped")?.cast(&DataType::UInt64)?;
+    let flipped = df.column("flipped")?.cast(&DataType::UInt64)?;
+    let (unflipped, flipped) = (unflipped.u64()?, flipped.u64()?);
+
+    let mut rows: HashMap<Key, (u64, u64)> = HashMap::new();
+    for i in 0..df.height() {
+        let key = (
+            barcode1.get(i).unwrap_or_default().to_string(),
+            barcode2.get(i).unwrap_or_default().to_string(),
+            gre.get(i).unwrap_or_default().to_string(),
+        );
+        let u = unflipped.get(i).unwrap_or(0);
+        let f = flipped.get(i).unwrap_or(0);
+
+        let entry = rows.entry(key).or_default();
+        entry.0 += u + f;
+        entry.1 += f;
+    }
+
+    let label = dir
+        .file_name()
+        .map_or(dir.display().to_string(), |n| n.to_string_lossy().into());
+
+    Ok(RunCounts { label, rows })
+}
+
+/// Shared rows as (log1p counts a, log1p counts b, counts a, counts b,
+/// flipped fraction a, flipped fraction b).
+fn shared_rows(a: &RunCounts, b: &RunCounts) -> Vec<[f64; 6]> {
+    a.rows
+        .iter()
+        .filter_map(|(key, &(total_a, flipped_a))| {
+            let &(total_b, flipped_b) = b.rows.get(key)?;
+            let (ta, tb) = (total_a as f64, total_b as f64);
+
+            Some([
+                ta.ln_1p(),
+                tb.ln_1p(),
+                ta,
+                tb,
+                flipped_a as f64 / ta.max(1.0),
+                flipped_b as f64 / tb.max(1.0),
+            ])
+        })
+        .collect()
+}
+
+fn compare_pair(a: &RunCounts, b: &RunCounts) -> PairComparison {
+    let rows = shared_rows(a, b);
+    let column = |i: usize| rows.iter().map(|r| r[i]).collect::<Vec<f64>>();
+
+    let mean_abs_log2_ratio = if rows.is_empty() {
+        f64::NAN
+    } else {
+        rows.iter()
+            .map(|r| ((r[2] + 1.0) / (r[3] + 1.0)).log2().abs())
+            .sum::<f64>()
+            / rows.len() as f64
+    };
+
+    PairComparison {
+        run_a: a.label.clone(),
+        run_b: b.label.clone(),
+        shared: rows.len(),
+        only_a: a.rows.len() - rows.len(),
+        only_b: b.rows.len() - rows.len(),
+        pearson_log_counts: pearson(&column(0), &column(1)),
+        spearman_counts: spearman(&column(2), &column(3)),
+        pearson_flip_fraction: pearson(&column(4), &column(5)),
+        mean_abs_log2_ratio,
+    }
+}
+
+/// Scatter plot of the log counts of two runs as a standalone SVG.
+fn write_scatter_svg(
+    a: &RunCounts,
+    b: &RunCounts,
+    path: &Path,
+) -> io::Result<()> {
+    let rows = shared_rows(a, b);
+    let max = rows
+        .iter()
+        .flat_map(|r| [r[0], r[1]])
+        .fold(1.0_f64, f64::max);
+    let scale = |v: f64| v / max * (PLOT_SIZE - 40.0) + 20.0;
+
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(
+        out,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{0}" height="{0}">"#,
+        PLOT_SIZE
+    )?;
+    writeln!(
+        out,
+        r#"<line x1="20" y1="{0}" x2="{0}" y2="20" stroke="grey"/>"#,
+        PLOT_SIZE - 20.0
+    )?;
+
+    for r in &rows {
+        writeln!(
+            out,
+            r#"<circle cx="{:.1}" cy="{:.1}" r="1" fill-opacity="0.3"/>"#,
+            scale(r[0]),
+            PLOT_SIZE - scale(r[1])
+        )?;
+    }
+
+    writeln!(
+        out,
+        r#"<text x="20" y="14">{} vs {} (log1p counts)</text>"#,
+        a.label, b.label
+    )?;
+    writeln!(out, "</svg>")?;
+
+    out.flush()
+}
+
+// =========================================================
+// Comparison
+// =========================================================
+
+/// Compare every pair of runs, writing the table as TSV and, when
+/// `plots` is given, one SVG scatter plot per pair.
+pub fn compare_runs(
+    dirs: &[PathBuf],
+    output: &Path,
+    plots: Option<&Path>,
+) -> Result<Vec<PairComparison>, Box<dyn Error>> {
+    if dirs.len() < 2 {
+        return Err("at least two runs are needed".into());
+    }
+
+    let runs = dirs
+        .iter()
+        .map(|dir| load_run(dir))
+        .collect::<PolarsResult<Vec<_>>>()?;
+
+    if let Some(dir) = plots {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut comparisons = Vec::new();
+    for (i, a) in runs.iter().enumerate() {
+        for b in &runs[i + 1..] {
+            comparisons.push(compare_pair(a, b));
+
+            if let Some(dir) = plots {
+                let name = format!("{}_vs_{}.svg", a.label, b.label);
+                write_scatter_svg(a, b, &dir.join(name))?;
+            }
+        }
+    }
+
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(b'\t')
+        .from_path(output)?;
+    for comparison in &comparisons {
+        writer.serialize(comparison)?;
+    }
+    writer.flush()?;
+
+    Ok(comparisons)
+}
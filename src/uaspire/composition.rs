@@ -0,0 +1,77 @@
+/// Sequence composition of RBSs, for cloning feasibility filters: GC
+/// content, homopolymer runs and restriction sites.
+use std::str::FromStr;
+
+use crate::uaspire::overlap::reverse_complement;
+
+// ---------- Restriction sites ----------
+
+/// A named recognition site, given as `NAME=SEQUENCE` or just `SEQUENCE`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RestrictionSite {
+    pub name: String,
+    pub site: String,
+    reverse: String,
+}
+
+impl RestrictionSite {
+    /// Whether the site occurs on either strand of `seq`.
+    pub fn occurs_in(&self, seq: &str) -> bool {
+        seq.contains(&self.site) || seq.contains(&self.reverse)
+    }
+}
+
+impl FromStr for RestrictionSite {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, site) = match s.split_once('=') {
+            Some((name, site)) => (name.trim(), site.trim()),
+            None => (s.trim(), s.trim()),
+        };
+
+        let site = site.to_ascii_uppercase();
+        if site.is_empty() || !site.bytes().all(|b| b"ACGT".contains(&b)) {
+            return Err(format!("restriction site {s} is not ACGT only"));
+        }
+
+        Ok(RestrictionSite {
+            name: name.to_string(),
+            reverse: String::from_utf8(reverse_complement(site.as_bytes()))
+                .expect("Reverse complement is ASCII"),
+            site,
+        })
+    }
+}
+
+// =========================================================
+// Composition
+// =========================================================
+
+/// Percentage of G and C bases.
+pub fn gc_content(seq: &str) -> f64 {
+    if seq.is_empty() {
+        return 0.0;
+    }
+
+    let gc = seq.bytes().filter(|b| matches!(b, b'G' | b'C')).count();
+    100.0 * gc as f64 / seq.len() as f64
+}
+
+/// Length of the longest run of a single base.
+pub fn longest_homopolymer(seq: &str) -> usize {
+    let bytes = seq.as_bytes();
+    let mut longest = 0;
+    let mut run = 0;
+
+    for (i, &b) in bytes.iter().enumerate() {
+        run = if i > 0 && bytes[i - 1] == b {
+            run + 1
+        } else {
+            1
+        };
+        longest = longest.max(run);
+    }
+
+    longest
+}
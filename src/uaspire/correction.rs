@@ -0,0 +1,175 @@
+/// Mismatch-tolerant barcode matching and the report of its corrections.
+///
+/// An observed barcode missing from a whitelist is corrected to the single
+/// closest whitelist barcode within the allowed Hamming distance; ties are
+/// left uncorrected. The report counts reads per observed variant and
+/// whitelist barcode, so that corrections pulling reads from another
+/// sample's barcode (cross-talk) show up.
+use dashmap::DashMap;
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, Write},
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+// =========================================================
+// Matching
+// =========================================================
+
+/// Mismatches between two equally long sequences, Ns always mismatching.
+fn hamming(a: &str, b: &str) -> usize {
+    a.bytes()
+        .zip(b.bytes())
+        .filter(|&(x, y)| x != y || x == b'N')
+        .count()
+}
+
+/// Whitelist barcode an observed barcode stands for, if any.
+pub(crate) fn nearest_barcode<'a>(
+    lookup: &HashSet<&'a str>,
+    whitelist: &[&'a str],
+    observed: &str,
+    max_mismatches: usize,
+) -> Option<&'a str> {
+    if let Some(&barcode) = lookup.get(observed) {
+        return Some(barcode);
+    }
+    if max_mismatches == 0 {
+        return None;
+    }
+
+    let mut best: Option<(&'a str, usize)> = None;
+    let mut tied = false;
+    for &barcode in whitelist {
+        if barcode.len() != observed.len() {
+            continue;
+        }
+
+        let distance = hamming(observed, barcode);
+        if distance > max_mismatches {
+            continue;
+        }
+        match best {
+            Some((_, d)) if distance == d => tied = true,
+            Some((_, d)) if distance > d => {}
+            _ => {
+                best = Some((barcode, distance));
+                tied = false;
+            }
+        }
+    }
+
+    if tied {
+        return None;
+    }
+    best.map(|(barcode, _)| barcode)
+}
+
+// =========================================================
+// Report
+// =========================================================
+
+// (read, observed, corrected)
+type Correction = (u8, String, String);
+
+/// Reads corrected from each observed barcode to each whitelist barcode.
+#[derive(Debug, Default)]
+pub struct BarcodeCorrections {
+    counts: DashMap<Correction, AtomicU64>,
+}
+
+impl BarcodeCorrections {
+    /// Tally a read whose barcode of read `read` was corrected.
+    pub(crate) fn record(&self, read: u8, observed: &str, corrected: &str) {
+        let key = (read, observed.to_owned(), corrected.to_owned());
+        self.counts
+            .entry(key)
+            .or_default()
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Corrected barcodes, a read counting once per corrected barcode.
+    pub fn total(&self) -> u64 {
+        self.counts
+            .iter()
+            .map(|entry| entry.value().load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Corrections as TSV, most frequent first.
+    pub fn write_tsv(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut rows: Vec<(Correction, u64)> = self
+            .counts
+            .iter()
+            .map(|entry| {
+                (entry.key().clone(), entry.value().load(Ordering::Relaxed))
+            })
+            .collect();
+        rows.sort_by(|(a, n), (b, m)| m.cmp(n).then_with(|| a.cmp(b)));
+
+        let mut out = io::BufWriter::new(fs::File::create(path)?);
+        writeln!(out, "read\tobserved\tcorrected\tmismatches\treads")?;
+
+        for ((read, observed, corrected), reads) in rows {
+            writeln!(
+                out,
+                "barcode{}\t{}\t{}\t{}\t{}",
+                read,
+                observed,
+                corrected,
+                hamming(&observed, &corrected),
+                reads
+            )?;
+        }
+
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITELIST: [&str; 3] = ["ACGTAC", "ACGTTT", "GGGGGG"];
+
+    fn nearest(observed: &str, max_mismatches: usize) -> Option<&'static str> {
+        let lookup: HashSet<&str> = WHITELIST.into_iter().collect();
+        nearest_barcode(&lookup, &WHITELIST, observed, max_mismatches)
+    }
+
+    #[test]
+    fn exact_match_without_mismatches() {
+        assert_eq!(nearest("GGGGGG", 0), Some("GGGGGG"));
+        assert_eq!(nearest("GGGGGA", 0), None);
+    }
+
+    #[test]
+    fn closest_barcode_within_distance() {
+        assert_eq!(nearest("GGGGGA", 1), Some("GGGGGG"));
+        assert_eq!(nearest("GGGAAA", 2), None);
+        assert_eq!(nearest("GGGAAA", 3), Some("GGGGGG"));
+    }
+
+    #[test]
+    fn ties_are_left_uncorrected() {
+        // One mismatch away from both ACGTAC and ACGTTT
+        assert_eq!(nearest("ACGTAT", 1), None);
+        assert_eq!(nearest("ACGTAT", 2), None);
+    }
+
+    #[test]
+    fn other_lengths_are_skipped() {
+        assert_eq!(nearest("GGGGG", 1), None);
+        assert_eq!(nearest("GGGGGGG", 1), None);
+    }
+
+    #[test]
+    fn n_is_always_a_mismatch() {
+        assert_eq!(hamming("GGGNGG", "GGGGGG"), 1);
+        assert_eq!(hamming("GGGNGG", "GGGNGG"), 1);
+        assert_eq!(nearest("GGGNGG", 1), Some("GGGGGG"));
+        assert_eq!(nearest("GGNNGG", 1), None);
+    }
+}
@@ -0,0 +1,103 @@
+/// Count-only fast path writing a single gzipped TSV, without Polars.
+use dashmap::DashMap;
+use flate2::{write::GzEncoder, Compression};
+use rayon::prelude::*;
+use tracing::info;
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::atomic::Ordering,
+};
+
+use crate::uaspire::classify::{
+    add_to_table, classify_pair, Config, Counters, FailReason, SampleTable,
+};
+use crate::uaspire::reader::ChunkReader;
+
+/// Write the counts table as a gzipped TSV sorted by barcode pair and RBS.
+fn write_counts_tsv(table: &SampleTable, path: &Path) -> io::Result<()> {
+    let mut rows: Vec<(String, String, String, u64, u64)> = table
+        .iter()
+        .flat_map(|sample_map| {
+            let sample = sample_map.key().clone();
+
+            sample_map
+                .value()
+                .iter()
+                .map(|rbs_counts| {
+                    let counts = rbs_counts.value();
+                    (
+                        sample.barcode1.clone(),
+                        sample.barcode2.clone(),
+                        rbs_counts.key().clone(),
+                        counts[0].load(Ordering::Relaxed),
+                        counts[1].load(Ordering::Relaxed),
+                    )
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    rows.sort();
+
+    let file = File::create(path)?;
+    let mut writer =
+        BufWriter::new(GzEncoder::new(file, Compression::default()));
+
+    writeln!(writer, "barcode1\tbarcode2\tgre\tunflipped\tflipped")?;
+    for (bc1, bc2, rbs, unflipped, flipped) in rows {
+        writeln!(writer, "{bc1}\t{bc2}\t{rbs}\t{unflipped}\t{flipped}")?;
+    }
+
+    writer.into_inner().map_err(|e| e.into_error())?.finish()?;
+    Ok(())
+}
+
+/// Classify a FASTQ pair and write the counts to a gzipped TSV.
+pub fn count_fastq(
+    path1: impl AsRef<Path>,
+    path2: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    chunk_size: usize,
+) -> io::Result<()> {
+    let cfg = Config::from_constants();
+
+    let (reader1, reader2) = ChunkReader::spawn_pair(path1, path2, chunk_size)?;
+
+    let counters = Counters::default();
+    let table: SampleTable = DashMap::new();
+
+    for (chunk1, chunk2) in reader1.zip(reader2) {
+        chunk1
+            .par_iter()
+            .zip(chunk2.par_iter())
+            .for_each(|(rec1, rec2)| {
+                counters.inc_total();
+
+                let (Ok(rec1), Ok(rec2)) = (rec1, rec2) else {
+                    counters.inc_fail(FailReason::MalformedRecord);
+                    return;
+                };
+
+                match classify_pair(&cfg, rec1, rec2) {
+                    Ok(Ok((sample, rbs, flipped))) => {
+                        counters.inc_valid();
+                        add_to_table(&table, sample, rbs, flipped);
+                    }
+                    Ok(Err(reason)) => counters.inc_fail(reason),
+                    Err(_) => counters.inc_fail(FailReason::MalformedRecord),
+                }
+            });
+    }
+
+    for (name, value) in counters.qc_rows() {
+        info!("{}: {}", name, value);
+    }
+
+    write_counts_tsv(&table, output.as_ref())?;
+    info!("Wrote {}", output.as_ref().display());
+
+    Ok(())
+}
@@ -0,0 +1,223 @@
+/// CRISPR screen guide counting on the shared counting pipeline.
+///
+/// Sample barcodes are read at fixed offsets from the start of both reads
+/// and looked up in the barcode whitelists. The guide follows an anchor in
+/// read 1 (the end of the U6 promoter by default) and is matched against
+/// the guide library, exactly or within one mismatch. Guides are counted
+/// under their library name, always non-flipped, so the counts have the
+/// same layout as uASPIre counts.
+use bio::io::fastq;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::uaspire::assay::{inline_barcodes, Classification, ReadClassifier};
+use crate::uaspire::classify::{
+    validate_pairs, Config, FailReason, Flip, Sample,
+};
+use crate::uaspire::spikein::{parse_csv, parse_fasta};
+
+// ---------- Options ----------
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GuideOptions {
+    // FASTA or `name,sequence` CSV of the guides
+    pub library: PathBuf,
+    // Sequence right before the guide in read 1
+    pub anchor: String,
+    // 0 for exact matching, 1 to allow a single substitution
+    pub max_mismatches: usize,
+    // Barcode starts in read 1 and read 2
+    pub barcode_offsets: (usize, usize),
+}
+
+impl Default for GuideOptions {
+    fn default() -> Self {
+        GuideOptions {
+            library: PathBuf::new(),
+            anchor: "CACCG".to_string(),
+            max_mismatches: 0,
+            barcode_offsets: (0, 0),
+        }
+    }
+}
+
+// ---------- Guide library ----------
+
+pub struct GuideLibrary {
+    names: Vec<String>,
+    index: HashMap<String, usize>,
+    guide_len: usize,
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl GuideLibrary {
+    /// Read guides from a FASTA file, or a CSV with `name,sequence`
+    /// columns. All guides must have the same length.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+
+        let pairs = if text.trim_start().starts_with('>') {
+            parse_fasta(&text)
+        } else {
+            parse_csv(&text)?
+        };
+
+        let mut library = GuideLibrary {
+            names: Vec::new(),
+            index: HashMap::new(),
+            guide_len: 0,
+        };
+
+        for (name, sequence) in pairs {
+            let sequence = sequence.to_ascii_uppercase();
+            if !sequence.bytes().all(|b| b"ACGT".contains(&b)) {
+                return Err(invalid(format!("guide {name} is not ACGT only")));
+            }
+            if library.names.is_empty() {
+                library.guide_len = sequence.len();
+            } else if sequence.len() != library.guide_len {
+                return Err(invalid(format!(
+                    "guide {name} is not {} bases long",
+                    library.guide_len
+                )));
+            }
+            if library.index.contains_key(&sequence) {
+                return Err(invalid(format!(
+                    "duplicated guide sequence {sequence}"
+                )));
+            }
+
+            library.index.insert(sequence, library.names.len());
+            library.names.push(name);
+        }
+
+        if library.guide_len == 0 {
+            return Err(invalid("empty guide library".to_string()));
+        }
+
+        Ok(library)
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Library guide an observed guide stands for.
+    ///
+    /// With one mismatch allowed, every single substitution of the observed
+    /// guide is looked up; a guide reached from two library guides is
+    /// ambiguous and left unassigned.
+    fn lookup(&self, observed: &str, max_mismatches: usize) -> Option<usize> {
+        if let Some(&i) = self.index.get(observed) {
+            return Some(i);
+        }
+        if max_mismatches == 0 {
+            return None;
+        }
+
+        let mut found: Option<usize> = None;
+        let mut variant = observed.as_bytes().to_vec();
+        for pos in 0..variant.len() {
+            let base = variant[pos];
+            for &alt in b"ACGT".iter().filter(|&&b| b != base) {
+                variant[pos] = alt;
+                let hit = std::str::from_utf8(&variant)
+                    .ok()
+                    .and_then(|v| self.index.get(v));
+
+                match (found, hit) {
+                    (Some(f), Some(&i)) if f != i => return None,
+                    (None, Some(&i)) => found = Some(i),
+                    _ => {}
+                }
+            }
+            variant[pos] = base;
+        }
+
+        found
+    }
+}
+
+// =========================================================
+// Classifier
+// =========================================================
+
+pub(crate) struct GuideClassifier<'c> {
+    cfg: &'c Config<'c>,
+    library: &'c GuideLibrary,
+    opts: &'c GuideOptions,
+}
+
+impl<'c> GuideClassifier<'c> {
+    pub(crate) fn new(
+        cfg: &'c Config<'c>,
+        library: &'c GuideLibrary,
+        opts: &'c GuideOptions,
+    ) -> Self {
+        GuideClassifier { cfg, library, opts }
+    }
+}
+
+impl ReadClassifier for GuideClassifier<'_> {
+    fn classify_pair<'s>(
+        &'s self,
+        rec1: &'s fastq::Record,
+        rec2: &'s fastq::Record,
+    ) -> Classification<'s> {
+        if !validate_pairs(rec1, rec2, self.cfg.strict_ids) {
+            return Ok(Err(FailReason::IdMismatch));
+        }
+
+        let seq1 = std::str::from_utf8(rec1.seq())?;
+        let seq2 = std::str::from_utf8(rec2.seq())?;
+
+        // -----------------------------------------------------
+        // 1. Extract sample barcodes
+        // -----------------------------------------------------
+        let offsets = self.opts.barcode_offsets;
+        let [bc1, bc2] = match inline_barcodes(self.cfg, seq1, seq2, offsets) {
+            Ok(barcodes) => barcodes,
+            Err(reason) => return Ok(Err(reason)),
+        };
+
+        // -----------------------------------------------------
+        // 2. Extract guide after the anchor
+        // -----------------------------------------------------
+        let after_barcode = offsets.0 + self.cfg.barcode_len;
+        let anchor = self.opts.anchor.as_str();
+        let start = match seq1[after_barcode..].find(anchor) {
+            Some(pos) => after_barcode + pos + anchor.len(),
+            None => return Ok(Err(FailReason::Guide)),
+        };
+        let guide = match seq1.get(start..start + self.library.guide_len) {
+            Some(guide) => guide,
+            None => return Ok(Err(FailReason::ReadTooShort)),
+        };
+        let Some(i) = self.library.lookup(guide, self.opts.max_mismatches)
+        else {
+            return Ok(Err(FailReason::Guide));
+        };
+
+        self.cfg.record_corrections(bc1, bc2);
+
+        Ok(Ok((
+            Sample {
+                barcode1: bc1.1.to_owned(),
+                barcode2: bc2.1.to_owned(),
+            },
+            self.library.names[i].as_str(),
+            Flip::NonFlipped,
+        )))
+    }
+}
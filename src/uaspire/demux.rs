@@ -0,0 +1,96 @@
+/// Gzipped FASTQ pair writers keyed by name, shared across rayon workers.
+use bio::io::fastq;
+use dashmap::DashMap;
+use flate2::{write::GzEncoder, Compression};
+
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+// Owned down to the encoder, whose trailer is written by `finish` so that
+// its errors aren't lost in a drop
+type GzWriter = BufWriter<GzEncoder<File>>;
+
+fn write_record(writer: &mut GzWriter, rec: &fastq::Record) -> io::Result<()> {
+    writer.write_all(b"@")?;
+    writer.write_all(rec.id().as_bytes())?;
+    if let Some(desc) = rec.desc() {
+        writer.write_all(b" ")?;
+        writer.write_all(desc.as_bytes())?;
+    }
+    writer.write_all(b"\n")?;
+    writer.write_all(rec.seq())?;
+    writer.write_all(b"\n+\n")?;
+    writer.write_all(rec.qual())?;
+    writer.write_all(b"\n")
+}
+
+fn finish_writer(writer: GzWriter) -> io::Result<()> {
+    let encoder = writer.into_inner().map_err(|e| e.into_error())?;
+    encoder.finish()?;
+    Ok(())
+}
+
+pub(crate) struct FastqPairWriters {
+    dir: PathBuf,
+    writers: DashMap<String, Mutex<(GzWriter, GzWriter)>>,
+}
+
+impl FastqPairWriters {
+    pub(crate) fn new(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        Ok(FastqPairWriters {
+            dir,
+            writers: DashMap::new(),
+        })
+    }
+
+    fn open(&self, name: &str) -> io::Result<Mutex<(GzWriter, GzWriter)>> {
+        let open = |read: &str| -> io::Result<GzWriter> {
+            let path = self.dir.join(format!("{name}_{read}.fastq.gz"));
+            let file = File::create(path)?;
+            Ok(BufWriter::new(GzEncoder::new(file, Compression::default())))
+        };
+
+        Ok(Mutex::new((open("R1")?, open("R2")?)))
+    }
+
+    /// Append a read pair to the `<name>_R1/R2.fastq.gz` files.
+    pub(crate) fn write(
+        &self,
+        name: &str,
+        rec1: &fastq::Record,
+        rec2: &fastq::Record,
+    ) -> io::Result<()> {
+        let pair = match self.writers.get(name) {
+            Some(pair) => pair,
+            None => self
+                .writers
+                .entry(name.to_string())
+                .or_try_insert_with(|| self.open(name))?
+                .downgrade(),
+        };
+
+        let mut pair = pair.lock().unwrap();
+        write_record(&mut pair.0, rec1)?;
+        write_record(&mut pair.1, rec2)?;
+
+        Ok(())
+    }
+
+    /// Flush all files and write their gzip trailers.
+    pub(crate) fn finish(self) -> io::Result<()> {
+        for (_, pair) in self.writers {
+            let (writer1, writer2) = pair.into_inner().unwrap();
+            finish_writer(writer1)?;
+            finish_writer(writer2)?;
+        }
+
+        Ok(())
+    }
+}
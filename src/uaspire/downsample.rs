@@ -0,0 +1,210 @@
+/// Per barcode pair downsampling of the counts to a common depth.
+///
+/// Samples multiplexed at unequal depths are compared on equal footing by
+/// keeping, for every barcode pair above the target depth, a uniform random
+/// subset of its reads, drawn without replacement as a reservoir would.
+/// The random stream of a pair is seeded by its barcodes and its rows are
+/// visited by RBS, so the result depends neither on how the counts were
+/// partitioned nor on the order they were merged in.
+use polars::prelude::*;
+use std::collections::HashMap;
+
+use crate::uaspire::hashing::{mix, stable_hash};
+
+// ---------- Options ----------
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DownsampleOptions {
+    // Reads kept per barcode pair, flipped and unflipped together
+    pub depth: u64,
+    pub seed: u64,
+}
+
+impl Default for DownsampleOptions {
+    fn default() -> Self {
+        DownsampleOptions {
+            depth: 100_000,
+            seed: 0,
+        }
+    }
+}
+
+// ---------- Random numbers ----------
+
+/// SplitMix64, small and good enough to pick reads.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        mix(self.0)
+    }
+
+    /// Uniform value below `n`.
+    fn below(&mut self, n: u64) -> u64 {
+        ((self.next() as u128 * n as u128) >> 64) as u64
+    }
+}
+
+fn pair_rng(seed: u64, barcode1: &str, barcode2: &str) -> SplitMix64 {
+    SplitMix64(stable_hash(&[
+        &seed.to_le_bytes(),
+        barcode1.as_bytes(),
+        barcode2.as_bytes(),
+    ]))
+}
+
+// =========================================================
+// Downsampling
+// =========================================================
+
+/// Reads kept out of `counts`, `depth` at most, by selection sampling: each
+/// read is kept with the probability of still being needed.
+fn select(counts: &[u64], depth: u64, rng: &mut SplitMix64) -> Vec<u64> {
+    let mut remaining: u64 = counts.iter().sum();
+    let mut needed = depth.min(remaining);
+
+    counts
+        .iter()
+        .map(|&count| {
+            let mut kept = 0;
+            for _ in 0..count {
+                if needed == 0 {
+                    break;
+                }
+                if rng.below(remaining) < needed {
+                    kept += 1;
+                    needed -= 1;
+                }
+                remaining -= 1;
+            }
+            kept
+        })
+        .collect()
+}
+
+/// Downsample every barcode pair of the counts to `opts.depth` reads.
+///
+/// Returns the counts without the rows left empty, and one row per barcode
+/// pair with its depth before and after.
+pub fn downsample_counts(
+    df: DataFrame,
+    opts: &DownsampleOptions,
+) -> PolarsResult<(DataFrame, DataFrame)> {
+    let barcode1 = df.column("barcode1")?.str()?;
+    let barcode2 = df.column("barcode2")?.str()?;
+    let gre = df.column("gre")?.str()?;
+    let unflipped = df.column("unflipped")?.u64()?;
+    let flipped = df.column("flipped")?.u64()?;
+
+    // Rows of every barcode pair, in order of first appearance
+    let mut pairs: Vec<(&str, &str)> = Vec::new();
+    let mut rows: HashMap<(&str, &str), Vec<usize>> = HashMap::new();
+    for (i, pair) in barcode1.into_iter().zip(barcode2).enumerate() {
+        let pair = (pair.0.unwrap_or(""), pair.1.unwrap_or(""));
+        rows.entry(pair)
+            .or_insert_with(|| {
+                pairs.push(pair);
+                Vec::new()
+            })
+            .push(i);
+    }
+    // Rows come in merge order, which varies from run to run
+    for indices in rows.values_mut() {
+        indices.sort_by_key(|&i| gre.get(i));
+    }
+
+    let mut new_unflipped = vec![0u64; df.height()];
+    let mut new_flipped = vec![0u64; df.height()];
+    let mut depths: [Vec<u64>; 2] = Default::default();
+
+    for pair in &pairs {
+        // Unflipped and flipped reads of each row, side by side
+        let cells: Vec<u64> = rows[pair]
+            .iter()
+            .flat_map(|&i| {
+                [unflipped.get(i).unwrap_or(0), flipped.get(i).unwrap_or(0)]
+            })
+            .collect();
+
+        let mut rng = pair_rng(opts.seed, pair.0, pair.1);
+        let kept = select(&cells, opts.depth, &mut rng);
+
+        for (&i, kept) in rows[pair].iter().zip(kept.chunks(2)) {
+            new_unflipped[i] = kept[0];
+            new_flipped[i] = kept[1];
+        }
+        depths[0].push(cells.iter().sum());
+        depths[1].push(kept.iter().sum());
+    }
+
+    let keep: BooleanChunked = new_unflipped
+        .iter()
+        .zip(&new_flipped)
+        .map(|(u, f)| u + f > 0)
+        .collect();
+
+    let mut counts = df.clone();
+    counts.with_column(Series::new("unflipped".into(), new_unflipped))?;
+    counts.with_column(Series::new("flipped".into(), new_flipped))?;
+    let counts = counts.filter(&keep)?;
+
+    let [before, after] = depths;
+    let depths = DataFrame::new(vec![
+        Series::new(
+            "barcode1".into(),
+            pairs.iter().map(|p| p.0).collect::<Vec<_>>(),
+        )
+        .into(),
+        Series::new(
+            "barcode2".into(),
+            pairs.iter().map(|p| p.1).collect::<Vec<_>>(),
+        )
+        .into(),
+        Series::new("reads_before".into(), before).into(),
+        Series::new("reads_after".into(), after).into(),
+    ])?;
+
+    Ok((counts, depths))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn exactly_depth_reads_kept() {
+        let counts = [5, 0, 12, 3, 40, 1];
+        for depth in [0, 1, 10, 60] {
+            let mut rng = pair_rng(0, "ACGT", "TTTT");
+            let kept = select(&counts, depth, &mut rng);
+            assert_eq!(kept.iter().sum::<u64>(), depth, "{depth}");
+            assert!(kept.iter().zip(&counts).all(|(k, c)| k <= c));
+        }
+    }
+
+    #[test]
+    fn shallow_pairs_are_left_whole() {
+        let counts = [5, 0, 12, 3];
+        let mut rng = pair_rng(0, "ACGT", "TTTT");
+        assert_eq!(select(&counts, 20, &mut rng), counts);
+        assert_eq!(select(&counts, 100, &mut rng), counts);
+    }
+
+    #[test]
+    fn same_pair_and_seed_same_reads() {
+        let counts = [50, 20, 0, 31, 7];
+        let draw = |seed, barcode1| {
+            select(&counts, 40, &mut pair_rng(seed, barcode1, "TTTT"))
+        };
+        assert_eq!(draw(0, "ACGT"), draw(0, "ACGT"));
+
+        let seeds: HashSet<Vec<u64>> =
+            (0..8).map(|seed| draw(seed, "ACGT")).collect();
+        assert!(seeds.len() > 1);
+        let pairs: HashSet<Vec<u64>> =
+            ["ACGT", "ACGA", "ACCT", "AGGT"].map(|b| draw(0, b)).into();
+        assert!(pairs.len() > 1);
+    }
+}
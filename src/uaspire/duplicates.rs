@@ -0,0 +1,95 @@
+/// PCR duplicate rate estimated on a subsample of the valid read pairs.
+///
+/// Duplicates of one molecule share their barcodes, their RBS and the start
+/// of read 1, so a pair whose tuple was already seen is counted as a
+/// duplicate. Pairs are sampled by a hash of that tuple, so that every copy
+/// of a sampled molecule is sampled too and the same pairs are sampled
+/// whatever the chunking and thread count. Heavy duplication inflates single
+/// molecules into many counts and makes flip ratios look more certain than
+/// they are.
+use dashmap::DashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::uaspire::classify::Sample;
+use crate::uaspire::hashing::stable_hash;
+
+// ---------- Options ----------
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DuplicateOptions {
+    // One tuple in this many is sampled
+    pub sample_every: u64,
+    // Bases of read 1 in the tuple
+    pub prefix_len: usize,
+}
+
+impl Default for DuplicateOptions {
+    fn default() -> Self {
+        DuplicateOptions {
+            sample_every: 100,
+            prefix_len: 20,
+        }
+    }
+}
+
+// ---------- Sampler ----------
+
+pub struct DuplicateSampler {
+    opts: DuplicateOptions,
+    seen: DashSet<u64>,
+    sampled: AtomicU64,
+    duplicates: AtomicU64,
+}
+
+impl DuplicateSampler {
+    pub fn new(opts: DuplicateOptions) -> Self {
+        DuplicateSampler {
+            opts,
+            seen: DashSet::new(),
+            sampled: AtomicU64::new(0),
+            duplicates: AtomicU64::new(0),
+        }
+    }
+
+    /// Tally a valid pair if it is sampled, returning whether it was.
+    pub(crate) fn record(
+        &self,
+        sample: &Sample,
+        rbs: &str,
+        seq1: &[u8],
+    ) -> bool {
+        let prefix = &seq1[..self.opts.prefix_len.min(seq1.len())];
+        let key = stable_hash(&[
+            sample.barcode1.as_bytes(),
+            sample.barcode2.as_bytes(),
+            rbs.as_bytes(),
+            prefix,
+        ]);
+        if key % self.opts.sample_every.max(1) != 0 {
+            return false;
+        }
+
+        self.sampled.fetch_add(1, Ordering::Relaxed);
+        if !self.seen.insert(key) {
+            self.duplicates.fetch_add(1, Ordering::Relaxed);
+        }
+
+        true
+    }
+
+    /// Pairs sampled so far.
+    pub fn sampled(&self) -> u64 {
+        self.sampled.load(Ordering::Relaxed)
+    }
+
+    /// Sampled pairs whose tuple had already been seen.
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates.load(Ordering::Relaxed)
+    }
+
+    /// Estimated duplicate fraction, `None` before any pair was sampled.
+    pub fn fraction(&self) -> Option<f64> {
+        let sampled = self.sampled();
+        (sampled > 0).then(|| self.duplicates() as f64 / sampled as f64)
+    }
+}
@@ -0,0 +1,165 @@
+/// Export of the merged counts table into SQL databases.
+use clap::ValueEnum;
+use diesel::prelude::*;
+use polars::prelude::*;
+use tracing::info;
+
+use std::{error::Error, path::Path};
+
+// Same limit as uniprot::db, which uASPIre modules don't depend on
+const SQLITE_MAX_PARAMETERS: usize = 999;
+// Parameters bound per row, one per column
+const COLUMNS: usize = 6;
+
+diesel::table! {
+    uaspire_counts (sample, barcode1, barcode2, gre) {
+        sample -> Text,
+        barcode1 -> Text,
+        barcode2 -> Text,
+        gre -> Text,
+        unflipped -> BigInt,
+        flipped -> BigInt,
+    }
+}
+
+const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS uaspire_counts (
+    sample VARCHAR NOT NULL,
+    barcode1 VARCHAR NOT NULL,
+    barcode2 VARCHAR NOT NULL,
+    gre VARCHAR NOT NULL,
+    unflipped BIGINT NOT NULL,
+    flipped BIGINT NOT NULL,
+    PRIMARY KEY (sample, barcode1, barcode2, gre)
+)";
+
+// Rows are appended here first, then upserted into the counts table
+#[cfg(feature = "duckdb")]
+const CREATE_STAGING: &str = "DROP TABLE IF EXISTS uaspire_counts_staging;
+CREATE TABLE uaspire_counts_staging AS
+    SELECT * FROM uaspire_counts LIMIT 0;";
+
+#[cfg(feature = "duckdb")]
+const UPSERT_STAGING: &str = "INSERT OR REPLACE INTO uaspire_counts
+    SELECT * FROM uaspire_counts_staging;
+DROP TABLE uaspire_counts_staging;";
+
+// ---------- Database kinds ----------
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum DbKind {
+    Sqlite,
+    Duckdb,
+}
+
+// ---------- Rows ----------
+
+#[derive(Insertable)]
+#[diesel(table_name = uaspire_counts)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+struct CountRow {
+    sample: String,
+    barcode1: String,
+    barcode2: String,
+    gre: String,
+    unflipped: i64,
+    flipped: i64,
+}
+
+fn dataframe_rows(
+    df: &DataFrame,
+    sample_name: &str,
+) -> PolarsResult<Vec<CountRow>> {
+    let barcode1 = df.column("barcode1")?.str()?;
+    let barcode2 = df.column("barcode2")?.str()?;
+    let gre = df.column("gre")?.str()?;
+    let unflipped = df.column("unflipped")?.u64()?;
+    let flipped = df.column("flipped")?.u64()?;
+
+    let rows = (0..df.height())
+        .map(|i| CountRow {
+            sample: sample_name.to_string(),
+            barcode1: barcode1.get(i).unwrap_or_default().to_string(),
+            barcode2: barcode2.get(i).unwrap_or_default().to_string(),
+            gre: gre.get(i).unwrap_or_default().to_string(),
+            unflipped: unflipped.get(i).unwrap_or(0) as i64,
+            flipped: flipped.get(i).unwrap_or(0) as i64,
+        })
+        .collect();
+
+    Ok(rows)
+}
+
+// =========================================================
+// Writers
+// =========================================================
+
+fn export_sqlite(rows: &[CountRow], path: &Path) -> Result<(), Box<dyn Error>> {
+    let url = path.to_str().ok_or("Invalid database path")?;
+    let mut connection = SqliteConnection::establish(url)?;
+
+    connection.transaction(|conn| {
+        diesel::sql_query(CREATE_TABLE).execute(conn)?;
+
+        for batch in rows.chunks(SQLITE_MAX_PARAMETERS / COLUMNS) {
+            diesel::replace_into(uaspire_counts::table)
+                .values(batch)
+                .execute(conn)?;
+        }
+
+        Ok::<_, diesel::result::Error>(())
+    })?;
+
+    Ok(())
+}
+
+#[cfg(feature = "duckdb")]
+fn export_duckdb(rows: &[CountRow], path: &Path) -> Result<(), Box<dyn Error>> {
+    let connection = duckdb::Connection::open(path)?;
+    connection.execute_batch(CREATE_TABLE)?;
+    connection.execute_batch(CREATE_STAGING)?;
+
+    // Rows of an earlier export of the sample are replaced
+    let mut appender = connection.appender("uaspire_counts_staging")?;
+    for row in rows {
+        appender.append_row(duckdb::params![
+            row.sample,
+            row.barcode1,
+            row.barcode2,
+            row.gre,
+            row.unflipped,
+            row.flipped,
+        ])?;
+    }
+    appender.flush()?;
+    drop(appender);
+
+    connection.execute_batch(UPSERT_STAGING)?;
+
+    Ok(())
+}
+
+#[cfg(not(feature = "duckdb"))]
+fn export_duckdb(
+    _rows: &[CountRow],
+    _path: &Path,
+) -> Result<(), Box<dyn Error>> {
+    Err("DuckDB export requires the `duckdb` feature".into())
+}
+
+/// Write the merged counts of a sample into a SQLite or DuckDB database.
+pub fn export_counts(
+    df: &DataFrame,
+    sample_name: &str,
+    path: &Path,
+    kind: DbKind,
+) -> Result<(), Box<dyn Error>> {
+    let rows = dataframe_rows(df, sample_name)?;
+
+    match kind {
+        DbKind::Sqlite => export_sqlite(&rows, path)?,
+        DbKind::Duckdb => export_duckdb(&rows, path)?,
+    }
+
+    info!("Exported {} rows to {}", rows.len(), path.display());
+    Ok(())
+}
@@ -1,143 +1,266 @@
 /// This module processes FASTQ files to count barcode pairs and RBS sequences.
-use bio::io::fastq;
 use dashmap::DashMap;
-use flate2::read::MultiGzDecoder;
 use polars::prelude::*;
 use rayon::prelude::*;
-use tracing::{error, info};
-
-use strum::EnumCount;
-use strum_macros::EnumCount;
+use tracing::{error, info, info_span, warn};
 
 use std::{
+    collections::{hash_map::Entry, HashMap},
     fs::{self, File},
-    hash::Hash,
-    io::{self, BufReader},
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
     path::{Path, PathBuf},
     sync::atomic::{AtomicU64, Ordering},
     sync::Arc,
+    time::Instant,
 };
 
+use crate::uaspire::assay::{Assay, ReadClassifier};
+use crate::uaspire::classify::{
+    add_to_table, Config, Counters, FailReason, Geometry, Sample, SampleTable,
+};
+use crate::uaspire::composition::{
+    gc_content, longest_homopolymer, RestrictionSite,
+};
 use crate::uaspire::constants;
+use crate::uaspire::correction::BarcodeCorrections;
+use crate::uaspire::crispr::{GuideClassifier, GuideLibrary, GuideOptions};
+use crate::uaspire::demux::FastqPairWriters;
+use crate::uaspire::downsample::{downsample_counts, DownsampleOptions};
+use crate::uaspire::duplicates::{DuplicateOptions, DuplicateSampler};
+use crate::uaspire::export::{export_counts, DbKind};
+use crate::uaspire::hashing::stable_hash;
+use crate::uaspire::interrupt::is_interrupted;
+use crate::uaspire::longread::{count_long_read, LongReadOptions};
+use crate::uaspire::manifest::{
+    CountsShape, Manifest, OutputLayout, RunStatus,
+};
+use crate::uaspire::matrix::sample_files;
+use crate::uaspire::metadata::{OutputMetadata, OutputSchema};
+use crate::uaspire::metrics::MetricsServer;
+use crate::uaspire::mpra::{
+    element_counts, read_association, MpraClassifier, MpraOptions,
+};
+use crate::uaspire::notification::NotifyOptions;
+use crate::uaspire::overlap::{merge_pair, OverlapOptions};
+use crate::uaspire::probe::learn_window;
+use crate::uaspire::processor::{RunError, RunSummary};
+use crate::uaspire::reader::ChunkReader;
+use crate::uaspire::retention::{self, OutputKind, RetentionPolicy};
+use crate::uaspire::spikein::SpikeIns;
+use crate::uaspire::strength::{predicted_strength, sd_free_energy};
+use crate::uaspire::trace::{
+    write_versions_yml, ChunkPerformance, ChunkTrace, Performance,
+};
+use crate::uaspire::variants::{read_reference, VariantOptions, VariantPileup};
+
+// Approximate heap cost of one RBS entry besides the sequence itself
+const TABLE_ENTRY_OVERHEAD: usize = 72;
+
+// Subdirectories the chunk files are spread over by barcode pair
+const SPILL_PARTITIONS: u64 = 16;
+
+// Cheap zstd level for chunk files, which are read back only once
+const TMP_ZSTD_LEVEL: i32 = 1;
+
+// ---------- Processing options ----------
+
+#[derive(Debug, Clone)]
+pub struct ProcessOptions {
+    pub chunk_size: usize,
+    pub parquet_size: usize,
+    pub max_memory: usize,
+    pub merge_streaming: bool,
+    pub partition_by: Vec<String>,
+    pub export_db: Option<(PathBuf, DbKind)>,
+    pub csv_stdout: bool,
+    pub write_fastq: bool,
+    pub write_rejects: bool,
+    pub annotate_rejects: bool,
+    pub strict_ids: bool,
+    pub max_errors: Option<u64>,
+    pub retention: RetentionPolicy,
+    pub geometry: Geometry,
+    pub trace: bool,
+    pub normalize: bool,
+    pub min_count: u64,
+    pub spike_ins: Option<PathBuf>,
+    pub spike_in_normalize: bool,
+    pub deterministic: bool,
+    pub in_memory: bool,
+    pub layout: OutputLayout,
+    pub overwrite: OverwritePolicy,
+    pub predict_strength: bool,
+    pub composition: bool,
+    pub restriction_sites: Vec<RestrictionSite>,
+    pub merge_overlap: Option<OverlapOptions>,
+    pub long_reads: Option<LongReadOptions>,
+    pub append: bool,
+    pub duplicates: Option<DuplicateOptions>,
+    pub barcode_corrections: bool,
+    pub assay: Assay,
+    pub guides: Option<GuideOptions>,
+    pub mpra: Option<MpraOptions>,
+    pub variants: Option<VariantOptions>,
+    pub notify: NotifyOptions,
+    pub metrics_port: Option<u16>,
+    pub metrics_addr: IpAddr,
+    pub downsample: Option<DownsampleOptions>,
+    pub auto_window: bool,
+    pub counts_shape: CountsShape,
+    pub max_tmp_bytes: Option<usize>,
+    pub resume: bool,
+}
 
-// ---------- Sample table ----------
-
-type SampleTable = DashMap<Sample, DashMap<String, [AtomicU64; 2]>>;
-
-// ---------- Configuration ----------
-
-#[derive(Clone, Debug)]
-struct Config<'a> {
-    barcodes1: &'a [&'a str],
-    barcodes2: &'a [&'a str],
-    const_region: &'a str,
-    window: (usize, usize),
-    rbs_len: usize,
-    barcode_len: usize,
-    max_n: usize,
-    non_flipped: &'a str,
-    flipped: &'a str,
-    disc_offset: usize,
+impl Default for ProcessOptions {
+    fn default() -> Self {
+        ProcessOptions {
+            chunk_size: 10_000,
+            parquet_size: 10_000,
+            max_memory: 4_000_000_000,
+            merge_streaming: false,
+            partition_by: vec!["barcode1".into(), "barcode2".into()],
+            export_db: None,
+            csv_stdout: false,
+            write_fastq: false,
+            write_rejects: false,
+            annotate_rejects: false,
+            strict_ids: false,
+            max_errors: None,
+            retention: RetentionPolicy::default(),
+            geometry: Geometry::default(),
+            trace: false,
+            normalize: false,
+            min_count: 0,
+            spike_ins: None,
+            spike_in_normalize: false,
+            deterministic: false,
+            in_memory: false,
+            layout: OutputLayout::Hive,
+            overwrite: OverwritePolicy::Merge,
+            predict_strength: false,
+            composition: false,
+            restriction_sites: Vec::new(),
+            merge_overlap: None,
+            long_reads: None,
+            append: false,
+            duplicates: None,
+            barcode_corrections: false,
+            assay: Assay::default(),
+            guides: None,
+            mpra: None,
+            variants: None,
+            notify: NotifyOptions::default(),
+            metrics_port: None,
+            metrics_addr: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            downsample: None,
+            auto_window: false,
+            counts_shape: CountsShape::Wide,
+            max_tmp_bytes: None,
+            resume: false,
+        }
+    }
 }
 
 // ---------- Directory layout ----------
 
+/// What to do when the output directory already holds files.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum OverwritePolicy {
+    // Write next to the existing files
+    #[default]
+    Merge,
+    // Remove the directory first
+    Overwrite,
+    // Refuse to run
+    NoClobber,
+}
+
 #[derive(Debug)]
 pub struct DirLayout {
     pub root: PathBuf,
     pub data: PathBuf,
     pub counts: PathBuf,
+    pub elements: PathBuf,
     pub qc: PathBuf,
+    pub fastq: PathBuf,
+    pub rejects: PathBuf,
     pub tmp: PathBuf,
     pub parquet: PathBuf,
 }
 
-// ---------- Reads classication ---------
-
-#[derive(Debug, EnumCount)]
-enum FailReason {
-    BaseCalls,
-    ConstantSeq,
-    ConstantPos,
-    Barcode1,
-    Barcode2,
-    DiscSeq,
-    DiscPos,
-}
+impl DirLayout {
+    pub fn new(output_dir: impl AsRef<Path>) -> Self {
+        let root = output_dir.as_ref().to_path_buf();
+        let data = root.join("data");
+        let tmp = root.join("tmp");
+
+        DirLayout {
+            counts: data.join("counts"),
+            elements: data.join("elements"),
+            qc: data.join("qc"),
+            fastq: data.join("fastq"),
+            rejects: data.join("rejects"),
+            parquet: tmp.join("parquet"),
+            root,
+            data,
+            tmp,
+        }
+    }
 
-// ---------- Discriminator status ----------
+    /// Directories created up front, FASTQ and element outputs are created
+    /// on demand.
+    pub fn created(&self) -> [&PathBuf; 6] {
+        [
+            &self.root,
+            &self.data,
+            &self.counts,
+            &self.qc,
+            &self.tmp,
+            &self.parquet,
+        ]
+    }
 
-#[derive(Debug)]
-enum Flip {
-    NonFlipped,
-    Flipped,
-}
+    /// Chunk directory of a barcode pair, the same in every chunk so that
+    /// partitions can be merged independently.
+    pub(crate) fn partition(&self, sample: &Sample) -> PathBuf {
+        let partition = stable_hash(&[
+            sample.barcode1.as_bytes(),
+            sample.barcode2.as_bytes(),
+        ]) % SPILL_PARTITIONS;
 
-// ---------- Sample is a barcode pair ----------
+        self.parquet.join(format!("partition={partition:03}"))
+    }
 
-#[derive(Debug, Clone, Hash, Eq, PartialEq)]
-struct Sample {
-    barcode1: String,
-    barcode2: String,
+    /// Chunk directories written so far, in order.
+    pub fn partitions(&self) -> io::Result<Vec<PathBuf>> {
+        let mut dirs: Vec<PathBuf> = fs::read_dir(&self.parquet)?
+            .filter_map(|entry| {
+                let p = entry.ok()?.path();
+                p.is_dir().then_some(p)
+            })
+            .collect();
+
+        dirs.sort();
+        Ok(dirs)
+    }
 }
 
-// ---------- Read types counter ----------
-
-#[derive(Default)]
-struct Counters {
-    total: AtomicU64,
-    valid: AtomicU64,
-    fails: [AtomicU64; FailReason::COUNT],
-}
+// ---------- QC table ----------
 
 impl Counters {
-    fn inc_total(&self) {
-        self.total.fetch_add(1, Ordering::Relaxed);
-    }
-    fn inc_valid(&self) {
-        self.valid.fetch_add(1, Ordering::Relaxed);
-    }
-    fn inc_fail(&self, r: FailReason) {
-        self.fails[r as usize].fetch_add(1, Ordering::Relaxed);
-    }
-
-    fn to_dataframe(&self) -> Result<DataFrame, polars::error::PolarsError> {
-        let names = Series::new(
-            "name".into(),
-            &[
-                "total",
-                "valid",
-                "base_calls",
-                "constant_seq",
-                "constant_pos",
-                "barcode_1",
-                "barcode_2",
-                "disc_seq",
-                "disc_pos",
-            ],
-        );
+    pub(crate) fn to_dataframe(
+        &self,
+        extra: &[(&'static str, u64)],
+    ) -> Result<DataFrame, polars::error::PolarsError> {
+        let mut rows = self.qc_rows();
+        rows.extend_from_slice(extra);
 
-        let values = Series::new(
-            "value".into(),
-            &[
-                self.total.load(Ordering::Relaxed),
-                self.valid.load(Ordering::Relaxed),
-                self.fails[FailReason::BaseCalls as usize]
-                    .load(Ordering::Relaxed),
-                self.fails[FailReason::ConstantSeq as usize]
-                    .load(Ordering::Relaxed),
-                self.fails[FailReason::ConstantPos as usize]
-                    .load(Ordering::Relaxed),
-                self.fails[FailReason::Barcode1 as usize]
-                    .load(Ordering::Relaxed),
-                self.fails[FailReason::Barcode2 as usize]
-                    .load(Ordering::Relaxed),
-                self.fails[FailReason::DiscSeq as usize]
-                    .load(Ordering::Relaxed),
-                self.fails[FailReason::DiscPos as usize]
-                    .load(Ordering::Relaxed),
-            ],
-        );
+        let names: Vec<&str> = rows.iter().map(|(name, _)| *name).collect();
+        let values: Vec<u64> = rows.iter().map(|(_, value)| *value).collect();
+
+        let names = Series::new("name".into(), names);
+        let values = Series::new("value".into(), values);
 
         DataFrame::new(vec![names.into(), values.into()]).map_err(|e| {
             error!("Failed to create DataFrame: {}", e);
@@ -150,48 +273,58 @@ impl Counters {
 // Helper functions
 // =========================================================
 
-/// Validates that the IDs of two FASTQ records match.
-fn validate_pairs(rec1: &fastq::Record, rec2: &fastq::Record) -> bool {
-    let id1 = rec1.id();
-    let id2 = rec2.id();
+// barcode1, barcode2, gre, unflipped, flipped
+type CountRow = (String, String, String, u64, u64);
 
-    if id1 != id2 {
-        panic!("Record IDs do not match: {} vs {}", id1, id2);
-    }
-
-    true
+/// Rows of one barcode pair of a `SampleTable`.
+fn sample_rows(
+    sample: &Sample,
+    rbs_map: &DashMap<String, [AtomicU64; 2]>,
+) -> Vec<CountRow> {
+    rbs_map
+        .iter()
+        .map(|rbs_counts| {
+            let rbs = rbs_counts.key();
+            let counts = rbs_counts.value();
+            let unflipped = counts[0].load(Ordering::Relaxed);
+            let flipped = counts[1].load(Ordering::Relaxed);
+
+            (
+                sample.barcode1.clone(),
+                sample.barcode2.clone(),
+                rbs.clone(),
+                unflipped,
+                flipped,
+            )
+        })
+        .collect()
 }
 
-/// Converts a `SampleTable` to a Polars `DataFrame`.
+/// Converts a `SampleTable` to a Polars `DataFrame`, with rows sorted by
+/// barcode pair and RBS when `sorted`.
 fn table_to_dataframe(
     table: &SampleTable,
+    sorted: bool,
 ) -> Result<DataFrame, polars::error::PolarsError> {
-    let rows: Vec<_> = table
+    let rows = table
         .iter()
         .flat_map(|sample_map| {
-            let sample = sample_map.key().clone();
-
-            sample_map
-                .value()
-                .iter()
-                .map(|rbs_counts| {
-                    let rbs = rbs_counts.key();
-                    let counts = rbs_counts.value();
-                    let unflipped = counts[0].load(Ordering::Relaxed);
-                    let flipped = counts[1].load(Ordering::Relaxed);
-
-                    (
-                        sample.barcode1.clone(),
-                        sample.barcode2.clone(),
-                        rbs.clone(),
-                        unflipped,
-                        flipped,
-                    )
-                })
-                .collect::<Vec<(String, String, String, u64, u64)>>()
+            sample_rows(sample_map.key(), sample_map.value())
         })
         .collect();
 
+    rows_to_dataframe(rows, sorted)
+}
+
+fn rows_to_dataframe(
+    mut rows: Vec<CountRow>,
+    sorted: bool,
+) -> Result<DataFrame, polars::error::PolarsError> {
+    // Map iteration order changes from run to run
+    if sorted {
+        rows.sort_unstable();
+    }
+
     let df = {
         let mut bc1: Series = rows.iter().map(|r| r.0.clone()).collect();
         let mut bc2: Series = rows.iter().map(|r| r.1.clone()).collect();
@@ -211,6 +344,54 @@ fn table_to_dataframe(
     Ok(df)
 }
 
+/// Number of (barcode pair, RBS) entries in a `SampleTable`.
+fn table_entries(table: &SampleTable) -> usize {
+    table.iter().map(|inner| inner.value().len()).sum()
+}
+
+/// Rough estimate of the memory used by a `SampleTable`.
+fn estimate_table_bytes(table: &SampleTable, rbs_len: usize) -> usize {
+    table_entries(table) * (TABLE_ENTRY_OVERHEAD + rbs_len)
+}
+
+/// Flush a `SampleTable` to one chunk Parquet file per partition and clear
+/// it.
+fn spill_table(
+    table: &SampleTable,
+    dirs: &DirLayout,
+    i: usize,
+    sorted: bool,
+) -> Result<(), String> {
+    let mut partitions: HashMap<PathBuf, Vec<CountRow>> = HashMap::new();
+    for sample_map in table.iter() {
+        partitions
+            .entry(dirs.partition(sample_map.key()))
+            .or_default()
+            .extend(sample_rows(sample_map.key(), sample_map.value()));
+    }
+
+    partitions.into_par_iter().try_for_each(|(dir, rows)| {
+        let df = rows_to_dataframe(rows, sorted).map_err(|e| {
+            format!("chunk {i:06}: table to DataFrame failed: {e}")
+        })?;
+
+        fs::create_dir_all(&dir).map_err(|err| {
+            format!("chunk {i:06}: failed to create {}: {err}", dir.display())
+        })?;
+        let path = dir.join(format!("chunk_{i:09}.parquet"));
+
+        write_tmp_chunk(&df, &path).map_err(|err| {
+            format!("chunk {i:06}: failed to write parquet: {err}")
+        })?;
+        info!("Wrote {} ({} rows)", path.display(), df.height());
+
+        Ok(())
+    })?;
+
+    table.clear();
+    Ok(())
+}
+
 /// Write a `DataFrame` to a Parquet file on disk.
 fn write_parquet_chunk(df: &DataFrame, path: &str) -> Result<u64, PolarsError> {
     let mut df = df.clone();
@@ -218,29 +399,92 @@ fn write_parquet_chunk(df: &DataFrame, path: &str) -> Result<u64, PolarsError> {
     ParquetWriter::new(file).finish(&mut df)
 }
 
-/// Create all required directories under `output_dir`.
-fn prepare_dirs(output_dir: impl AsRef<Path>) -> io::Result<DirLayout> {
-    let root = output_dir.as_ref().to_path_buf();
-    let data = root.join("data");
-    let counts = data.join("counts");
-    let qc = data.join("qc");
-    let tmp = root.join("tmp");
-    let parquet = tmp.join("parquet");
+/// Write a chunk file under `tmp/parquet`, lightly compressed.
+fn write_tmp_chunk(df: &DataFrame, path: &Path) -> PolarsResult<u64> {
+    let compression = ZstdLevel::try_new(TMP_ZSTD_LEVEL)?;
+    let file = File::create(path)?;
+    ParquetWriter::new(file)
+        .with_compression(ParquetCompression::Zstd(Some(compression)))
+        .finish(&mut df.clone())
+}
 
-    let all = [&root, &data, &counts, &qc, &tmp, &parquet];
+/// Merge the chunk files of every partition into a single one once the
+/// chunk files use more than `max_bytes`, returning whether they did.
+fn compact_chunks(
+    dirs: &DirLayout,
+    i: usize,
+    max_bytes: usize,
+) -> Result<bool, String> {
+    let used = retention::dir_size(&dirs.parquet).unwrap_or(0);
+    if used <= max_bytes as u64 {
+        return Ok(false);
+    }
+    info!("Chunk files use {} bytes, compacting", used);
+
+    let partitions = dirs.partitions().map_err(|err| {
+        format!(
+            "{}: couldn't list partitions: {err}",
+            dirs.parquet.display()
+        )
+    })?;
+
+    partitions.par_iter().try_for_each(|dir| {
+        let files = list_parquet_files(dir)
+            .map_err(|err| format!("{}: {err}", dir.display()))?;
+        if files.len() < 2 {
+            return Ok(());
+        }
+
+        // Aggregated by the streaming engine, as large as the partition is
+        let df = concat_parquet_dir(dir, true)
+            .map_err(|err| format!("{}: {err}", dir.display()))?;
+        let path = dir.join(format!("compact_{i:09}.parquet"));
+        write_tmp_chunk(&df, &path)
+            .map_err(|err| format!("{}: {err}", path.display()))?;
+
+        for file in files {
+            fs::remove_file(&file)
+                .map_err(|err| format!("{}: {err}", file.display()))?;
+        }
+
+        Ok::<_, String>(())
+    })?;
+
+    let compacted = retention::dir_size(&dirs.parquet).unwrap_or(0);
+    info!("Chunk files compacted to {} bytes", compacted);
+    if compacted > max_bytes as u64 {
+        warn!("Chunk files still use more than {} bytes", max_bytes);
+    }
+
+    Ok(true)
+}
 
-    for dir in all {
+/// Create all required directories under `output_dir`.
+pub(crate) fn prepare_dirs(
+    output_dir: impl AsRef<Path>,
+) -> io::Result<DirLayout> {
+    let dirs = DirLayout::new(output_dir);
+
+    for dir in dirs.created() {
         fs::create_dir_all(dir)?;
     }
 
-    Ok(DirLayout {
-        root,
-        data,
-        counts,
-        qc,
-        tmp,
-        parquet,
-    })
+    Ok(dirs)
+}
+
+/// Write the element-level counts of an MPRA run.
+fn write_element_counts(
+    df: &mut DataFrame,
+    elements_dir: &Path,
+    meta: &OutputMetadata,
+) -> PolarsResult<()> {
+    fs::create_dir_all(elements_dir)?;
+
+    let path = elements_dir.join(format!("{}.parquet", meta.sample_name));
+    meta.writer(File::create(path)?, OutputSchema::Elements)
+        .finish(df)?;
+
+    Ok(())
 }
 
 /// List all Parquet files in a directory.
@@ -257,100 +501,585 @@ fn list_parquet_files(dir: impl AsRef<Path>) -> std::io::Result<Vec<PathBuf>> {
     Ok(files)
 }
 
-/// Concatenate all Parquet files in a directory into a single `DataFrame`.
-fn concat_parquet_dir(dir: impl AsRef<Path>) -> DataFrame {
-    let files = match list_parquet_files(&dir) {
-        Ok(files) => files,
-        Err(e) => {
-            error!("Failed to list parquet files: {}", e);
-            panic!("Failed to list parquet files");
-        }
+/// Sum the counts of identical (barcode1, barcode2, gre) rows.
+fn aggregate_counts(lf: LazyFrame, stable: bool) -> LazyFrame {
+    let keys = [col("barcode1"), col("barcode2"), col("gre")];
+    let grouped = if stable {
+        lf.group_by_stable(keys)
+    } else {
+        lf.group_by(keys)
     };
 
+    grouped.agg([
+        col("unflipped").sum().alias("unflipped"),
+        col("flipped").sum().alias("flipped"),
+    ])
+}
+
+/// Concatenate all Parquet files in a directory into a single `DataFrame`.
+///
+/// With `streaming`, the files are scanned lazily and aggregated by the
+/// streaming engine so the chunks never need to fit in memory together.
+fn concat_parquet_dir(
+    dir: impl AsRef<Path>,
+    streaming: bool,
+) -> PolarsResult<DataFrame> {
+    let files = list_parquet_files(&dir)?;
+
     if files.is_empty() {
-        panic!("No parquet files");
+        polars_bail!(ComputeError: "no parquet files");
+    }
+
+    if streaming {
+        let lf = LazyFrame::scan_parquet_files(
+            files.into(),
+            ScanArgsParquet::default(),
+        )?;
+
+        return aggregate_counts(lf, false)
+            .collect_with_engine(Engine::Streaming);
     }
 
     let mut dfs = Vec::with_capacity(files.len());
     for path in files {
-        let file =
-            std::fs::File::open(&path).expect("Cannot open parquet file");
-        let df = ParquetReader::new(file)
-            .finish()
-            .expect("Cannot read parquet file");
+        let df = ParquetReader::new(File::open(&path)?).finish()?;
         dfs.push(df.lazy());
     }
 
-    concat(&dfs, UnionArgs::default())
-        .expect("Cannot concatenate dataframes")
+    let df = concat(&dfs, UnionArgs::default())?.collect()?;
+
+    aggregate_counts(df.lazy(), true).collect()
+}
+
+/// Output of the sample under `dir`, its directory in the Hive layout.
+fn sample_output(
+    dir: &Path,
+    sample_name: &str,
+    layout: OutputLayout,
+) -> PathBuf {
+    match layout {
+        OutputLayout::Hive => dir.join(format!("sample={sample_name}")),
+        OutputLayout::Flat => dir.join(format!("{sample_name}.parquet")),
+    }
+}
+
+/// Copy the counts and QC of an earlier run of the sample into the chunk
+/// partitions and the counters, returning the number of count rows. They
+/// stay in place until the new ones replace them, see `promote_staged`.
+fn take_previous_run(
+    dirs: &DirLayout,
+    sample_name: &str,
+    layout: OutputLayout,
+    counters: &Counters,
+) -> PolarsResult<usize> {
+    let counts_path = sample_output(&dirs.counts, sample_name, layout);
+    let qc_path = match layout {
+        OutputLayout::Hive => {
+            sample_output(&dirs.qc, sample_name, layout).join("part-0.parquet")
+        }
+        OutputLayout::Flat => sample_output(&dirs.qc, sample_name, layout),
+    };
+
+    let qc = ParquetReader::new(File::open(&qc_path)?).finish()?;
+    let names = qc.column("name")?.str()?;
+    for (name, value) in names.into_iter().zip(qc.column("value")?.u64()?) {
+        if let (Some(name), Some(value)) = (name, value) {
+            counters.add_named(name, value);
+        }
+    }
+
+    let files: Vec<PathBuf> = if counts_path.is_dir() {
+        sample_files(&counts_path)?
+            .into_iter()
+            .map(|(_, path)| path)
+            .collect()
+    } else if counts_path.exists() {
+        vec![counts_path.clone()]
+    } else {
+        Vec::new()
+    };
+
+    let mut frames = Vec::with_capacity(files.len());
+    for path in files {
+        let mut df = ParquetReader::new(File::open(&path)?).finish()?;
+        if df.get_column_index("state").is_some() {
+            df = long_to_wide(df)?;
+        }
+        frames.push(df.lazy().select([
+            col("barcode1"),
+            col("barcode2"),
+            col("gre"),
+            col("unflipped").cast(DataType::UInt64),
+            col("flipped").cast(DataType::UInt64),
+        ]));
+    }
+
+    if frames.is_empty() {
+        return Ok(0);
+    }
+    let counts = concat(&frames, UnionArgs::default())?.collect()?;
+
+    // Same partitions as the new chunks, so that they merge together
+    let mut partitions: HashMap<PathBuf, DataFrame> = HashMap::new();
+    for part in counts.partition_by_stable(["barcode1", "barcode2"], true)? {
+        let sample = {
+            let key = |name: &str| -> PolarsResult<String> {
+                let values = part.column(name)?.str()?;
+                Ok(values.get(0).unwrap_or_default().to_string())
+            };
+
+            Sample {
+                barcode1: key("barcode1")?,
+                barcode2: key("barcode2")?,
+            }
+        };
+
+        match partitions.entry(dirs.partition(&sample)) {
+            Entry::Occupied(mut entry) => {
+                entry.get_mut().vstack_mut(&part)?;
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(part);
+            }
+        }
+    }
+
+    for (dir, df) in partitions {
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("previous.parquet");
+        write_parquet_chunk(&df, &path.to_string_lossy())?;
+    }
+
+    Ok(counts.height())
+}
+
+/// Put the QC and counts of the sample written under `staging` in place of
+/// those of the earlier run, once they are complete.
+fn promote_staged(
+    staging: &Path,
+    dirs: &DirLayout,
+    sample_name: &str,
+    layout: OutputLayout,
+) -> io::Result<()> {
+    for (name, target) in [("qc", &dirs.qc), ("counts", &dirs.counts)] {
+        let staged = sample_output(&staging.join(name), sample_name, layout);
+        let current = sample_output(target, sample_name, layout);
+        if !staged.exists() {
+            continue;
+        }
+
+        // Moved aside first, a rename can't replace a directory
+        let previous = staging.join(format!("previous-{name}"));
+        if current.exists() {
+            fs::rename(&current, &previous)?;
+        }
+        fs::rename(&staged, &current)?;
+    }
+
+    fs::remove_dir_all(staging)
+}
+
+/// Merge the chunks of every partition in parallel, as a barcode pair never
+/// spans two partitions.
+fn merge_partitions(
+    dirs: &DirLayout,
+    streaming: bool,
+) -> PolarsResult<Vec<DataFrame>> {
+    let partitions = dirs.partitions()?;
+
+    // No read was valid
+    if partitions.is_empty() {
+        return Ok(vec![rows_to_dataframe(Vec::new(), false)?]);
+    }
+
+    partitions
+        .par_iter()
+        .map(|dir| {
+            concat_parquet_dir(dir, streaming).map_err(
+                |err| polars_err!(ComputeError: "{}: {err}", dir.display()),
+            )
+        })
         .collect()
-        .expect("Cannot finalise dataframe")
-        .lazy()
-        .group_by_stable([col("barcode1"), col("barcode2"), col("gre")])
-        .agg([
-            col("unflipped").sum().alias("unflipped"),
-            col("flipped").sum().alias("flipped"),
+}
+
+/// Stack finished partitions into the whole counts table.
+fn concat_counts(
+    partitions: &[DataFrame],
+    sorted: bool,
+) -> PolarsResult<DataFrame> {
+    let mut counts = partitions[0].clone();
+    for df in &partitions[1..] {
+        counts.vstack_mut(df)?;
+    }
+
+    if sorted {
+        let keys = ["barcode1", "barcode2", "gre"];
+        counts = counts.sort(keys, SortMultipleOptions::default())?;
+    }
+
+    Ok(counts)
+}
+
+/// Filter, annotate, normalize and sort one partition of the merged counts,
+/// returned along with the number of rows filtered out.
+pub(crate) fn finish_counts(
+    mut df: DataFrame,
+    opts: &ProcessOptions,
+    valid: u64,
+    spike_reads: Option<&HashMap<(String, String), u64>>,
+) -> PolarsResult<(DataFrame, u64)> {
+    let before = df.height();
+    if opts.min_count > 0 {
+        df = filter_min_count(df, opts.min_count)?;
+    }
+    let filtered = (before - df.height()) as u64;
+
+    // Variable length RBSs get their observed length as a column
+    if opts.geometry.rbs_anchor.is_some() {
+        df = df
+            .lazy()
+            .with_column(col("gre").str().len_chars().alias("rbs_length"))
+            .collect()?;
+    }
+
+    if opts.predict_strength {
+        df = annotate_strength(df)?;
+    }
+
+    if opts.composition || !opts.restriction_sites.is_empty() {
+        df = annotate_composition(
+            df,
+            opts.composition,
+            &opts.restriction_sites,
+        )?;
+    }
+
+    if opts.normalize {
+        df = normalize_counts(df, valid)?;
+    }
+
+    if let Some(spike_reads) = spike_reads {
+        df = normalize_by_spike_ins(df, spike_reads)?;
+    }
+
+    // Identical inputs give byte-identical outputs
+    if opts.deterministic {
+        let keys = ["barcode1", "barcode2", "gre"];
+        df = df.sort(keys, SortMultipleOptions::default())?;
+    }
+
+    Ok((df, filtered))
+}
+
+/// Drop rows with fewer than `min_count` reads, flipped or not.
+fn filter_min_count(df: DataFrame, min_count: u64) -> PolarsResult<DataFrame> {
+    let total = col("unflipped") + col("flipped");
+
+    df.lazy()
+        .filter(total.cast(DataType::UInt64).gt_eq(lit(min_count)))
+        .collect()
+}
+
+/// Sum of an unsigned column, 0 when missing.
+fn sum_column(df: &DataFrame, name: &str) -> u64 {
+    df.column(name)
+        .ok()
+        .and_then(|c| c.u64().ok().and_then(|c| c.sum()))
+        .unwrap_or(0)
+}
+
+/// Append the SD free energy and predicted strength of every RBS.
+fn annotate_strength(mut df: DataFrame) -> PolarsResult<DataFrame> {
+    let gres = df.column("gre")?.str()?;
+
+    let dg: Vec<Option<f64>> =
+        gres.into_iter().map(|g| g.map(sd_free_energy)).collect();
+    let strength: Vec<Option<f64>> = gres
+        .into_iter()
+        .map(|g| g.map(predicted_strength))
+        .collect();
+
+    df.with_column(Series::new("sd_dg".into(), dg))?;
+    df.with_column(Series::new("predicted_strength".into(), strength))?;
+
+    Ok(df)
+}
+
+/// Append the GC content and longest homopolymer of every RBS, and a
+/// `site_<name>` flag per restriction site.
+fn annotate_composition(
+    mut df: DataFrame,
+    composition: bool,
+    sites: &[RestrictionSite],
+) -> PolarsResult<DataFrame> {
+    let gres = df.column("gre")?.str()?.clone();
+
+    if composition {
+        let gc: Vec<Option<f64>> =
+            gres.into_iter().map(|g| g.map(gc_content)).collect();
+        let homopolymer: Vec<Option<u32>> = gres
+            .into_iter()
+            .map(|g| g.map(|g| longest_homopolymer(g) as u32))
+            .collect();
+
+        df.with_column(Series::new("gc_content".into(), gc))?;
+        df.with_column(Series::new("max_homopolymer".into(), homopolymer))?;
+    }
+
+    for site in sites {
+        let present: Vec<Option<bool>> = gres
+            .into_iter()
+            .map(|g| g.map(|g| site.occurs_in(g)))
+            .collect();
+
+        let name = format!("site_{}", site.name);
+        df.with_column(Series::new(name.into(), present))?;
+    }
+
+    Ok(df)
+}
+
+/// Append counts scaled to a million spike-in reads of their barcode pair,
+/// null for the pairs without any.
+fn normalize_by_spike_ins(
+    mut df: DataFrame,
+    spike_reads: &HashMap<(String, String), u64>,
+) -> PolarsResult<DataFrame> {
+    let barcode1 = df.column("barcode1")?.str()?;
+    let barcode2 = df.column("barcode2")?.str()?;
+    let unflipped = df.column("unflipped")?.u64()?;
+    let flipped = df.column("flipped")?.u64()?;
+
+    let norm: Vec<Option<f64>> = barcode1
+        .into_iter()
+        .zip(barcode2)
+        .zip(unflipped.into_iter().zip(flipped))
+        .map(|((b1, b2), (u, f))| {
+            let key = (b1?.to_string(), b2?.to_string());
+            let spikes = *spike_reads.get(&key).filter(|&&n| n > 0)?;
+            Some((u? + f?) as f64 * 1e6 / spikes as f64)
+        })
+        .collect();
+
+    df.with_column(Series::new("spikein_norm".into(), norm))?;
+    Ok(df)
+}
+
+/// Append counts per million valid reads and the flipped fraction.
+fn normalize_counts(df: DataFrame, valid: u64) -> PolarsResult<DataFrame> {
+    let total = col("unflipped") + col("flipped");
+    let scale = 1e6 / valid.max(1) as f64;
+
+    df.lazy()
+        .with_columns([
+            (total.clone().cast(DataType::Float64) * lit(scale)).alias("cpm"),
+            (col("flipped").cast(DataType::Float64)
+                / total.cast(DataType::Float64))
+            .alias("fraction_flipped"),
         ])
         .collect()
-        .expect("Cannot convert LazyFrame to DataFrame")
 }
 
-fn write_qc_parquet(
+/// Write the per-chunk performance of a run to `performance.parquet`.
+fn write_performance_parquet(
+    perf: &Performance,
+    qc_dir: &Path,
+    meta: &OutputMetadata,
+) -> PolarsResult<()> {
+    let sample_name = meta.sample_name.as_str();
+    let chunks = &perf.chunks;
+    let n = chunks.len();
+    let ints = |f: fn(&ChunkPerformance) -> usize| -> Vec<u64> {
+        chunks.iter().map(|c| f(c) as u64).collect()
+    };
+
+    let wall: Vec<u64> =
+        chunks.iter().map(|c| c.wall.as_millis() as u64).collect();
+    let rate: Vec<f64> = chunks.iter().map(|c| c.reads_per_sec()).collect();
+    let rss: Vec<Option<u64>> = chunks.iter().map(|c| c.rss).collect();
+    let peak: Vec<Option<u64>> = chunks.iter().map(|c| c.peak_rss).collect();
+
+    let mut df = DataFrame::new(vec![
+        Series::new("sample".into(), vec![sample_name; n]).into(),
+        Series::new("version".into(), vec![env!("CARGO_PKG_VERSION"); n])
+            .into(),
+        Series::new("chunk".into(), ints(|c| c.chunk)).into(),
+        Series::new("records".into(), ints(|c| c.records)).into(),
+        Series::new("wall_ms".into(), wall).into(),
+        Series::new("reads_per_sec".into(), rate).into(),
+        Series::new("table_samples".into(), ints(|c| c.table_samples)).into(),
+        Series::new("table_entries".into(), ints(|c| c.table_entries)).into(),
+        Series::new("rss_bytes".into(), rss).into(),
+        Series::new("peak_rss_bytes".into(), peak).into(),
+    ])?;
+
+    let file = File::create(qc_dir.join("performance.parquet"))?;
+    meta.writer(file, OutputSchema::Performance)
+        .finish(&mut df)?;
+
+    Ok(())
+}
+
+pub(crate) fn write_qc_parquet(
     df: &DataFrame,
     output_root: &Path,
-    sample: &str,
+    meta: &OutputMetadata,
+    layout: OutputLayout,
 ) -> PolarsResult<()> {
-    let output_dir = output_root.join(format!("sample={sample}"));
-    fs::create_dir_all(&output_dir)?;
-    let path = output_dir.join("part-0.parquet");
+    let sample = &meta.sample_name;
+    let path = match layout {
+        OutputLayout::Hive => {
+            let output_dir = output_root.join(format!("sample={sample}"));
+            fs::create_dir_all(&output_dir)?;
+            output_dir.join("part-0.parquet")
+        }
+        OutputLayout::Flat => output_root.join(format!("{sample}.parquet")),
+    };
     let file = std::fs::File::create(&path)?;
-    ParquetWriter::new(file)
-        .with_compression(ParquetCompression::Zstd(None))
+    meta.writer(file, OutputSchema::Qc)
         .finish(&mut df.clone())?;
 
     Ok(())
 }
 
-/// Write a partitioned Parquet file for each unique barcode pair.
+/// Write a partitioned Parquet file for each unique combination of the
+/// partition columns, under `sample=/<column>=<value>/...` directories.
 fn write_partitioned_parquet(
     df: &DataFrame,
     output_root: &Path,
-    sample_name: &str,
+    meta: &OutputMetadata,
+    schema: OutputSchema,
+    partition_by: &[String],
     parquet_size: Option<usize>,
 ) -> PolarsResult<()> {
-
     let parquet_size = parquet_size.unwrap_or(10_000);
+    let sample_dir = output_root.join(format!("sample={}", meta.sample_name));
+
+    if partition_by.is_empty() {
+        return save_by_chunks(
+            df,
+            &sample_dir,
+            Some(parquet_size),
+            meta,
+            schema,
+        );
+    }
 
-    let barcode1s = df.column("barcode1")?.str()?.unique()?;
-    let barcode2s = df.column("barcode2")?.str()?.unique()?;
-
-    for b1 in barcode1s.into_no_null_iter() {
-        for b2 in barcode2s.into_no_null_iter() {
-            let filtered = df
-                .clone()
-                .lazy()
-                .filter(
-                    col("barcode1")
-                        .eq(lit(b1))
-                        .and(col("barcode2").eq(lit(b2))),
-                )
-                .collect()?;
+    // Single pass over the data, only present combinations are produced
+    for partition in df.partition_by_stable(partition_by.to_vec(), true)? {
+        let mut path = sample_dir.clone();
 
-            // Not all combinations are supposed to be present
-            if filtered.height() == 0 {
-                continue;
-            }
+        for name in partition_by {
+            let values = partition.column(name)?.cast(&DataType::String)?;
+            let value = values.str()?.get(0).unwrap_or("null").to_string();
+            path = path.join(format!("{}={}", name, value));
+        }
+
+        save_by_chunks(&partition, &path, Some(parquet_size), meta, schema)?;
+    }
+
+    Ok(())
+}
+
+/// Write the finished count partitions in the output layout.
+pub(crate) fn write_counts(
+    partitions: &[DataFrame],
+    counts_dir: &Path,
+    sample_name: &str,
+    opts: &ProcessOptions,
+) -> PolarsResult<()> {
+    let partitions = match opts.counts_shape {
+        CountsShape::Wide => partitions.to_vec(),
+        CountsShape::Long => partitions
+            .iter()
+            .map(|df| wide_to_long(df, opts.deterministic))
+            .collect::<PolarsResult<Vec<DataFrame>>>()?,
+    };
+    let partitions = partitions.as_slice();
 
-            let path = output_root
-                .join(format!("sample={}", sample_name))
-                .join(format!("barcode1={}", b1))
-                .join(format!("barcode2={}", b2));
+    let meta = OutputMetadata::new(sample_name, opts);
+    let schema = match opts.counts_shape {
+        CountsShape::Wide => OutputSchema::Counts,
+        CountsShape::Long => OutputSchema::CountsLong,
+    };
 
-            save_by_chunks(&filtered, &path, Some(parquet_size))?;
+    // A partition never shares a counts directory with another one when
+    // partitioning by barcode pair, so those are written in parallel
+    let by_pair = ["barcode1", "barcode2"]
+        .iter()
+        .all(|name| opts.partition_by.iter().any(|p| p == name));
+
+    match opts.layout {
+        OutputLayout::Hive if by_pair => {
+            partitions.par_iter().try_for_each(|df| {
+                write_partitioned_parquet(
+                    df,
+                    counts_dir,
+                    &meta,
+                    schema,
+                    &opts.partition_by,
+                    Some(opts.parquet_size),
+                )
+            })
+        }
+        OutputLayout::Hive => write_partitioned_parquet(
+            &concat_counts(partitions, opts.deterministic)?,
+            counts_dir,
+            &meta,
+            schema,
+            &opts.partition_by,
+            Some(opts.parquet_size),
+        ),
+        OutputLayout::Flat => {
+            let path = counts_dir.join(format!("{sample_name}.parquet"));
+            meta.writer(File::create(path)?, schema)
+                .finish(&mut concat_counts(partitions, opts.deterministic)?)
+                .map(|_| ())
         }
     }
+}
 
-    Ok(())
+/// One row per flip state instead of the unflipped and flipped columns.
+fn wide_to_long(df: &DataFrame, sorted: bool) -> PolarsResult<DataFrame> {
+    // Every other column is repeated on both rows
+    let index: Vec<Expr> = df
+        .get_column_names()
+        .into_iter()
+        .filter(|name| !["unflipped", "flipped"].contains(&name.as_str()))
+        .map(|name| col(name.clone()))
+        .collect();
+
+    let state = |name: &str| {
+        let mut exprs = index.clone();
+        exprs.push(lit(name).alias("state"));
+        exprs.push(col(name).alias("count"));
+        df.clone().lazy().select(exprs)
+    };
+    let mut long =
+        concat([state("unflipped"), state("flipped")], UnionArgs::default())?
+            .collect()?;
+
+    if sorted {
+        let keys = ["barcode1", "barcode2", "gre", "state"];
+        long = long.sort(keys, SortMultipleOptions::default())?;
+    }
+
+    Ok(long)
+}
+
+/// Back to the unflipped and flipped columns, from long counts.
+fn long_to_wide(df: DataFrame) -> PolarsResult<DataFrame> {
+    let state = |name: &str| {
+        col("count")
+            .filter(col("state").eq(lit(name)))
+            .sum()
+            .alias(name)
+    };
+
+    df.lazy()
+        .group_by_stable([col("barcode1"), col("barcode2"), col("gre")])
+        .agg([state("unflipped"), state("flipped")])
+        .collect()
 }
 
 /// Save a DataFrame to Parquet files in chunks.
@@ -358,6 +1087,8 @@ fn save_by_chunks(
     df: &DataFrame,
     output_dir: &Path,
     chunk_size: Option<usize>,
+    meta: &OutputMetadata,
+    schema: OutputSchema,
 ) -> PolarsResult<()> {
     let chunk_size = chunk_size.unwrap_or(10_000);
 
@@ -376,273 +1107,928 @@ fn save_by_chunks(
         let path = output_dir.join(filename);
         let file = std::fs::File::create(&path)?;
 
-        ParquetWriter::new(file)
-            .with_compression(ParquetCompression::Zstd(None))
-            .finish(&mut chunk.clone())?;
+        meta.writer(file, schema).finish(&mut chunk.clone())?;
     }
 
     Ok(())
 }
 
 // =========================================================
-// Core logic
+// Main processing function
 // =========================================================
 
-fn classify_pair<'a>(
-    cfg: &Config<'a>,
-    rec1: &'a fastq::Record,
-    rec2: &'a fastq::Record,
-) -> Result<Result<(Sample, &'a str, Flip), FailReason>, std::str::Utf8Error> {
-    validate_pairs(rec1, rec2);
+pub fn process_fastq(
+    path1: &str,
+    path2: &str,
+    sample_name: &str,
+    output_dir: &str,
+    opts: &ProcessOptions,
+) -> Result<RunSummary, RunError> {
+    let start = Instant::now();
+
+    // Every log line of the run carries the sample name
+    let _sample_span = info_span!("sample", sample = sample_name).entered();
 
-    let seq1 = std::str::from_utf8(rec1.seq())?;
-    let seq2 = std::str::from_utf8(rec2.seq())?;
+    info!("Creating output directories if they do not exist");
 
     // -----------------------------------------------------
-    // 1. Fast rejection for base call
+    // Create output directories
     // -----------------------------------------------------
-    if seq1.bytes().filter(|&b| b == b'N').count()
-        + seq2.bytes().filter(|&b| b == b'N').count()
-        > cfg.max_n
-    {
-        return Ok(Err(FailReason::BaseCalls));
-    }
 
-    // -----------------------------------------------------
-    // 2. Reject when missing constant region
-    // -----------------------------------------------------
-    let (win_lo, win_hi) = cfg.window;
-    let window = &seq2[win_lo - 1..win_hi];
-    let const_offset = match window.find(cfg.const_region) {
-        Some(local) => local + win_lo - 1,
-        None => return Ok(Err(FailReason::ConstantSeq)),
-    };
+    let dirs = prepare_dirs(output_dir)
+        .map_err(|e| RunError::Output(format!("{output_dir}: {e}")))?;
 
     // -----------------------------------------------------
-    // 3. Reject when constant region is too skewed
+    // Configuration
     // -----------------------------------------------------
-    if const_offset < cfg.barcode_len
-        || const_offset + cfg.barcode_len + cfg.rbs_len > seq2.len()
-    {
-        return Ok(Err(FailReason::ConstantPos));
+
+    // The constant region window may be learned from the first chunk, then
+    // holds for every chunk
+    let mut geometry = opts.geometry.clone();
+    if opts.auto_window && opts.long_reads.is_none() {
+        let region = constants::CONSTANT_REGION;
+        match learn_window(path2, opts.chunk_size, region) {
+            Ok(Some(window)) => {
+                info!(
+                    "Learned constant region window {}:{} from the first chunk",
+                    window.0, window.1
+                );
+                geometry.window = window;
+            }
+            Ok(None) => warn!(
+                "No constant region in the first chunk, keeping window {}:{}",
+                geometry.window.0, geometry.window.1
+            ),
+            Err(err) => return Err(RunError::Input(format!("{path2}: {err}"))),
+        }
     }
 
-    // -----------------------------------------------------
-    // 4. Extract RBS
-    // -----------------------------------------------------
-    let rbs_start = const_offset + cfg.const_region.len();
-    let rbs = &seq2[rbs_start..rbs_start + cfg.rbs_len];
+    let mut cfg = Config::from_constants().with_geometry(&geometry);
+    cfg.strict_ids = opts.strict_ids;
 
-    // -----------------------------------------------------
-    // 5. Extract barcode 2
-    // -----------------------------------------------------
-    let barcode2 = &seq2[const_offset - cfg.barcode_len..const_offset];
-    if !cfg.barcodes2.contains(&barcode2) {
-        return Ok(Err(FailReason::Barcode2));
+    // Corrected barcodes tallied for qc/barcode_corrections.tsv
+    let corrections = opts
+        .barcode_corrections
+        .then(|| Arc::new(BarcodeCorrections::default()));
+    if let Some(corrections) = &corrections {
+        cfg = cfg.with_corrections(Arc::clone(corrections));
     }
 
-    // -----------------------------------------------------
-    // 6. Extract discriminator
-    // -----------------------------------------------------
-    let (disc_pos, flipped) =
-        match (seq1.find(cfg.non_flipped), seq1.find(cfg.flipped)) {
-            (Some(p), _) => (p, Flip::NonFlipped),
-            (None, Some(p)) => (p, Flip::Flipped),
-            _ => return Ok(Err(FailReason::DiscSeq)),
-        };
-    if disc_pos < cfg.disc_offset + cfg.barcode_len {
-        return Ok(Err(FailReason::DiscPos));
-    }
+    let (min1, min2) = cfg.min_read_lengths();
+    info!("Minimum read lengths: {min1} bp (read 1), {min2} bp (read 2)");
 
-    // -----------------------------------------------------
-    // 6. Extract barcode 1
-    // -----------------------------------------------------
-    let barcode1_start = disc_pos - cfg.disc_offset - cfg.barcode_len;
-    let barcode1 = &seq1[barcode1_start..barcode1_start + cfg.barcode_len];
-    if !cfg.barcodes1.contains(&barcode1) {
-        return Ok(Err(FailReason::Barcode1));
+    if !cfg.cross_check {
+        info!("Barcode whitelists are identical, cross-assignment QC is off");
     }
 
-    // -----------------------------------------------------
-    // 7. End
-    // -----------------------------------------------------
-
-    Ok(Ok((
-        Sample {
-            barcode1: barcode1.to_owned(),
-            barcode2: barcode2.to_owned(),
+    // Read pairs go through the classifier of the assay
+    let guides = opts.guides.as_ref().map(|guides| {
+        let library = GuideLibrary::load(&guides.library).map_err(|e| {
+            RunError::Input(format!("{}: {e}", guides.library.display()))
+        })?;
+        info!("Loaded {} guides", library.len());
+        Ok((library, guides))
+    });
+    let guides = guides.transpose()?;
+    let crispr = guides
+        .as_ref()
+        .map(|(library, guides)| GuideClassifier::new(&cfg, library, guides));
+    let mpra = opts
+        .mpra
+        .as_ref()
+        .map(|mpra| MpraClassifier::new(&cfg, mpra));
+    let classifier: &dyn ReadClassifier = match opts.assay {
+        Assay::Uaspire => &cfg,
+        Assay::Crispr => match &crispr {
+            Some(crispr) => crispr,
+            None => {
+                return Err(RunError::Config(
+                    "the CRISPR assay needs a guide library".into(),
+                ))
+            }
+        },
+        Assay::Mpra => match &mpra {
+            Some(mpra) => mpra,
+            None => {
+                return Err(RunError::Config(
+                    "the MPRA assay needs an anchor".into(),
+                ))
+            }
         },
-        rbs,
-        flipped,
-    )))
-}
-
-// =========================================================
-// Main processing function
-// =========================================================
-
-pub fn process_fastq(
-    path1: &str,
-    path2: &str,
-    sample_name: &str,
-    output_dir: &str,
-    chunk_size: usize,
-    parquet_size: usize,
-) {
-    info!("Creating output directories if they do not exist");
-
-    // -----------------------------------------------------
-    // Create output directories
-    // -----------------------------------------------------
-
-    let dirs = match prepare_dirs(output_dir) {
-        Ok(dir) => dir,
-        Err(e) => {
-            error!("Failed to create output directories: {}", e);
-            panic!("Failed to create output directories");
-        }
     };
 
     // -----------------------------------------------------
-    // Configuration
+    // Load FASTQ files
     // -----------------------------------------------------
 
-    let cfg = Config {
-        barcodes1: &constants::BARCODES_1,
-        barcodes2: &constants::BARCODES_2,
-        const_region: constants::CONSTANT_REGION,
-        window: constants::CONSTANT_REGION_WINDOW,
-        rbs_len: constants::RBS_LEN,
-        barcode_len: constants::BARCODE_LEN,
-        max_n: constants::MAX_N_COUNT,
-        non_flipped: constants::NON_FLIPPED_SEQ,
-        flipped: constants::FLIPPED_SEQ,
-        disc_offset: constants::DISCRIMINATOR_OFFSET,
+    // Each file is decompressed on its own thread, long reads come from a
+    // single file
+    let (mut reader1, mut reader2) = if opts.long_reads.is_some() {
+        info!("Processing long read FASTQ file: {}", path1);
+        let reader1 = ChunkReader::spawn(path1, opts.chunk_size)
+            .map_err(|e| RunError::Input(format!("{path1}: {e}")))?;
+        (reader1, None)
+    } else {
+        info!("Processing FASTQ files: {} and {}", path1, path2);
+        let (reader1, reader2) =
+            ChunkReader::spawn_pair(path1, path2, opts.chunk_size).map_err(
+                |e| RunError::Input(format!("{path1}, {path2}: {e}")),
+            )?;
+        (reader1, Some(reader2))
     };
 
     // -----------------------------------------------------
-    // Load FASTQ files
+    // Initialise counters
     // -----------------------------------------------------
 
-    info!("Processing FASTQ files: {} and {}", path1, path2);
+    let mut i = 0;
+    let mut n = 0;
+    let mut compactions = 0;
+    let mut interrupted = false;
+    let counters = Arc::new(Counters::default());
 
-    let file1 = BufReader::new(File::open(path1).unwrap());
-    let file2 = BufReader::new(File::open(path2).unwrap());
+    // Chunks left over by an earlier run would be merged again
+    for stale in dirs.partitions().unwrap_or_default() {
+        fs::remove_dir_all(&stale).map_err(|e| {
+            RunError::Output(format!("{}: {e}", stale.display()))
+        })?;
+    }
 
-    let reader1 = fastq::Reader::new(MultiGzDecoder::new(file1));
-    let reader2 = fastq::Reader::new(MultiGzDecoder::new(file2));
+    // Counts of an earlier run are merged as one more chunk
+    let mut resume_from = 0;
+    let previous_inputs = if opts.append || opts.resume {
+        let path = dirs.root.join("manifest.json");
+        let previous = Manifest::read(&path).map_err(|e| {
+            RunError::Output(format!("{}: {e}", path.display()))
+        })?;
+
+        match take_previous_run(&dirs, sample_name, opts.layout, &counters) {
+            Ok(rows) => info!("Appending to {} previous count rows", rows),
+            Err(err) => {
+                return Err(RunError::Output(format!("previous run: {err}")))
+            }
+        }
 
-    let mut iter1 = reader1.records();
-    let mut iter2 = reader2.records();
+        // A resumed run carries on with the same inputs
+        if opts.resume {
+            resume_from = previous.processed_pairs.unwrap_or(0);
+            info!("Resuming after {} read pairs", resume_from);
+            previous.previous_inputs
+        } else {
+            [previous.previous_inputs, previous.inputs].concat()
+        }
+    } else {
+        Vec::new()
+    };
+    let mut skipped = 0;
+    let mut warnings = Vec::new();
+
+    // Counts accumulate across chunks until the memory budget is exceeded
+    let table: SampleTable = DashMap::new();
+
+    // Demultiplexed reads, created lazily under data/fastq
+    let demux = opts
+        .write_fastq
+        .then(|| FastqPairWriters::new(&dirs.fastq))
+        .transpose()
+        .map_err(|e| {
+            RunError::Output(format!("{}: {e}", dirs.fastq.display()))
+        })?;
+
+    // Read pairs failing classification
+    let rejects = opts
+        .write_rejects
+        .then(|| FastqPairWriters::new(&dirs.rejects))
+        .transpose()
+        .map_err(|e| {
+            RunError::Output(format!("{}: {e}", dirs.rejects.display()))
+        })?;
+
+    // Control RBSs tallied on the side
+    let spikes = opts
+        .spike_ins
+        .as_ref()
+        .map(|path| {
+            SpikeIns::load(path).map_err(|e| {
+                RunError::Input(format!("{}: {e}", path.display()))
+            })
+        })
+        .transpose()?;
+
+    // Subsample of the valid pairs for the duplicate rate
+    let duplicates = opts.duplicates.map(DuplicateSampler::new);
+
+    // Per-position variants of a subsample against the reference construct
+    let pileup = opts
+        .variants
+        .as_ref()
+        .map(|variants| {
+            let reference =
+                read_reference(&variants.reference).map_err(|e| {
+                    RunError::Input(format!(
+                        "{}: {e}",
+                        variants.reference.display()
+                    ))
+                })?;
+            Ok(VariantPileup::new(reference, variants.sample_every))
+        })
+        .transpose()?;
 
-    // -----------------------------------------------------
-    // Initialise counters
-    // -----------------------------------------------------
+    // Per-chunk resources in qc/performance.parquet
+    let mut perf = Performance::default();
 
-    let mut i = 0;
-    let mut n = 0;
-    let counters = Arc::new(Counters::default());
+    // Live counters for Prometheus, served until the end of the run
+    let metrics = opts
+        .metrics_port
+        .map(|port| {
+            let addr = SocketAddr::new(opts.metrics_addr, port);
+            MetricsServer::start(addr, sample_name, counters.clone())
+        })
+        .transpose()
+        .map_err(|e| RunError::Config(format!("metrics port: {e}")))?;
+
+    // Per-chunk timings in trace.tsv
+    let trace_path = dirs.root.join("trace.tsv");
+    let mut trace = opts
+        .trace
+        .then(|| ChunkTrace::create(&trace_path))
+        .transpose()
+        .map_err(|e| {
+            RunError::Output(format!("{}: {e}", trace_path.display()))
+        })?;
 
     // -----------------------------------------------------
     // Process FASTQ files in chunks
     // -----------------------------------------------------
-    loop {
+    for chunk in 0.. {
+        let _chunk_span = info_span!("chunk", index = chunk).entered();
         info!("Processing {}", n);
 
-        let chunk1: Vec<_> = iter1.by_ref().take(chunk_size).collect();
-        let chunk2: Vec<_> = iter2.by_ref().take(chunk_size).collect();
+        if let Some(metrics) = &metrics {
+            metrics.set_chunk(chunk);
+        }
+
+        let chunk_start = Instant::now();
+        let read_start = Instant::now();
+        let mut chunk1 = reader1.next().unwrap_or_default();
+        let mut chunk2 = match &mut reader2 {
+            Some(reader2) => reader2.next().unwrap_or_default(),
+            None => Vec::new(),
+        };
+        let read_time = read_start.elapsed();
 
-        if chunk1.is_empty() || chunk2.is_empty() {
+        if chunk1.is_empty() || (reader2.is_some() && chunk2.is_empty()) {
             info!("No more records to process.");
             break;
         }
 
-        let table: SampleTable = DashMap::new();
+        // Pairs counted by the interrupted run are read past
+        if skipped < resume_from {
+            let skip = (resume_from - skipped).min(chunk1.len() as u64);
+            chunk1.drain(..skip as usize);
+            chunk2.drain(..(skip as usize).min(chunk2.len()));
+            skipped += skip;
+            if chunk1.is_empty() {
+                continue;
+            }
+        }
+
+        let classify_start = Instant::now();
 
-        chunk1
-            .par_iter()
-            .zip(chunk2.par_iter())
-            .for_each(|(rec1, rec2)| {
+        // Each long read may hold several constructs, or none
+        if let Some(long_reads) = &opts.long_reads {
+            chunk1.par_iter().for_each(|rec| {
                 counters.inc_total();
 
-                match classify_pair(
-                    &cfg,
-                    &rec1.as_ref().unwrap(),
-                    &rec2.as_ref().unwrap(),
-                ) {
-                    Ok(Ok((sample, rbs, flipped))) => {
-                        counters.inc_valid();
+                match rec {
+                    Ok(rec) => count_long_read(
+                        &cfg,
+                        rec,
+                        long_reads,
+                        &counters,
+                        &table,
+                        spikes.as_ref(),
+                    ),
+                    Err(e) => {
+                        warn!("Malformed record: {}", e);
+                        counters.inc_fail(FailReason::MalformedRecord);
+                    }
+                }
+            });
+        }
 
-                        let inner = table
-                            .entry(sample)
-                            .or_insert_with(|| DashMap::new());
+        // Failures to write demultiplexed or rejected reads end the run
+        let written = chunk1.par_iter().zip(chunk2.par_iter()).try_for_each(
+            |(rec1, rec2)| {
+                counters.inc_total();
 
-                        let cell = inner
-                            .entry(rbs.to_owned())
-                            .or_insert_with(|| Default::default());
+                let (rec1, rec2) = match (rec1, rec2) {
+                    (Ok(rec1), Ok(rec2)) => (rec1, rec2),
+                    (rec1, rec2) => {
+                        for e in [rec1.as_ref().err(), rec2.as_ref().err()]
+                            .into_iter()
+                            .flatten()
+                        {
+                            warn!("Malformed record: {}", e);
+                        }
+                        counters.inc_fail(FailReason::MalformedRecord);
+                        return Ok(());
+                    }
+                };
+
+                // Overlapping mates are classified as a single fragment
+                let merged = opts.merge_overlap.and_then(|overlap| {
+                    merge_pair(
+                        rec1.seq(),
+                        rec1.qual(),
+                        rec2.seq(),
+                        rec2.qual(),
+                        &overlap,
+                    )
+                });
+                let classified = match &merged {
+                    Some(merged) => {
+                        counters.inc_merged();
+                        classifier.classify_merged(rec1, rec2, merged)
+                    }
+                    None => classifier.classify_pair(rec1, rec2),
+                };
 
-                        let idx = match flipped {
-                            Flip::NonFlipped => 0,
-                            Flip::Flipped => 1,
-                        };
+                match classified {
+                    Ok(Ok((sample, rbs, flipped))) => {
+                        counters.inc_valid();
 
-                        cell[idx].fetch_add(1, Ordering::Relaxed);
+                        if let Some(demux) = &demux {
+                            let name = format!(
+                                "{}_{}",
+                                sample.barcode1, sample.barcode2
+                            );
+                            demux.write(&name, rec1, rec2)?;
+                        }
+
+                        // Controls are tallied apart, never counted
+                        let spike = spikes
+                            .as_ref()
+                            .is_some_and(|s| s.record(&sample, rbs, flipped));
+
+                        if let Some(duplicates) = &duplicates {
+                            duplicates.record(&sample, rbs, rec1.seq());
+                        }
+
+                        if let (Some(pileup), Some(variants)) =
+                            (&pileup, &opts.variants)
+                        {
+                            if pileup.is_sampled(rec1.id()) {
+                                let seq = match variants.read {
+                                    1 => rec1.seq(),
+                                    _ => rec2.seq(),
+                                };
+                                pileup.record(&sample, seq);
+                            }
+                        }
+
+                        if !spike {
+                            add_to_table(&table, sample, rbs, flipped);
+                        }
+                    }
+                    Ok(Err(reason)) => {
+                        counters.inc_fail(reason);
+
+                        if let Some(rejects) = &rejects {
+                            let written = if opts.annotate_rejects {
+                                rejects.write(
+                                    "rejects",
+                                    &reason.annotate(rec1),
+                                    &reason.annotate(rec2),
+                                )
+                            } else {
+                                rejects.write("rejects", rec1, rec2)
+                            };
+                            written?;
+                        }
+                    }
+                    Err(func_err) => {
+                        warn!("Malformed record {}: {}", rec1.id(), func_err);
+                        counters.inc_fail(FailReason::MalformedRecord);
                     }
-                    Ok(Err(reason)) => counters.inc_fail(reason),
-                    Err(func_err) => panic!("Problem in pairs: {}", func_err),
                 }
-            });
+
+                Ok::<_, io::Error>(())
+            },
+        );
+        written.map_err(|e| RunError::Output(format!("FASTQ output: {e}")))?;
 
         n += chunk1.len();
-        i += 1;
+        let classify_time = classify_start.elapsed();
 
         // -----------------------------------------------------
-        // Write results to Parquet
+        // Spill results to Parquet when over the memory budget
         // -----------------------------------------------------
-        let df = match table_to_dataframe(&table) {
-            Ok(df) => df,
-            Err(e) => {
-                // TODO: Handle zero padding more gracefully
-                panic!("chunk {i:06}: table to DataFrame failed: {e}");
+        let spill_start = Instant::now();
+        let (table_samples, entries) = (table.len(), table_entries(&table));
+        let used = estimate_table_bytes(&table, cfg.rbs_len);
+        if used > opts.max_memory {
+            i += 1;
+            info!("Table uses ~{} bytes, spilling to disk", used);
+            spill_table(&table, &dirs, i, opts.deterministic)
+                .map_err(RunError::Output)?;
+
+            if let Some(max_bytes) = opts.max_tmp_bytes {
+                if compact_chunks(&dirs, i, max_bytes)
+                    .map_err(RunError::Output)?
+                {
+                    compactions += 1;
+                }
             }
-        };
+        }
 
-        let path = dirs.parquet.join(format!("chunk_{i:09}.parquet"));
+        if let Some(trace) = &mut trace {
+            trace
+                .record(
+                    chunk,
+                    chunk1.len(),
+                    read_time,
+                    classify_time,
+                    spill_start.elapsed(),
+                )
+                .map_err(|e| {
+                    RunError::Output(format!("{}: {e}", trace_path.display()))
+                })?;
+        }
 
-        match write_parquet_chunk(&df, &path.to_string_lossy()) {
-            Ok(_) => {
-                info!("Wrote {} ({} rows)", path.display(), df.height());
+        perf.record(
+            chunk,
+            chunk1.len(),
+            chunk_start.elapsed(),
+            table_samples,
+            entries,
+        );
+
+        let errors = counters.fail_count(FailReason::MalformedRecord);
+        if opts.max_errors.is_some_and(|max| errors > max) {
+            warn!("Too many malformed records ({}), stopping early", errors);
+            warnings.push(format!(
+                "stopped early after {errors} malformed records"
+            ));
+            break;
+        }
+
+        if is_interrupted() {
+            warn!("Interrupted, stopping after {} read pairs", n);
+            interrupted = true;
+            break;
+        }
+    }
+
+    if let Some(trace) = trace {
+        match trace.finish() {
+            Ok(_) => info!("Wrote chunk trace"),
+            Err(err) => {
+                return Err(RunError::Output(format!("chunk trace: {err}")))
+            }
+        }
+    }
+
+    if let Some(demux) = demux {
+        match demux.finish() {
+            Ok(_) => info!("Wrote demultiplexed FASTQ files"),
+            Err(err) => {
+                return Err(RunError::Output(format!(
+                    "demultiplexed FASTQ: {err}"
+                )))
+            }
+        }
+    }
+
+    if let Some(rejects) = rejects {
+        match rejects.finish() {
+            Ok(_) => info!("Wrote rejected reads"),
+            Err(err) => {
+                return Err(RunError::Output(format!("rejected reads: {err}")))
             }
-            Err(err) => panic!("chunk {i:06}: failed to write parquet: {err}"),
         }
     }
 
+    let malformed = counters.fail_count(FailReason::MalformedRecord);
+    if malformed > 0 {
+        warnings.push(format!("{malformed} malformed records skipped"));
+    }
+
+    // -----------------------------------------------------
+    // Merge chunks
+
+    let partitions = if opts.in_memory && i == 0 && !opts.append && !opts.resume
+    {
+        // Nothing was spilled, the table already holds the final counts
+        info!("Building counts from memory");
+        match table_to_dataframe(&table, opts.deterministic) {
+            Ok(df) => vec![df],
+            Err(err) => {
+                return Err(RunError::Processing(format!(
+                    "couldn't build counts: {err}"
+                )))
+            }
+        }
+    } else {
+        if !table.is_empty() {
+            i += 1;
+            spill_table(&table, &dirs, i, opts.deterministic)
+                .map_err(RunError::Output)?;
+        }
+
+        info!("Merging Parquet files...");
+        merge_partitions(&dirs, opts.merge_streaming).map_err(|err| {
+            RunError::Output(format!("couldn't merge chunk files: {err}"))
+        })?
+    };
+
+    // Deeper barcode pairs are cut down to the target depth first
+    let (partitions, depths) = match &opts.downsample {
+        Some(downsample) => {
+            info!("Downsampling barcode pairs to {} reads", downsample.depth);
+            match partitions
+                .into_par_iter()
+                .map(|df| downsample_counts(df, downsample))
+                .collect::<PolarsResult<Vec<(DataFrame, DataFrame)>>>()
+            {
+                Ok(downsampled) => {
+                    let (partitions, depths): (Vec<_>, Vec<_>) =
+                        downsampled.into_iter().unzip();
+                    (partitions, Some(depths))
+                }
+                Err(err) => {
+                    return Err(RunError::Processing(format!(
+                        "couldn't downsample counts: {err}"
+                    )))
+                }
+            }
+        }
+        None => (partitions, None),
+    };
+    let depths = depths
+        .map(|depths| {
+            let mut depths = depths.into_iter();
+            let first = depths.next().unwrap_or_default();
+            depths.try_fold(first, |mut acc, df| {
+                acc.vstack_mut(&df)?;
+                Ok::<_, PolarsError>(acc)
+            })
+        })
+        .transpose()
+        .map_err(|e| {
+            RunError::Processing(format!("couldn't stack depths: {e}"))
+        })?;
+
+    // CPMs are relative to the reads left after downsampling
+    let valid = match &depths {
+        Some(depths) => sum_column(depths, "reads_after"),
+        None => counters.valid_count(),
+    };
+
+    // Rows never depend on other barcode pairs, so every partition is
+    // finished on its own
+    let spike_reads = spikes
+        .as_ref()
+        .filter(|_| opts.spike_in_normalize)
+        .map(|spikes| spikes.sample_reads());
+    let finished = match partitions
+        .into_par_iter()
+        .map(|df| finish_counts(df, opts, valid, spike_reads.as_ref()))
+        .collect::<PolarsResult<Vec<(DataFrame, u64)>>>()
+    {
+        Ok(finished) => finished,
+        Err(err) => {
+            return Err(RunError::Processing(format!(
+                "couldn't finish counts: {err}"
+            )))
+        }
+    };
+    let (partitions, filtered): (Vec<DataFrame>, Vec<u64>) =
+        finished.into_iter().unzip();
+
+    if opts.min_count > 0 {
+        let filtered = filtered.iter().sum();
+        info!("Filtered {} rows below {} reads", filtered, opts.min_count);
+        counters.add_filtered_rows(filtered);
+    }
+
     // -----------------------------------------------------
     // Save QC results
 
     info!("Write QC parquet file");
-    let qc = counters.to_dataframe().unwrap();
+    let mut extra_rows = match &spikes {
+        Some(spikes) => vec![
+            ("spikein_reads", spikes.total_reads()),
+            ("spikeins_recovered", spikes.recovered()),
+            ("spikeins_expected", spikes.len() as u64),
+        ],
+        None => Vec::new(),
+    };
+    if let Some(duplicates) = &duplicates {
+        extra_rows.push(("duplicates_sampled", duplicates.sampled()));
+        extra_rows.push(("duplicates_found", duplicates.duplicates()));
+        if let Some(fraction) = duplicates.fraction() {
+            info!(
+                "Estimated duplicate fraction {:.4} from {} sampled pairs",
+                fraction,
+                duplicates.sampled()
+            );
+        }
+        // Parts per million, QC values being integers
+        extra_rows.push((
+            "duplicate_ppm",
+            duplicates
+                .fraction()
+                .map_or(0, |f| (f * 1_000_000.0).round() as u64),
+        ));
+    }
+    if opts.max_tmp_bytes.is_some() {
+        extra_rows.push(("tmp_compactions", compactions));
+    }
+    if let Some(depths) = &depths {
+        let downsampled = depths
+            .clone()
+            .lazy()
+            .filter(col("reads_before").gt(col("reads_after")))
+            .collect()
+            .map_or(0, |df| df.height());
+        extra_rows.push(("downsampled_pairs", downsampled as u64));
+        extra_rows.push((
+            "reads_before_downsampling",
+            sum_column(depths, "reads_before"),
+        ));
+        extra_rows.push((
+            "reads_after_downsampling",
+            sum_column(depths, "reads_after"),
+        ));
+    }
+    let qc = counters.to_dataframe(&extra_rows).map_err(|e| {
+        RunError::Processing(format!("couldn't build QC table: {e}"))
+    })?;
+
+    // Outputs carry the tool, schema and settings in their metadata
+    let meta = OutputMetadata::new(sample_name, opts);
+
+    // QC and counts merged with an earlier run are written aside and moved
+    // into place once complete, so that a failure leaves that run intact
+    let staging =
+        (opts.append || opts.resume).then(|| dirs.tmp.join("staging"));
+    let (qc_dir, counts_dir) = match &staging {
+        Some(staging) => {
+            // Left over by a run that failed while replacing its outputs
+            if staging.exists() {
+                fs::remove_dir_all(staging).map_err(|e| {
+                    RunError::Output(format!("{}: {e}", staging.display()))
+                })?;
+            }
+            let dirs = (staging.join("qc"), staging.join("counts"));
+            for dir in [&dirs.0, &dirs.1] {
+                fs::create_dir_all(dir).map_err(|e| {
+                    RunError::Output(format!("{}: {e}", dir.display()))
+                })?;
+            }
+            dirs
+        }
+        None => (dirs.qc.clone(), dirs.counts.clone()),
+    };
 
-    match write_qc_parquet(&qc, &dirs.qc, "sample_name") {
+    match write_qc_parquet(&qc, &qc_dir, &meta, opts.layout) {
         Ok(_) => info!("Wrote QC parquet file"),
-        Err(err) => panic!("Couldn't write QC parquet file: {err}"),
+        Err(err) => {
+            return Err(RunError::Output(format!("QC parquet file: {err}")))
+        }
+    }
+
+    match write_performance_parquet(&perf, &dirs.qc, &meta) {
+        Ok(_) => info!(
+            "Wrote performance parquet file (peak RSS {} bytes)",
+            perf.peak_rss().map_or("unknown".into(), |b| b.to_string())
+        ),
+        Err(err) => {
+            return Err(RunError::Output(format!(
+                "performance parquet file: {err}"
+            )))
+        }
+    }
+
+    if let Some(spikes) = &spikes {
+        match spikes.write_tsv(dirs.qc.join("spikeins.tsv")) {
+            Ok(_) => info!("Wrote spike-in recovery"),
+            Err(err) => {
+                return Err(RunError::Output(format!(
+                    "spike-in recovery: {err}"
+                )))
+            }
+        }
+
+        if spikes.recovered() < spikes.len() as u64 {
+            warnings.push(format!(
+                "{} of {} spike-ins not recovered",
+                spikes.len() as u64 - spikes.recovered(),
+                spikes.len()
+            ));
+        }
+    }
+
+    if let Some(mut depths) = depths {
+        let written = File::create(dirs.qc.join("downsampling.parquet"))
+            .map_err(PolarsError::from)
+            .and_then(|file| {
+                meta.writer(file, OutputSchema::Downsampling)
+                    .finish(&mut depths)
+            });
+        match written {
+            Ok(_) => info!("Wrote depths of {} barcode pairs", depths.height()),
+            Err(err) => {
+                return Err(RunError::Output(format!(
+                    "downsampling depths: {err}"
+                )))
+            }
+        }
+    }
+
+    if let Some(pileup) = &pileup {
+        let written = pileup.to_dataframe(sample_name).and_then(|mut df| {
+            let file = File::create(dirs.qc.join("variants.parquet"))?;
+            meta.writer(file, OutputSchema::Variants).finish(&mut df)?;
+            Ok(())
+        });
+        match written {
+            Ok(_) => {
+                info!("Wrote variants of {} aligned reads", pileup.aligned())
+            }
+            Err(err) => {
+                return Err(RunError::Output(format!("variants: {err}")))
+            }
+        }
+    }
+
+    if let Some(corrections) = &corrections {
+        let path = dirs.qc.join("barcode_corrections.tsv");
+        match corrections.write_tsv(path) {
+            Ok(_) => info!("Wrote {} barcode corrections", corrections.total()),
+            Err(err) => {
+                return Err(RunError::Output(format!(
+                    "barcode corrections: {err}"
+                )))
+            }
+        }
     }
 
     // -----------------------------------------------------
     // Write final results to Parquet
 
-    info!("Merging Parquet files...");
-    let counts = concat_parquet_dir(&dirs.parquet);
+    let whole = |partitions: &[DataFrame]| {
+        concat_counts(partitions, opts.deterministic).map_err(|e| {
+            RunError::Processing(format!("couldn't concatenate counts: {e}"))
+        })
+    };
 
-    match write_partitioned_parquet(
-        &counts,
-        &dirs.counts,
-        sample_name,
-        Some(parquet_size),
-    ) {
+    match write_counts(&partitions, &counts_dir, sample_name, opts) {
         Ok(_) => info!("Wrote counts parquet files"),
-        Err(err) => panic!("Couldn't write counts parquet files: {err}"),
+        Err(err) => {
+            return Err(RunError::Output(format!(
+                "counts parquet files: {err}"
+            )))
+        }
+    }
+
+    if let Some(staging) = &staging {
+        match promote_staged(staging, &dirs, sample_name, opts.layout) {
+            Ok(_) => info!("Replaced the counts of the earlier run"),
+            Err(err) => {
+                return Err(RunError::Output(format!("earlier run: {err}")))
+            }
+        }
+    }
+
+    // Element-level counts through the barcode association of an MPRA
+    let association = opts.mpra.as_ref().and_then(|m| m.association.as_ref());
+    if let Some(path) = association {
+        let association = read_association(path)
+            .map_err(|e| RunError::Input(format!("{}: {e}", path.display())))?;
+
+        let (mut elements, unassociated) =
+            match element_counts(&whole(&partitions)?, &association) {
+                Ok(counts) => counts,
+                Err(err) => {
+                    return Err(RunError::Processing(format!(
+                        "couldn't build element counts: {err}"
+                    )))
+                }
+            };
+        info!("{} reads with barcodes of no element", unassociated);
+
+        match write_element_counts(&mut elements, &dirs.elements, &meta) {
+            Ok(_) => info!("Wrote {} element count rows", elements.height()),
+            Err(err) => {
+                return Err(RunError::Output(format!("element counts: {err}")))
+            }
+        }
+    }
+
+    if opts.csv_stdout || opts.export_db.is_some() {
+        let counts = whole(&partitions)?;
+
+        if opts.csv_stdout {
+            let shaped = match opts.counts_shape {
+                CountsShape::Wide => Ok(counts.clone()),
+                CountsShape::Long => wide_to_long(&counts, opts.deterministic),
+            };
+            let written = shaped.and_then(|mut counts| {
+                CsvWriter::new(io::stdout()).finish(&mut counts)
+            });
+            match written {
+                Ok(_) => info!("Wrote counts CSV to stdout"),
+                Err(err) => {
+                    return Err(RunError::Output(format!("counts CSV: {err}")))
+                }
+            }
+        }
+
+        if let Some((path, kind)) = &opts.export_db {
+            match export_counts(&counts, sample_name, path, *kind) {
+                Ok(_) => info!("Exported counts to {}", path.display()),
+                Err(err) => {
+                    return Err(RunError::Output(format!(
+                        "counts export: {err}"
+                    )))
+                }
+            }
+        }
+    }
+
+    // -----------------------------------------------------
+    // Enforce retention policy and write manifest
+
+    let mut outputs = vec![
+        (OutputKind::Qc, dirs.qc.clone()),
+        (OutputKind::Counts, dirs.counts.clone()),
+        (OutputKind::Tmp, dirs.tmp.clone()),
+    ];
+
+    if association.is_some() {
+        outputs.push((OutputKind::Elements, dirs.elements.clone()));
+    }
+
+    if opts.write_fastq {
+        outputs.push((OutputKind::Fastq, dirs.fastq.clone()));
+    }
+
+    if opts.write_rejects {
+        outputs.push((OutputKind::Rejects, dirs.rejects.clone()));
+    }
+
+    let pruned = match retention::enforce(&opts.retention, &outputs) {
+        Ok(pruned) => pruned,
+        Err(err) => {
+            return Err(RunError::Output(format!("retention policy: {err}")))
+        }
+    };
+
+    for p in pruned.iter().filter(|p| p.reason != "dropped") {
+        warnings.push(format!("{} pruned: {}", p.kind, p.reason));
+    }
+
+    let manifest = Manifest {
+        sample_name: sample_name.to_string(),
+        layout: opts.layout,
+        counts_shape: opts.counts_shape,
+        status: if interrupted {
+            RunStatus::Interrupted
+        } else {
+            RunStatus::Completed
+        },
+        processed_pairs: interrupted.then_some(skipped + n as u64),
+        inputs: vec![PathBuf::from(path1), PathBuf::from(path2)],
+        previous_inputs,
+        min_count: opts.min_count,
+        outputs: outputs
+            .into_iter()
+            .filter(|(kind, _)| !pruned.iter().any(|p| p.kind == *kind))
+            .collect(),
+        pruned,
+    };
+
+    match manifest.write(dirs.root.join("manifest.json")) {
+        Ok(_) => info!("Wrote run manifest"),
+        Err(err) => {
+            return Err(RunError::Output(format!("run manifest: {err}")))
+        }
+    }
+
+    match write_versions_yml(dirs.root.join("versions.yml")) {
+        Ok(_) => info!("Wrote versions file"),
+        Err(err) => {
+            return Err(RunError::Output(format!("versions file: {err}")))
+        }
     }
 
     info!("Processing complete.");
+
+    let mut summary =
+        RunSummary::new(&counters, manifest, warnings, start.elapsed());
+    summary.duplicate_fraction =
+        duplicates.as_ref().and_then(DuplicateSampler::fraction);
+    Ok(summary)
 }
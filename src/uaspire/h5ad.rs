@@ -0,0 +1,181 @@
+/// AnnData (`.h5ad`) export of count matrices, with one observation per RBS
+/// and one variable per matrix column.
+use polars::prelude::*;
+
+use std::{error::Error, path::Path};
+
+/// Dense values of every column but the first, row-major.
+#[cfg(feature = "h5ad")]
+fn dense_values(matrix: &DataFrame) -> PolarsResult<Vec<f64>> {
+    let columns = matrix
+        .get_columns()
+        .iter()
+        .skip(1)
+        .map(|c| c.cast(&DataType::Float64))
+        .collect::<PolarsResult<Vec<_>>>()?;
+
+    let mut values = Vec::with_capacity(matrix.height() * columns.len());
+    for row in 0..matrix.height() {
+        for column in &columns {
+            values.push(column.f64()?.get(row).unwrap_or(f64::NAN));
+        }
+    }
+
+    Ok(values)
+}
+
+#[cfg(feature = "h5ad")]
+mod encode {
+    use hdf5::types::VarLenUnicode;
+    use hdf5::{Group, Location, Result};
+
+    use std::str::FromStr;
+
+    fn unicode(s: &str) -> VarLenUnicode {
+        VarLenUnicode::from_str(s).unwrap_or_default()
+    }
+
+    /// Tag an element with the AnnData encoding attributes.
+    pub(super) fn encoding(
+        location: &Location,
+        kind: &str,
+        version: &str,
+    ) -> Result<()> {
+        location
+            .new_attr::<VarLenUnicode>()
+            .create("encoding-type")?
+            .write_scalar(&unicode(kind))?;
+        location
+            .new_attr::<VarLenUnicode>()
+            .create("encoding-version")?
+            .write_scalar(&unicode(version))
+    }
+
+    pub(super) fn strings(
+        group: &Group,
+        name: &str,
+        values: &[String],
+    ) -> Result<()> {
+        let values: Vec<VarLenUnicode> =
+            values.iter().map(|s| unicode(s)).collect();
+        let dataset = group
+            .new_dataset::<VarLenUnicode>()
+            .shape(values.len())
+            .create(name)?;
+        dataset.write_raw(&values)?;
+        encoding(&dataset, "string-array", "0.2.0")
+    }
+
+    pub(super) fn numbers(
+        group: &Group,
+        name: &str,
+        values: &[f64],
+    ) -> Result<()> {
+        let dataset = group
+            .new_dataset::<f64>()
+            .shape(values.len())
+            .create(name)?;
+        dataset.write_raw(values)?;
+        encoding(&dataset, "array", "0.2.0")
+    }
+
+    /// Dataframe group indexed by `index`, with numeric columns.
+    pub(super) fn dataframe(
+        parent: &Group,
+        name: &str,
+        index: &[String],
+        columns: &[(String, Vec<f64>)],
+    ) -> Result<()> {
+        let group = parent.create_group(name)?;
+        encoding(&group, "dataframe", "0.2.0")?;
+
+        group
+            .new_attr::<VarLenUnicode>()
+            .create("_index")?
+            .write_scalar(&unicode("_index"))?;
+
+        let order: Vec<VarLenUnicode> =
+            columns.iter().map(|(name, _)| unicode(name)).collect();
+        group
+            .new_attr::<VarLenUnicode>()
+            .shape(order.len())
+            .create("column-order")?
+            .write_raw(&order)?;
+
+        strings(&group, "_index", index)?;
+        for (name, values) in columns {
+            numbers(&group, name, values)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Write `matrix`, whose first column holds the RBS, as an AnnData file.
+///
+/// `obs` holds per-RBS metadata in the same row order as the matrix, its
+/// first column being the RBS as well.
+#[cfg(feature = "h5ad")]
+pub fn write_h5ad(
+    matrix: &DataFrame,
+    obs: &DataFrame,
+    path: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let index = |df: &DataFrame| -> PolarsResult<Vec<String>> {
+        let column = df.get_columns()[0].cast(&DataType::String)?;
+        Ok(column
+            .str()?
+            .into_iter()
+            .map(|s| s.unwrap_or_default().to_string())
+            .collect())
+    };
+
+    let obs_names = index(matrix)?;
+    let var_names: Vec<String> = matrix
+        .get_column_names()
+        .iter()
+        .skip(1)
+        .map(|s| s.to_string())
+        .collect();
+
+    let mut obs_columns = Vec::new();
+    for column in obs.get_columns().iter().skip(1) {
+        let values = column.cast(&DataType::Float64)?;
+        let values: Vec<f64> = values
+            .f64()?
+            .into_iter()
+            .map(|v| v.unwrap_or(f64::NAN))
+            .collect();
+        obs_columns.push((column.name().to_string(), values));
+    }
+
+    let file = hdf5::File::create(path)?;
+    encode::encoding(&file, "anndata", "0.1.0")?;
+
+    let x = file
+        .new_dataset::<f64>()
+        .shape((obs_names.len(), var_names.len()))
+        .create("X")?;
+    x.write_raw(&dense_values(matrix)?)?;
+    encode::encoding(&x, "array", "0.2.0")?;
+
+    encode::dataframe(&file, "obs", &obs_names, &obs_columns)?;
+    encode::dataframe(&file, "var", &var_names, &[])?;
+
+    // Empty containers expected by readers
+    for name in ["uns", "obsm", "varm", "layers", "obsp", "varp"] {
+        let group = file.create_group(name)?;
+        encode::encoding(&group, "dict", "0.1.0")?;
+    }
+
+    Ok(())
+}
+
+#[cfg(not(feature = "h5ad"))]
+pub fn write_h5ad(
+    _matrix: &DataFrame,
+    _obs: &DataFrame,
+    _path: &Path,
+) -> Result<(), Box<dyn Error>> {
+    Err("AnnData export requires the `h5ad` feature".into())
+}
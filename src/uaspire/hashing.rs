@@ -0,0 +1,32 @@
+/// Hashes that decide outputs, such as the chunk partition of a barcode
+/// pair or the pairs sampled for QC.
+///
+/// `DefaultHasher` may change between Rust releases, while these values
+/// must hold across runs resumed or appended by another build. FNV-1a is
+/// fully specified, its result is finished by the SplitMix64 mixer so that
+/// nearby inputs land far apart.
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Hash of `parts`, each preceded by its length so that no two lists of
+/// parts run together.
+pub(crate) fn stable_hash(parts: &[&[u8]]) -> u64 {
+    let mut hash = FNV_OFFSET;
+    for part in parts {
+        let len = (part.len() as u64).to_le_bytes();
+        for &byte in len.iter().chain(part.iter()) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    mix(hash)
+}
+
+/// SplitMix64 finalizer.
+pub(crate) fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
@@ -0,0 +1,176 @@
+/// Human-readable overview of the counts written by a run.
+use polars::prelude::*;
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::File,
+    io::{self, Write},
+    path::Path,
+};
+
+use crate::uaspire::matrix::{counts_root, sample_files};
+
+// ---------- Report ----------
+
+#[derive(Debug, Clone)]
+pub struct SampleSummary {
+    pub sample: String,
+    pub rows: u64,
+    pub reads: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct TopRbs {
+    pub sample: String,
+    pub gre: String,
+    pub reads: u64,
+}
+
+/// Schema, partitions, per-sample totals and most frequent RBSs of a run.
+#[derive(Debug, Clone)]
+pub struct InspectReport {
+    pub schema: Vec<(String, String)>,
+    pub partitions: BTreeMap<String, BTreeSet<String>>,
+    pub samples: Vec<SampleSummary>,
+    pub top: Vec<TopRbs>,
+}
+
+impl InspectReport {
+    pub fn print(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "Schema")?;
+        for (name, dtype) in &self.schema {
+            writeln!(out, "  {:<24} {}", name, dtype)?;
+        }
+
+        writeln!(out)?;
+        writeln!(out, "Partitions")?;
+        for (sample, partitions) in &self.partitions {
+            writeln!(out, "  {} ({} partitions)", sample, partitions.len())?;
+            for partition in partitions.iter().filter(|p| !p.is_empty()) {
+                writeln!(out, "    {}", partition)?;
+            }
+        }
+
+        writeln!(out)?;
+        writeln!(out, "{:<24} {:>12} {:>14}", "sample", "rows", "reads")?;
+        for s in &self.samples {
+            writeln!(out, "{:<24} {:>12} {:>14}", s.sample, s.rows, s.reads)?;
+        }
+
+        writeln!(out)?;
+        writeln!(out, "{:<24} {:<32} {:>14}", "sample", "rbs", "reads")?;
+        for t in &self.top {
+            writeln!(out, "{:<24} {:<32} {:>14}", t.sample, t.gre, t.reads)?;
+        }
+
+        Ok(())
+    }
+}
+
+// =========================================================
+// Core logic
+// =========================================================
+
+/// Inspect the counts of a run output directory, or of a counts directory,
+/// listing the `top` RBSs by total reads.
+pub fn inspect(dir: &Path, top: usize) -> PolarsResult<InspectReport> {
+    let root = counts_root(dir);
+    let files = sample_files(&root)?;
+
+    let Some((_, first)) = files.first() else {
+        polars_bail!(ComputeError: "no counts found in {}", root.display());
+    };
+
+    let schema = ParquetReader::new(File::open(first)?)
+        .schema()?
+        .iter()
+        .map(|(name, field)| (name.to_string(), format!("{:?}", field.dtype)))
+        .collect();
+
+    // Hive directories between the sample and the files
+    let mut partitions: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    let mut frames = Vec::with_capacity(files.len());
+    for (sample, path) in &files {
+        let partition = path
+            .parent()
+            .and_then(|p| p.strip_prefix(&root).ok())
+            .map(|p| {
+                p.components()
+                    .filter_map(|c| c.as_os_str().to_str())
+                    .filter(|c| !c.starts_with("sample="))
+                    .collect::<Vec<_>>()
+                    .join("/")
+            })
+            .unwrap_or_default();
+        partitions
+            .entry(sample.clone())
+            .or_default()
+            .insert(partition);
+
+        let df = ParquetReader::new(File::open(path)?).finish()?;
+        frames.push(
+            df.lazy().select([
+                lit(sample.as_str()).alias("sample"),
+                col("gre"),
+                (col("unflipped") + col("flipped"))
+                    .cast(DataType::UInt64)
+                    .alias("reads"),
+            ]),
+        );
+    }
+
+    let counts = concat(&frames, UnionArgs::default())?;
+
+    let per_sample = counts
+        .clone()
+        .group_by_stable([col("sample")])
+        .agg([
+            len().cast(DataType::UInt64).alias("rows"),
+            col("reads").sum(),
+        ])
+        .sort(["sample"], SortMultipleOptions::default())
+        .collect()?;
+
+    let samples = per_sample
+        .column("sample")?
+        .str()?
+        .into_iter()
+        .zip(per_sample.column("rows")?.u64()?)
+        .zip(per_sample.column("reads")?.u64()?)
+        .map(|((sample, rows), reads)| SampleSummary {
+            sample: sample.unwrap_or_default().to_string(),
+            rows: rows.unwrap_or(0),
+            reads: reads.unwrap_or(0),
+        })
+        .collect();
+
+    let top_rbs = counts
+        .group_by([col("sample"), col("gre")])
+        .agg([col("reads").sum()])
+        .sort(
+            ["reads"],
+            SortMultipleOptions::default().with_order_descending(true),
+        )
+        .limit(top as IdxSize)
+        .collect()?;
+
+    let top = top_rbs
+        .column("sample")?
+        .str()?
+        .into_iter()
+        .zip(top_rbs.column("gre")?.str()?)
+        .zip(top_rbs.column("reads")?.u64()?)
+        .map(|((sample, gre), reads)| TopRbs {
+            sample: sample.unwrap_or_default().to_string(),
+            gre: gre.unwrap_or_default().to_string(),
+            reads: reads.unwrap_or(0),
+        })
+        .collect();
+
+    Ok(InspectReport {
+        schema,
+        partitions,
+        samples,
+        top,
+    })
+}
@@ -0,0 +1,46 @@
+/// Graceful stop of a run on SIGINT or SIGTERM.
+///
+/// The first signal raises a flag checked between chunks, so that the run
+/// stops after the chunk in flight and still writes the counts and QC of
+/// the read pairs processed so far. A second signal exits right away.
+use signal_hook::{consts::TERM_SIGNALS, flag};
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, OnceLock,
+    },
+};
+
+// Conventional exit code of a process stopped by SIGINT
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+static INTERRUPTED: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+
+/// Install the signal handlers, once per process.
+pub fn install_handlers() -> io::Result<()> {
+    if INTERRUPTED.get().is_some() {
+        return Ok(());
+    }
+
+    let interrupted = Arc::new(AtomicBool::new(false));
+    for &signal in TERM_SIGNALS {
+        // Registered first, so it only fires when the flag is already set
+        flag::register_conditional_shutdown(
+            signal,
+            EXIT_INTERRUPTED,
+            Arc::clone(&interrupted),
+        )?;
+        flag::register(signal, Arc::clone(&interrupted))?;
+    }
+
+    let _ = INTERRUPTED.set(interrupted);
+    Ok(())
+}
+
+/// Whether a stop was requested since the handlers were installed.
+pub fn is_interrupted() -> bool {
+    INTERRUPTED
+        .get()
+        .is_some_and(|interrupted| interrupted.load(Ordering::Relaxed))
+}
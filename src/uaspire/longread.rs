@@ -0,0 +1,341 @@
+/// Long read mode for Nanopore data.
+///
+/// Long noisy reads hold whole constructs, sometimes several of them
+/// (concatemers), at no fixed position. Instead of fixed windows, the whole
+/// read is scanned on both strands with approximate matching of the constant
+/// region and the discriminators, which tolerates substitutions as well as
+/// indels. Each construct is then anchored on a constant region hit and the
+/// closest discriminator hit before it.
+use bio::io::fastq;
+use tracing::warn;
+
+use crate::uaspire::classify::{
+    add_to_table, find_anchored, Config, Counters, FailReason, Flip, Sample,
+    SampleTable,
+};
+use crate::uaspire::overlap::reverse_complement;
+use crate::uaspire::spikein::SpikeIns;
+
+// ---------- Options ----------
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LongReadOptions {
+    // Edits allowed per base of an anchor sequence
+    pub max_edit_rate: f64,
+}
+
+impl Default for LongReadOptions {
+    fn default() -> Self {
+        LongReadOptions {
+            max_edit_rate: 0.15,
+        }
+    }
+}
+
+impl LongReadOptions {
+    fn max_edits(&self, pattern: &str) -> usize {
+        (pattern.len() as f64 * self.max_edit_rate).floor() as usize
+    }
+}
+
+// ---------- Approximate matching ----------
+
+/// Approximate occurrence of a pattern, `end` being exclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hit {
+    pub start: usize,
+    pub end: usize,
+    pub edits: usize,
+}
+
+#[derive(Clone, Copy)]
+struct Cell {
+    cost: usize,
+    start: usize,
+}
+
+/// Non-overlapping occurrences of `pattern` in `text` within `max_edits`
+/// substitutions, insertions and deletions, sorted by position.
+///
+/// This is a semi-global edit distance where the pattern may start anywhere
+/// in the text; each cell carries the text position its alignment started
+/// at, so that no traceback is needed. Overlapping candidates keep the one
+/// with the fewest edits.
+pub fn find_approx(text: &[u8], pattern: &[u8], max_edits: usize) -> Vec<Hit> {
+    let m = pattern.len();
+    let mut hits: Vec<Hit> = Vec::new();
+    if m == 0 {
+        return hits;
+    }
+
+    let mut prev: Vec<Cell> =
+        (0..=m).map(|i| Cell { cost: i, start: 0 }).collect();
+    let mut cur = prev.clone();
+
+    for (j, &base) in text.iter().enumerate() {
+        cur[0] = Cell {
+            cost: 0,
+            start: j + 1,
+        };
+
+        for i in 1..=m {
+            let substitution = Cell {
+                cost: prev[i - 1].cost + usize::from(pattern[i - 1] != base),
+                start: prev[i - 1].start,
+            };
+            let extra_base = Cell {
+                cost: prev[i].cost + 1,
+                start: prev[i].start,
+            };
+            let missing_base = Cell {
+                cost: cur[i - 1].cost + 1,
+                start: cur[i - 1].start,
+            };
+
+            cur[i] = [extra_base, missing_base].into_iter().fold(
+                substitution,
+                |best, c| {
+                    if c.cost < best.cost {
+                        c
+                    } else {
+                        best
+                    }
+                },
+            );
+        }
+
+        let end = cur[m];
+        if end.cost <= max_edits {
+            let hit = Hit {
+                start: end.start,
+                end: j + 1,
+                edits: end.cost,
+            };
+
+            match hits.last_mut() {
+                Some(last) if hit.start < last.end => {
+                    if hit.edits < last.edits {
+                        *last = hit;
+                    }
+                }
+                _ => hits.push(hit),
+            }
+        }
+
+        std::mem::swap(&mut prev, &mut cur);
+    }
+
+    hits
+}
+
+// =========================================================
+// Core logic
+// =========================================================
+
+type Construct = Result<(Sample, String, Flip), FailReason>;
+
+/// Discriminator hits of both flip states, the better one kept where they
+/// overlap.
+fn find_discriminators(
+    cfg: &Config,
+    seq: &[u8],
+    opts: &LongReadOptions,
+) -> Vec<(Hit, Flip)> {
+    let mut hits: Vec<(Hit, Flip)> = Vec::new();
+    for (disc, flip) in [
+        (cfg.non_flipped, Flip::NonFlipped),
+        (cfg.flipped, Flip::Flipped),
+    ] {
+        for hit in find_approx(seq, disc.as_bytes(), opts.max_edits(disc)) {
+            hits.push((hit, flip));
+        }
+    }
+    hits.sort_by_key(|(hit, _)| (hit.start, hit.edits));
+
+    let mut kept: Vec<(Hit, Flip)> = Vec::with_capacity(hits.len());
+    for (hit, flip) in hits {
+        match kept.last_mut() {
+            Some((last, last_flip)) if hit.start < last.end => {
+                if hit.edits < last.edits {
+                    *last = hit;
+                    *last_flip = flip;
+                }
+            }
+            _ => kept.push((hit, flip)),
+        }
+    }
+
+    kept
+}
+
+/// Barcodes and RBS of one construct, `forward` being read in the read 1
+/// direction and `reverse` in the read 2 direction.
+fn extract_construct(
+    cfg: &Config,
+    forward: &str,
+    reverse: &str,
+    disc: (Hit, Flip),
+    constant: Hit,
+) -> Construct {
+    // -----------------------------------------------------
+    // 1. Extract RBS and barcode 2 around the constant region
+    // -----------------------------------------------------
+    if constant.start < cfg.barcode_len {
+        return Err(FailReason::ConstantPos);
+    }
+    let rbs = match cfg.rbs_anchor {
+        Some(anchor) => {
+            match find_anchored(reverse, constant.end, anchor, cfg) {
+                Some(rbs) => rbs,
+                None => return Err(FailReason::RbsLength),
+            }
+        }
+        None => match reverse.get(constant.end..constant.end + cfg.rbs_len) {
+            Some(rbs) => rbs,
+            None => return Err(FailReason::ConstantPos),
+        },
+    };
+
+    let observed2 = &reverse[constant.start - cfg.barcode_len..constant.start];
+    let Some(barcode2) = cfg.match_barcode2(observed2) else {
+        if cfg.cross_check && cfg.is_barcode1(observed2) {
+            return Err(FailReason::Barcode2Cross);
+        }
+        return Err(FailReason::Barcode2);
+    };
+
+    // -----------------------------------------------------
+    // 2. Extract barcode 1 before the discriminator
+    // -----------------------------------------------------
+    let (disc, flipped) = disc;
+    if disc.start < cfg.disc_offset + cfg.barcode_len {
+        return Err(FailReason::DiscPos);
+    }
+    let barcode1_start = disc.start - cfg.disc_offset - cfg.barcode_len;
+    let observed1 = &forward[barcode1_start..barcode1_start + cfg.barcode_len];
+    let Some(barcode1) = cfg.match_barcode1(observed1) else {
+        if cfg.cross_check && cfg.is_barcode2(observed1) {
+            return Err(FailReason::Barcode1Cross);
+        }
+        return Err(FailReason::Barcode1);
+    };
+    cfg.record_corrections((observed1, barcode1), (observed2, barcode2));
+
+    Ok((
+        Sample {
+            barcode1: barcode1.to_owned(),
+            barcode2: barcode2.to_owned(),
+        },
+        rbs.to_owned(),
+        flipped,
+    ))
+}
+
+/// Constructs of one strand, the read 1 part coming first in `forward`
+/// and the constant region being found reverse complemented after it.
+fn scan_strand(
+    cfg: &Config,
+    forward: &str,
+    reverse: &str,
+    opts: &LongReadOptions,
+) -> Vec<Construct> {
+    let len = forward.len();
+    let discs = find_discriminators(cfg, forward.as_bytes(), opts);
+
+    let mut constants = find_approx(
+        reverse.as_bytes(),
+        cfg.const_region.as_bytes(),
+        opts.max_edits(cfg.const_region),
+    );
+    constants.reverse();
+
+    // Each constant region takes the last discriminator since the previous
+    // construct
+    let mut boundary = 0;
+    let mut constructs = Vec::with_capacity(constants.len());
+    for constant in constants {
+        let const_start = len - constant.end;
+        let disc = discs
+            .iter()
+            .rev()
+            .find(|(d, _)| d.start >= boundary && d.end <= const_start);
+        boundary = len - constant.start;
+
+        constructs.push(match disc {
+            Some(&disc) => {
+                extract_construct(cfg, forward, reverse, disc, constant)
+            }
+            None => Err(FailReason::DiscSeq),
+        });
+    }
+
+    constructs
+}
+
+/// Every construct found on either strand of a long read.
+pub(crate) fn scan_read(
+    cfg: &Config,
+    seq: &[u8],
+    opts: &LongReadOptions,
+) -> Result<Vec<Construct>, std::str::Utf8Error> {
+    let forward = std::str::from_utf8(seq)?;
+    let reverse = String::from_utf8(reverse_complement(seq))
+        .expect("Reverse complement is ASCII");
+
+    let mut constructs = scan_strand(cfg, forward, &reverse, opts);
+    constructs.extend(scan_strand(cfg, &reverse, forward, opts));
+
+    if constructs.is_empty() {
+        constructs.push(Err(FailReason::ConstantSeq));
+    }
+
+    Ok(constructs)
+}
+
+/// Classify a long read and count each of its constructs. The read is
+/// valid when any construct is, otherwise it fails for the reason of its
+/// first construct.
+pub(crate) fn count_long_read(
+    cfg: &Config,
+    rec: &fastq::Record,
+    opts: &LongReadOptions,
+    counters: &Counters,
+    table: &SampleTable,
+    spikes: Option<&SpikeIns>,
+) {
+    let constructs = match scan_read(cfg, rec.seq(), opts) {
+        Ok(constructs) => constructs,
+        Err(e) => {
+            warn!("Malformed record {}: {}", rec.id(), e);
+            counters.inc_fail(FailReason::MalformedRecord);
+            return;
+        }
+    };
+
+    let mut valid = false;
+    let mut first_fail = None;
+    for construct in constructs {
+        match construct {
+            Ok((sample, rbs, flipped)) => {
+                valid = true;
+                counters.inc_construct();
+
+                // Controls are tallied apart, never counted
+                if spikes.is_some_and(|s| s.record(&sample, &rbs, flipped)) {
+                    continue;
+                }
+
+                add_to_table(table, sample, &rbs, flipped);
+            }
+            Err(reason) => {
+                first_fail.get_or_insert(reason);
+            }
+        }
+    }
+
+    if valid {
+        counters.inc_valid();
+    } else if let Some(reason) = first_fail {
+        counters.inc_fail(reason);
+    }
+}
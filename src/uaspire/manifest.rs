@@ -0,0 +1,103 @@
+/// Run manifest written at the root of the output directory.
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::uaspire::retention::OutputKind;
+
+// ---------- Output layout ----------
+
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, ValueEnum, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputLayout {
+    // sample=<name>/<column>=<value>/part-N.parquet directories
+    #[default]
+    Hive,
+    // One <name>.parquet file per output category
+    Flat,
+}
+
+// ---------- Counts shape ----------
+
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, ValueEnum, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum CountsShape {
+    // unflipped and flipped count columns
+    #[default]
+    Wide,
+    // One row per flip state, with state and count columns
+    Long,
+}
+
+// ---------- Run status ----------
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    #[default]
+    Completed,
+    // Stopped by a signal, the outputs hold the pairs processed until then
+    Interrupted,
+}
+
+// ---------- Pruned outputs ----------
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrunedOutput {
+    pub kind: OutputKind,
+    pub path: PathBuf,
+    pub bytes: u64,
+    pub reason: String,
+}
+
+// ---------- Manifest ----------
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub sample_name: String,
+    pub layout: OutputLayout,
+    #[serde(default)]
+    pub counts_shape: CountsShape,
+    #[serde(default)]
+    pub status: RunStatus,
+    // Read pairs of the inputs processed, where a resumed run starts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub processed_pairs: Option<u64>,
+    pub inputs: Vec<PathBuf>,
+    // Chunk files written by a classification pass
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunks: Option<u64>,
+    // Inputs of the earlier runs this one was appended to
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub previous_inputs: Vec<PathBuf>,
+    // Count rows below this many reads were dropped, 0 when none was
+    #[serde(default)]
+    pub min_count: u64,
+    pub outputs: BTreeMap<OutputKind, PathBuf>,
+    pub pruned: Vec<PrunedOutput>,
+}
+
+impl Manifest {
+    /// Read the manifest of an earlier run.
+    pub fn read(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::open(path)?;
+        Ok(serde_json::from_reader(file)?)
+    }
+
+    /// Write the manifest as pretty-printed JSON.
+    pub fn write(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,197 @@
+/// Wide count matrices pivoted from the partitioned counts of one or more
+/// runs.
+use clap::ValueEnum;
+use polars::prelude::*;
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+// ---------- Matrix shape ----------
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum MatrixColumns {
+    Sample,
+    BarcodePair,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum MatrixValue {
+    Counts,
+    FlipFraction,
+}
+
+// =========================================================
+// Helper functions
+// =========================================================
+
+/// Counts directory of a run, or the directory itself.
+pub(crate) fn counts_root(dir: &Path) -> PathBuf {
+    let counts = dir.join("data").join("counts");
+    if counts.is_dir() {
+        counts
+    } else {
+        dir.to_path_buf()
+    }
+}
+
+/// Parquet files below `dir` with the sample of their `sample=` directory.
+pub(crate) fn sample_files(
+    dir: &Path,
+) -> std::io::Result<Vec<(String, PathBuf)>> {
+    let mut files = Vec::new();
+    let mut stack = vec![dir.to_path_buf()];
+
+    while let Some(current) = stack.pop() {
+        for entry in fs::read_dir(&current)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                stack.push(path);
+                continue;
+            }
+
+            if path.extension().is_none_or(|ext| ext != "parquet") {
+                continue;
+            }
+
+            // Flat layouts name the file after the sample
+            let sample = path
+                .ancestors()
+                .find_map(|p| p.file_name()?.to_str()?.strip_prefix("sample="))
+                .or_else(|| path.file_stem()?.to_str());
+            if let Some(sample) = sample {
+                files.push((sample.to_string(), path.clone()));
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Read the counts of all runs into one long table with a sample column.
+pub fn load_counts(dirs: &[PathBuf]) -> PolarsResult<DataFrame> {
+    let mut frames = Vec::new();
+
+    for dir in dirs {
+        for (sample, path) in sample_files(&counts_root(dir))? {
+            let df = ParquetReader::new(File::open(&path)?).finish()?;
+            frames.push(df.lazy().select([
+                lit(sample).alias("sample"),
+                col("barcode1"),
+                col("barcode2"),
+                col("gre"),
+                col("unflipped"),
+                col("flipped"),
+            ]));
+        }
+    }
+
+    if frames.is_empty() {
+        polars_bail!(ComputeError: "no counts found");
+    }
+
+    concat(&frames, UnionArgs::default())?.collect()
+}
+
+// =========================================================
+// Pivot
+// =========================================================
+
+/// Pivot long counts into one row per RBS and one column per sample or
+/// barcode pair.
+pub fn build_matrix(
+    counts: &DataFrame,
+    columns: MatrixColumns,
+    value: MatrixValue,
+) -> PolarsResult<DataFrame> {
+    let key = match columns {
+        MatrixColumns::Sample => col("sample"),
+        MatrixColumns::BarcodePair => concat_str(
+            [col("sample"), col("barcode1"), col("barcode2")],
+            "_",
+            true,
+        ),
+    };
+
+    let long = counts
+        .clone()
+        .lazy()
+        .with_column(key.alias("column"))
+        .group_by([col("gre"), col("column")])
+        .agg([
+            col("unflipped").sum().cast(DataType::UInt64),
+            col("flipped").sum().cast(DataType::UInt64),
+        ])
+        .collect()?;
+
+    let gres = long.column("gre")?.str()?.clone();
+    let keys = long.column("column")?.str()?.clone();
+    let unflipped = long.column("unflipped")?.u64()?.clone();
+    let flipped = long.column("flipped")?.u64()?.clone();
+
+    let mut cells: BTreeMap<&str, BTreeMap<&str, f64>> = BTreeMap::new();
+    let mut names = BTreeSet::new();
+
+    for i in 0..long.height() {
+        let (Some(gre), Some(name)) = (gres.get(i), keys.get(i)) else {
+            continue;
+        };
+        let u = unflipped.get(i).unwrap_or(0) as f64;
+        let f = flipped.get(i).unwrap_or(0) as f64;
+
+        let cell = match value {
+            MatrixValue::Counts => u + f,
+            MatrixValue::FlipFraction if u + f > 0.0 => f / (u + f),
+            MatrixValue::FlipFraction => f64::NAN,
+        };
+
+        names.insert(name);
+        cells.entry(gre).or_default().insert(name, cell);
+    }
+
+    let mut series = vec![Series::new(
+        "gre".into(),
+        cells.keys().copied().collect::<Vec<_>>(),
+    )];
+
+    // Missing combinations are null, not zero
+    for name in names {
+        let values: Vec<Option<f64>> =
+            cells.values().map(|row| row.get(name).copied()).collect();
+        series.push(Series::new(name.into(), values));
+    }
+
+    DataFrame::new(series.into_iter().map(Column::from).collect())
+}
+
+/// Per-RBS totals and number of samples, sorted like the matrix rows.
+pub fn rbs_metadata(counts: &DataFrame) -> PolarsResult<DataFrame> {
+    counts
+        .clone()
+        .lazy()
+        .group_by([col("gre")])
+        .agg([
+            col("unflipped").sum().alias("total_unflipped"),
+            col("flipped").sum().alias("total_flipped"),
+            col("sample").n_unique().alias("n_samples"),
+        ])
+        .sort(["gre"], SortMultipleOptions::default())
+        .collect()
+}
+
+/// Write the matrix as CSV when the path ends in `.csv`, Parquet otherwise.
+pub fn write_matrix(df: &mut DataFrame, path: &Path) -> PolarsResult<()> {
+    let file = File::create(path)?;
+
+    if path.extension().is_some_and(|ext| ext == "csv") {
+        CsvWriter::new(file).finish(df)
+    } else {
+        ParquetWriter::new(file)
+            .with_compression(ParquetCompression::Zstd(None))
+            .finish(df)
+            .map(|_| ())
+    }
+}
@@ -0,0 +1,200 @@
+/// Versioned output schemas and the metadata embedded in output files.
+///
+/// Every Parquet output carries key/value metadata naming the tool version,
+/// the schema and its version, the sample and a hash of the assay settings,
+/// so that downstream readers can tell incompatible outputs apart. A schema
+/// version is bumped whenever columns are renamed, retyped or removed.
+use polars::prelude::*;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
+
+use crate::uaspire::assay::Assay;
+use crate::uaspire::classify::Geometry;
+use crate::uaspire::crispr::GuideOptions;
+use crate::uaspire::fastq::ProcessOptions;
+use crate::uaspire::mpra::MpraOptions;
+
+// Prefix of the metadata keys
+const KEY_PREFIX: &str = "uaspire";
+
+// ---------- Schemas ----------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIter)]
+pub enum OutputSchema {
+    Counts,
+    CountsLong,
+    Qc,
+    Elements,
+    Performance,
+    Variants,
+    Downsampling,
+}
+
+impl OutputSchema {
+    pub fn name(self) -> &'static str {
+        match self {
+            OutputSchema::Counts => "counts",
+            OutputSchema::CountsLong => "counts_long",
+            OutputSchema::Qc => "qc",
+            OutputSchema::Elements => "elements",
+            OutputSchema::Performance => "performance",
+            OutputSchema::Variants => "variants",
+            OutputSchema::Downsampling => "downsampling",
+        }
+    }
+
+    pub fn version(self) -> u32 {
+        1
+    }
+
+    /// Columns always present, annotation columns may follow.
+    pub fn columns(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            OutputSchema::Counts => &[
+                ("barcode1", "str"),
+                ("barcode2", "str"),
+                ("gre", "str"),
+                ("unflipped", "u64"),
+                ("flipped", "u64"),
+            ],
+            OutputSchema::CountsLong => &[
+                ("barcode1", "str"),
+                ("barcode2", "str"),
+                ("gre", "str"),
+                ("state", "str"),
+                ("count", "u64"),
+            ],
+            OutputSchema::Qc => &[("name", "str"), ("value", "u64")],
+            OutputSchema::Elements => &[
+                ("barcode1", "str"),
+                ("barcode2", "str"),
+                ("element", "str"),
+                ("count", "u64"),
+                ("barcodes", "u32"),
+            ],
+            OutputSchema::Performance => &[
+                ("sample", "str"),
+                ("version", "str"),
+                ("chunk", "u64"),
+                ("records", "u64"),
+                ("wall_ms", "u64"),
+                ("reads_per_sec", "f64"),
+                ("table_samples", "u64"),
+                ("table_entries", "u64"),
+                ("rss_bytes", "u64"),
+                ("peak_rss_bytes", "u64"),
+            ],
+            OutputSchema::Variants => &[
+                ("sample", "str"),
+                ("barcode1", "str"),
+                ("barcode2", "str"),
+                ("position", "u32"),
+                ("reference", "str"),
+                ("matches", "u64"),
+                ("substitutions", "u64"),
+                ("insertions", "u64"),
+                ("deletions", "u64"),
+                ("depth", "u64"),
+                ("substitutions_rate", "f64"),
+                ("insertions_rate", "f64"),
+                ("deletions_rate", "f64"),
+            ],
+            OutputSchema::Downsampling => &[
+                ("barcode1", "str"),
+                ("barcode2", "str"),
+                ("reads_before", "u64"),
+                ("reads_after", "u64"),
+            ],
+        }
+    }
+}
+
+/// Expected schemas and their versions, one line each.
+pub fn print_schemas(out: &mut impl std::io::Write) -> std::io::Result<()> {
+    writeln!(out, "{:<14} {:>7}  columns", "schema", "version")?;
+
+    for schema in OutputSchema::iter() {
+        let columns: Vec<String> = schema
+            .columns()
+            .iter()
+            .map(|(name, dtype)| format!("{name}:{dtype}"))
+            .collect();
+        writeln!(
+            out,
+            "{:<14} {:>7}  {}",
+            schema.name(),
+            schema.version(),
+            columns.join(", ")
+        )?;
+    }
+
+    Ok(())
+}
+
+// ---------- Metadata ----------
+
+// Settings deciding what the outputs hold, hashed as JSON, whose field
+// order and format don't change between builds
+#[derive(Serialize)]
+struct AssaySettings<'a> {
+    assay: Assay,
+    geometry: &'a Geometry,
+    guides: Option<&'a GuideOptions>,
+    mpra: Option<&'a MpraOptions>,
+}
+
+#[derive(Debug, Clone)]
+pub struct OutputMetadata {
+    pub sample_name: String,
+    pub config_hash: String,
+}
+
+impl OutputMetadata {
+    pub(crate) fn new(sample_name: &str, opts: &ProcessOptions) -> Self {
+        let settings = AssaySettings {
+            assay: opts.assay,
+            geometry: &opts.geometry,
+            guides: opts.guides.as_ref(),
+            mpra: opts.mpra.as_ref(),
+        };
+        // Plain structs of strings and numbers always serialize
+        let json = serde_json::to_vec(&settings).unwrap_or_default();
+
+        OutputMetadata {
+            sample_name: sample_name.to_string(),
+            config_hash: Sha256::digest(&json)
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect(),
+        }
+    }
+
+    pub fn key_values(&self, schema: OutputSchema) -> Vec<(String, String)> {
+        [
+            ("tool_version", env!("CARGO_PKG_VERSION").to_string()),
+            ("schema", schema.name().to_string()),
+            ("schema_version", schema.version().to_string()),
+            ("sample", self.sample_name.clone()),
+            ("config_hash", self.config_hash.clone()),
+        ]
+        .into_iter()
+        .map(|(key, value)| (format!("{KEY_PREFIX}.{key}"), value))
+        .collect()
+    }
+
+    /// Compressed Parquet writer embedding the metadata of `schema`.
+    pub(crate) fn writer(
+        &self,
+        file: File,
+        schema: OutputSchema,
+    ) -> ParquetWriter<File> {
+        ParquetWriter::new(file)
+            .with_compression(ParquetCompression::Zstd(None))
+            .with_key_value_metadata(Some(KeyValueMetadata::from_static(
+                self.key_values(schema),
+            )))
+    }
+}
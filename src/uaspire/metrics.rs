@@ -0,0 +1,191 @@
+/// Live run counters served over HTTP in the Prometheus text format.
+///
+/// A background thread answers scrapes on `/metrics` from the shared
+/// counters while the chunks are processed, and stops when the server is
+/// dropped at the end of the run.
+use std::{
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+use strum::IntoEnumIterator;
+use tracing::{info, warn};
+
+use crate::uaspire::classify::{Counters, FailReason};
+
+// How often the listener checks whether the run is over
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// ---------- Shared state ----------
+
+struct MetricsState {
+    sample_name: String,
+    counters: Arc<Counters>,
+    chunk: AtomicU64,
+    stop: AtomicBool,
+}
+
+impl MetricsState {
+    /// Counters in the Prometheus text exposition format.
+    fn render(&self) -> String {
+        let sample =
+            self.sample_name.replace('\\', "\\\\").replace('"', "\\\"");
+        let total = self.counters.total_count();
+        let valid = self.counters.valid_count();
+        let rate = if total == 0 {
+            0.0
+        } else {
+            valid as f64 / total as f64
+        };
+
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} {kind}");
+            let _ = writeln!(out, "{name}{{sample=\"{sample}\"}} {value}");
+        };
+
+        metric(
+            "uaspire_reads_total",
+            "counter",
+            "Read pairs processed.",
+            total.to_string(),
+        );
+        metric(
+            "uaspire_valid_reads_total",
+            "counter",
+            "Read pairs passing classification.",
+            valid.to_string(),
+        );
+        metric(
+            "uaspire_valid_rate",
+            "gauge",
+            "Fraction of the read pairs passing classification.",
+            rate.to_string(),
+        );
+        metric(
+            "uaspire_chunk",
+            "gauge",
+            "Index of the chunk being processed.",
+            self.chunk.load(Ordering::Relaxed).to_string(),
+        );
+
+        let name = "uaspire_failed_reads_total";
+        let _ = writeln!(out, "# HELP {name} Read pairs failing, by reason.");
+        let _ = writeln!(out, "# TYPE {name} counter");
+        for reason in FailReason::iter() {
+            let _ = writeln!(
+                out,
+                "{name}{{sample=\"{sample}\",reason=\"{}\"}} {}",
+                reason.name(),
+                self.counters.fail_count(reason)
+            );
+        }
+
+        out
+    }
+}
+
+// ---------- Server ----------
+
+pub(crate) struct MetricsServer {
+    state: Arc<MetricsState>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl MetricsServer {
+    /// Listen at `addr` and serve the counters.
+    pub(crate) fn start(
+        addr: SocketAddr,
+        sample_name: &str,
+        counters: Arc<Counters>,
+    ) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        // Polled so that the thread notices the end of the run
+        listener.set_nonblocking(true)?;
+        info!("Serving metrics on {}", listener.local_addr()?);
+
+        let state = Arc::new(MetricsState {
+            sample_name: sample_name.to_string(),
+            counters,
+            chunk: AtomicU64::new(0),
+            stop: AtomicBool::new(false),
+        });
+
+        let shared = state.clone();
+        let handle = thread::spawn(move || {
+            while !shared.stop.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        if let Err(err) = respond(stream, &shared) {
+                            warn!("Couldn't answer metrics request: {err}");
+                        }
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                        thread::sleep(POLL_INTERVAL);
+                    }
+                    Err(err) => warn!("Metrics connection failed: {err}"),
+                }
+            }
+        });
+
+        Ok(MetricsServer {
+            state,
+            handle: Some(handle),
+        })
+    }
+
+    pub(crate) fn set_chunk(&self, chunk: usize) {
+        self.state.chunk.store(chunk as u64, Ordering::Relaxed);
+    }
+}
+
+impl Drop for MetricsServer {
+    fn drop(&mut self) {
+        self.state.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn respond(mut stream: TcpStream, state: &MetricsState) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+    // Only the request line matters, e.g. `GET /metrics HTTP/1.1`, but the
+    // headers are read up to the blank line so that closing the socket
+    // doesn't reset the connection before the client reads the response
+    let mut reader = BufReader::new(&stream);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    let mut header = String::new();
+    loop {
+        header.clear();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+    let path = request.split_whitespace().nth(1).unwrap_or("");
+
+    let (status, body) = match path {
+        "/metrics" | "/" => ("200 OK", state.render()),
+        _ => ("404 Not Found", String::new()),
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\n\
+         Content-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()
+}
@@ -1,2 +1,58 @@
+#[cfg(feature = "parquet")]
+pub mod assay;
+#[cfg(feature = "parquet")]
+pub mod batch;
+pub mod bench;
+pub mod check;
+pub mod classify;
+#[cfg(feature = "parquet")]
+pub mod compare;
+pub mod composition;
 pub mod constants;
+pub mod correction;
+pub mod count;
+#[cfg(feature = "parquet")]
+pub mod crispr;
+pub mod demux;
+#[cfg(feature = "parquet")]
+pub mod downsample;
+pub mod duplicates;
+#[cfg(feature = "parquet")]
+pub mod export;
+#[cfg(feature = "parquet")]
 pub mod fastq;
+#[cfg(feature = "parquet")]
+pub mod h5ad;
+pub mod hashing;
+#[cfg(feature = "parquet")]
+pub mod inspect;
+pub mod interrupt;
+pub mod longread;
+pub mod manifest;
+#[cfg(feature = "parquet")]
+pub mod matrix;
+#[cfg(feature = "parquet")]
+pub mod metadata;
+pub mod metrics;
+#[cfg(feature = "parquet")]
+pub mod mpra;
+#[cfg(feature = "parquet")]
+pub mod notification;
+#[cfg(feature = "parquet")]
+pub mod output;
+pub mod overlap;
+pub mod probe;
+#[cfg(feature = "parquet")]
+pub mod processor;
+pub mod reader;
+pub mod retention;
+pub mod samplesheet;
+pub mod spikein;
+pub mod strength;
+pub mod trace;
+#[cfg(feature = "parquet")]
+pub mod twopass;
+#[cfg(feature = "parquet")]
+pub mod variants;
+#[cfg(feature = "parquet")]
+pub mod watch;
@@ -0,0 +1,210 @@
+/// MPRA barcode counting on the shared counting pipeline.
+///
+/// Sample barcodes are read at fixed offsets from the start of both reads,
+/// as for CRISPR guides. The random MPRA barcode is read right after (or
+/// right before) a constant anchor and counted as the feature, always
+/// non-flipped. A barcode to element association table then sums the
+/// barcode counts of each promoter or enhancer into element-level counts.
+use bio::io::fastq;
+use polars::prelude::*;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::uaspire::assay::{inline_barcodes, Classification, ReadClassifier};
+use crate::uaspire::classify::{
+    validate_pairs, Config, FailReason, Flip, Sample,
+};
+
+// ---------- Options ----------
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MpraOptions {
+    // Constant sequence next to the random barcode
+    pub anchor: String,
+    pub barcode_len: usize,
+    // Barcode before the anchor instead of after it
+    pub upstream: bool,
+    // Read holding the anchor and the barcode, 1 or 2
+    pub read: u8,
+    // Sample barcode starts in read 1 and read 2
+    pub barcode_offsets: (usize, usize),
+    // `barcode,element` CSV or TSV
+    pub association: Option<PathBuf>,
+}
+
+impl Default for MpraOptions {
+    fn default() -> Self {
+        MpraOptions {
+            anchor: String::new(),
+            barcode_len: 20,
+            upstream: false,
+            read: 2,
+            barcode_offsets: (0, 0),
+            association: None,
+        }
+    }
+}
+
+// =========================================================
+// Classifier
+// =========================================================
+
+pub(crate) struct MpraClassifier<'c> {
+    cfg: &'c Config<'c>,
+    opts: &'c MpraOptions,
+}
+
+impl<'c> MpraClassifier<'c> {
+    pub(crate) fn new(cfg: &'c Config<'c>, opts: &'c MpraOptions) -> Self {
+        MpraClassifier { cfg, opts }
+    }
+
+    /// Random barcode next to the anchor in `seq`.
+    fn extract<'s>(&self, seq: &'s str, from: usize) -> Option<&'s str> {
+        let anchor = self.opts.anchor.as_str();
+        let pos = from + seq.get(from..)?.find(anchor)?;
+
+        let barcode = if self.opts.upstream {
+            seq.get(pos.checked_sub(self.opts.barcode_len)?..pos)?
+        } else {
+            let start = pos + anchor.len();
+            seq.get(start..start + self.opts.barcode_len)?
+        };
+
+        (!barcode.contains('N')).then_some(barcode)
+    }
+}
+
+impl ReadClassifier for MpraClassifier<'_> {
+    fn classify_pair<'s>(
+        &'s self,
+        rec1: &'s fastq::Record,
+        rec2: &'s fastq::Record,
+    ) -> Classification<'s> {
+        if !validate_pairs(rec1, rec2, self.cfg.strict_ids) {
+            return Ok(Err(FailReason::IdMismatch));
+        }
+
+        let seq1 = std::str::from_utf8(rec1.seq())?;
+        let seq2 = std::str::from_utf8(rec2.seq())?;
+
+        // -----------------------------------------------------
+        // 1. Extract sample barcodes
+        // -----------------------------------------------------
+        let offsets = self.opts.barcode_offsets;
+        let [bc1, bc2] = match inline_barcodes(self.cfg, seq1, seq2, offsets) {
+            Ok(barcodes) => barcodes,
+            Err(reason) => return Ok(Err(reason)),
+        };
+
+        // -----------------------------------------------------
+        // 2. Extract the random barcode next to the anchor
+        // -----------------------------------------------------
+        let (seq, from) = match self.opts.read {
+            1 => (seq1, offsets.0 + self.cfg.barcode_len),
+            _ => (seq2, offsets.1 + self.cfg.barcode_len),
+        };
+        let Some(barcode) = self.extract(seq, from) else {
+            return Ok(Err(FailReason::MpraBarcode));
+        };
+
+        self.cfg.record_corrections(bc1, bc2);
+
+        Ok(Ok((
+            Sample {
+                barcode1: bc1.1.to_owned(),
+                barcode2: bc2.1.to_owned(),
+            },
+            barcode,
+            Flip::NonFlipped,
+        )))
+    }
+}
+
+// =========================================================
+// Element-level counts
+// =========================================================
+
+/// Barcode to element table, tab separated when the file ends in `.tsv`.
+pub fn read_association(
+    path: impl AsRef<Path>,
+) -> io::Result<HashMap<String, String>> {
+    let path = path.as_ref();
+    let delimiter = match path.extension().and_then(|e| e.to_str()) {
+        Some("tsv") => b'\t',
+        _ => b',',
+    };
+
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+
+    let mut association = HashMap::new();
+    for record in reader.deserialize() {
+        let (barcode, element): (String, String) = record?;
+        if association
+            .insert(barcode.to_ascii_uppercase(), element)
+            .is_some()
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("barcode {barcode} is associated twice"),
+            ));
+        }
+    }
+
+    Ok(association)
+}
+
+/// Sum the MPRA barcode counts of each element, per barcode pair.
+///
+/// Returns the element counts and the number of reads whose barcode has no
+/// associated element.
+pub fn element_counts(
+    counts: &DataFrame,
+    association: &HashMap<String, String>,
+) -> PolarsResult<(DataFrame, u64)> {
+    let elements: Vec<Option<&str>> = counts
+        .column("gre")?
+        .str()?
+        .into_iter()
+        .map(|barcode| {
+            barcode.and_then(|b| association.get(b).map(String::as_str))
+        })
+        .collect();
+
+    let mut df = counts.clone();
+    df.with_column(Series::new("element".into(), elements))?;
+
+    let unassociated = df
+        .clone()
+        .lazy()
+        .filter(col("element").is_null())
+        .select([col("unflipped").sum()])
+        .collect()?
+        .column("unflipped")?
+        .u64()?
+        .get(0)
+        .unwrap_or(0);
+
+    let elements = df
+        .lazy()
+        .filter(col("element").is_not_null())
+        .group_by_stable([col("barcode1"), col("barcode2"), col("element")])
+        .agg([
+            col("unflipped").sum().alias("count"),
+            col("gre").n_unique().alias("barcodes"),
+        ])
+        .sort(
+            ["barcode1", "barcode2", "element"],
+            SortMultipleOptions::default(),
+        )
+        .collect()?;
+
+    Ok((elements, unassociated))
+}
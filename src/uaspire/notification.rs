@@ -0,0 +1,166 @@
+/// Notifications sent when a run completes or fails.
+///
+/// The same JSON payload is posted to a webhook and/or written to the
+/// standard input of a shell command, so that long overnight runs can ping
+/// a pipeline channel. A failed notification is logged and never fails the
+/// run itself.
+use serde::Serialize;
+use std::{
+    io::Write,
+    path::Path,
+    process::{Command, Stdio},
+    time::Duration,
+};
+use tracing::{info, warn};
+
+use crate::uaspire::interrupt::EXIT_INTERRUPTED;
+use crate::uaspire::processor::{RunError, RunSummary, EXIT_COMPLETED};
+
+// Webhooks that do not answer in time are given up on
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(30);
+
+// ---------- Options ----------
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NotifyOptions {
+    // URL receiving the summary as a JSON POST
+    pub webhook: Option<String>,
+    // Shell command receiving the summary on its standard input
+    pub command: Option<String>,
+}
+
+impl NotifyOptions {
+    pub fn is_enabled(&self) -> bool {
+        self.webhook.is_some() || self.command.is_some()
+    }
+}
+
+// ---------- Payload ----------
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RunNotification {
+    pub sample_name: String,
+    // completed, warnings, interrupted or failed
+    pub status: &'static str,
+    pub exit_code: i32,
+    pub total: u64,
+    pub valid: u64,
+    pub valid_fraction: f64,
+    // Seconds
+    pub duration: f64,
+    pub output_dir: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+impl RunNotification {
+    pub fn new(
+        sample_name: &str,
+        output_dir: &Path,
+        result: &Result<RunSummary, RunError>,
+        duration: Duration,
+    ) -> Self {
+        let mut notification = RunNotification {
+            sample_name: sample_name.to_string(),
+            status: "failed",
+            exit_code: 0,
+            total: 0,
+            valid: 0,
+            valid_fraction: 0.0,
+            duration: duration.as_secs_f64(),
+            output_dir: output_dir.display().to_string(),
+            error: None,
+            warnings: Vec::new(),
+        };
+
+        match result {
+            Ok(summary) => {
+                notification.exit_code = summary.exit_code();
+                notification.status = match notification.exit_code {
+                    EXIT_COMPLETED => "completed",
+                    EXIT_INTERRUPTED => "interrupted",
+                    _ => "warnings",
+                };
+                notification.total = summary.total;
+                notification.valid = summary.valid;
+                notification.valid_fraction = summary.valid_fraction();
+                notification.warnings = summary.warnings.clone();
+            }
+            Err(err) => {
+                notification.exit_code = err.exit_code();
+                notification.error = Some(err.to_string());
+            }
+        }
+
+        notification
+    }
+}
+
+// =========================================================
+// Sending
+// =========================================================
+
+fn post_webhook(url: &str, body: &str) -> Result<(), String> {
+    let client = reqwest::blocking::Client::builder()
+        .timeout(WEBHOOK_TIMEOUT)
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let response = client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body.to_string())
+        .send()
+        .map_err(|e| e.to_string())?;
+
+    match response.status() {
+        status if status.is_success() => Ok(()),
+        status => Err(format!("server answered {status}")),
+    }
+}
+
+fn run_command(command: &str, body: &str) -> Result<(), String> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // A command ignoring its input closes the pipe early, that is fine
+        let _ = stdin.write_all(body.as_bytes());
+    }
+
+    match child.wait().map_err(|e| e.to_string())? {
+        status if status.success() => Ok(()),
+        status => Err(format!("command exited with {status}")),
+    }
+}
+
+/// Send a notification to every configured target.
+pub fn notify(opts: &NotifyOptions, notification: &RunNotification) {
+    let body = match serde_json::to_string(notification) {
+        Ok(body) => body,
+        Err(err) => {
+            warn!("Couldn't serialize the run notification: {err}");
+            return;
+        }
+    };
+
+    if let Some(url) = &opts.webhook {
+        match post_webhook(url, &body) {
+            Ok(_) => info!("Run notification posted to {url}"),
+            Err(err) => warn!("Couldn't post the run notification: {err}"),
+        }
+    }
+
+    if let Some(command) = &opts.command {
+        match run_command(command, &body) {
+            Ok(_) => info!("Run notification sent to `{command}`"),
+            Err(err) => warn!("Couldn't run the notification command: {err}"),
+        }
+    }
+}
@@ -0,0 +1,71 @@
+/// Lazy queries over the outputs of a run, for downstream Rust tools.
+///
+/// The run manifest tells where the files of the sample are, whatever the
+/// output layout. Partition columns are stored in the files as well, so the
+/// frames have the same schema with either layout, plus a `sample` column
+/// taken from the manifest.
+use polars::prelude::*;
+use std::path::{Path, PathBuf};
+
+use crate::uaspire::fastq::DirLayout;
+use crate::uaspire::manifest::{Manifest, OutputLayout};
+use crate::uaspire::matrix::sample_files;
+
+/// Parquet files of the sample of a run below `root`.
+fn run_files(root: &Path, manifest: &Manifest) -> PolarsResult<Vec<PathBuf>> {
+    let sample = &manifest.sample_name;
+
+    let files: Vec<PathBuf> = match manifest.layout {
+        OutputLayout::Hive => {
+            let dir = root.join(format!("sample={sample}"));
+            if dir.is_dir() {
+                sample_files(&dir)?.into_iter().map(|(_, p)| p).collect()
+            } else {
+                Vec::new()
+            }
+        }
+        OutputLayout::Flat => {
+            let path = root.join(format!("{sample}.parquet"));
+            if path.exists() {
+                vec![path]
+            } else {
+                Vec::new()
+            }
+        }
+    };
+
+    if files.is_empty() {
+        polars_bail!(
+            ComputeError: "no files of {} in {}", sample, root.display()
+        );
+    }
+
+    Ok(files)
+}
+
+fn scan_run(
+    dir: &Path,
+    root: impl Fn(&DirLayout) -> &Path,
+) -> PolarsResult<LazyFrame> {
+    let manifest = Manifest::read(dir.join("manifest.json"))?;
+    let layout = DirLayout::new(dir);
+    let files = run_files(root(&layout), &manifest)?;
+
+    let lf = LazyFrame::scan_parquet_files(
+        files.into(),
+        ScanArgsParquet::default(),
+    )?;
+    Ok(lf.with_column(lit(manifest.sample_name).alias("sample")))
+}
+
+/// Counts of the run in output directory `dir`, in the counts shape of the
+/// run: unflipped and flipped columns, or state and count columns.
+pub fn scan_counts(dir: impl AsRef<Path>) -> PolarsResult<LazyFrame> {
+    scan_run(dir.as_ref(), |layout| layout.counts.as_path())
+}
+
+/// QC counters of the run in output directory `dir`, as name and value
+/// columns.
+pub fn scan_qc(dir: impl AsRef<Path>) -> PolarsResult<LazyFrame> {
+    scan_run(dir.as_ref(), |layout| layout.qc.as_path())
+}
@@ -0,0 +1,194 @@
+/// Overlap merging of read pairs from short fragments.
+///
+/// When a fragment is shorter than both reads together, the end of read 1
+/// and the reverse complement of read 2 cover the same bases. Such pairs are
+/// merged back into the whole fragment, which is classified as a single
+/// sequence so that elements split across the reads are still recovered.
+
+// ---------- Options ----------
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OverlapOptions {
+    pub min_overlap: usize,
+    pub max_mismatch_rate: f64,
+}
+
+impl Default for OverlapOptions {
+    fn default() -> Self {
+        OverlapOptions {
+            min_overlap: 12,
+            max_mismatch_rate: 0.1,
+        }
+    }
+}
+
+// ---------- Merged fragment ----------
+
+/// A fragment reconstructed from a read pair, along with its reverse
+/// complement which starts where read 2 started.
+#[derive(Debug, Clone)]
+pub struct MergedRead {
+    pub forward: Vec<u8>,
+    pub reverse: Vec<u8>,
+    pub overlap: usize,
+}
+
+// =========================================================
+// Helper functions
+// =========================================================
+
+fn complement(base: u8) -> u8 {
+    match base {
+        b'A' => b'T',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'T' => b'A',
+        _ => b'N',
+    }
+}
+
+pub fn reverse_complement(seq: &[u8]) -> Vec<u8> {
+    seq.iter().rev().map(|&b| complement(b)).collect()
+}
+
+/// Mismatches between two equally long sequences, Ns never mismatching.
+fn mismatches(a: &[u8], b: &[u8]) -> usize {
+    a.iter()
+        .zip(b)
+        .filter(|(&x, &y)| x != y && x != b'N' && y != b'N')
+        .count()
+}
+
+// =========================================================
+// Core logic
+// =========================================================
+
+/// Merge read 1 and read 2 on their best overlap.
+///
+/// Every overlap length from the longest possible down to `min_overlap` is
+/// scored by its mismatch rate; the lowest rate wins, ties going to the
+/// longer overlap. Within the overlap, the base with the higher quality is
+/// kept. Returns `None` when no overlap is within `max_mismatch_rate`.
+pub fn merge_pair(
+    seq1: &[u8],
+    qual1: &[u8],
+    seq2: &[u8],
+    qual2: &[u8],
+    opts: &OverlapOptions,
+) -> Option<MergedRead> {
+    let seq2 = reverse_complement(seq2);
+    let qual2: Vec<u8> = qual2.iter().rev().copied().collect();
+
+    let longest = seq1.len().min(seq2.len());
+    if opts.min_overlap == 0 || longest < opts.min_overlap {
+        return None;
+    }
+
+    let mut best: Option<(usize, f64)> = None;
+    for overlap in (opts.min_overlap..=longest).rev() {
+        let tail = &seq1[seq1.len() - overlap..];
+        let rate = mismatches(tail, &seq2[..overlap]) as f64 / overlap as f64;
+
+        if rate <= opts.max_mismatch_rate
+            && best.is_none_or(|(_, best_rate)| rate < best_rate)
+        {
+            best = Some((overlap, rate));
+        }
+    }
+    let (overlap, _) = best?;
+
+    let start = seq1.len() - overlap;
+    let mut forward = Vec::with_capacity(start + seq2.len());
+    forward.extend_from_slice(&seq1[..start]);
+    for j in 0..overlap {
+        let (b1, q1) = (seq1[start + j], qual1.get(start + j).copied());
+        let (b2, q2) = (seq2[j], qual2.get(j).copied());
+        let keep_first = b2 == b'N' || (b1 != b'N' && q1 >= q2);
+        forward.push(if keep_first { b1 } else { b2 });
+    }
+    forward.extend_from_slice(&seq2[overlap..]);
+
+    let reverse = reverse_complement(&forward);
+    Some(MergedRead {
+        forward,
+        reverse,
+        overlap,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 24 bp fragment, read 1 covering bases 0..18 and read 2 bases 6..24
+    const FRAGMENT: &[u8] = b"GATTACAGCTTCGAAGCTCCATGG";
+
+    fn reads() -> (Vec<u8>, Vec<u8>) {
+        (FRAGMENT[..18].to_vec(), reverse_complement(&FRAGMENT[6..]))
+    }
+
+    fn merge(
+        seq1: &[u8],
+        qual1: &[u8],
+        seq2: &[u8],
+        qual2: &[u8],
+        min_overlap: usize,
+        max_mismatch_rate: f64,
+    ) -> Option<MergedRead> {
+        let opts = OverlapOptions {
+            min_overlap,
+            max_mismatch_rate,
+        };
+        merge_pair(seq1, qual1, seq2, qual2, &opts)
+    }
+
+    #[test]
+    fn fragment_is_rebuilt() {
+        let (seq1, seq2) = reads();
+        let qual = [b'I'; 18];
+        let merged = merge(&seq1, &qual, &seq2, &qual, 12, 0.1).unwrap();
+        assert_eq!(merged.overlap, 12);
+        assert_eq!(merged.forward, FRAGMENT);
+        assert_eq!(merged.reverse, reverse_complement(FRAGMENT));
+    }
+
+    #[test]
+    fn overlap_shorter_than_minimum() {
+        let (seq1, seq2) = reads();
+        let qual = [b'I'; 18];
+        assert!(merge(&seq1, &qual, &seq2, &qual, 13, 0.1).is_none());
+        assert!(merge(&seq1, &qual, &seq2, &qual, 0, 0.1).is_none());
+    }
+
+    #[test]
+    fn unrelated_reads_are_not_merged() {
+        let (seq1, _) = reads();
+        let qual = [b'I'; 18];
+        let seq2 = [b'A'; 18];
+        assert!(merge(&seq1, &qual, &seq2, &qual, 12, 0.1).is_none());
+    }
+
+    #[test]
+    fn higher_quality_base_is_kept() {
+        let (mut seq1, seq2) = reads();
+        let qual2 = [b'I'; 18];
+
+        // Base 10 of the fragment, in the overlap, miscalled in read 1
+        seq1[10] = b'A';
+        let mut qual1 = [b'I'; 18];
+        assert!(merge(&seq1, &qual1, &seq2, &qual2, 12, 0.0).is_none());
+
+        let merged = merge(&seq1, &qual1, &seq2, &qual2, 12, 0.1).unwrap();
+        assert_eq!(merged.forward[10], b'A');
+
+        qual1[10] = b'#';
+        let merged = merge(&seq1, &qual1, &seq2, &qual2, 12, 0.1).unwrap();
+        assert_eq!(merged.forward, FRAGMENT);
+    }
+
+    #[test]
+    fn n_never_mismatches() {
+        assert_eq!(mismatches(b"ACGN", b"ACGT"), 0);
+        assert_eq!(mismatches(b"ACGT", b"NNNA"), 1);
+    }
+}
@@ -0,0 +1,273 @@
+/// Geometry detection on the first read pairs of a run.
+///
+/// Instead of looking for the construct elements where the configuration
+/// says they are, the reads are searched end to end. The offsets where the
+/// constant region and the discriminators actually occur, and the gap
+/// between barcode 1 and the discriminator that yields whitelist barcodes,
+/// give the window and offsets to configure.
+use std::{collections::BTreeMap, io, path::Path};
+
+use crate::uaspire::classify::Config;
+use crate::uaspire::constants;
+use crate::uaspire::reader::open_fastq;
+
+// Largest gap between barcode 1 and the discriminator tried
+const MAX_DISC_OFFSET: usize = 30;
+
+// Share of the constant region hits the suggested window must cover
+const WINDOW_COVERAGE: f64 = 0.99;
+
+// ---------- Report ----------
+
+#[derive(Debug, Default)]
+pub struct ProbeReport {
+    pub pairs: u64,
+    // Constant region found in read 2, or only in read 1 (swapped mates)
+    pub constant_read2: u64,
+    pub constant_read1: u64,
+    // 0-based start offsets in read 2 and read 1
+    pub constant_offsets: BTreeMap<usize, u64>,
+    pub non_flipped_offsets: BTreeMap<usize, u64>,
+    pub flipped_offsets: BTreeMap<usize, u64>,
+    // Whitelist barcode 1 hits per gap to the discriminator
+    pub barcode1_hits: BTreeMap<usize, BTreeMap<String, u64>>,
+    // Whitelist barcode 2 hits right before the constant region
+    pub barcode2_hits: BTreeMap<String, u64>,
+    pub const_len: usize,
+    pub barcode_len: usize,
+}
+
+/// Smallest offset range holding `coverage` of the counts, dropping the
+/// same share from each tail.
+fn central_range(
+    counts: &BTreeMap<usize, u64>,
+    coverage: f64,
+) -> Option<(usize, usize)> {
+    let n: u64 = counts.values().sum();
+    if n == 0 {
+        return None;
+    }
+
+    let tail = ((1.0 - coverage) / 2.0 * n as f64).floor() as u64;
+    let mut seen = 0;
+    let mut lo = None;
+    let mut hi = 0;
+    for (&offset, &count) in counts {
+        seen += count;
+        if lo.is_none() && seen > tail {
+            lo = Some(offset);
+        }
+        hi = offset;
+        if seen >= n - tail {
+            break;
+        }
+    }
+
+    lo.map(|lo| (lo, hi))
+}
+
+impl ProbeReport {
+    /// 1-based constant region window covering most of the hits in read 2.
+    pub fn suggested_window(&self) -> Option<(usize, usize)> {
+        central_range(&self.constant_offsets, WINDOW_COVERAGE)
+            .map(|(lo, hi)| (lo + 1, hi + self.const_len))
+    }
+
+    /// Gap between barcode 1 and the discriminator with the most whitelist
+    /// hits.
+    pub fn suggested_disc_offset(&self) -> Option<usize> {
+        self.barcode1_hits
+            .iter()
+            .map(|(&offset, hits)| (offset, hits.values().sum::<u64>()))
+            .filter(|&(_, n)| n > 0)
+            .max_by_key(|&(offset, n)| (n, std::cmp::Reverse(offset)))
+            .map(|(offset, _)| offset)
+    }
+
+    pub fn print(&self) {
+        let pct = |n: u64| 100.0 * n as f64 / self.pairs.max(1) as f64;
+
+        println!("Read pairs scanned: {}", self.pairs);
+        println!(
+            "Constant region in read 2: {} ({:.1}%)",
+            self.constant_read2,
+            pct(self.constant_read2)
+        );
+        if self.constant_read1 > 0 {
+            println!(
+                "Constant region only in read 1: {} ({:.1}%), mates may be \
+                 swapped",
+                self.constant_read1,
+                pct(self.constant_read1)
+            );
+        }
+
+        for (name, counts) in [
+            ("Constant region offsets (read 2)", &self.constant_offsets),
+            ("Non-flipped offsets (read 1)", &self.non_flipped_offsets),
+            ("Flipped offsets (read 1)", &self.flipped_offsets),
+        ] {
+            println!();
+            println!("{name}:");
+            print_top(counts, self.pairs);
+        }
+
+        println!();
+        println!("Barcode 2 whitelist hits:");
+        for (barcode, n) in &self.barcode2_hits {
+            println!("  {barcode}  {n:>10} ({:.1}%)", pct(*n));
+        }
+
+        if let Some(offset) = self.suggested_disc_offset() {
+            println!();
+            println!(
+                "Barcode 1 whitelist hits (discriminator offset {offset}):"
+            );
+            for (barcode, n) in &self.barcode1_hits[&offset] {
+                println!("  {barcode}  {n:>10} ({:.1}%)", pct(*n));
+            }
+        }
+
+        println!();
+        let Some((lo, hi)) = self.suggested_window() else {
+            println!("No constant region found, check the read files");
+            return;
+        };
+        let (default_lo, default_hi) = constants::CONSTANT_REGION_WINDOW;
+        println!(
+            "Suggested window: --window {lo}:{hi} (current \
+             {default_lo}:{default_hi})"
+        );
+        if let Some(offset) = self.suggested_disc_offset() {
+            println!(
+                "Suggested discriminator offset: {offset} (current {})",
+                constants::DISCRIMINATOR_OFFSET
+            );
+        }
+    }
+}
+
+/// Most frequent offsets, in offset order.
+fn print_top(counts: &BTreeMap<usize, u64>, pairs: u64) {
+    let mut top: Vec<(usize, u64)> =
+        counts.iter().map(|(&o, &n)| (o, n)).collect();
+    top.sort_by(|a, b| b.1.cmp(&a.1));
+    top.truncate(10);
+    top.sort();
+
+    for (offset, n) in top {
+        let pct = 100.0 * n as f64 / pairs.max(1) as f64;
+        println!("  {offset:>5}  {n:>10} ({pct:.1}%)");
+    }
+}
+
+// =========================================================
+// Probe
+// =========================================================
+
+/// Constant region window learned from the first `max_reads` reads of
+/// read 2, 1-based like `Geometry::window`.
+pub(crate) fn learn_window(
+    path2: impl AsRef<Path>,
+    max_reads: usize,
+    const_region: &str,
+) -> io::Result<Option<(usize, usize)>> {
+    let mut offsets = BTreeMap::new();
+
+    for rec in open_fastq(path2)?.records().take(max_reads) {
+        let rec = rec.map_err(io::Error::other)?;
+        let seq = String::from_utf8_lossy(rec.seq());
+        if let Some(offset) = seq.find(const_region) {
+            *offsets.entry(offset).or_default() += 1;
+        }
+    }
+
+    Ok(central_range(&offsets, WINDOW_COVERAGE)
+        .map(|(lo, hi)| (lo + 1, hi + const_region.len())))
+}
+
+/// Scan up to `max_pairs` read pairs for the construct elements.
+pub fn probe_pair(
+    path1: impl AsRef<Path>,
+    path2: impl AsRef<Path>,
+    max_pairs: u64,
+) -> io::Result<ProbeReport> {
+    let cfg = Config::from_constants();
+    let len = cfg.barcode_len;
+
+    let mut report = ProbeReport {
+        const_len: cfg.const_region.len(),
+        barcode_len: len,
+        ..ProbeReport::default()
+    };
+
+    let records1 = open_fastq(path1)?.records();
+    let records2 = open_fastq(path2)?.records();
+
+    for (rec1, rec2) in records1.zip(records2).take(max_pairs as usize) {
+        let rec1 = rec1.map_err(io::Error::other)?;
+        let rec2 = rec2.map_err(io::Error::other)?;
+        let seq1 = String::from_utf8_lossy(rec1.seq());
+        let seq2 = String::from_utf8_lossy(rec2.seq());
+        report.pairs += 1;
+
+        // -----------------------------------------------------
+        // 1. Constant region and barcode 2
+        // -----------------------------------------------------
+        match seq2.find(cfg.const_region) {
+            Some(offset) => {
+                report.constant_read2 += 1;
+                *report.constant_offsets.entry(offset).or_default() += 1;
+
+                let barcode2 =
+                    offset.checked_sub(len).and_then(|s| seq2.get(s..offset));
+                if let Some(barcode2) = barcode2.filter(|b| cfg.is_barcode2(b))
+                {
+                    *report
+                        .barcode2_hits
+                        .entry(barcode2.to_string())
+                        .or_default() += 1;
+                }
+            }
+            None if seq1.contains(cfg.const_region) => {
+                report.constant_read1 += 1;
+            }
+            None => {}
+        }
+
+        // -----------------------------------------------------
+        // 2. Discriminators and barcode 1
+        // -----------------------------------------------------
+        let disc = match (seq1.find(cfg.non_flipped), seq1.find(cfg.flipped)) {
+            (Some(p), _) => {
+                *report.non_flipped_offsets.entry(p).or_default() += 1;
+                Some(p)
+            }
+            (None, Some(p)) => {
+                *report.flipped_offsets.entry(p).or_default() += 1;
+                Some(p)
+            }
+            _ => None,
+        };
+
+        if let Some(disc) = disc {
+            for gap in 0..=MAX_DISC_OFFSET {
+                let Some(start) = disc.checked_sub(gap + len) else {
+                    break;
+                };
+                let barcode1 = seq1.get(start..start + len);
+                if let Some(barcode1) = barcode1.filter(|b| cfg.is_barcode1(b))
+                {
+                    *report
+                        .barcode1_hits
+                        .entry(gap)
+                        .or_default()
+                        .entry(barcode1.to_string())
+                        .or_default() += 1;
+                }
+            }
+        }
+    }
+
+    Ok(report)
+}
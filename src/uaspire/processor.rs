@@ -0,0 +1,619 @@
+/// Library entry point to run the uASPIre pipeline on one sample.
+use serde::Serialize;
+use strum::IntoEnumIterator;
+use thiserror::Error;
+
+use std::{
+    collections::BTreeMap,
+    env,
+    fs::{self, File},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::uaspire::assay::Assay;
+use crate::uaspire::classify::{Config, Counters, FailReason, Geometry};
+use crate::uaspire::crispr::GuideLibrary;
+use crate::uaspire::fastq::{
+    process_fastq, DirLayout, OverwritePolicy, ProcessOptions,
+};
+use crate::uaspire::interrupt::EXIT_INTERRUPTED;
+use crate::uaspire::manifest::{Manifest, PrunedOutput, RunStatus};
+use crate::uaspire::mpra::read_association;
+use crate::uaspire::notification::{notify, RunNotification};
+use crate::uaspire::reader::{estimate_records, STDIN_PATH};
+use crate::uaspire::retention::OutputKind;
+use crate::uaspire::spikein::SpikeIns;
+use crate::uaspire::variants::read_reference;
+
+// Process exit codes, following sysexits.h for the error categories
+pub const EXIT_COMPLETED: i32 = 0;
+pub const EXIT_WARNINGS: i32 = 3;
+pub const EXIT_CONFIG: i32 = 64;
+pub const EXIT_INPUT: i32 = 66;
+pub const EXIT_PROCESSING: i32 = 70;
+pub const EXIT_OUTPUT: i32 = 73;
+
+// ---------- Run errors ----------
+
+#[derive(Error, Debug)]
+pub enum RunError {
+    #[error("Invalid configuration: {0}")]
+    Config(String),
+
+    #[error("Cannot read input: {0}")]
+    Input(String),
+
+    #[error("Cannot write output: {0}")]
+    Output(String),
+
+    #[error("Processing failed: {0}")]
+    Processing(String),
+}
+
+impl RunError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            RunError::Config(_) => EXIT_CONFIG,
+            RunError::Input(_) => EXIT_INPUT,
+            RunError::Output(_) => EXIT_OUTPUT,
+            RunError::Processing(_) => EXIT_PROCESSING,
+        }
+    }
+}
+
+// ---------- Run summary ----------
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RunSummary {
+    pub sample_name: String,
+    pub total: u64,
+    pub valid: u64,
+    pub fails: BTreeMap<&'static str, u64>,
+    pub filtered_rows: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duplicate_fraction: Option<f64>,
+    pub outputs: BTreeMap<OutputKind, PathBuf>,
+    pub pruned: Vec<PrunedOutput>,
+    pub warnings: Vec<String>,
+    pub elapsed: Duration,
+    // Stopped by a signal before the end of the inputs
+    pub interrupted: bool,
+}
+
+impl RunSummary {
+    pub(crate) fn new(
+        counters: &Counters,
+        manifest: Manifest,
+        warnings: Vec<String>,
+        elapsed: Duration,
+    ) -> Self {
+        RunSummary {
+            sample_name: manifest.sample_name,
+            total: counters.total_count(),
+            valid: counters.valid_count(),
+            fails: FailReason::iter()
+                .map(|r| (r.name(), counters.fail_count(r)))
+                .collect(),
+            filtered_rows: counters.filtered_count(),
+            duplicate_fraction: None,
+            interrupted: manifest.status == RunStatus::Interrupted,
+            outputs: manifest.outputs,
+            pruned: manifest.pruned,
+            warnings,
+            elapsed,
+        }
+    }
+
+    /// Fraction of read pairs that passed classification.
+    pub fn valid_fraction(&self) -> f64 {
+        if self.total == 0 {
+            0.0
+        } else {
+            self.valid as f64 / self.total as f64
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        if self.interrupted {
+            EXIT_INTERRUPTED
+        } else if self.warnings.is_empty() {
+            EXIT_COMPLETED
+        } else {
+            EXIT_WARNINGS
+        }
+    }
+
+    /// Human readable summary.
+    pub fn print(&self, out: &mut impl std::io::Write) -> std::io::Result<()> {
+        writeln!(out, "sample          {}", self.sample_name)?;
+        writeln!(out, "total pairs     {}", self.total)?;
+        writeln!(
+            out,
+            "valid pairs     {} ({:.2}%)",
+            self.valid,
+            100.0 * self.valid_fraction()
+        )?;
+        if let Some(fraction) = self.duplicate_fraction {
+            writeln!(
+                out,
+                "duplicates      {:.2}% (estimated)",
+                100.0 * fraction
+            )?;
+        }
+        writeln!(out, "elapsed         {:.1}s", self.elapsed.as_secs_f64())?;
+        if self.interrupted {
+            writeln!(out, "status          interrupted, rerun with --resume")?;
+        }
+
+        for (kind, path) in &self.outputs {
+            writeln!(out, "output {:<8} {}", kind, path.display())?;
+        }
+
+        for warning in &self.warnings {
+            writeln!(out, "warning         {}", warning)?;
+        }
+
+        Ok(())
+    }
+
+    /// Summary as a single JSON object.
+    pub fn print_json(
+        &self,
+        out: &mut impl std::io::Write,
+    ) -> std::io::Result<()> {
+        let mut value = serde_json::to_value(self)?;
+        value["valid_fraction"] = self.valid_fraction().into();
+        value["elapsed"] = self.elapsed.as_secs_f64().into();
+
+        serde_json::to_writer(&mut *out, &value)?;
+        writeln!(out)
+    }
+}
+
+// ---------- Dry-run plan ----------
+
+#[derive(Debug, Clone)]
+pub struct InputPlan {
+    pub path: PathBuf,
+    pub bytes: Option<u64>,
+    pub estimated_records: Option<u64>,
+}
+
+/// What a run would read and create, computed without writing anything.
+#[derive(Debug, Clone)]
+pub struct RunPlan {
+    pub sample_name: String,
+    pub inputs: Vec<InputPlan>,
+    pub output_exists: bool,
+    pub min_read_lengths: (usize, usize),
+    pub dirs: Vec<PathBuf>,
+}
+
+impl RunPlan {
+    pub fn print(&self, out: &mut impl std::io::Write) -> std::io::Result<()> {
+        writeln!(out, "sample  {}", self.sample_name)?;
+
+        for input in &self.inputs {
+            let bytes = input.bytes.map_or("-".into(), |b| b.to_string());
+            let records = input
+                .estimated_records
+                .map_or("-".into(), |r| format!("~{r}"));
+            writeln!(
+                out,
+                "input   {} ({} bytes, {} records)",
+                input.path.display(),
+                bytes,
+                records
+            )?;
+        }
+
+        let (min1, min2) = self.min_read_lengths;
+        writeln!(
+            out,
+            "reads   at least {min1} bp (read 1), {min2} bp (read 2)"
+        )?;
+
+        if self.output_exists {
+            writeln!(out, "note    output directory already exists")?;
+        }
+
+        for dir in &self.dirs {
+            writeln!(out, "create  {}", dir.display())?;
+        }
+
+        Ok(())
+    }
+}
+
+// ---------- Processor ----------
+
+/// Builder configuring and running the pipeline for one sample, so other
+/// tools can embed it without going through the CLI.
+#[derive(Debug, Clone)]
+pub struct UaspireProcessor {
+    sample_name: String,
+    read1: PathBuf,
+    read2: PathBuf,
+    output_dir: PathBuf,
+    opts: ProcessOptions,
+}
+
+impl UaspireProcessor {
+    pub fn new(sample_name: impl Into<String>) -> Self {
+        let sample_name = sample_name.into();
+
+        UaspireProcessor {
+            output_dir: PathBuf::from("output").join(&sample_name),
+            sample_name,
+            read1: PathBuf::new(),
+            read2: PathBuf::new(),
+            opts: ProcessOptions::default(),
+        }
+    }
+
+    pub fn inputs(
+        mut self,
+        read1: impl AsRef<Path>,
+        read2: impl AsRef<Path>,
+    ) -> Self {
+        self.read1 = read1.as_ref().to_path_buf();
+        self.read2 = read2.as_ref().to_path_buf();
+        self
+    }
+
+    pub fn output_dir(mut self, dir: impl AsRef<Path>) -> Self {
+        self.output_dir = dir.as_ref().to_path_buf();
+        self
+    }
+
+    pub fn geometry(mut self, geometry: Geometry) -> Self {
+        self.opts.geometry = geometry;
+        self
+    }
+
+    /// Replace all processing options at once.
+    pub fn options(mut self, opts: ProcessOptions) -> Self {
+        self.opts = opts;
+        self
+    }
+
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.opts.chunk_size = chunk_size;
+        self
+    }
+
+    pub fn max_memory(mut self, bytes: usize) -> Self {
+        self.opts.max_memory = bytes;
+        self
+    }
+
+    pub fn write_fastq(mut self, enabled: bool) -> Self {
+        self.opts.write_fastq = enabled;
+        self
+    }
+
+    pub fn write_rejects(mut self, enabled: bool) -> Self {
+        self.opts.write_rejects = enabled;
+        self
+    }
+
+    /// FASTQ files read by the run, a single one for long reads.
+    fn input_paths(&self) -> Vec<&PathBuf> {
+        if self.opts.long_reads.is_some() {
+            vec![&self.read1]
+        } else {
+            vec![&self.read1, &self.read2]
+        }
+    }
+
+    /// Catch the errors that can be told before reading any record.
+    fn validate(&self) -> Result<(), RunError> {
+        if self.read1.as_os_str().is_empty() {
+            return Err(RunError::Config("no input FASTQ files".into()));
+        }
+
+        if self.opts.chunk_size == 0 || self.opts.parquet_size == 0 {
+            return Err(RunError::Config("sizes must be positive".into()));
+        }
+
+        Config::from_constants()
+            .with_geometry(&self.opts.geometry)
+            .validate()
+            .map_err(RunError::Config)?;
+
+        if self.read1.as_os_str() == STDIN_PATH {
+            // Both mates come from the interleaved stream
+            let read2 = self.read2.as_os_str();
+            if !read2.is_empty() && read2 != STDIN_PATH {
+                return Err(RunError::Config(format!(
+                    "READ2 ({}) cannot be given when READ1 is {STDIN_PATH}",
+                    self.read2.display()
+                )));
+            }
+
+            if self.opts.auto_window {
+                return Err(RunError::Config(
+                    "--window auto cannot read from standard input".into(),
+                ));
+            }
+        }
+
+        if self.opts.downsample.is_some_and(|d| d.depth == 0) {
+            return Err(RunError::Config(
+                "the downsampling depth must be positive".into(),
+            ));
+        }
+
+        if let Some(path) = &self.opts.spike_ins {
+            let spikes = SpikeIns::load(path).map_err(|e| {
+                RunError::Config(format!("{}: {e}", path.display()))
+            })?;
+
+            if spikes.is_empty() {
+                return Err(RunError::Config(format!(
+                    "no spike-ins in {}",
+                    path.display()
+                )));
+            }
+        }
+
+        match (self.opts.assay, &self.opts.guides) {
+            (Assay::Crispr, None) => {
+                return Err(RunError::Config(
+                    "the CRISPR assay needs a guide library".into(),
+                ));
+            }
+            (_, Some(guides)) => {
+                GuideLibrary::load(&guides.library).map_err(|e| {
+                    RunError::Config(format!(
+                        "{}: {e}",
+                        guides.library.display()
+                    ))
+                })?;
+            }
+            _ => {}
+        }
+
+        if let Some(variants) = &self.opts.variants {
+            read_reference(&variants.reference).map_err(|e| {
+                RunError::Config(format!(
+                    "{}: {e}",
+                    variants.reference.display()
+                ))
+            })?;
+        }
+
+        match (self.opts.assay, &self.opts.mpra) {
+            (Assay::Mpra, None) => {
+                return Err(RunError::Config(
+                    "the MPRA assay needs an anchor".into(),
+                ));
+            }
+            (_, Some(mpra))
+                if mpra.anchor.is_empty() || mpra.barcode_len == 0 =>
+            {
+                return Err(RunError::Config(
+                    "the MPRA anchor and barcode must not be empty".into(),
+                ));
+            }
+            (_, Some(mpra)) => {
+                if let Some(path) = &mpra.association {
+                    read_association(path).map_err(|e| {
+                        RunError::Config(format!("{}: {e}", path.display()))
+                    })?;
+                }
+            }
+            _ => {}
+        }
+
+        if self.read1.as_os_str() != STDIN_PATH {
+            for path in self.input_paths() {
+                File::open(path).map_err(|e| {
+                    RunError::Input(format!("{}: {e}", path.display()))
+                })?;
+            }
+        }
+
+        if self.output_dir.exists() && !self.output_dir.is_dir() {
+            return Err(RunError::Output(format!(
+                "{} is not a directory",
+                self.output_dir.display()
+            )));
+        }
+
+        let non_empty = fs::read_dir(&self.output_dir)
+            .is_ok_and(|mut entries| entries.next().is_some());
+        if non_empty && self.opts.overwrite == OverwritePolicy::NoClobber {
+            return Err(RunError::Output(format!(
+                "{} is not empty",
+                self.output_dir.display()
+            )));
+        }
+        if non_empty && self.opts.overwrite == OverwritePolicy::Overwrite {
+            self.check_removable()?;
+        }
+
+        if self.opts.append || self.opts.resume {
+            let path = self.output_dir.join("manifest.json");
+            let previous = Manifest::read(&path).map_err(|e| {
+                RunError::Output(format!(
+                    "no run to append to, {}: {e}",
+                    path.display()
+                ))
+            })?;
+
+            if self.opts.resume && previous.status != RunStatus::Interrupted {
+                return Err(RunError::Config(
+                    "nothing to resume, the previous run completed".into(),
+                ));
+            }
+
+            if previous.sample_name != self.sample_name {
+                return Err(RunError::Config(format!(
+                    "cannot append {} to a run of {}",
+                    self.sample_name, previous.sample_name
+                )));
+            }
+            if previous.layout != self.opts.layout {
+                return Err(RunError::Config(
+                    "cannot append with another output layout".into(),
+                ));
+            }
+            if previous.counts_shape != self.opts.counts_shape {
+                return Err(RunError::Config(
+                    "cannot append with another counts shape".into(),
+                ));
+            }
+            // The rows it dropped can't be added back
+            if previous.min_count > 0 {
+                return Err(RunError::Config(format!(
+                    "cannot append to a run filtered with --min-count {}",
+                    previous.min_count
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Refuse to overwrite an output directory holding the working
+    /// directory or an input.
+    fn check_removable(&self) -> Result<(), RunError> {
+        let output = fs::canonicalize(&self.output_dir).map_err(|e| {
+            RunError::Output(format!("{}: {e}", self.output_dir.display()))
+        })?;
+
+        if env::current_dir()
+            .and_then(fs::canonicalize)
+            .is_ok_and(|cwd| cwd.starts_with(&output))
+        {
+            return Err(RunError::Output(format!(
+                "won't overwrite {}, it holds the working directory",
+                self.output_dir.display()
+            )));
+        }
+
+        for path in self.input_paths() {
+            let inside = fs::canonicalize(path)
+                .is_ok_and(|path| path.starts_with(&output));
+            if inside {
+                return Err(RunError::Output(format!(
+                    "won't overwrite {}, it holds the input {}",
+                    self.output_dir.display(),
+                    path.display()
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Validate the run and describe it, without writing anything.
+    pub fn plan(&self) -> Result<RunPlan, RunError> {
+        self.validate()?;
+
+        let mut inputs = Vec::new();
+        if self.read1.as_os_str() == STDIN_PATH {
+            inputs.push(InputPlan {
+                path: self.read1.clone(),
+                bytes: None,
+                estimated_records: None,
+            });
+        } else {
+            for path in self.input_paths() {
+                let bytes = fs::metadata(path).map(|m| m.len()).ok();
+                let estimated_records =
+                    Some(estimate_records(path).map_err(|e| {
+                        RunError::Input(format!("{}: {e}", path.display()))
+                    })?);
+
+                inputs.push(InputPlan {
+                    path: path.clone(),
+                    bytes,
+                    estimated_records,
+                });
+            }
+        }
+
+        let layout = DirLayout::new(&self.output_dir);
+        let mut dirs: Vec<PathBuf> =
+            layout.created().into_iter().cloned().collect();
+        if self.opts.write_fastq {
+            dirs.push(layout.fastq.clone());
+        }
+        if self.opts.write_rejects {
+            dirs.push(layout.rejects.clone());
+        }
+
+        Ok(RunPlan {
+            sample_name: self.sample_name.clone(),
+            inputs,
+            output_exists: self.output_dir.exists(),
+            min_read_lengths: Config::from_constants()
+                .with_geometry(&self.opts.geometry)
+                .min_read_lengths(),
+            dirs,
+        })
+    }
+
+    /// Run the pipeline, turning a failed run into a categorised error.
+    ///
+    /// The configured notification targets are told about the outcome,
+    /// failed validation included.
+    pub fn run(&self) -> Result<RunSummary, RunError> {
+        let start = Instant::now();
+        let result = self.run_pipeline();
+
+        if self.opts.notify.is_enabled() {
+            let notification = RunNotification::new(
+                &self.sample_name,
+                &self.output_dir,
+                &result,
+                start.elapsed(),
+            );
+            notify(&self.opts.notify, &notification);
+        }
+
+        result
+    }
+
+    fn run_pipeline(&self) -> Result<RunSummary, RunError> {
+        self.validate()?;
+
+        if self.opts.overwrite == OverwritePolicy::Overwrite
+            && self.output_dir.exists()
+        {
+            fs::remove_dir_all(&self.output_dir).map_err(|e| {
+                RunError::Output(format!("{}: {e}", self.output_dir.display()))
+            })?;
+        }
+
+        fs::create_dir_all(&self.output_dir).map_err(|e| {
+            RunError::Output(format!("{}: {e}", self.output_dir.display()))
+        })?;
+
+        // Bad inputs and outputs come back as errors, a panic left in the
+        // pipeline is a processing failure
+        panic::catch_unwind(AssertUnwindSafe(|| {
+            process_fastq(
+                &self.read1.to_string_lossy(),
+                &self.read2.to_string_lossy(),
+                &self.sample_name,
+                &self.output_dir.to_string_lossy(),
+                &self.opts,
+            )
+        }))
+        .map_err(|payload| {
+            payload
+                .downcast_ref::<String>()
+                .cloned()
+                .or_else(|| {
+                    payload.downcast_ref::<&str>().map(|s| s.to_string())
+                })
+                .unwrap_or_else(|| "unknown error".to_string())
+        })
+        .map_err(RunError::Processing)?
+    }
+}
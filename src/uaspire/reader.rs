@@ -0,0 +1,241 @@
+/// Background FASTQ readers.
+///
+/// Each input file is decompressed and parsed on its own thread, and records
+/// are handed over to the processing loop in chunks through a bounded channel
+/// so that gzip decoding overlaps with classification.
+use bio::io::fastq;
+use flate2::read::MultiGzDecoder;
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+    sync::mpsc::{self, Receiver},
+    sync::Arc,
+    thread,
+};
+
+// Number of chunks a reader thread may decode ahead of the consumer
+const QUEUE_CHUNKS: usize = 4;
+
+// Records decoded to estimate the size of a whole file
+const ESTIMATE_SAMPLE: usize = 10_000;
+
+// Path standing for the standard input
+pub const STDIN_PATH: &str = "-";
+
+pub type RecordChunk = Vec<Result<fastq::Record, fastq::Error>>;
+
+// =========================================================
+// Helper functions
+// =========================================================
+
+/// Wrap a reader in a gzip decoder when the stream starts with the gzip
+/// magic bytes, so both plain and compressed FASTQ are accepted.
+pub(crate) fn maybe_gunzip<R: Read + Send + 'static>(
+    input: R,
+) -> io::Result<Box<dyn Read + Send>> {
+    let mut buffered = BufReader::new(input);
+    let is_gzip = buffered.fill_buf()?.starts_with(&[0x1f, 0x8b]);
+
+    if is_gzip {
+        Ok(Box::new(MultiGzDecoder::new(buffered)))
+    } else {
+        Ok(Box::new(buffered))
+    }
+}
+
+/// Open a plain or gzipped FASTQ file for sequential reading.
+pub(crate) fn open_fastq(
+    path: impl AsRef<Path>,
+) -> io::Result<fastq::Reader<BufReader<Box<dyn Read + Send>>>> {
+    let input = maybe_gunzip(File::open(path)?)?;
+    Ok(fastq::Reader::new(input))
+}
+
+/// Reader counting the bytes pulled from the underlying file.
+struct CountingReader<R> {
+    inner: R,
+    count: Arc<AtomicU64>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+/// Rough number of records in a plain or gzipped FASTQ file, extrapolated
+/// from the on-disk bytes used by the first records.
+pub(crate) fn estimate_records(path: impl AsRef<Path>) -> io::Result<u64> {
+    let file = File::open(path)?;
+    let size = file.metadata()?.len();
+
+    let count = Arc::new(AtomicU64::new(0));
+    let counting = CountingReader {
+        inner: file,
+        count: count.clone(),
+    };
+
+    let reader = fastq::Reader::new(maybe_gunzip(counting)?);
+    let mut sampled = 0;
+    for record in reader.records().take(ESTIMATE_SAMPLE) {
+        record.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        sampled += 1;
+    }
+
+    // The whole file was read, the count is exact
+    if sampled < ESTIMATE_SAMPLE as u64 {
+        return Ok(sampled);
+    }
+
+    let consumed = count.load(Ordering::Relaxed).max(1);
+    Ok((size as f64 * sampled as f64 / consumed as f64) as u64)
+}
+
+// ---------- Chunked reader ----------
+
+pub struct ChunkReader {
+    receiver: Receiver<RecordChunk>,
+}
+
+impl ChunkReader {
+    /// Open a FASTQ file, or the standard input for `-`, and start decoding
+    /// it on a dedicated thread.
+    pub fn spawn(
+        path: impl AsRef<Path>,
+        chunk_size: usize,
+    ) -> io::Result<Self> {
+        let path = path.as_ref();
+        let input = if path == Path::new(STDIN_PATH) {
+            maybe_gunzip(io::stdin())?
+        } else {
+            maybe_gunzip(File::open(path)?)?
+        };
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_CHUNKS);
+
+        let name = format!("reader-{}", path.display());
+        thread::Builder::new().name(name).spawn(move || {
+            let reader = fastq::Reader::new(input);
+            let mut records = reader.records();
+
+            let mut failed = false;
+
+            while !failed {
+                let mut chunk: RecordChunk = Vec::with_capacity(chunk_size);
+
+                // A parse error usually means a truncated or corrupt stream,
+                // nothing after it can be trusted
+                for record in records.by_ref().take(chunk_size) {
+                    failed = record.is_err();
+                    chunk.push(record);
+                    if failed {
+                        break;
+                    }
+                }
+
+                // Stop when the file is exhausted or the consumer hung up
+                if chunk.is_empty() || sender.send(chunk).is_err() {
+                    break;
+                }
+            }
+        })?;
+
+        // The thread exits on its own once the receiver is dropped
+        Ok(ChunkReader { receiver })
+    }
+
+    /// Read an interleaved FASTQ stream (R1, R2, R1, R2, ...) from the
+    /// standard input and split it into two chunked readers.
+    pub fn spawn_interleaved_stdin(
+        chunk_size: usize,
+    ) -> io::Result<(Self, Self)> {
+        let input = maybe_gunzip(io::stdin())?;
+        let (sender1, receiver1) = mpsc::sync_channel(QUEUE_CHUNKS);
+        let (sender2, receiver2) = mpsc::sync_channel(QUEUE_CHUNKS);
+
+        let name = "reader-stdin".to_string();
+        thread::Builder::new().name(name).spawn(move || {
+            let reader = fastq::Reader::new(input);
+            let mut records = reader.records();
+
+            loop {
+                let mut chunk1 = Vec::with_capacity(chunk_size);
+                let mut chunk2 = Vec::with_capacity(chunk_size);
+
+                let mut failed = false;
+
+                while chunk1.len() < chunk_size && !failed {
+                    match (records.next(), records.next()) {
+                        (Some(rec1), Some(rec2)) => {
+                            failed = rec1.is_err() || rec2.is_err();
+                            chunk1.push(rec1);
+                            chunk2.push(rec2);
+                        }
+                        (Some(rec1), None) => {
+                            // An odd trailing record has no mate
+                            failed = true;
+                            chunk1.push(rec1);
+                            chunk2.push(Err(fastq::Error::IncompleteRecord));
+                        }
+                        _ => break,
+                    }
+                }
+
+                if chunk1.is_empty()
+                    || sender1.send(chunk1).is_err()
+                    || sender2.send(chunk2).is_err()
+                    || failed
+                {
+                    break;
+                }
+            }
+        })?;
+
+        Ok((
+            ChunkReader {
+                receiver: receiver1,
+            },
+            ChunkReader {
+                receiver: receiver2,
+            },
+        ))
+    }
+
+    /// Open a pair of readers, `-` as first path meaning interleaved stdin.
+    pub fn spawn_pair(
+        path1: impl AsRef<Path>,
+        path2: impl AsRef<Path>,
+        chunk_size: usize,
+    ) -> io::Result<(Self, Self)> {
+        if path1.as_ref() == Path::new(STDIN_PATH) {
+            let path2 = path2.as_ref();
+            if !path2.as_os_str().is_empty() && path2 != Path::new(STDIN_PATH) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "{}: READ2 cannot be given when READ1 is {STDIN_PATH}",
+                        path2.display()
+                    ),
+                ));
+            }
+            return Self::spawn_interleaved_stdin(chunk_size);
+        }
+
+        Ok((
+            Self::spawn(path1, chunk_size)?,
+            Self::spawn(path2, chunk_size)?,
+        ))
+    }
+}
+
+impl Iterator for ChunkReader {
+    type Item = RecordChunk;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.receiver.recv().ok()
+    }
+}
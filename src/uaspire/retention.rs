@@ -0,0 +1,198 @@
+/// Retention policies and size budget applied to the outputs of a run.
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use strum_macros::Display;
+use tracing::{info, warn};
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::uaspire::manifest::PrunedOutput;
+
+// ---------- Output categories ----------
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    ValueEnum,
+    Display,
+    Serialize,
+    Deserialize,
+)]
+#[strum(serialize_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum OutputKind {
+    Qc,
+    Counts,
+    Elements,
+    Fastq,
+    Rejects,
+    Tmp,
+}
+
+// ---------- Policy ----------
+
+// Kept when no category is given explicitly
+const DEFAULT_KEEP: [OutputKind; 3] =
+    [OutputKind::Qc, OutputKind::Counts, OutputKind::Elements];
+
+#[derive(Debug, Clone)]
+pub struct RetentionPolicy {
+    pub keep: Vec<OutputKind>,
+    pub drop: Vec<OutputKind>,
+    pub max_output_bytes: Option<u64>,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        RetentionPolicy {
+            keep: DEFAULT_KEEP.to_vec(),
+            drop: Vec::new(),
+            max_output_bytes: None,
+        }
+    }
+}
+
+impl RetentionPolicy {
+    /// Build a policy, rejecting categories that are both kept and dropped
+    /// and budgets that aren't a positive size.
+    /// Without `keep`, the default categories are kept except the dropped
+    /// ones.
+    pub fn new(
+        keep: Option<Vec<OutputKind>>,
+        drop: Vec<OutputKind>,
+        max_output_gb: Option<f64>,
+    ) -> Result<Self, String> {
+        let keep = match keep {
+            Some(keep) => {
+                if let Some(kind) = drop.iter().find(|k| keep.contains(k)) {
+                    return Err(format!(
+                        "'{kind}' cannot be both kept and dropped"
+                    ));
+                }
+                keep
+            }
+            None => DEFAULT_KEEP
+                .into_iter()
+                .filter(|kind| !drop.contains(kind))
+                .collect(),
+        };
+
+        if let Some(gb) = max_output_gb {
+            if !gb.is_finite() || gb <= 0.0 {
+                return Err(format!("invalid output budget of {gb} GB"));
+            }
+        }
+        let max_output_bytes = max_output_gb.map(|gb| (gb * 1e9) as u64);
+
+        Ok(RetentionPolicy {
+            keep,
+            drop,
+            max_output_bytes,
+        })
+    }
+}
+
+// =========================================================
+// Helper functions
+// =========================================================
+
+/// Total size in bytes of all files under `path`.
+pub fn dir_size(path: impl AsRef<Path>) -> io::Result<u64> {
+    let path = path.as_ref();
+
+    if !path.exists() {
+        return Ok(0);
+    }
+
+    if path.is_file() {
+        return Ok(path.metadata()?.len());
+    }
+
+    let mut total = 0;
+    for entry in fs::read_dir(path)? {
+        total += dir_size(entry?.path())?;
+    }
+
+    Ok(total)
+}
+
+fn prune(
+    kind: OutputKind,
+    path: &Path,
+    reason: &str,
+) -> io::Result<PrunedOutput> {
+    let bytes = dir_size(path)?;
+    fs::remove_dir_all(path)?;
+    info!("Pruned {} ({} bytes): {}", path.display(), bytes, reason);
+
+    Ok(PrunedOutput {
+        kind,
+        path: path.to_path_buf(),
+        bytes,
+        reason: reason.to_string(),
+    })
+}
+
+// =========================================================
+// Enforcement
+// =========================================================
+
+/// Apply the policy to the outputs of a run and return what was pruned.
+///
+/// Dropped categories are always removed. When a size budget is set, the
+/// largest categories that are not explicitly kept are then removed until
+/// the outputs fit in the budget.
+pub fn enforce(
+    policy: &RetentionPolicy,
+    outputs: &[(OutputKind, PathBuf)],
+) -> io::Result<Vec<PrunedOutput>> {
+    let mut pruned = Vec::new();
+    let mut remaining = Vec::new();
+
+    for (kind, path) in outputs {
+        if policy.drop.contains(kind) && path.exists() {
+            pruned.push(prune(*kind, path, "dropped")?);
+        } else {
+            remaining.push((*kind, path.clone(), dir_size(path)?));
+        }
+    }
+
+    let Some(budget) = policy.max_output_bytes else {
+        return Ok(pruned);
+    };
+
+    let mut total: u64 = remaining.iter().map(|(_, _, bytes)| bytes).sum();
+
+    // Largest candidates first
+    remaining.sort_by(|a, b| b.2.cmp(&a.2));
+
+    for (kind, path, bytes) in &remaining {
+        if total <= budget {
+            break;
+        }
+
+        if policy.keep.contains(kind) || *bytes == 0 {
+            continue;
+        }
+
+        pruned.push(prune(*kind, path, "over output budget")?);
+        total -= bytes;
+    }
+
+    if total > budget {
+        warn!(
+            "Outputs still use {} bytes, above the {} bytes budget",
+            total, budget
+        );
+    }
+
+    Ok(pruned)
+}
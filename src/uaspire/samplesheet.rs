@@ -0,0 +1,240 @@
+/// Validation of a barcode sample sheet before processing.
+///
+/// A sample sheet is a CSV with `sample,barcode1,barcode2` columns naming
+/// each barcode pair. Barcodes too close to each other get reads assigned
+/// to the wrong sample once errors or correction come into play, so the
+/// pairwise Hamming distances are checked along with duplicates, lengths
+/// and labels.
+use serde::Deserialize;
+use std::{collections::HashMap, path::Path};
+
+// ---------- Sample sheet entries ----------
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SampleSheetEntry {
+    pub sample: String,
+    pub barcode1: String,
+    pub barcode2: String,
+}
+
+pub fn read_samplesheet(
+    path: impl AsRef<Path>,
+) -> Result<Vec<SampleSheetEntry>, csv::Error> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+    reader.deserialize().collect()
+}
+
+// ---------- Report ----------
+
+/// Pairwise distances of the distinct barcodes of one read.
+#[derive(Debug, Default)]
+pub struct DistanceMatrix {
+    pub barcodes: Vec<String>,
+    // None when the lengths differ
+    pub distances: Vec<Vec<Option<usize>>>,
+}
+
+impl DistanceMatrix {
+    fn new(barcodes: Vec<String>) -> Self {
+        let distances = barcodes
+            .iter()
+            .map(|a| barcodes.iter().map(|b| hamming(a, b)).collect())
+            .collect();
+
+        DistanceMatrix {
+            barcodes,
+            distances,
+        }
+    }
+
+    /// Closest pair of distinct barcodes of the same length.
+    pub fn min_distance(&self) -> Option<(usize, &str, &str)> {
+        let mut closest: Option<(usize, &str, &str)> = None;
+
+        for (i, row) in self.distances.iter().enumerate() {
+            for (j, d) in row.iter().enumerate().skip(i + 1) {
+                if let Some(d) = *d {
+                    if closest.is_none_or(|(best, _, _)| d < best) {
+                        closest =
+                            Some((d, &self.barcodes[i], &self.barcodes[j]));
+                    }
+                }
+            }
+        }
+
+        closest
+    }
+
+    pub fn print(&self, name: &str) {
+        println!("{name} distances");
+
+        let width = self.barcodes.iter().map(String::len).max().unwrap_or(0);
+        print!("{:width$}", "");
+        for i in 0..self.barcodes.len() {
+            print!(" {:>3}", i + 1);
+        }
+        println!();
+
+        for (i, (barcode, row)) in
+            self.barcodes.iter().zip(&self.distances).enumerate()
+        {
+            print!("{barcode:width$}");
+            for (j, d) in row.iter().enumerate() {
+                match d {
+                    _ if i == j => print!(" {:>3}", "-"),
+                    Some(d) => print!(" {d:>3}"),
+                    None => print!(" {:>3}", "?"),
+                }
+            }
+            println!();
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SampleSheetReport {
+    pub entries: usize,
+    pub barcodes1: DistanceMatrix,
+    pub barcodes2: DistanceMatrix,
+    pub errors: Vec<String>,
+}
+
+impl SampleSheetReport {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    pub fn print(&self) {
+        println!("Samples: {}", self.entries);
+
+        for (name, matrix) in
+            [("barcode1", &self.barcodes1), ("barcode2", &self.barcodes2)]
+        {
+            println!();
+            matrix.print(name);
+
+            if let Some((d, a, b)) = matrix.min_distance() {
+                println!("Minimum {name} distance: {d} ({a} vs {b})");
+            }
+        }
+
+        println!();
+        for error in &self.errors {
+            println!("Error: {}", error);
+        }
+
+        println!("Status: {}", if self.is_ok() { "OK" } else { "FAILED" });
+    }
+}
+
+// =========================================================
+// Helper functions
+// =========================================================
+
+fn hamming(a: &str, b: &str) -> Option<usize> {
+    (a.len() == b.len())
+        .then(|| a.bytes().zip(b.bytes()).filter(|(x, y)| x != y).count())
+}
+
+/// Distinct values in order of first appearance.
+fn distinct<'e>(values: impl Iterator<Item = &'e str>) -> Vec<String> {
+    let mut seen: Vec<String> = Vec::new();
+    for value in values {
+        if !seen.iter().any(|s| s == value) {
+            seen.push(value.to_string());
+        }
+    }
+    seen
+}
+
+// =========================================================
+// Check
+// =========================================================
+
+/// Validate the entries of a sample sheet.
+///
+/// Barcodes must be ACGT only and all of the same length, `barcode_len`
+/// when given; barcode pairs and sample labels must be unique, labels also
+/// when compared case-insensitively since they become directory names; and
+/// the barcodes of each read must be at least `min_distance` apart.
+pub fn check_samplesheet(
+    entries: &[SampleSheetEntry],
+    min_distance: usize,
+    barcode_len: Option<usize>,
+) -> SampleSheetReport {
+    let mut errors = Vec::new();
+
+    let mut pairs: HashMap<(&str, &str), &str> = HashMap::new();
+    let mut labels: HashMap<String, &str> = HashMap::new();
+
+    for entry in entries {
+        if entry.sample.is_empty() {
+            errors.push("empty sample label".to_string());
+        }
+
+        for barcode in [&entry.barcode1, &entry.barcode2] {
+            if barcode.is_empty()
+                || !barcode.bytes().all(|b| b"ACGT".contains(&b))
+            {
+                errors.push(format!(
+                    "{}: barcode '{barcode}' is not ACGT only",
+                    entry.sample
+                ));
+            }
+            if let Some(len) = barcode_len.filter(|&l| barcode.len() != l) {
+                errors.push(format!(
+                    "{}: barcode {barcode} is not {len} bases long",
+                    entry.sample
+                ));
+            }
+        }
+
+        let pair = (entry.barcode1.as_str(), entry.barcode2.as_str());
+        if let Some(other) = pairs.insert(pair, &entry.sample) {
+            errors.push(format!(
+                "{} and {} share barcodes {}+{}",
+                other, entry.sample, pair.0, pair.1
+            ));
+        }
+
+        let key = entry.sample.to_lowercase();
+        if let Some(other) = labels.insert(key, &entry.sample) {
+            errors.push(format!(
+                "label {} collides with {}",
+                entry.sample, other
+            ));
+        }
+    }
+
+    let barcodes1 =
+        DistanceMatrix::new(distinct(entries.iter().map(|e| &*e.barcode1)));
+    let barcodes2 =
+        DistanceMatrix::new(distinct(entries.iter().map(|e| &*e.barcode2)));
+
+    for (name, matrix) in [("barcode1", &barcodes1), ("barcode2", &barcodes2)] {
+        let mut lengths: Vec<usize> =
+            matrix.barcodes.iter().map(String::len).collect();
+        lengths.sort_unstable();
+        lengths.dedup();
+        if lengths.len() > 1 {
+            errors.push(format!("{name} lengths differ: {lengths:?}"));
+        }
+
+        if let Some((d, a, b)) = matrix.min_distance() {
+            if d < min_distance {
+                errors.push(format!(
+                    "{name} {a} and {b} are {d} apart, below {min_distance}"
+                ));
+            }
+        }
+    }
+
+    SampleSheetReport {
+        entries: entries.len(),
+        barcodes1,
+        barcodes2,
+        errors,
+    }
+}
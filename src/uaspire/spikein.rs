@@ -0,0 +1,197 @@
+/// Spike-in controls: known RBS sequences tallied apart from the counts.
+use dashmap::DashMap;
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Write},
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::uaspire::classify::{Flip, Sample};
+
+// ---------- Controls ----------
+
+pub struct SpikeIns {
+    names: Vec<String>,
+    sequences: Vec<String>,
+    index: HashMap<String, usize>,
+    counts: Vec<[AtomicU64; 2]>,
+    // Reads assigned to any control, by barcode pair
+    sample_reads: DashMap<Sample, AtomicU64>,
+}
+
+impl SpikeIns {
+    /// Read controls from a FASTA file, or a CSV with `name,sequence`
+    /// columns.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+
+        let pairs = if text.trim_start().starts_with('>') {
+            parse_fasta(&text)
+        } else {
+            parse_csv(&text)?
+        };
+
+        let mut spikes = SpikeIns {
+            names: Vec::new(),
+            sequences: Vec::new(),
+            index: HashMap::new(),
+            counts: Vec::new(),
+            sample_reads: DashMap::new(),
+        };
+
+        for (name, sequence) in pairs {
+            let sequence = sequence.to_ascii_uppercase();
+            if spikes.index.contains_key(&sequence) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("duplicated spike-in sequence {sequence}"),
+                ));
+            }
+
+            spikes.index.insert(sequence.clone(), spikes.names.len());
+            spikes.names.push(name);
+            spikes.sequences.push(sequence);
+            spikes.counts.push(Default::default());
+        }
+
+        Ok(spikes)
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Tally an RBS of `sample` if it is a control, returning whether it
+    /// was one.
+    pub(crate) fn record(
+        &self,
+        sample: &Sample,
+        rbs: &str,
+        flipped: Flip,
+    ) -> bool {
+        let Some(&i) = self.index.get(rbs) else {
+            return false;
+        };
+
+        let idx = match flipped {
+            Flip::NonFlipped => 0,
+            Flip::Flipped => 1,
+        };
+        self.counts[i][idx].fetch_add(1, Ordering::Relaxed);
+        self.sample_reads
+            .entry(sample.clone())
+            .or_default()
+            .fetch_add(1, Ordering::Relaxed);
+
+        true
+    }
+
+    fn counts(&self, i: usize) -> (u64, u64) {
+        (
+            self.counts[i][0].load(Ordering::Relaxed),
+            self.counts[i][1].load(Ordering::Relaxed),
+        )
+    }
+
+    /// Reads assigned to any control.
+    pub fn total_reads(&self) -> u64 {
+        (0..self.len())
+            .map(|i| {
+                let (unflipped, flipped) = self.counts(i);
+                unflipped + flipped
+            })
+            .sum()
+    }
+
+    /// Reads assigned to any control, by barcode pair.
+    pub(crate) fn sample_reads(&self) -> HashMap<(String, String), u64> {
+        self.sample_reads
+            .iter()
+            .map(|entry| {
+                let sample = entry.key();
+                (
+                    (sample.barcode1.clone(), sample.barcode2.clone()),
+                    entry.value().load(Ordering::Relaxed),
+                )
+            })
+            .collect()
+    }
+
+    /// Number of controls seen at least once.
+    pub fn recovered(&self) -> u64 {
+        (0..self.len())
+            .filter(|&i| {
+                let (unflipped, flipped) = self.counts(i);
+                unflipped + flipped > 0
+            })
+            .count() as u64
+    }
+
+    /// Per-control counts and flip ratio as TSV.
+    pub fn write_tsv(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut out = io::BufWriter::new(fs::File::create(path)?);
+        writeln!(out, "name\tsequence\tunflipped\tflipped\tflip_ratio")?;
+
+        for i in 0..self.len() {
+            let (unflipped, flipped) = self.counts(i);
+            let total = unflipped + flipped;
+            let ratio = if total > 0 {
+                format!("{:.4}", flipped as f64 / total as f64)
+            } else {
+                "NA".to_string()
+            };
+
+            writeln!(
+                out,
+                "{}\t{}\t{}\t{}\t{}",
+                self.names[i], self.sequences[i], unflipped, flipped, ratio
+            )?;
+        }
+
+        out.flush()
+    }
+}
+
+// =========================================================
+// Helper functions
+// =========================================================
+
+pub(crate) fn parse_fasta(text: &str) -> Vec<(String, String)> {
+    let mut pairs: Vec<(String, String)> = Vec::new();
+
+    for line in text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        match line.strip_prefix('>') {
+            Some(header) => {
+                let name = header.split_whitespace().next().unwrap_or("");
+                pairs.push((name.to_string(), String::new()));
+            }
+            None => {
+                if let Some((_, sequence)) = pairs.last_mut() {
+                    sequence.push_str(line);
+                }
+            }
+        }
+    }
+
+    pairs
+}
+
+pub(crate) fn parse_csv(text: &str) -> io::Result<Vec<(String, String)>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(text.as_bytes());
+
+    let mut pairs = Vec::new();
+    for record in reader.deserialize() {
+        let (name, sequence): (String, String) = record?;
+        pairs.push((name, sequence));
+    }
+
+    Ok(pairs)
+}
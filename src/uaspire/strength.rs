@@ -0,0 +1,79 @@
+/// First-pass RBS strength from the Shine-Dalgarno / anti-Shine-Dalgarno
+/// hybridisation free energy.
+///
+/// Only ungapped Watson-Crick helices are considered, with Turner
+/// nearest-neighbour stacking energies at 37 °C. This ranks RBSs by their
+/// SD strength, it is not a translation rate model.
+
+// 3' end of the E. coli 16S rRNA read 3' to 5', facing the mRNA 5' to 3'
+const ANTI_SD_3_TO_5: &[u8] = b"AUUCCUCCA";
+
+// Duplex initiation penalty (kcal/mol)
+const INITIATION: f64 = 4.09;
+
+// Boltzmann factor scaling the free energy into a relative strength
+const BETA: f64 = 0.45;
+
+/// Stacking energy (kcal/mol) of the top strand dinucleotide `a b`, paired
+/// with its Watson-Crick complement.
+fn stack(a: u8, b: u8) -> f64 {
+    match (a, b) {
+        (b'A', b'A') | (b'U', b'U') => -0.93,
+        (b'A', b'U') => -1.10,
+        (b'U', b'A') => -1.33,
+        (b'C', b'U') | (b'A', b'G') => -2.08,
+        (b'C', b'A') | (b'U', b'G') => -2.11,
+        (b'G', b'U') | (b'A', b'C') => -2.24,
+        (b'G', b'A') | (b'U', b'C') => -2.35,
+        (b'C', b'G') => -2.36,
+        (b'G', b'G') | (b'C', b'C') => -3.26,
+        (b'G', b'C') => -3.42,
+        _ => 0.0,
+    }
+}
+
+fn pairs(a: u8, b: u8) -> bool {
+    matches!(
+        (a, b),
+        (b'A', b'U') | (b'U', b'A') | (b'G', b'C') | (b'C', b'G')
+    )
+}
+
+/// Lowest free energy (kcal/mol) of an ungapped helix between the RBS and
+/// the anti-SD, zero when no helix is favourable.
+pub fn sd_free_energy(rbs: &str) -> f64 {
+    let mrna: Vec<u8> = rbs
+        .bytes()
+        .map(|b| match b.to_ascii_uppercase() {
+            b'T' => b'U',
+            b => b,
+        })
+        .collect();
+
+    let mut best = 0.0_f64;
+
+    for i in 0..mrna.len() {
+        for j in 0..ANTI_SD_3_TO_5.len() {
+            let mut energy = INITIATION;
+            let mut k = 0;
+
+            while i + k < mrna.len()
+                && j + k < ANTI_SD_3_TO_5.len()
+                && pairs(mrna[i + k], ANTI_SD_3_TO_5[j + k])
+            {
+                if k > 0 {
+                    energy += stack(mrna[i + k - 1], mrna[i + k]);
+                    best = best.min(energy);
+                }
+                k += 1;
+            }
+        }
+    }
+
+    best
+}
+
+/// Relative strength, `exp(-beta * dG)`.
+pub fn predicted_strength(rbs: &str) -> f64 {
+    (-BETA * sd_free_energy(rbs)).exp()
+}
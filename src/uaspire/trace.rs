@@ -0,0 +1,154 @@
+/// Pipeline friendly run metadata: `versions.yml` and per-chunk trace.
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::Path,
+    time::Duration,
+};
+
+// Process name used as top-level key, as nf-core modules do
+const PROCESS_NAME: &str = "UASPIRE_PROCESS_SAMPLE";
+
+// ---------- Versions ----------
+
+/// Write the tool and dependency versions in the nf-core `versions.yml`
+/// format.
+pub fn write_versions_yml(path: impl AsRef<Path>) -> io::Result<()> {
+    let mut file = File::create(path)?;
+
+    writeln!(file, "\"{}\":", PROCESS_NAME)?;
+    writeln!(
+        file,
+        "    {}: {}",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION")
+    )?;
+
+    // Several locked versions of a crate are all reported
+    for dependency in env!("DEPENDENCY_VERSIONS").split(';') {
+        if let Some((name, version)) = dependency.split_once('=') {
+            writeln!(file, "    {}: {}", name, version)?;
+        }
+    }
+
+    Ok(())
+}
+
+// ---------- Chunk trace ----------
+
+/// Tab-separated timings of every processed chunk.
+pub(crate) struct ChunkTrace {
+    writer: csv::Writer<File>,
+}
+
+impl ChunkTrace {
+    pub(crate) fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut writer =
+            csv::WriterBuilder::new().delimiter(b'\t').from_path(path)?;
+
+        writer.write_record([
+            "chunk",
+            "records",
+            "read_ms",
+            "classify_ms",
+            "spill_ms",
+        ])?;
+
+        Ok(ChunkTrace { writer })
+    }
+
+    pub(crate) fn record(
+        &mut self,
+        chunk: usize,
+        records: usize,
+        read: Duration,
+        classify: Duration,
+        spill: Duration,
+    ) -> io::Result<()> {
+        self.writer.write_record([
+            chunk.to_string(),
+            records.to_string(),
+            read.as_millis().to_string(),
+            classify.as_millis().to_string(),
+            spill.as_millis().to_string(),
+        ])?;
+
+        Ok(())
+    }
+
+    pub(crate) fn finish(mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// ---------- Performance ----------
+
+/// Current and peak resident set size of this process in bytes, read from
+/// `/proc` and so only known on Linux.
+pub(crate) fn memory_usage() -> Option<(u64, u64)> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let field = |name: &str| -> Option<u64> {
+        let value = status.lines().find_map(|l| l.strip_prefix(name))?;
+        let kb = value
+            .trim()
+            .strip_suffix("kB")?
+            .trim()
+            .parse::<u64>()
+            .ok()?;
+        Some(kb * 1024)
+    };
+
+    Some((field("VmRSS:")?, field("VmHWM:")?))
+}
+
+/// Resources used by one chunk.
+#[derive(Debug, Clone)]
+pub(crate) struct ChunkPerformance {
+    pub(crate) chunk: usize,
+    pub(crate) records: usize,
+    pub(crate) wall: Duration,
+    pub(crate) table_samples: usize,
+    pub(crate) table_entries: usize,
+    pub(crate) rss: Option<u64>,
+    pub(crate) peak_rss: Option<u64>,
+}
+
+impl ChunkPerformance {
+    pub(crate) fn reads_per_sec(&self) -> f64 {
+        self.records as f64 / self.wall.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+/// Performance of every chunk of a run, kept to track regressions across
+/// versions and to size cluster resources.
+#[derive(Debug, Default)]
+pub(crate) struct Performance {
+    pub(crate) chunks: Vec<ChunkPerformance>,
+}
+
+impl Performance {
+    pub(crate) fn record(
+        &mut self,
+        chunk: usize,
+        records: usize,
+        wall: Duration,
+        table_samples: usize,
+        table_entries: usize,
+    ) {
+        let (rss, peak_rss) = memory_usage().unzip();
+
+        self.chunks.push(ChunkPerformance {
+            chunk,
+            records,
+            wall,
+            table_samples,
+            table_entries,
+            rss,
+            peak_rss,
+        });
+    }
+
+    pub(crate) fn peak_rss(&self) -> Option<u64> {
+        self.chunks.iter().filter_map(|c| c.peak_rss).max()
+    }
+}
@@ -0,0 +1,310 @@
+/// Two-pass processing: classification of the reads into per-read
+/// assignments, then aggregation of the assignments into counts.
+///
+/// Classification is the expensive part of a run. Keeping its output lets
+/// the aggregation parameters (minimum counts, normalization, annotations)
+/// be changed and re-run without reading the FASTQ files again.
+use polars::prelude::*;
+use rayon::prelude::*;
+use tracing::{info, warn};
+
+use std::{
+    fmt::Display,
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::uaspire::classify::{
+    classify_pair, Config, Counters, FailReason, Flip, Geometry,
+};
+use crate::uaspire::fastq::{
+    finish_counts, prepare_dirs, write_counts, write_qc_parquet, ProcessOptions,
+};
+use crate::uaspire::manifest::Manifest;
+use crate::uaspire::metadata::OutputMetadata;
+use crate::uaspire::processor::RunError;
+use crate::uaspire::reader::ChunkReader;
+use crate::uaspire::trace::write_versions_yml;
+
+// ---------- Assignments directory ----------
+
+/// Files of an assignments directory.
+#[derive(Debug, Clone)]
+pub struct AssignmentLayout {
+    pub root: PathBuf,
+    pub reads: PathBuf,
+    pub qc: PathBuf,
+    pub manifest: PathBuf,
+}
+
+impl AssignmentLayout {
+    pub fn new(dir: impl AsRef<Path>) -> Self {
+        let root = dir.as_ref().to_path_buf();
+
+        AssignmentLayout {
+            reads: root.join("reads"),
+            qc: root.join("qc.parquet"),
+            manifest: root.join("manifest.json"),
+            root,
+        }
+    }
+}
+
+// (barcode1, barcode2, gre, flipped) of a valid read pair
+type Assignment = (String, String, String, bool);
+
+// =========================================================
+// Helper functions
+// =========================================================
+
+fn input_error(path: &Path, e: impl Display) -> RunError {
+    RunError::Input(format!("{}: {e}", path.display()))
+}
+
+fn output_error(path: &Path, e: impl Display) -> RunError {
+    RunError::Output(format!("{}: {e}", path.display()))
+}
+
+/// Write the assignments of one chunk as a Zstd-compressed Parquet file.
+fn write_assignments(
+    assignments: &[Assignment],
+    path: &Path,
+) -> PolarsResult<()> {
+    let column = |name: &str, f: fn(&Assignment) -> &str| -> Column {
+        let values: Vec<&str> = assignments.iter().map(f).collect();
+        Series::new(name.into(), values).into()
+    };
+    let flipped: Vec<bool> = assignments.iter().map(|a| a.3).collect();
+
+    let mut df = DataFrame::new(vec![
+        column("barcode1", |a| a.0.as_str()),
+        column("barcode2", |a| a.1.as_str()),
+        column("gre", |a| a.2.as_str()),
+        Series::new("flipped".into(), flipped).into(),
+    ])?;
+
+    let file = File::create(path)?;
+    ParquetWriter::new(file)
+        .with_compression(ParquetCompression::Zstd(None))
+        .finish(&mut df)?;
+
+    Ok(())
+}
+
+// =========================================================
+// Classification pass
+// =========================================================
+
+/// Classify a FASTQ pair and write one assignment per valid read pair,
+/// along with the QC counters and a manifest naming the inputs.
+pub fn classify_to_assignments(
+    path1: &Path,
+    path2: &Path,
+    sample_name: &str,
+    output_dir: &Path,
+    geometry: &Geometry,
+    chunk_size: usize,
+) -> Result<u64, RunError> {
+    let cfg = Config::from_constants().with_geometry(geometry);
+    cfg.validate().map_err(RunError::Config)?;
+
+    let (reader1, reader2) = ChunkReader::spawn_pair(path1, path2, chunk_size)
+        .map_err(|e| {
+            RunError::Input(format!(
+                "{}, {}: {e}",
+                path1.display(),
+                path2.display()
+            ))
+        })?;
+
+    // Chunks of an earlier pass would be aggregated with the new ones
+    let layout = AssignmentLayout::new(output_dir);
+    match fs::remove_dir_all(&layout.reads) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            return Err(output_error(&layout.reads, e));
+        }
+        _ => {}
+    }
+    fs::create_dir_all(&layout.reads)
+        .map_err(|e| output_error(&layout.reads, e))?;
+
+    let counters = Counters::default();
+    let mut chunks = 0;
+
+    for (chunk, (chunk1, chunk2)) in reader1.zip(reader2).enumerate() {
+        let assignments: Vec<Assignment> = chunk1
+            .par_iter()
+            .zip(chunk2.par_iter())
+            .filter_map(|(rec1, rec2)| {
+                counters.inc_total();
+
+                let (Ok(rec1), Ok(rec2)) = (rec1, rec2) else {
+                    counters.inc_fail(FailReason::MalformedRecord);
+                    return None;
+                };
+
+                match classify_pair(&cfg, rec1, rec2) {
+                    Ok(Ok((sample, rbs, flipped))) => {
+                        counters.inc_valid();
+                        Some((
+                            sample.barcode1,
+                            sample.barcode2,
+                            rbs.to_string(),
+                            matches!(flipped, Flip::Flipped),
+                        ))
+                    }
+                    Ok(Err(reason)) => {
+                        counters.inc_fail(reason);
+                        None
+                    }
+                    Err(e) => {
+                        warn!("Malformed record {}: {}", rec1.id(), e);
+                        counters.inc_fail(FailReason::MalformedRecord);
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        let path = layout.reads.join(format!("chunk_{chunk:09}.parquet"));
+        write_assignments(&assignments, &path)
+            .map_err(|e| output_error(&path, e))?;
+        chunks += 1;
+        info!(
+            "Wrote {} ({} assignments)",
+            path.display(),
+            assignments.len()
+        );
+    }
+
+    let mut qc = counters.to_dataframe(&[]).map_err(|e| {
+        RunError::Processing(format!("couldn't tally the counters: {e}"))
+    })?;
+    File::create(&layout.qc)
+        .map_err(PolarsError::from)
+        .and_then(|file| ParquetWriter::new(file).finish(&mut qc))
+        .map_err(|e| output_error(&layout.qc, e))?;
+
+    let manifest = Manifest {
+        sample_name: sample_name.to_string(),
+        inputs: vec![path1.to_path_buf(), path2.to_path_buf()],
+        chunks: Some(chunks),
+        ..Manifest::default()
+    };
+    manifest
+        .write(&layout.manifest)
+        .map_err(|e| output_error(&layout.manifest, e))?;
+
+    Ok(counters.valid_count())
+}
+
+// =========================================================
+// Aggregation pass
+// =========================================================
+
+/// Turn the assignments of a classification pass into counts, finished and
+/// written as by a single-pass run.
+pub fn aggregate_assignments(
+    assignments_dir: &Path,
+    output_dir: &Path,
+    opts: &ProcessOptions,
+) -> Result<u64, RunError> {
+    let layout = AssignmentLayout::new(assignments_dir);
+    let previous = Manifest::read(&layout.manifest)
+        .map_err(|e| input_error(&layout.manifest, e))?;
+    let sample_name = previous.sample_name.as_str();
+
+    let counters = Counters::default();
+    let read_qc = || -> PolarsResult<()> {
+        let qc = ParquetReader::new(File::open(&layout.qc)?).finish()?;
+        let names = qc.column("name")?.str()?;
+        for (name, value) in names.into_iter().zip(qc.column("value")?.u64()?) {
+            if let (Some(name), Some(value)) = (name, value) {
+                counters.add_named(name, value);
+            }
+        }
+        Ok(())
+    };
+    read_qc().map_err(|e| input_error(&layout.qc, e))?;
+
+    let mut files: Vec<PathBuf> = fs::read_dir(&layout.reads)
+        .map_err(|e| input_error(&layout.reads, e))?
+        .filter_map(|entry| {
+            let p = entry.ok()?.path();
+            p.extension()
+                .is_some_and(|ext| ext == "parquet")
+                .then_some(p)
+        })
+        .collect();
+    files.sort();
+
+    if files.is_empty() {
+        return Err(input_error(&layout.reads, "no assignments"));
+    }
+
+    // Manifests written before the chunk count was recorded have none
+    if let Some(chunks) = previous.chunks {
+        if files.len() as u64 != chunks {
+            return Err(input_error(
+                &layout.reads,
+                format!(
+                    "{} chunk files where the classification wrote {chunks}",
+                    files.len()
+                ),
+            ));
+        }
+    }
+
+    let flipped = col("flipped").cast(DataType::UInt64);
+    let counts =
+        LazyFrame::scan_parquet_files(files.into(), ScanArgsParquet::default())
+            .and_then(|lf| {
+                lf.group_by_stable([
+                    col("barcode1"),
+                    col("barcode2"),
+                    col("gre"),
+                ])
+                .agg([
+                    (lit(1u64) - flipped.clone()).sum().alias("unflipped"),
+                    flipped.sum().alias("flipped"),
+                ])
+                .collect()
+            })
+            .and_then(|counts| {
+                finish_counts(counts, opts, counters.valid_count(), None)
+            });
+    let (counts, filtered) = counts.map_err(|e| {
+        RunError::Processing(format!("couldn't aggregate assignments: {e}"))
+    })?;
+    counters.add_filtered_rows(filtered);
+
+    let dirs =
+        prepare_dirs(output_dir).map_err(|e| output_error(output_dir, e))?;
+    let meta = OutputMetadata::new(sample_name, opts);
+    counters
+        .to_dataframe(&[])
+        .and_then(|qc| write_qc_parquet(&qc, &dirs.qc, &meta, opts.layout))
+        .map_err(|e| output_error(&dirs.qc, e))?;
+    write_counts(&[counts], &dirs.counts, sample_name, opts)
+        .map_err(|e| output_error(&dirs.counts, e))?;
+
+    let manifest = Manifest {
+        sample_name: sample_name.to_string(),
+        layout: opts.layout,
+        counts_shape: opts.counts_shape,
+        inputs: [previous.inputs, vec![assignments_dir.to_path_buf()]].concat(),
+        previous_inputs: previous.previous_inputs,
+        min_count: opts.min_count,
+        ..Manifest::default()
+    };
+    let manifest_path = dirs.root.join("manifest.json");
+    manifest
+        .write(&manifest_path)
+        .map_err(|e| output_error(&manifest_path, e))?;
+    let versions_path = dirs.root.join("versions.yml");
+    write_versions_yml(&versions_path)
+        .map_err(|e| output_error(&versions_path, e))?;
+
+    Ok(counters.valid_count())
+}
@@ -0,0 +1,206 @@
+/// Library fidelity: per-position variant frequencies against a reference
+/// construct.
+///
+/// Reads of a subsample are aligned semi-globally to the reference, the
+/// read being aligned end to end within it, and each reference position
+/// tallies matches, substitutions, insertions before it and deletions, per
+/// barcode pair. The variable elements show up as substitutions too; the
+/// constant regions are expected to be clean.
+use bio::alignment::pairwise::Aligner;
+use bio::alignment::AlignmentOperation;
+use dashmap::DashMap;
+use polars::prelude::*;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::uaspire::classify::{pair_id, Sample};
+use crate::uaspire::hashing::stable_hash;
+use crate::uaspire::spikein::parse_fasta;
+
+// Alignment scores
+const MATCH: i32 = 1;
+const MISMATCH: i32 = -1;
+const GAP_OPEN: i32 = -5;
+const GAP_EXTEND: i32 = -1;
+
+// ---------- Options ----------
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VariantOptions {
+    // FASTA holding the reference construct
+    pub reference: PathBuf,
+    // Read aligned to the reference, 1 or 2
+    pub read: u8,
+    // One valid pair in this many is aligned
+    pub sample_every: u64,
+}
+
+impl Default for VariantOptions {
+    fn default() -> Self {
+        VariantOptions {
+            reference: PathBuf::new(),
+            read: 2,
+            sample_every: 10,
+        }
+    }
+}
+
+/// First sequence of a FASTA file.
+pub fn read_reference(path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+    let text = fs::read_to_string(path)?;
+
+    match parse_fasta(&text).into_iter().next() {
+        Some((_, sequence)) if !sequence.is_empty() => {
+            Ok(sequence.to_ascii_uppercase().into_bytes())
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "no reference sequence",
+        )),
+    }
+}
+
+// ---------- Pileup ----------
+
+// matches, substitutions, insertions before the position, deletions
+type PositionCounts = [AtomicU64; 4];
+
+pub struct VariantPileup {
+    reference: Vec<u8>,
+    sample_every: u64,
+    counts: DashMap<Sample, Vec<PositionCounts>>,
+    aligned: AtomicU64,
+}
+
+impl VariantPileup {
+    pub fn new(reference: Vec<u8>, sample_every: u64) -> Self {
+        VariantPileup {
+            reference,
+            sample_every: sample_every.max(1),
+            counts: DashMap::new(),
+            aligned: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether a pair is in the aligned subsample, decided by its ID so
+    /// that chunking and threads do not change the subsample.
+    pub(crate) fn is_sampled(&self, id: &str) -> bool {
+        stable_hash(&[pair_id(id).as_bytes()]) % self.sample_every == 0
+    }
+
+    /// Reads aligned so far.
+    pub fn aligned(&self) -> u64 {
+        self.aligned.load(Ordering::Relaxed)
+    }
+
+    /// Align a read and tally its operations under its barcode pair.
+    pub(crate) fn record(&self, sample: &Sample, seq: &[u8]) {
+        let score = |a: u8, b: u8| if a == b { MATCH } else { MISMATCH };
+        let mut aligner = Aligner::with_capacity(
+            seq.len(),
+            self.reference.len(),
+            GAP_OPEN,
+            GAP_EXTEND,
+            score,
+        );
+        let alignment = aligner.semiglobal(seq, &self.reference);
+
+        let counts = self.counts.entry(sample.clone()).or_insert_with(|| {
+            (0..self.reference.len())
+                .map(|_| Default::default())
+                .collect()
+        });
+        let tally = |pos: usize, kind: usize| {
+            if let Some(cell) = counts.get(pos) {
+                cell[kind].fetch_add(1, Ordering::Relaxed);
+            }
+        };
+
+        let mut pos = alignment.ystart;
+        for op in &alignment.operations {
+            match op {
+                AlignmentOperation::Match => {
+                    tally(pos, 0);
+                    pos += 1;
+                }
+                AlignmentOperation::Subst => {
+                    tally(pos, 1);
+                    pos += 1;
+                }
+                AlignmentOperation::Ins => tally(pos, 2),
+                AlignmentOperation::Del => {
+                    tally(pos, 3);
+                    pos += 1;
+                }
+                // Clipped ends are outside the aligned span
+                AlignmentOperation::Xclip(_) | AlignmentOperation::Yclip(_) => {
+                }
+            }
+        }
+
+        self.aligned.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// One row per barcode pair and reference position, with counts and
+    /// frequencies over the reads covering the position.
+    pub fn to_dataframe(&self, sample_name: &str) -> PolarsResult<DataFrame> {
+        let mut barcode1: Vec<String> = Vec::new();
+        let mut barcode2: Vec<String> = Vec::new();
+        let mut position: Vec<u32> = Vec::new();
+        let mut reference: Vec<String> = Vec::new();
+        let mut values: [Vec<u64>; 4] = Default::default();
+
+        for entry in self.counts.iter() {
+            for (pos, cell) in entry.value().iter().enumerate() {
+                barcode1.push(entry.key().barcode1.clone());
+                barcode2.push(entry.key().barcode2.clone());
+                position.push(pos as u32 + 1);
+                reference.push((self.reference[pos] as char).to_string());
+                for (kind, column) in values.iter_mut().enumerate() {
+                    column.push(cell[kind].load(Ordering::Relaxed));
+                }
+            }
+        }
+
+        let n = position.len();
+        let [matches, substitutions, insertions, deletions] = values;
+        let df = DataFrame::new(vec![
+            Series::new("sample".into(), vec![sample_name; n]).into(),
+            Series::new("barcode1".into(), barcode1).into(),
+            Series::new("barcode2".into(), barcode2).into(),
+            Series::new("position".into(), position).into(),
+            Series::new("reference".into(), reference).into(),
+            Series::new("matches".into(), matches).into(),
+            Series::new("substitutions".into(), substitutions).into(),
+            Series::new("insertions".into(), insertions).into(),
+            Series::new("deletions".into(), deletions).into(),
+        ])?;
+
+        let depth = col("matches") + col("substitutions") + col("deletions");
+        let rate = |name: &str| {
+            when(depth.clone().gt(lit(0)))
+                .then(
+                    col(name).cast(DataType::Float64)
+                        / depth.clone().cast(DataType::Float64),
+                )
+                .otherwise(lit(NULL))
+                .alias(format!("{name}_rate"))
+        };
+
+        df.lazy()
+            .with_columns([
+                depth.clone().alias("depth"),
+                rate("substitutions"),
+                rate("insertions"),
+                rate("deletions"),
+            ])
+            .sort(
+                ["barcode1", "barcode2", "position"],
+                SortMultipleOptions::default(),
+            )
+            .collect()
+    }
+}
@@ -0,0 +1,201 @@
+/// Watch a sequencer run folder and process FASTQ pairs once complete.
+use glob::Pattern;
+use notify::{RecursiveMode, Watcher};
+use tracing::{info, warn};
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
+use crate::uaspire::batch::{process_entry, BatchEntry};
+use crate::uaspire::fastq::ProcessOptions;
+
+// Rescan interval when no file system event arrives
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+// Runs of a failing pair before it is listed in FAILURES_FILE and left
+const MAX_ATTEMPTS: u32 = 3;
+const FAILURES_FILE: &str = "failed_samples.txt";
+
+// ---------- Watch settings ----------
+
+#[derive(Debug, Clone)]
+pub struct WatchOptions {
+    pub pattern: Pattern,
+    pub read1_tag: String,
+    pub read2_tag: String,
+    pub settle: Duration,
+}
+
+// ---------- File state ----------
+
+struct FileState {
+    size: u64,
+    since: Instant,
+}
+
+// =========================================================
+// Helper functions
+// =========================================================
+
+/// All R1 files matching the pattern, paired with their R2 file.
+fn find_pairs(dir: &Path, watch: &WatchOptions) -> Vec<(PathBuf, PathBuf)> {
+    let mut pairs = Vec::new();
+    let mut stack = vec![dir.to_path_buf()];
+
+    while let Some(current) = stack.pop() {
+        let Ok(entries) = fs::read_dir(&current) else {
+            continue;
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                stack.push(path);
+                continue;
+            }
+
+            let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+
+            if !watch.pattern.matches(name) || !name.contains(&watch.read1_tag)
+            {
+                continue;
+            }
+
+            let read2 = path.with_file_name(name.replacen(
+                &watch.read1_tag,
+                &watch.read2_tag,
+                1,
+            ));
+            pairs.push((path, read2));
+        }
+    }
+
+    pairs.sort();
+    pairs
+}
+
+/// Sample name, the file name up to the R1 tag.
+fn sample_name(read1: &Path, read1_tag: &str) -> String {
+    let name = read1.file_name().unwrap_or_default().to_string_lossy();
+    match name.find(read1_tag) {
+        Some(pos) => name[..pos].to_string(),
+        None => name.to_string(),
+    }
+}
+
+/// Whether a file kept the same size for the settle period.
+fn is_settled(
+    path: &Path,
+    states: &mut HashMap<PathBuf, FileState>,
+    settle: Duration,
+) -> bool {
+    let Ok(size) = fs::metadata(path).map(|m| m.len()) else {
+        return false;
+    };
+
+    let state = states.entry(path.to_path_buf()).or_insert(FileState {
+        size,
+        since: Instant::now(),
+    });
+
+    if state.size != size {
+        state.size = size;
+        state.since = Instant::now();
+    }
+
+    size > 0 && state.since.elapsed() >= settle
+}
+
+/// Append a sample that kept failing to the failures list of `root`.
+fn record_failure(root: &Path, sample: &str, read1: &Path) -> io::Result<()> {
+    fs::create_dir_all(root)?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(root.join(FAILURES_FILE))?;
+    writeln!(file, "{}\t{}", sample, read1.display())
+}
+
+// =========================================================
+// Watch loop
+// =========================================================
+
+/// Watch `dir` forever, processing each complete pair into its own
+/// directory under `output_root`. A failed pair is retried once settled
+/// again, up to `MAX_ATTEMPTS` runs, then listed in `FAILURES_FILE`.
+pub fn watch_folder(
+    dir: &Path,
+    output_root: &Path,
+    watch: &WatchOptions,
+    opts: &ProcessOptions,
+) -> notify::Result<()> {
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    watcher.watch(dir, RecursiveMode::Recursive)?;
+
+    info!("Watching {} for {}", dir.display(), watch.pattern);
+
+    let mut states: HashMap<PathBuf, FileState> = HashMap::new();
+    let mut done: HashSet<PathBuf> = HashSet::new();
+    let mut attempts: HashMap<PathBuf, u32> = HashMap::new();
+
+    loop {
+        // Events only wake the loop up, completion is decided on sizes
+        match receiver.recv_timeout(POLL_INTERVAL) {
+            Ok(Err(e)) => warn!("Watch error: {}", e),
+            Ok(Ok(_)) | Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+
+        for (read1, read2) in find_pairs(dir, watch) {
+            if done.contains(&read1) {
+                continue;
+            }
+
+            let settled1 = is_settled(&read1, &mut states, watch.settle);
+            let settled2 = is_settled(&read2, &mut states, watch.settle);
+            if !settled1 || !settled2 {
+                continue;
+            }
+
+            let entry = BatchEntry {
+                sample: sample_name(&read1, &watch.read1_tag),
+                read1: read1.clone(),
+                read2,
+                output: None,
+            };
+
+            if process_entry(&entry, output_root, opts) {
+                done.insert(read1);
+                continue;
+            }
+
+            let tries = attempts.entry(read1.clone()).or_default();
+            *tries += 1;
+            if *tries < MAX_ATTEMPTS {
+                warn!(
+                    "Sample {} failed ({}/{}), retrying once settled",
+                    entry.sample, tries, MAX_ATTEMPTS
+                );
+                states.remove(&read1);
+                states.remove(&entry.read2);
+                continue;
+            }
+
+            warn!("Sample {} failed {} times, giving up", entry.sample, tries);
+            if let Err(e) = record_failure(output_root, &entry.sample, &read1) {
+                warn!("Couldn't record the failure of {}: {}", entry.sample, e);
+            }
+            done.insert(read1);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,322 @@
+/// Downloaded release files, kept by release and checksum.
+///
+/// Every downloaded file is stored in a directory named after the UniProt
+/// release it declares, under a name starting with its SHA256, next to a
+/// JSON sidecar recording its URL, full checksum, size and HTTP
+/// validators. A copy is checked against its checksum before being read,
+/// so that re-imports parse the very bytes first downloaded, and copies of
+/// older releases stay around until `uniprot cache clean` removes them.
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+use crate::uniprot::output::{write_rows, OutputError, OutputFormat, Row};
+
+// Directory of the files declaring no release
+const UNKNOWN_RELEASE: &str = "unknown";
+// Lines at the top of a file searched for its release
+const HEADER_LINES: usize = 50;
+// Hex digits of the checksum in the name of a copy
+const NAME_DIGITS: usize = 16;
+
+#[derive(Error, Debug)]
+pub enum CacheError {
+    #[error("{0}")]
+    IoError(#[from] io::Error),
+
+    #[error("{} doesn't match its SHA256 {expected}", path.display())]
+    Checksum { path: PathBuf, expected: String },
+
+    #[error("No copy of {url} from release {release} in the cache")]
+    Missing { url: String, release: String },
+}
+
+/// HTTP validators of a downloaded file, to ask whether it changed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+/// Copy of a downloaded file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub url: String,
+    pub release: Option<String>,
+    pub sha256: String,
+    pub size: u64,
+    // Seconds since the epoch
+    pub downloaded: u64,
+    #[serde(flatten)]
+    pub validators: Validators,
+    // Location of the copy, the sidecar being next to it
+    #[serde(skip_deserializing)]
+    pub path: PathBuf,
+}
+
+impl CacheEntry {
+    fn sidecar(&self) -> PathBuf {
+        sidecar(&self.path)
+    }
+}
+
+fn sidecar(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".json");
+    PathBuf::from(name)
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Release declared in the header of `text`, such as
+/// `Release:     2024_01 of 24-Jan-2024`.
+pub fn detect_release(text: &str) -> Option<String> {
+    let pattern = Regex::new(r"\bRelease:?\s+(\d{4}_\d{2})\b").ok()?;
+    text.lines()
+        .take(HEADER_LINES)
+        .find_map(|line| pattern.captures(line))
+        .map(|caps| caps[1].to_string())
+}
+
+// =========================================================
+// Cache
+// =========================================================
+
+pub struct RawCache {
+    dir: PathBuf,
+}
+
+impl RawCache {
+    pub fn new(dir: &Path) -> Self {
+        RawCache {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    /// Every copy, by URL then oldest first. Files without a readable
+    /// sidecar aren't copies and are left out.
+    pub fn entries(&self) -> io::Result<Vec<CacheEntry>> {
+        let mut entries = Vec::new();
+        if !self.dir.is_dir() {
+            return Ok(entries);
+        }
+
+        for release in fs::read_dir(&self.dir)? {
+            let release = release?.path();
+            if !release.is_dir() {
+                continue;
+            }
+            for file in fs::read_dir(&release)? {
+                let path = file?.path();
+                if path.extension().is_some_and(|e| e == "json") {
+                    continue;
+                }
+                let Ok(json) = fs::read_to_string(sidecar(&path)) else {
+                    continue;
+                };
+                let Ok(entry) = serde_json::from_str::<CacheEntry>(&json)
+                else {
+                    continue;
+                };
+                entries.push(CacheEntry { path, ..entry });
+            }
+        }
+
+        entries.sort_by(|a, b| {
+            (&a.url, a.downloaded, &a.path).cmp(&(
+                &b.url,
+                b.downloaded,
+                &b.path,
+            ))
+        });
+        Ok(entries)
+    }
+
+    /// Latest copy of `url`.
+    pub fn latest(&self, url: &str) -> io::Result<Option<CacheEntry>> {
+        Ok(self.entries()?.into_iter().rfind(|entry| entry.url == url))
+    }
+
+    /// Latest copy of `url` from `release`.
+    pub fn find(
+        &self,
+        url: &str,
+        release: &str,
+    ) -> io::Result<Option<CacheEntry>> {
+        Ok(self.entries()?.into_iter().rfind(|entry| {
+            entry.url == url && entry.release.as_deref() == Some(release)
+        }))
+    }
+
+    /// Store `text`, downloaded from `url`. The same content downloaded
+    /// again only refreshes its sidecar.
+    pub fn store(
+        &self,
+        url: &str,
+        text: &str,
+        validators: Validators,
+    ) -> io::Result<CacheEntry> {
+        let release = detect_release(text);
+        let sha256 = sha256_hex(text.as_bytes());
+        let basename = url
+            .rsplit('/')
+            .find(|s| !s.is_empty())
+            .unwrap_or("download");
+
+        let dir = self.dir.join(release.as_deref().unwrap_or(UNKNOWN_RELEASE));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(format!("{}-{basename}", &sha256[..NAME_DIGITS]));
+
+        if !path.exists() {
+            // Renamed into place, an interrupted write never looks complete
+            let tmp = path.with_extension("part");
+            fs::write(&tmp, text)?;
+            fs::rename(&tmp, &path)?;
+        }
+
+        let entry = CacheEntry {
+            url: url.to_string(),
+            release,
+            sha256,
+            size: text.len() as u64,
+            downloaded: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs()),
+            validators,
+            path,
+        };
+        let json =
+            serde_json::to_string_pretty(&entry).map_err(io::Error::other)?;
+        fs::write(entry.sidecar(), json)?;
+
+        Ok(entry)
+    }
+
+    /// Text of the copy `entry`, checked against its checksum.
+    pub fn read(&self, entry: &CacheEntry) -> Result<String, CacheError> {
+        let text = fs::read_to_string(&entry.path)?;
+        if sha256_hex(text.as_bytes()) != entry.sha256 {
+            return Err(CacheError::Checksum {
+                path: entry.path.clone(),
+                expected: entry.sha256.clone(),
+            });
+        }
+        Ok(text)
+    }
+
+    pub fn remove(&self, entry: &CacheEntry) -> io::Result<()> {
+        fs::remove_file(entry.sidecar())?;
+        match fs::remove_file(&entry.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+
+        // The directory of a release goes with its last copy
+        if let Some(dir) = entry.path.parent() {
+            if fs::read_dir(dir)?.next().is_none() {
+                fs::remove_dir(dir)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// ---------- Cleaning ----------
+
+#[derive(Debug, Clone)]
+pub struct CleanOptions {
+    // Latest copies of every URL kept, the others are removed
+    pub keep: usize,
+    // Only the copies of this release are removed
+    pub release: Option<String>,
+}
+
+impl Default for CleanOptions {
+    fn default() -> Self {
+        CleanOptions {
+            keep: 1,
+            release: None,
+        }
+    }
+}
+
+/// Copies `clean` would remove, those failing their checksum whatever
+/// their age.
+pub fn stale_entries(
+    cache: &RawCache,
+    opts: &CleanOptions,
+) -> io::Result<Vec<CacheEntry>> {
+    let mut by_url: BTreeMap<String, Vec<CacheEntry>> = BTreeMap::new();
+    for entry in cache.entries()? {
+        by_url.entry(entry.url.clone()).or_default().push(entry);
+    }
+
+    let mut stale = Vec::new();
+    for mut copies in by_url.into_values() {
+        // Newest first
+        copies.reverse();
+        for (i, entry) in copies.into_iter().enumerate() {
+            let old = i >= opts.keep;
+            let corrupt =
+                matches!(cache.read(&entry), Err(CacheError::Checksum { .. }));
+            let in_release =
+                opts.release.is_none() || entry.release == opts.release;
+            if in_release && (old || corrupt) {
+                stale.push(entry);
+            }
+        }
+    }
+
+    Ok(stale)
+}
+
+/// Remove the copies selected by `opts`, returns them.
+pub fn clean(
+    cache: &RawCache,
+    opts: &CleanOptions,
+) -> io::Result<Vec<CacheEntry>> {
+    let stale = stale_entries(cache, opts)?;
+    for entry in &stale {
+        cache.remove(entry)?;
+    }
+    Ok(stale)
+}
+
+// =========================================================
+// Output
+// =========================================================
+
+impl Row for CacheEntry {
+    const COLUMNS: &'static [&'static str] =
+        &["release", "url", "sha256", "size", "last_modified", "path"];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.release.clone().unwrap_or_default(),
+            self.url.clone(),
+            self.sha256.clone(),
+            self.size.to_string(),
+            self.validators.last_modified.clone().unwrap_or_default(),
+            self.path.display().to_string(),
+        ]
+    }
+}
+
+pub fn write_cache_entries(
+    entries: &[CacheEntry],
+    format: OutputFormat,
+    out: impl Write,
+) -> Result<(), OutputError> {
+    write_rows(entries, format, out)
+}
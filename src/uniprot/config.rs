@@ -0,0 +1,264 @@
+/// Settings of the UniProt commands, the `[uniprot]` tables of the config
+/// file.
+///
+/// The whole section is checked when loaded, so that a misspelt or
+/// forgotten key is reported at once with every other one rather than when
+/// a command first reads it. Environment variables and `.env` override the
+/// file, DATABASE_URL standing in for `uniprot.database_url`.
+use config::{Config, Environment, File, Map, Value, ValueKind};
+use dotenvy::dotenv;
+use reqwest::Url;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+// Keys of the section, those of the sources are required
+const REQUIRED_KEYS: [&str; 9] = [
+    "similar.url",
+    "similar.species",
+    "keywlist.url",
+    "enzyme.url",
+    "humsavar.url",
+    "go.url",
+    "speclist.url",
+    "delac.url",
+    "sec_ac.url",
+];
+const OPTIONAL_KEYS: [&str; 5] = [
+    "database_url",
+    "cache_dir",
+    "rest.requests_per_second",
+    "rest.concurrency",
+    "rest.retries",
+];
+
+#[derive(Error, Debug)]
+pub enum UniprotConfigError {
+    #[error("Couldn't read {}: {source}", path.display())]
+    Read {
+        path: PathBuf,
+        source: config::ConfigError,
+    },
+
+    #[error("{}: {}", path.display(), describe_keys(missing, unknown))]
+    Keys {
+        path: PathBuf,
+        missing: Vec<String>,
+        unknown: Vec<String>,
+    },
+
+    #[error("No database, set uniprot.database_url or DATABASE_URL")]
+    NoDatabase,
+
+    #[error("Invalid settings in {}: {}", path.display(), problems.join("; "))]
+    Invalid {
+        path: PathBuf,
+        problems: Vec<String>,
+    },
+}
+
+fn describe_keys(missing: &[String], unknown: &[String]) -> String {
+    let mut parts = Vec::new();
+    if !missing.is_empty() {
+        parts.push(format!("missing {}", missing.join(", ")));
+    }
+    if !unknown.is_empty() {
+        parts.push(format!("unknown {}", unknown.join(", ")));
+    }
+    parts.join("; ")
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct UniprotConfig {
+    // SQLite database, DATABASE_URL when unset
+    #[serde(default)]
+    pub database_url: String,
+    // Downloaded files, kept between runs
+    #[serde(default = "default_cache_dir")]
+    pub cache_dir: PathBuf,
+    // Defaults of the commands querying the REST APIs
+    #[serde(default)]
+    pub rest: RateLimits,
+
+    pub similar: SimilarSource,
+    pub keywlist: Source,
+    pub enzyme: Source,
+    pub humsavar: Source,
+    pub go: Source,
+    pub speclist: Source,
+    // Deleted Swiss-Prot accessions, delac_sp.txt
+    pub delac: Source,
+    // Secondary accessions of merged entries, sec_ac.txt
+    pub sec_ac: Source,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Source {
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SimilarSource {
+    pub url: String,
+    // Mnemonics, NCBI taxids or scientific names
+    pub species: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RateLimits {
+    pub requests_per_second: f64,
+    // Requests in flight at once
+    pub concurrency: usize,
+    pub retries: u32,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        RateLimits {
+            requests_per_second: 2.0,
+            concurrency: 4,
+            retries: 3,
+        }
+    }
+}
+
+fn default_cache_dir() -> PathBuf {
+    PathBuf::from(".uniprot-cache")
+}
+
+// =========================================================
+// Loading
+// =========================================================
+
+// Dotted keys of the leaves of `table`
+fn leaf_keys(prefix: &str, table: &Map<String, Value>, keys: &mut Vec<String>) {
+    for (name, value) in table {
+        let key = if prefix.is_empty() {
+            name.clone()
+        } else {
+            format!("{prefix}.{name}")
+        };
+        match &value.kind {
+            ValueKind::Table(table) => leaf_keys(&key, table, keys),
+            _ => keys.push(key),
+        }
+    }
+}
+
+fn check_keys(
+    path: &Path,
+    section: &Map<String, Value>,
+) -> Result<(), UniprotConfigError> {
+    let mut keys = Vec::new();
+    leaf_keys("", section, &mut keys);
+
+    let missing: Vec<String> = REQUIRED_KEYS
+        .iter()
+        .filter(|key| !keys.iter().any(|k| k == *key))
+        .map(|key| format!("uniprot.{key}"))
+        .collect();
+    let mut unknown: Vec<String> = keys
+        .iter()
+        .filter(|key| {
+            !REQUIRED_KEYS.contains(&key.as_str())
+                && !OPTIONAL_KEYS.contains(&key.as_str())
+        })
+        .map(|key| format!("uniprot.{key}"))
+        .collect();
+    unknown.sort();
+
+    if missing.is_empty() && unknown.is_empty() {
+        return Ok(());
+    }
+
+    Err(UniprotConfigError::Keys {
+        path: path.to_path_buf(),
+        missing,
+        unknown,
+    })
+}
+
+impl UniprotConfig {
+    /// Read and check the `[uniprot]` section of the config file at `path`,
+    /// its extension may be left out.
+    pub fn load(path: &Path) -> Result<Self, UniprotConfigError> {
+        dotenv().ok();
+
+        let read_error = |source| UniprotConfigError::Read {
+            path: path.to_path_buf(),
+            source,
+        };
+        let settings = Config::builder()
+            .add_source(File::with_name(&path.to_string_lossy()))
+            .add_source(Environment::default())
+            .build()
+            .map_err(read_error)?;
+
+        let section = match settings.get_table("uniprot") {
+            Ok(section) => section,
+            Err(config::ConfigError::NotFound(_)) => Map::new(),
+            Err(err) => return Err(read_error(err)),
+        };
+        check_keys(path, &section)?;
+
+        let mut config: UniprotConfig =
+            settings.get("uniprot").map_err(read_error)?;
+        if config.database_url.is_empty() {
+            config.database_url = settings
+                .get("DATABASE_URL")
+                .map_err(|_| UniprotConfigError::NoDatabase)?;
+        }
+
+        config.validate(path)?;
+        Ok(config)
+    }
+
+    fn validate(&self, path: &Path) -> Result<(), UniprotConfigError> {
+        let mut problems = Vec::new();
+
+        let sources = [
+            ("similar", &self.similar.url),
+            ("keywlist", &self.keywlist.url),
+            ("enzyme", &self.enzyme.url),
+            ("humsavar", &self.humsavar.url),
+            ("go", &self.go.url),
+            ("speclist", &self.speclist.url),
+            ("delac", &self.delac.url),
+            ("sec_ac", &self.sec_ac.url),
+        ];
+        for (name, url) in sources {
+            match Url::parse(url) {
+                Ok(url) if ["http", "https"].contains(&url.scheme()) => {}
+                Ok(_) => problems.push(format!(
+                    "uniprot.{name}.url {url} isn't an HTTP URL"
+                )),
+                Err(err) => {
+                    problems.push(format!("uniprot.{name}.url {url}: {err}"))
+                }
+            }
+        }
+
+        if self.similar.species.is_empty() {
+            problems.push("uniprot.similar.species is empty".to_string());
+        }
+        if self.rest.requests_per_second <= 0.0 {
+            problems.push(
+                "uniprot.rest.requests_per_second must be positive".to_string(),
+            );
+        }
+        if self.rest.concurrency == 0 {
+            problems
+                .push("uniprot.rest.concurrency must be positive".to_string());
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(UniprotConfigError::Invalid {
+                path: path.to_path_buf(),
+                problems,
+            })
+        }
+    }
+}
@@ -0,0 +1,136 @@
+/// Connections to the UniProt database and its schema, embedded in the
+/// binary.
+///
+/// Connections come from a pool shared by the commands and the server.
+/// Every connection runs in WAL mode and waits for locks held by another
+/// one, so that readers don't block the writer and concurrent writers queue
+/// up instead of failing with SQLITE_BUSY.
+///
+/// The SQL migrations of `migrations/` are compiled in, so that a fresh
+/// database is created and an older one upgraded before any data is loaded,
+/// without the Diesel CLI.
+use diesel::connection::SimpleConnection;
+use diesel::prelude::*;
+use diesel::r2d2::{
+    ConnectionManager, CustomizeConnection, Pool, PoolError, PooledConnection,
+};
+use diesel_migrations::{
+    embed_migrations, EmbeddedMigrations, MigrationHarness,
+};
+use log::info;
+use std::time::Duration;
+use thiserror::Error;
+
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+// Connections opened at most, more are only opened while in use
+const MAX_CONNECTIONS: u32 = 8;
+// Wait for a connection or a lock this long before failing
+const BUSY_TIMEOUT: Duration = Duration::from_secs(30);
+
+// SQLite builds before 3.32 bind at most 999 parameters per statement
+pub(crate) const SQLITE_MAX_PARAMETERS: usize = 999;
+
+type MigrationError = Box<dyn std::error::Error + Send + Sync>;
+
+pub type DbConnection = PooledConnection<ConnectionManager<SqliteConnection>>;
+
+#[derive(Error, Debug)]
+pub enum DatabaseError {
+    #[error("Couldn't connect to {url}: {source}")]
+    Connect { url: String, source: PoolError },
+
+    #[error("No database connection available: {0}")]
+    Pool(#[from] PoolError),
+}
+
+/// Rows per multi-row insert into a table with `columns` columns.
+pub(crate) fn batch_rows(columns: usize) -> usize {
+    SQLITE_MAX_PARAMETERS / columns
+}
+
+// =========================================================
+// Connections
+// =========================================================
+
+#[derive(Debug)]
+struct SqliteSettings;
+
+impl CustomizeConnection<SqliteConnection, diesel::r2d2::Error>
+    for SqliteSettings
+{
+    fn on_acquire(
+        &self,
+        connection: &mut SqliteConnection,
+    ) -> Result<(), diesel::r2d2::Error> {
+        connection
+            .batch_execute(&format!(
+                "PRAGMA busy_timeout = {}; \
+                 PRAGMA journal_mode = WAL; \
+                 PRAGMA synchronous = NORMAL;",
+                BUSY_TIMEOUT.as_millis()
+            ))
+            .map_err(diesel::r2d2::Error::QueryError)
+    }
+}
+
+/// Pool of connections to the database, cheap to clone.
+#[derive(Clone)]
+pub struct Database {
+    pool: Pool<ConnectionManager<SqliteConnection>>,
+}
+
+impl Database {
+    /// Open a pool on `database_url`, the schema is left as is.
+    pub fn open(database_url: &str) -> Result<Self, DatabaseError> {
+        let pool = Pool::builder()
+            .max_size(MAX_CONNECTIONS)
+            .min_idle(Some(1))
+            .connection_timeout(BUSY_TIMEOUT)
+            .connection_customizer(Box::new(SqliteSettings))
+            .build(ConnectionManager::new(database_url))
+            .map_err(|source| DatabaseError::Connect {
+                url: database_url.to_string(),
+                source,
+            })?;
+
+        Ok(Database { pool })
+    }
+
+    /// Connection of the pool, back to it when dropped.
+    pub fn get(&self) -> Result<DbConnection, DatabaseError> {
+        Ok(self.pool.get()?)
+    }
+}
+
+// =========================================================
+// Migrations
+// =========================================================
+
+/// Versions of the migrations already applied to the database.
+pub fn applied_migrations(
+    connection: &mut SqliteConnection,
+) -> Result<Vec<String>, MigrationError> {
+    Ok(connection
+        .applied_migrations()?
+        .iter()
+        .map(|version| version.to_string())
+        .collect())
+}
+
+/// Apply the pending migrations, returns their versions.
+pub fn run_migrations(
+    connection: &mut SqliteConnection,
+) -> Result<Vec<String>, MigrationError> {
+    let versions: Vec<String> = connection
+        .run_pending_migrations(MIGRATIONS)?
+        .iter()
+        .map(|version| version.to_string())
+        .collect();
+
+    for version in &versions {
+        info!("Applied migration {version}");
+    }
+
+    Ok(versions)
+}
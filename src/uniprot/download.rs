@@ -0,0 +1,221 @@
+/// Download of UniProt release files with retries and a local cache.
+///
+/// A downloaded file is kept in the cache, see `cache`, together with its
+/// ETag and Last-Modified validators, so that later runs only ask the
+/// server whether it changed and reuse the cached copy when it did not. A
+/// release kept in the cache can also be read again without any request.
+use log::{info, warn};
+use reqwest::header::{
+    HeaderName, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+};
+use reqwest::StatusCode;
+use reqwest::{Client, Response};
+use std::{io, path::PathBuf, time::Duration};
+use thiserror::Error;
+
+use crate::uniprot::cache::{CacheError, RawCache, Validators};
+use crate::uniprot::fetch::{block_on, Fetcher};
+
+// Large release files take a while on slow links
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(300);
+// Delay before the first retry, doubled on every attempt
+pub(crate) const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+#[derive(Error, Debug)]
+pub enum DownloadError {
+    #[error("Request to {url} failed: {source}")]
+    Request {
+        url: String,
+        #[source]
+        source: reqwest::Error,
+    },
+
+    #[error("{url} answered {status}")]
+    Status { url: String, status: StatusCode },
+
+    #[error("Couldn't decode the answer of {url}: {source}")]
+    Decode {
+        url: String,
+        #[source]
+        source: reqwest::Error,
+    },
+
+    #[error("Cache error: {0}")]
+    Cache(#[from] CacheError),
+
+    #[error("Couldn't start the async runtime: {0}")]
+    Runtime(#[source] io::Error),
+}
+
+impl DownloadError {
+    /// `Decode` for an answer that isn't valid text or JSON, `Request` for
+    /// any other failure.
+    pub(crate) fn from_reqwest(url: &str, source: reqwest::Error) -> Self {
+        let url = url.to_string();
+        if source.is_decode() {
+            DownloadError::Decode { url, source }
+        } else {
+            DownloadError::Request { url, source }
+        }
+    }
+
+    // Timeouts, dropped connections and overloaded servers are worth a retry
+    pub(crate) fn is_transient(&self) -> bool {
+        match self {
+            DownloadError::Request { .. } => true,
+            DownloadError::Status { status, .. } => {
+                status.is_server_error()
+                    || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            DownloadError::Decode { .. }
+            | DownloadError::Cache(_)
+            | DownloadError::Runtime(_) => false,
+        }
+    }
+}
+
+// ---------- Options ----------
+
+#[derive(Debug, Clone)]
+pub struct DownloadOptions {
+    pub cache_dir: PathBuf,
+    // Attempts after the first one
+    pub retries: u32,
+    // Download again even when the cached copy is up to date
+    pub force_refresh: bool,
+    // Copy of this release read from the cache, without any request
+    pub release: Option<String>,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        DownloadOptions {
+            cache_dir: PathBuf::from(".uniprot-cache"),
+            retries: 3,
+            force_refresh: false,
+            release: None,
+        }
+    }
+}
+
+// =========================================================
+// Download
+// =========================================================
+
+fn header_value(response: &Response, name: HeaderName) -> Option<String> {
+    response
+        .headers()
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string())
+}
+
+/// One conditional request, `None` meaning the cached copy is current.
+async fn fetch(
+    client: &Client,
+    url: &str,
+    cached: Option<&Validators>,
+) -> Result<Option<(String, Validators)>, DownloadError> {
+    let request_error = |source| DownloadError::from_reqwest(url, source);
+
+    let mut request = client.get(url);
+    if let Some(validators) = cached {
+        if let Some(etag) = &validators.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &validators.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+
+    let response = request.send().await.map_err(request_error)?;
+
+    match response.status() {
+        StatusCode::NOT_MODIFIED if cached.is_some() => Ok(None),
+        status if status.is_success() => {
+            let validators = Validators {
+                etag: header_value(&response, ETAG),
+                last_modified: header_value(&response, LAST_MODIFIED),
+            };
+            let text = response.text().await.map_err(request_error)?;
+            Ok(Some((text, validators)))
+        }
+        status => Err(DownloadError::Status {
+            url: url.to_string(),
+            status,
+        }),
+    }
+}
+
+/// Text of `url`, from the cache when the server reports it unchanged or
+/// `opts.release` names a cached release.
+pub fn download_text(
+    url: &str,
+    opts: &DownloadOptions,
+) -> Result<String, DownloadError> {
+    let cache = RawCache::new(&opts.cache_dir);
+    if let Some(release) = &opts.release {
+        let Some(entry) = cache.find(url, release).map_err(CacheError::from)?
+        else {
+            return Err(CacheError::Missing {
+                url: url.to_string(),
+                release: release.clone(),
+            }
+            .into());
+        };
+        info!("Using {} of release {release}", entry.path.display());
+        return Ok(cache.read(&entry)?);
+    }
+
+    let client = Client::builder().timeout(REQUEST_TIMEOUT).build().map_err(
+        |source| DownloadError::Request {
+            url: url.to_string(),
+            source,
+        },
+    )?;
+
+    let cached = if opts.force_refresh {
+        None
+    } else {
+        cache.latest(url).map_err(CacheError::from)?
+    };
+
+    // One request, only the retries of the fetcher matter
+    let fetcher = Fetcher::new(client, Duration::ZERO, opts.retries);
+    let fetched = block_on(fetcher.run(|| {
+        fetch(
+            fetcher.client(),
+            url,
+            cached.as_ref().map(|entry| &entry.validators),
+        )
+    }))??;
+
+    match (fetched, cached) {
+        (Some((text, validators)), _) => {
+            let entry = cache
+                .store(url, &text, validators)
+                .map_err(CacheError::from)?;
+            info!("Downloaded {url} to {}", entry.path.display());
+            Ok(text)
+        }
+        (None, Some(entry)) => match cache.read(&entry) {
+            Ok(text) => {
+                info!("{url} unchanged, using {}", entry.path.display());
+                Ok(text)
+            }
+            // Downloaded again in full rather than parsed corrupt
+            Err(e @ CacheError::Checksum { .. }) => {
+                warn!("{e}, downloading {url} again");
+                cache.remove(&entry).map_err(CacheError::from)?;
+                let opts = DownloadOptions {
+                    force_refresh: true,
+                    ..opts.clone()
+                };
+                download_text(url, &opts)
+            }
+            Err(e) => Err(e.into()),
+        },
+        // Not modified is only accepted with a cached copy
+        (None, None) => unreachable!(),
+    }
+}
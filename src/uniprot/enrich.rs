@@ -0,0 +1,405 @@
+/// Annotation of the stored entries from the UniProtKB REST API.
+///
+/// Accessions are sent in batches and the mass, sequence length, protein
+/// name, gene name and review status of the returned entries are written
+/// back to
+/// `uniprot_entries`, their PDB, Pfam and InterPro cross-references to
+/// `uniprot_xrefs`. Batches are requested concurrently, every batch is
+/// stored as soon as it is fetched and only entries without a length are
+/// requested, so an interrupted run resumes where it stopped.
+use diesel::prelude::*;
+use log::{info, warn};
+use reqwest::Client;
+use serde::Deserialize;
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::schema::*;
+use crate::uniprot::db::batch_rows;
+use crate::uniprot::download::DownloadError;
+use crate::uniprot::fetch::{block_on, for_each_concurrent, Fetcher};
+use crate::uniprot::history::record_changes;
+use crate::uniprot::models::{EntryChange, UniprotXref};
+
+pub(crate) const UNIPROTKB_REST_URL: &str =
+    "https://rest.uniprot.org/uniprotkb";
+const FIELDS: &str = concat!(
+    "accession,reviewed,mass,length,protein_name,gene_primary,",
+    "xref_pdb,xref_pfam,xref_interpro",
+);
+/// Databases of the cross-references kept by the enrichment.
+pub const XREF_DATABASES: [&str; 3] = ["PDB", "Pfam", "InterPro"];
+
+#[derive(Error, Debug)]
+pub enum EnrichError {
+    #[error("Couldn't query UniProtKB: {0}")]
+    Download(#[from] DownloadError),
+
+    #[error("Database error: {0}")]
+    Database(#[from] diesel::result::Error),
+}
+
+// ---------- Options ----------
+
+#[derive(Debug, Clone)]
+pub struct EnrichOptions {
+    pub batch_size: usize,
+    // Requests in flight at once
+    pub concurrency: usize,
+    // Minimum delay between two requests
+    pub interval: Duration,
+    pub retries: u32,
+    // Entries already annotated are requested again
+    pub all: bool,
+}
+
+impl Default for EnrichOptions {
+    fn default() -> Self {
+        EnrichOptions {
+            batch_size: 100,
+            concurrency: 4,
+            interval: Duration::from_millis(500),
+            retries: 3,
+            all: false,
+        }
+    }
+}
+
+// ---------- REST API ----------
+
+#[derive(Debug, Deserialize)]
+struct ApiResults {
+    results: Vec<ApiEntry>,
+}
+
+// Only the requested fields are present
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ApiEntry {
+    pub(crate) primary_accession: String,
+    // UniProtKB reviewed (Swiss-Prot) or UniProtKB unreviewed (TrEMBL)
+    entry_type: Option<String>,
+    protein_description: Option<ApiDescription>,
+    #[serde(default)]
+    genes: Vec<ApiGene>,
+    sequence: Option<ApiSequence>,
+    #[serde(default)]
+    pub(crate) keywords: Vec<ApiKeyword>,
+    #[serde(default, rename = "uniProtKBCrossReferences")]
+    pub(crate) cross_references: Vec<ApiCrossReference>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ApiKeyword {
+    // KW-0001
+    pub(crate) id: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ApiCrossReference {
+    // GO, PDB, Pfam...
+    pub(crate) database: String,
+    pub(crate) id: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiDescription {
+    recommended_name: Option<ApiName>,
+    #[serde(default)]
+    submission_names: Vec<ApiName>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiName {
+    full_name: ApiValue,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiGene {
+    gene_name: Option<ApiValue>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiValue {
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiSequence {
+    length: Option<i32>,
+    mol_weight: Option<i32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Annotation {
+    pub accession_number: String,
+    pub mass: Option<i32>,
+    pub seq_length: Option<i32>,
+    pub protein_name: Option<String>,
+    pub gene_name: Option<String>,
+    pub reviewed: Option<bool>,
+    // Database and identifier of the cross-references
+    pub xrefs: Vec<(String, String)>,
+}
+
+impl From<ApiEntry> for Annotation {
+    fn from(entry: ApiEntry) -> Self {
+        let protein_name = entry.protein_description.and_then(|d| {
+            d.recommended_name
+                .or_else(|| d.submission_names.into_iter().next())
+                .map(|name| name.full_name.value)
+        });
+        let gene_name = entry
+            .genes
+            .into_iter()
+            .find_map(|gene| gene.gene_name.map(|name| name.value));
+        let xrefs = entry
+            .cross_references
+            .into_iter()
+            .filter(|x| XREF_DATABASES.contains(&x.database.as_str()))
+            .map(|x| (x.database, x.id))
+            .collect();
+
+        Annotation {
+            accession_number: entry.primary_accession,
+            mass: entry.sequence.as_ref().and_then(|s| s.mol_weight),
+            seq_length: entry.sequence.as_ref().and_then(|s| s.length),
+            protein_name,
+            gene_name,
+            reviewed: entry
+                .entry_type
+                .map(|t| t.starts_with("UniProtKB reviewed")),
+            xrefs,
+        }
+    }
+}
+
+/// UniProtKB entries of `accessions`, with the given `fields` only.
+pub(crate) async fn fetch_entries(
+    client: &Client,
+    accessions: &[String],
+    fields: &str,
+) -> Result<Vec<ApiEntry>, DownloadError> {
+    let url = format!("{UNIPROTKB_REST_URL}/accessions");
+    let request_error = |source| DownloadError::from_reqwest(&url, source);
+
+    let response = client
+        .get(&url)
+        .query(&[
+            ("accessions", accessions.join(",").as_str()),
+            ("fields", fields),
+            ("format", "json"),
+        ])
+        .send()
+        .await
+        .map_err(request_error)?;
+
+    let status = response.status();
+    if !status.is_success() {
+        return Err(DownloadError::Status {
+            url: url.clone(),
+            status,
+        });
+    }
+
+    let results: ApiResults = response.json().await.map_err(request_error)?;
+    Ok(results.results)
+}
+
+async fn fetch_batch(
+    client: &Client,
+    accessions: &[String],
+) -> Result<Vec<Annotation>, DownloadError> {
+    let entries = fetch_entries(client, accessions, FIELDS).await?;
+    Ok(entries.into_iter().map(Annotation::from).collect())
+}
+
+// =========================================================
+// Enrichment
+// =========================================================
+
+fn pending_accessions(
+    connection: &mut SqliteConnection,
+    all: bool,
+) -> Result<Vec<String>, diesel::result::Error> {
+    let mut query = uniprot_entries::table
+        .select(uniprot_entries::accession_number)
+        .order(uniprot_entries::accession_number)
+        .into_boxed();
+
+    if !all {
+        query = query.filter(
+            uniprot_entries::seq_length
+                .is_null()
+                .or(uniprot_entries::reviewed.is_null()),
+        );
+    }
+
+    query.load(connection)
+}
+
+// Mass, length, protein name, gene name and review status
+type StoredAnnotation = (
+    Option<i32>,
+    Option<i32>,
+    Option<String>,
+    Option<String>,
+    Option<bool>,
+);
+
+// Annotation fields of `annotation` differing from those of `stored`
+fn changed_fields(
+    stored: &StoredAnnotation,
+    annotation: &Annotation,
+) -> Vec<&'static str> {
+    let (mass, seq_length, protein_name, gene_name, reviewed) = stored;
+    [
+        ("mass", *mass != annotation.mass),
+        ("seq_length", *seq_length != annotation.seq_length),
+        ("protein_name", *protein_name != annotation.protein_name),
+        ("gene_name", *gene_name != annotation.gene_name),
+        ("reviewed", *reviewed != annotation.reviewed),
+    ]
+    .into_iter()
+    .filter(|&(_, changed)| changed)
+    .map(|(field, _)| field)
+    .collect()
+}
+
+// Entries whose annotation changed are stamped with the import run `run`
+fn store_annotations(
+    connection: &mut SqliteConnection,
+    annotations: &[Annotation],
+    run: i32,
+) -> Result<(), diesel::result::Error> {
+    connection.transaction(|connection| {
+        let mut changes = Vec::new();
+        for annotation in annotations {
+            let stored = uniprot_entries::table
+                .find(&annotation.accession_number)
+                .select((
+                    uniprot_entries::mass,
+                    uniprot_entries::seq_length,
+                    uniprot_entries::protein_name,
+                    uniprot_entries::gene_name,
+                    uniprot_entries::reviewed,
+                ))
+                .first(connection)
+                .optional()?;
+            let changed = match &stored {
+                Some(stored) => changed_fields(stored, annotation),
+                None => Vec::new(),
+            };
+
+            if !changed.is_empty() {
+                diesel::update(
+                    uniprot_entries::table.find(&annotation.accession_number),
+                )
+                .set((
+                    uniprot_entries::mass.eq(annotation.mass),
+                    uniprot_entries::seq_length.eq(annotation.seq_length),
+                    uniprot_entries::protein_name.eq(&annotation.protein_name),
+                    uniprot_entries::gene_name.eq(&annotation.gene_name),
+                    uniprot_entries::reviewed.eq(annotation.reviewed),
+                    uniprot_entries::import_run.eq(run),
+                ))
+                .execute(connection)?;
+                changes.push(EntryChange::new(
+                    &annotation.accession_number,
+                    run,
+                    "annotated",
+                    Some(changed.join(", ")),
+                ));
+            }
+
+            diesel::delete(
+                uniprot_xrefs::table
+                    .filter(
+                        uniprot_xrefs::accession
+                            .eq(&annotation.accession_number),
+                    )
+                    .filter(uniprot_xrefs::database.eq_any(XREF_DATABASES)),
+            )
+            .execute(connection)?;
+        }
+
+        let xrefs: Vec<UniprotXref> = annotations
+            .iter()
+            .flat_map(|annotation| {
+                annotation
+                    .xrefs
+                    .iter()
+                    .map(|(database, xref_id)| UniprotXref {
+                        accession: annotation.accession_number.clone(),
+                        database: database.clone(),
+                        xref_id: xref_id.clone(),
+                    })
+            })
+            .collect();
+        for chunk in xrefs.chunks(batch_rows(3)) {
+            diesel::insert_into(uniprot_xrefs::table)
+                .values(chunk)
+                .on_conflict_do_nothing()
+                .execute(connection)?;
+        }
+
+        record_changes(connection, &changes)
+    })
+}
+
+/// Annotate the stored entries as the import run `run`, returns the
+/// number of entries updated.
+pub fn enrich_entries(
+    connection: &mut SqliteConnection,
+    run: i32,
+    opts: &EnrichOptions,
+) -> Result<usize, EnrichError> {
+    let accessions = pending_accessions(connection, opts.all)?;
+    info!("Enriching {} entries", accessions.len());
+
+    let client =
+        Client::builder()
+            .build()
+            .map_err(|source| DownloadError::Request {
+                url: UNIPROTKB_REST_URL.to_string(),
+                source,
+            })?;
+
+    let fetcher = &Fetcher::new(client, opts.interval, opts.retries);
+    let requests =
+        accessions
+            .chunks(opts.batch_size.max(1))
+            .map(|batch| async move {
+                let annotations = fetcher
+                    .run(|| fetch_batch(fetcher.client(), batch))
+                    .await?;
+                Ok::<_, EnrichError>((batch, annotations))
+            });
+
+    let mut enriched = 0;
+    block_on(for_each_concurrent(
+        opts.concurrency,
+        requests,
+        |(batch, annotations)| {
+            if annotations.len() < batch.len() {
+                warn!(
+                    "UniProtKB returned {} of {} entries",
+                    annotations.len(),
+                    batch.len()
+                );
+            }
+
+            store_annotations(connection, &annotations, run)?;
+            enriched += annotations.len();
+
+            info!("Enriched {enriched}/{}", accessions.len());
+            Ok(())
+        },
+    ))??;
+
+    Ok(enriched)
+}
@@ -0,0 +1,194 @@
+/// Enzyme classification from the ENZYME database flat file, enzyme.dat.
+///
+/// Every EC number lists the Swiss-Prot entries catalysing its reaction on
+/// DR lines, so the links to the stored entries come with the file and no
+/// REST request is needed.
+use diesel::prelude::*;
+use diesel::upsert::excluded;
+use log::info;
+use std::collections::HashSet;
+use std::path::Path;
+use thiserror::Error;
+
+use crate::schema::*;
+use crate::uniprot::db::batch_rows;
+use crate::uniprot::download::{download_text, DownloadError, DownloadOptions};
+use crate::uniprot::models::*;
+use crate::uniprot::similar::read_lines;
+
+#[derive(Error, Debug)]
+pub enum EnzymeError {
+    #[error("Couldn't read the input: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("Couldn't download enzyme.dat: {0}")]
+    DownloadError(#[from] DownloadError),
+
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] diesel::result::Error),
+
+    #[error("No enzyme found in enzyme.dat")]
+    NoEnzyme,
+}
+
+/// Enzymes of one enzyme.dat and the accessions listed for each.
+pub struct EnzymeFile {
+    pub enzymes: Vec<UniprotEnzyme>,
+    pub links: Vec<EntryEnzyme>,
+}
+
+// =========================================================
+// Parsing
+// =========================================================
+
+// Records are blocks of `XX   value` lines ended by `//`, DR lines list
+// `ACCESSION, ENTRY_NAME;` pairs. Transferred and deleted EC numbers are
+// left out.
+fn parse_enzyme_dat(lines: &[String]) -> Result<EnzymeFile, EnzymeError> {
+    let mut file = EnzymeFile {
+        enzymes: Vec::new(),
+        links: Vec::new(),
+    };
+
+    let mut ec_number: Option<String> = None;
+    let mut name: Vec<String> = Vec::new();
+    let mut alternative_names: Vec<String> = Vec::new();
+    let mut accessions: Vec<String> = Vec::new();
+
+    for line in lines {
+        let (code, value) = match line.split_at_checked(2) {
+            Some((code, value)) => (code, value.trim()),
+            None => continue,
+        };
+
+        match code {
+            "ID" => ec_number = Some(value.to_string()),
+            // Long names continue on the next DE line
+            "DE" => name.push(value.to_string()),
+            "AN" => {
+                alternative_names.push(value.trim_end_matches('.').to_string())
+            }
+            "DR" => accessions.extend(
+                value
+                    .split(';')
+                    .filter_map(|pair| pair.split(',').next())
+                    .map(|accession| accession.trim().to_string())
+                    .filter(|accession| !accession.is_empty()),
+            ),
+            "//" => {
+                let joined = name.join(" ");
+                let joined = joined.trim_end_matches('.');
+                let retired = joined.starts_with("Transferred entry")
+                    || joined.starts_with("Deleted entry");
+
+                if let (Some(ec_number), false) = (ec_number.take(), retired) {
+                    file.links.extend(accessions.iter().map(|accession| {
+                        EntryEnzyme {
+                            entry: accession.clone(),
+                            ec_number: ec_number.clone(),
+                        }
+                    }));
+                    file.enzymes.push(UniprotEnzyme {
+                        ec_number,
+                        name: joined.to_string(),
+                        alternative_names: Some(alternative_names.join("; "))
+                            .filter(|names| !names.is_empty()),
+                    });
+                }
+
+                name.clear();
+                alternative_names.clear();
+                accessions.clear();
+            }
+            _ => {}
+        }
+    }
+
+    if file.enzymes.is_empty() {
+        return Err(EnzymeError::NoEnzyme);
+    }
+
+    Ok(file)
+}
+
+pub fn get_enzymes(
+    url: &str,
+    opts: &DownloadOptions,
+) -> Result<EnzymeFile, EnzymeError> {
+    let text = download_text(url, opts)?;
+    let lines: Vec<String> = text.lines().map(|l| l.to_string()).collect();
+    parse_enzyme_dat(&lines)
+}
+
+pub fn read_enzymes(path: &Path) -> Result<EnzymeFile, EnzymeError> {
+    parse_enzyme_dat(&read_lines(path)?)
+}
+
+// =========================================================
+// Database
+// =========================================================
+
+/// Store the enzymes and their links to the stored entries, the links of
+/// entries missing from the database are dropped.
+pub fn insert_enzymes(
+    file: &EnzymeFile,
+    connection: &mut SqliteConnection,
+) -> Result<usize, diesel::result::Error> {
+    let known: HashSet<String> = uniprot_entries::table
+        .select(uniprot_entries::accession_number)
+        .load::<String>(connection)?
+        .into_iter()
+        .collect();
+    let links: Vec<EntryEnzyme> = file
+        .links
+        .iter()
+        .filter(|link| known.contains(&link.entry))
+        .cloned()
+        .collect();
+
+    info!(
+        "Inserting {} enzymes and {} entry links",
+        file.enzymes.len(),
+        links.len()
+    );
+
+    connection.transaction(|connection| {
+        for chunk in file.enzymes.chunks(batch_rows(3)) {
+            diesel::insert_into(uniprot_enzymes::table)
+                .values(chunk)
+                .on_conflict(uniprot_enzymes::ec_number)
+                .do_update()
+                .set((
+                    uniprot_enzymes::name.eq(excluded(uniprot_enzymes::name)),
+                    uniprot_enzymes::alternative_names
+                        .eq(excluded(uniprot_enzymes::alternative_names)),
+                ))
+                .execute(connection)?;
+        }
+
+        // Links follow the file, those gone from it are removed
+        diesel::delete(uniprot_entry_enzymes::table).execute(connection)?;
+        for chunk in links.chunks(batch_rows(2)) {
+            diesel::insert_into(uniprot_entry_enzymes::table)
+                .values(chunk)
+                .on_conflict_do_nothing()
+                .execute(connection)?;
+        }
+
+        Ok(links.len())
+    })
+}
+
+/// LIKE pattern of an EC class such as `2.7.*` or `2.7.-.-`, `None` for a
+/// full EC number.
+pub fn ec_class_pattern(ec: &str) -> Option<String> {
+    let prefix = ec
+        .trim()
+        .trim_end_matches(|c| c == '*' || c == '-' || c == '.');
+
+    if prefix.split('.').count() >= 4 && !ec.contains(['*', '-']) {
+        return None;
+    }
+
+    Some(format!("{prefix}.%"))
+}
@@ -0,0 +1,235 @@
+/// Dump of the UniProt tables as data frames.
+///
+/// Entries, families, memberships and cross-references are written one file
+/// per table, and optionally pre-joined into one row per family member, for
+/// analysts who would rather load Parquet or CSV than query SQLite.
+use clap::ValueEnum;
+use diesel::prelude::*;
+use log::info;
+use polars::prelude::*;
+use std::{fs::File, path::Path};
+
+use crate::schema::*;
+use crate::uniprot::models::*;
+use crate::uniprot::query::{query_entries, EntryFilter};
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ExportFormat {
+    Parquet,
+    Csv,
+    /// One JSON object per line
+    Ndjson,
+}
+
+impl ExportFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Parquet => "parquet",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Ndjson => "ndjson",
+        }
+    }
+}
+
+// =========================================================
+// Frames
+// =========================================================
+
+fn entries_frame(entries: &[UniprotEntry]) -> PolarsResult<DataFrame> {
+    let column = |name: &str, values: Vec<Option<String>>| -> Column {
+        Series::new(name.into(), values).into()
+    };
+
+    DataFrame::new(vec![
+        Series::new(
+            "accession_number".into(),
+            entries
+                .iter()
+                .map(|e| e.accession_number.as_str())
+                .collect::<Vec<_>>(),
+        )
+        .into(),
+        Series::new(
+            "entry_name".into(),
+            entries
+                .iter()
+                .map(|e| e.entry_name.as_str())
+                .collect::<Vec<_>>(),
+        )
+        .into(),
+        Series::new(
+            "mass".into(),
+            entries.iter().map(|e| e.mass).collect::<Vec<_>>(),
+        )
+        .into(),
+        Series::new(
+            "seq_length".into(),
+            entries.iter().map(|e| e.seq_length).collect::<Vec<_>>(),
+        )
+        .into(),
+        column(
+            "protein_name",
+            entries.iter().map(|e| e.protein_name.clone()).collect(),
+        ),
+        column(
+            "gene_name",
+            entries.iter().map(|e| e.gene_name.clone()).collect(),
+        ),
+        column("taxon", entries.iter().map(|e| e.taxon.clone()).collect()),
+    ])
+}
+
+fn families_frame(families: &[UniprotFamily]) -> PolarsResult<DataFrame> {
+    DataFrame::new(vec![Series::new(
+        "name".into(),
+        families.iter().map(|f| f.name.as_str()).collect::<Vec<_>>(),
+    )
+    .into()])
+}
+
+fn memberships_frame(links: &[BelongsToFamily]) -> PolarsResult<DataFrame> {
+    DataFrame::new(vec![
+        Series::new(
+            "entry".into(),
+            links.iter().map(|l| l.entry.as_str()).collect::<Vec<_>>(),
+        )
+        .into(),
+        Series::new(
+            "family".into(),
+            links.iter().map(|l| l.family.as_str()).collect::<Vec<_>>(),
+        )
+        .into(),
+        Series::new(
+            "obsolete".into(),
+            links.iter().map(|l| l.obsolete).collect::<Vec<_>>(),
+        )
+        .into(),
+    ])
+}
+
+fn xrefs_frame(xrefs: &[UniprotXref]) -> PolarsResult<DataFrame> {
+    DataFrame::new(vec![
+        Series::new(
+            "accession".into(),
+            xrefs
+                .iter()
+                .map(|x| x.accession.as_str())
+                .collect::<Vec<_>>(),
+        )
+        .into(),
+        Series::new(
+            "database".into(),
+            xrefs
+                .iter()
+                .map(|x| x.database.as_str())
+                .collect::<Vec<_>>(),
+        )
+        .into(),
+        Series::new(
+            "xref_id".into(),
+            xrefs.iter().map(|x| x.xref_id.as_str()).collect::<Vec<_>>(),
+        )
+        .into(),
+    ])
+}
+
+// One row per family member, the family first
+fn members_frame(rows: &[(String, UniprotEntry)]) -> PolarsResult<DataFrame> {
+    let entries: Vec<UniprotEntry> =
+        rows.iter().map(|(_, entry)| entry.clone()).collect();
+
+    let mut df = entries_frame(&entries)?;
+    df.insert_column(
+        0,
+        Series::new(
+            "family".into(),
+            rows.iter().map(|(f, _)| f.as_str()).collect::<Vec<_>>(),
+        ),
+    )?;
+
+    Ok(df)
+}
+
+// =========================================================
+// Export
+// =========================================================
+
+fn write_frame(
+    df: &mut DataFrame,
+    path: &Path,
+    format: ExportFormat,
+) -> PolarsResult<()> {
+    let file = File::create(path)?;
+
+    match format {
+        ExportFormat::Parquet => {
+            ParquetWriter::new(file)
+                .with_compression(ParquetCompression::Zstd(None))
+                .finish(df)?;
+        }
+        ExportFormat::Csv => CsvWriter::new(file).finish(df)?,
+        ExportFormat::Ndjson => JsonWriter::new(file)
+            .with_json_format(JsonFormat::JsonLines)
+            .finish(df)?,
+    }
+
+    info!("Wrote {} rows to {}", df.height(), path.display());
+    Ok(())
+}
+
+/// Write the entries, families, memberships and cross-references to `dir`,
+/// plus the joined members table when `denormalized` is set.
+pub fn export_tables(
+    connection: &mut SqliteConnection,
+    dir: impl AsRef<Path>,
+    format: ExportFormat,
+    denormalized: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let dir = dir.as_ref();
+    std::fs::create_dir_all(dir)?;
+    let path = |name: &str| dir.join(format!("{name}.{}", format.extension()));
+
+    let entries: Vec<UniprotEntry> = uniprot_entries::table
+        .select(UniprotEntry::as_select())
+        .order(uniprot_entries::accession_number)
+        .load(connection)?;
+    write_frame(&mut entries_frame(&entries)?, &path("entries"), format)?;
+
+    let families: Vec<UniprotFamily> =
+        uniprot_sequence_similarity_families::table
+            .select(UniprotFamily::as_select())
+            .order(uniprot_sequence_similarity_families::name)
+            .load(connection)?;
+    write_frame(&mut families_frame(&families)?, &path("families"), format)?;
+
+    let links: Vec<BelongsToFamily> =
+        belongs_to_uniprot_sequence_similarity_family::table
+            .select(BelongsToFamily::as_select())
+            .order((
+                belongs_to_uniprot_sequence_similarity_family::family,
+                belongs_to_uniprot_sequence_similarity_family::entry,
+            ))
+            .load(connection)?;
+    write_frame(
+        &mut memberships_frame(&links)?,
+        &path("memberships"),
+        format,
+    )?;
+
+    let xrefs: Vec<UniprotXref> = uniprot_xrefs::table
+        .select(UniprotXref::as_select())
+        .order((
+            uniprot_xrefs::accession,
+            uniprot_xrefs::database,
+            uniprot_xrefs::xref_id,
+        ))
+        .load(connection)?;
+    write_frame(&mut xrefs_frame(&xrefs)?, &path("xrefs"), format)?;
+
+    if denormalized {
+        let rows = query_entries(connection, &EntryFilter::default())?;
+        write_frame(&mut members_frame(&rows)?, &path("members"), format)?;
+    }
+
+    Ok(())
+}
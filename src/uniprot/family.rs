@@ -0,0 +1,179 @@
+/// Pairwise comparison of the members of one sequence similarity family.
+///
+/// Every pair of member sequences is aligned globally with BLOSUM62 scores
+/// and the identity taken as the identical columns over the alignment
+/// length. Alignments are spread over the rayon pool, a family of a few
+/// hundred members takes tens of thousands of them.
+use bio::alignment::pairwise::Aligner;
+use bio::alignment::AlignmentOperation;
+use bio::scores::blosum62;
+use rayon::prelude::*;
+use std::io::Write;
+
+use crate::uniprot::models::*;
+
+// Alignment scores, the defaults of BLAST for proteins
+const GAP_OPEN: i32 = -11;
+const GAP_EXTEND: i32 = -1;
+
+/// Identities between the members of a family, in the order of `members`.
+pub struct IdentityMatrix {
+    pub members: Vec<UniprotEntry>,
+    pub identities: Vec<Vec<f64>>,
+}
+
+/// Most identical member of another species.
+#[derive(Debug, Clone)]
+pub struct ClosestOrtholog<'a> {
+    pub entry: &'a UniprotEntry,
+    pub closest: &'a UniprotEntry,
+    pub identity: f64,
+}
+
+// =========================================================
+// Identity
+// =========================================================
+
+/// Identical columns of the global alignment of `a` and `b` over its
+/// length.
+pub fn global_identity(a: &[u8], b: &[u8]) -> f64 {
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+
+    let mut aligner = Aligner::with_capacity(
+        a.len(),
+        b.len(),
+        GAP_OPEN,
+        GAP_EXTEND,
+        blosum62,
+    );
+    let alignment = aligner.global(a, b);
+
+    let matches = alignment
+        .operations
+        .iter()
+        .filter(|op| matches!(op, AlignmentOperation::Match))
+        .count();
+
+    matches as f64 / alignment.operations.len().max(1) as f64
+}
+
+/// Align every pair of `sequences` in parallel.
+pub fn identity_matrix(
+    sequences: Vec<(UniprotEntry, UniprotSequence)>,
+) -> IdentityMatrix {
+    let n = sequences.len();
+    let pairs: Vec<(usize, usize)> = (0..n)
+        .flat_map(|i| (i + 1..n).map(move |j| (i, j)))
+        .collect();
+
+    let scores: Vec<(usize, usize, f64)> = pairs
+        .par_iter()
+        .map(|&(i, j)| {
+            let a = sequences[i].1.sequence.as_bytes();
+            let b = sequences[j].1.sequence.as_bytes();
+            (i, j, global_identity(a, b))
+        })
+        .collect();
+
+    let mut identities = vec![vec![1.0; n]; n];
+    for (i, j, identity) in scores {
+        identities[i][j] = identity;
+        identities[j][i] = identity;
+    }
+
+    IdentityMatrix {
+        members: sequences.into_iter().map(|(entry, _)| entry).collect(),
+        identities,
+    }
+}
+
+impl IdentityMatrix {
+    /// Closest member of another species for every member with a known
+    /// species, members alone in the family are left out.
+    pub fn closest_orthologs(&self) -> Vec<ClosestOrtholog<'_>> {
+        self.members
+            .iter()
+            .enumerate()
+            .filter_map(|(i, entry)| {
+                let species = entry.taxon.as_deref()?;
+                let (j, identity) = self.identities[i]
+                    .iter()
+                    .enumerate()
+                    .filter(|&(j, _)| {
+                        self.members[j]
+                            .taxon
+                            .as_deref()
+                            .is_some_and(|s| s != species)
+                    })
+                    .max_by(|a, b| a.1.total_cmp(b.1))?;
+
+                Some(ClosestOrtholog {
+                    entry,
+                    closest: &self.members[j],
+                    identity: *identity,
+                })
+            })
+            .collect()
+    }
+}
+
+// =========================================================
+// Output
+// =========================================================
+
+/// Write the matrix as tab separated values, accessions heading the rows
+/// and the columns.
+pub fn write_matrix(
+    matrix: &IdentityMatrix,
+    out: impl Write,
+) -> Result<(), csv::Error> {
+    let mut writer =
+        csv::WriterBuilder::new().delimiter(b'\t').from_writer(out);
+
+    let mut header = vec![String::new()];
+    header.extend(matrix.members.iter().map(|e| e.accession_number.clone()));
+    writer.write_record(&header)?;
+
+    for (entry, row) in matrix.members.iter().zip(&matrix.identities) {
+        let mut record = vec![entry.accession_number.clone()];
+        record.extend(row.iter().map(|identity| format!("{identity:.4}")));
+        writer.write_record(&record)?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn write_orthologs(
+    orthologs: &[ClosestOrtholog],
+    out: impl Write,
+) -> Result<(), csv::Error> {
+    let mut writer =
+        csv::WriterBuilder::new().delimiter(b'\t').from_writer(out);
+    writer.write_record([
+        "accession_number",
+        "entry_name",
+        "species",
+        "closest_accession_number",
+        "closest_entry_name",
+        "closest_species",
+        "identity",
+    ])?;
+
+    for ortholog in orthologs {
+        writer.write_record([
+            ortholog.entry.accession_number.as_str(),
+            ortholog.entry.entry_name.as_str(),
+            ortholog.entry.taxon.as_deref().unwrap_or(""),
+            ortholog.closest.accession_number.as_str(),
+            ortholog.closest.entry_name.as_str(),
+            ortholog.closest.taxon.as_deref().unwrap_or(""),
+            format!("{:.4}", ortholog.identity).as_str(),
+        ])?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
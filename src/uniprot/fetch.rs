@@ -0,0 +1,118 @@
+/// Concurrent requests to the UniProt services.
+///
+/// Requests run on a tokio runtime of the calling thread, at most
+/// `concurrency` of them in flight and started at least `interval` apart to
+/// stay polite to the servers. Results come back on the calling thread in
+/// completion order, so they are stored in SQLite without any locking.
+use futures::stream::{self, StreamExt};
+use log::warn;
+use reqwest::Client;
+use std::future::Future;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::{sleep, sleep_until, Instant};
+
+use crate::uniprot::download::{DownloadError, RETRY_DELAY};
+
+/// Client shared by the requests to one service, with its rate limit and
+/// retries.
+pub(crate) struct Fetcher {
+    client: Client,
+    interval: Duration,
+    retries: u32,
+    // Earliest start of the next request
+    next_start: Mutex<Instant>,
+}
+
+impl Fetcher {
+    pub(crate) fn new(
+        client: Client,
+        interval: Duration,
+        retries: u32,
+    ) -> Self {
+        Fetcher {
+            client,
+            interval,
+            retries,
+            next_start: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub(crate) fn client(&self) -> &Client {
+        &self.client
+    }
+
+    async fn wait_turn(&self) {
+        let start = {
+            let mut next_start = self.next_start.lock().await;
+            let start = (*next_start).max(Instant::now());
+            *next_start = start + self.interval;
+            start
+        };
+        sleep_until(start).await;
+    }
+
+    /// Send `request` once its turn comes, again on transient errors,
+    /// `retries` times at most.
+    pub(crate) async fn run<T, Fut>(
+        &self,
+        mut request: impl FnMut() -> Fut,
+    ) -> Result<T, DownloadError>
+    where
+        Fut: Future<Output = Result<T, DownloadError>>,
+    {
+        let mut delay = RETRY_DELAY;
+        let mut attempt = 0;
+
+        loop {
+            self.wait_turn().await;
+
+            match request().await {
+                Ok(value) => return Ok(value),
+                Err(err) if err.is_transient() && attempt < self.retries => {
+                    attempt += 1;
+                    warn!(
+                        "{err}, retrying in {}s ({attempt}/{})",
+                        delay.as_secs(),
+                        self.retries
+                    );
+                    sleep(delay).await;
+                    delay *= 2;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+/// Await `requests` with at most `concurrency` of them in flight, handing
+/// every result to `store` as it comes. Stops at the first error.
+pub(crate) async fn for_each_concurrent<T, E, Fut>(
+    concurrency: usize,
+    requests: impl IntoIterator<Item = Fut>,
+    mut store: impl FnMut(T) -> Result<(), E>,
+) -> Result<(), E>
+where
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut results =
+        stream::iter(requests).buffer_unordered(concurrency.max(1));
+
+    while let Some(result) = results.next().await {
+        store(result?)?;
+    }
+
+    Ok(())
+}
+
+/// Run `future` to completion on a runtime of the calling thread.
+pub(crate) fn block_on<F: Future>(
+    future: F,
+) -> Result<F::Output, DownloadError> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(DownloadError::Runtime)?;
+
+    Ok(runtime.block_on(future))
+}
@@ -0,0 +1,378 @@
+/// Gene Ontology terms, parsed from go-basic.obo, and the GO annotations of
+/// entries.
+///
+/// The ontology keeps the is_a and part_of relations between terms, so that
+/// a query for a term also matches the entries annotated with any of its
+/// descendants. The annotations of each stored entry come from the
+/// UniProtKB REST API.
+use diesel::dsl::{exists, not};
+use diesel::prelude::*;
+use diesel::upsert::excluded;
+use log::{info, warn};
+use reqwest::Client;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::Path;
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::schema::*;
+use crate::uniprot::db::batch_rows;
+use crate::uniprot::download::{download_text, DownloadError, DownloadOptions};
+use crate::uniprot::enrich::{fetch_entries, UNIPROTKB_REST_URL};
+use crate::uniprot::fetch::{block_on, for_each_concurrent, Fetcher};
+use crate::uniprot::models::*;
+use crate::uniprot::similar::read_lines;
+
+#[derive(Error, Debug)]
+pub enum GoError {
+    #[error("Couldn't read the input: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("Couldn't download: {0}")]
+    DownloadError(#[from] DownloadError),
+
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] diesel::result::Error),
+
+    #[error("No term found in the ontology")]
+    NoTerm,
+}
+
+/// Terms of one go-basic.obo and the relations between them.
+pub struct Ontology {
+    pub terms: Vec<GoTerm>,
+    pub parents: Vec<GoTermParent>,
+}
+
+// ---------- Options ----------
+
+#[derive(Debug, Clone)]
+pub struct GoAnnotationOptions {
+    pub batch_size: usize,
+    // Requests in flight at once
+    pub concurrency: usize,
+    // Minimum delay between two requests
+    pub interval: Duration,
+    pub retries: u32,
+    // Entries with annotations already are requested again
+    pub all: bool,
+}
+
+impl Default for GoAnnotationOptions {
+    fn default() -> Self {
+        GoAnnotationOptions {
+            batch_size: 100,
+            concurrency: 4,
+            interval: Duration::from_millis(500),
+            retries: 3,
+            all: false,
+        }
+    }
+}
+
+// =========================================================
+// Parsing
+// =========================================================
+
+// `tag: value` lines of a [Term] stanza
+#[derive(Default)]
+struct TermStanza {
+    id: Option<String>,
+    name: Option<String>,
+    namespace: Option<String>,
+    parents: Vec<String>,
+    obsolete: bool,
+}
+
+impl TermStanza {
+    fn finish(self, ontology: &mut Ontology) {
+        let (Some(id), Some(name), Some(namespace), false) =
+            (self.id, self.name, self.namespace, self.obsolete)
+        else {
+            return;
+        };
+
+        ontology
+            .parents
+            .extend(self.parents.into_iter().map(|parent| GoTermParent {
+                term: id.clone(),
+                parent,
+            }));
+        ontology.terms.push(GoTerm {
+            id,
+            name,
+            namespace,
+        });
+    }
+}
+
+// Stanzas start with a `[Term]` or `[Typedef]` header followed by
+// `tag: value` lines. Obsolete terms are left out.
+fn parse_obo(lines: &[String]) -> Result<Ontology, GoError> {
+    let mut ontology = Ontology {
+        terms: Vec::new(),
+        parents: Vec::new(),
+    };
+    let mut stanza: Option<TermStanza> = None;
+
+    for line in lines {
+        let line = line.trim();
+
+        if line.starts_with('[') {
+            if let Some(term) = stanza.take() {
+                term.finish(&mut ontology);
+            }
+            stanza = (line == "[Term]").then(TermStanza::default);
+            continue;
+        }
+
+        let Some(term) = stanza.as_mut() else {
+            continue;
+        };
+        let Some((tag, value)) = line.split_once(": ") else {
+            continue;
+        };
+        // Trailing `! comment` with the name of the target
+        let value = value.split(" ! ").next().unwrap_or(value).trim();
+
+        match tag {
+            "id" => term.id = Some(value.to_string()),
+            "name" => term.name = Some(value.to_string()),
+            "namespace" => term.namespace = Some(value.to_string()),
+            "is_a" => term.parents.push(value.to_string()),
+            "relationship" => {
+                if let Some(parent) = value.strip_prefix("part_of ") {
+                    term.parents.push(parent.trim().to_string());
+                }
+            }
+            "is_obsolete" => term.obsolete = value == "true",
+            _ => {}
+        }
+    }
+    if let Some(term) = stanza {
+        term.finish(&mut ontology);
+    }
+
+    if ontology.terms.is_empty() {
+        return Err(GoError::NoTerm);
+    }
+
+    Ok(ontology)
+}
+
+pub fn get_ontology(
+    url: &str,
+    opts: &DownloadOptions,
+) -> Result<Ontology, GoError> {
+    let text = download_text(url, opts)?;
+    let lines: Vec<String> = text.lines().map(|l| l.to_string()).collect();
+    parse_obo(&lines)
+}
+
+pub fn read_ontology(path: &Path) -> Result<Ontology, GoError> {
+    parse_obo(&read_lines(path)?)
+}
+
+// =========================================================
+// Database
+// =========================================================
+
+pub fn insert_ontology(
+    ontology: &Ontology,
+    connection: &mut SqliteConnection,
+) -> Result<(), diesel::result::Error> {
+    info!(
+        "Inserting {} GO terms and {} relations",
+        ontology.terms.len(),
+        ontology.parents.len()
+    );
+
+    connection.transaction(|connection| {
+        for chunk in ontology.terms.chunks(batch_rows(3)) {
+            diesel::insert_into(go_terms::table)
+                .values(chunk)
+                .on_conflict(go_terms::id)
+                .do_update()
+                .set((
+                    go_terms::name.eq(excluded(go_terms::name)),
+                    go_terms::namespace.eq(excluded(go_terms::namespace)),
+                ))
+                .execute(connection)?;
+        }
+
+        // Relations follow the ontology, those gone from it are removed
+        diesel::delete(go_term_parents::table).execute(connection)?;
+        for chunk in ontology.parents.chunks(batch_rows(2)) {
+            diesel::insert_into(go_term_parents::table)
+                .values(chunk)
+                .on_conflict_do_nothing()
+                .execute(connection)?;
+        }
+        Ok(())
+    })
+}
+
+/// `terms` and all their descendants through is_a and part_of relations.
+pub fn expand_go_terms(
+    terms: &[String],
+    connection: &mut SqliteConnection,
+) -> Result<Vec<String>, diesel::result::Error> {
+    let relations: Vec<(String, String)> = go_term_parents::table
+        .select((go_term_parents::term, go_term_parents::parent))
+        .load(connection)?;
+
+    let mut children: HashMap<&str, Vec<&str>> = HashMap::new();
+    for (term, parent) in &relations {
+        children.entry(parent).or_default().push(term);
+    }
+
+    let mut found: BTreeSet<String> = terms.iter().cloned().collect();
+    let mut pending: Vec<&str> = terms.iter().map(|t| t.as_str()).collect();
+    while let Some(term) = pending.pop() {
+        for &child in children.get(term).into_iter().flatten() {
+            if found.insert(child.to_string()) {
+                pending.push(child);
+            }
+        }
+    }
+
+    Ok(found.into_iter().collect())
+}
+
+// =========================================================
+// Annotations of the entries
+// =========================================================
+
+fn pending_accessions(
+    connection: &mut SqliteConnection,
+    all: bool,
+) -> Result<Vec<String>, diesel::result::Error> {
+    let mut query = uniprot_entries::table
+        .select(uniprot_entries::accession_number)
+        .order(uniprot_entries::accession_number)
+        .into_boxed();
+
+    if !all {
+        query = query.filter(not(exists(
+            uniprot_entry_go_terms::table.filter(
+                uniprot_entry_go_terms::entry
+                    .eq(uniprot_entries::accession_number),
+            ),
+        )));
+    }
+
+    query.load(connection)
+}
+
+// Replaces the annotations of the entries of the batch
+fn store_annotations(
+    connection: &mut SqliteConnection,
+    accessions: &[String],
+    links: &[EntryGoTerm],
+) -> Result<(), diesel::result::Error> {
+    connection.transaction(|connection| {
+        diesel::delete(
+            uniprot_entry_go_terms::table
+                .filter(uniprot_entry_go_terms::entry.eq_any(accessions)),
+        )
+        .execute(connection)?;
+
+        for chunk in links.chunks(batch_rows(2)) {
+            diesel::insert_into(uniprot_entry_go_terms::table)
+                .values(chunk)
+                .on_conflict_do_nothing()
+                .execute(connection)?;
+        }
+        Ok(())
+    })
+}
+
+/// Download the GO annotations of the stored entries, returns the number
+/// of annotations stored.
+///
+/// Terms missing from the loaded ontology are skipped, load go-basic.obo
+/// first.
+pub fn fetch_go_annotations(
+    connection: &mut SqliteConnection,
+    opts: &GoAnnotationOptions,
+) -> Result<usize, GoError> {
+    let known: HashSet<String> = go_terms::table
+        .select(go_terms::id)
+        .load::<String>(connection)?
+        .into_iter()
+        .collect();
+
+    let accessions = pending_accessions(connection, opts.all)?;
+    info!(
+        "Fetching the GO annotations of {} entries",
+        accessions.len()
+    );
+
+    let client =
+        Client::builder()
+            .build()
+            .map_err(|source| DownloadError::Request {
+                url: UNIPROTKB_REST_URL.to_string(),
+                source,
+            })?;
+
+    let fetcher = &Fetcher::new(client, opts.interval, opts.retries);
+    let requests =
+        accessions
+            .chunks(opts.batch_size.max(1))
+            .map(|batch| async move {
+                let entries = fetcher
+                    .run(|| {
+                        fetch_entries(fetcher.client(), batch, "accession,go")
+                    })
+                    .await?;
+                Ok::<_, GoError>((batch, entries))
+            });
+
+    let mut stored = 0;
+    let mut unknown = 0;
+    block_on(for_each_concurrent(
+        opts.concurrency,
+        requests,
+        |(batch, entries)| {
+            if entries.len() < batch.len() {
+                warn!(
+                    "UniProtKB returned {} of {} entries",
+                    entries.len(),
+                    batch.len()
+                );
+            }
+
+            let mut links: Vec<EntryGoTerm> = Vec::new();
+            for entry in &entries {
+                for reference in &entry.cross_references {
+                    if reference.database != "GO" {
+                        continue;
+                    }
+                    if !known.contains(&reference.id) {
+                        unknown += 1;
+                        continue;
+                    }
+                    links.push(EntryGoTerm {
+                        entry: entry.primary_accession.clone(),
+                        term: reference.id.clone(),
+                    });
+                }
+            }
+
+            store_annotations(connection, batch, &links)?;
+            stored += links.len();
+
+            info!("Stored {stored} GO annotations");
+            Ok(())
+        },
+    ))??;
+
+    if unknown > 0 {
+        warn!(
+            "Skipped {unknown} annotations to terms missing from the ontology"
+        );
+    }
+
+    Ok(stored)
+}
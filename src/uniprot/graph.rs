@@ -0,0 +1,245 @@
+/// Family membership as a graph, for Cytoscape or Gephi.
+///
+/// Entries and families are the two kinds of nodes, every membership an
+/// edge between them. Cross-references to the chosen databases, such as
+/// Pfam domains, can be merged in as a third kind of node linked to the
+/// entries, which shows families sharing domains.
+use clap::ValueEnum;
+use diesel::prelude::*;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+
+use crate::schema::*;
+use crate::uniprot::db::SQLITE_MAX_PARAMETERS;
+use crate::uniprot::models::*;
+use crate::uniprot::query::{query_entries, EntryFilter};
+
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+pub enum GraphFormat {
+    #[default]
+    Graphml,
+    /// Graphviz
+    Dot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    Entry,
+    Family,
+    Xref,
+}
+
+impl NodeKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            NodeKind::Entry => "entry",
+            NodeKind::Family => "family",
+            NodeKind::Xref => "xref",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    // Unique across kinds: the accession, `family:<name>` or
+    // `<database>:<id>`
+    pub id: String,
+    pub kind: NodeKind,
+    pub label: String,
+    // Mnemonic code of the species of entries
+    pub species: Option<String>,
+}
+
+/// Undirected graph, edges hold the ids of their two nodes.
+#[derive(Debug, Default)]
+pub struct Graph {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<(String, String)>,
+}
+
+// =========================================================
+// Graph
+// =========================================================
+
+fn family_id(name: &str) -> String {
+    format!("family:{name}")
+}
+
+/// Graph of the entries matching `filter` and their families, with the
+/// cross-references of the entries to `xref_databases` when not empty.
+pub fn build_graph(
+    connection: &mut SqliteConnection,
+    filter: &EntryFilter,
+    xref_databases: &[String],
+) -> Result<Graph, diesel::result::Error> {
+    let members = query_entries(connection, filter)?;
+
+    let mut entries: BTreeMap<&str, &UniprotEntry> = BTreeMap::new();
+    let mut families: BTreeSet<&str> = BTreeSet::new();
+    let mut graph = Graph::default();
+
+    for (family, entry) in &members {
+        entries.insert(&entry.accession_number, entry);
+        families.insert(family);
+        graph
+            .edges
+            .push((entry.accession_number.clone(), family_id(family)));
+    }
+
+    graph.nodes.extend(entries.values().map(|entry| Node {
+        id: entry.accession_number.clone(),
+        kind: NodeKind::Entry,
+        label: entry.entry_name.clone(),
+        species: entry.taxon.clone(),
+    }));
+    graph.nodes.extend(families.iter().map(|&family| Node {
+        id: family_id(family),
+        kind: NodeKind::Family,
+        label: family.to_string(),
+        species: None,
+    }));
+
+    if xref_databases.is_empty() {
+        return Ok(graph);
+    }
+
+    let accessions: Vec<&str> = entries.keys().copied().collect();
+    let mut xrefs: BTreeSet<String> = BTreeSet::new();
+    // The databases take parameters too
+    for chunk in accessions.chunks(SQLITE_MAX_PARAMETERS - xref_databases.len())
+    {
+        let found: Vec<UniprotXref> = uniprot_xrefs::table
+            .filter(uniprot_xrefs::accession.eq_any(chunk))
+            .filter(uniprot_xrefs::database.eq_any(xref_databases))
+            .select(UniprotXref::as_select())
+            .order((uniprot_xrefs::accession, uniprot_xrefs::xref_id))
+            .load(connection)?;
+
+        for xref in found {
+            let id = format!("{}:{}", xref.database, xref.xref_id);
+            graph.edges.push((xref.accession, id.clone()));
+            xrefs.insert(id);
+        }
+    }
+
+    graph.nodes.extend(xrefs.into_iter().map(|id| Node {
+        label: id.clone(),
+        id,
+        kind: NodeKind::Xref,
+        species: None,
+    }));
+
+    Ok(graph)
+}
+
+// =========================================================
+// Output
+// =========================================================
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn write_graphml(graph: &Graph, out: &mut impl Write) -> std::io::Result<()> {
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        out,
+        r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+    )?;
+    for key in ["kind", "label", "species"] {
+        writeln!(
+            out,
+            concat!(
+                r#"  <key id="{key}" for="node" attr.name="{key}""#,
+                r#" attr.type="string"/>"#
+            ),
+            key = key
+        )?;
+    }
+    writeln!(out, r#"  <graph id="uniprot" edgedefault="undirected">"#)?;
+
+    for node in &graph.nodes {
+        writeln!(out, r#"    <node id="{}">"#, xml_escape(&node.id))?;
+        writeln!(
+            out,
+            r#"      <data key="kind">{}</data>"#,
+            node.kind.as_str()
+        )?;
+        writeln!(
+            out,
+            r#"      <data key="label">{}</data>"#,
+            xml_escape(&node.label)
+        )?;
+        if let Some(species) = &node.species {
+            writeln!(
+                out,
+                r#"      <data key="species">{}</data>"#,
+                xml_escape(species)
+            )?;
+        }
+        writeln!(out, "    </node>")?;
+    }
+    for (source, target) in &graph.edges {
+        writeln!(
+            out,
+            r#"    <edge source="{}" target="{}"/>"#,
+            xml_escape(source),
+            xml_escape(target)
+        )?;
+    }
+
+    writeln!(out, "  </graph>")?;
+    writeln!(out, "</graphml>")
+}
+
+fn write_dot(graph: &Graph, out: &mut impl Write) -> std::io::Result<()> {
+    writeln!(out, "graph uniprot {{")?;
+
+    for node in &graph.nodes {
+        let shape = match node.kind {
+            NodeKind::Entry => "ellipse",
+            NodeKind::Family => "box",
+            NodeKind::Xref => "diamond",
+        };
+        write!(
+            out,
+            r#"  "{}" [label="{}", kind="{}", shape={shape}"#,
+            dot_escape(&node.id),
+            dot_escape(&node.label),
+            node.kind.as_str()
+        )?;
+        if let Some(species) = &node.species {
+            write!(out, r#", species="{}""#, dot_escape(species))?;
+        }
+        writeln!(out, "];")?;
+    }
+    for (source, target) in &graph.edges {
+        writeln!(
+            out,
+            r#"  "{}" -- "{}";"#,
+            dot_escape(source),
+            dot_escape(target)
+        )?;
+    }
+
+    writeln!(out, "}}")
+}
+
+pub fn write_graph(
+    graph: &Graph,
+    format: GraphFormat,
+    mut out: impl Write,
+) -> std::io::Result<()> {
+    match format {
+        GraphFormat::Graphml => write_graphml(graph, &mut out)?,
+        GraphFormat::Dot => write_dot(graph, &mut out)?,
+    }
+    out.flush()
+}
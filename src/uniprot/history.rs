@@ -0,0 +1,117 @@
+/// Provenance of the stored entries.
+///
+/// Every import of similar.txt, enrichment and retirement is an import run,
+/// and every entry it inserts or changes is stamped with the run and gets a
+/// row in `uniprot_entry_history` saying what changed. The history of an
+/// entry thus tells when and from which source and release its data last
+/// changed. It outlives the entry, pruning leaves it in place.
+use diesel::prelude::*;
+use serde::Serialize;
+use std::io::Write;
+
+use crate::schema::*;
+use crate::uniprot::db::batch_rows;
+use crate::uniprot::models::*;
+use crate::uniprot::output::{write_rows, OutputError, OutputFormat, Row};
+
+/// Change of an entry, with the import run that made it.
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryEvent {
+    pub run: i32,
+    pub imported_at: String,
+    pub source: String,
+    pub release_version: Option<String>,
+    pub change: String,
+    pub detail: Option<String>,
+}
+
+impl EntryChange {
+    pub fn new(
+        accession: &str,
+        run: i32,
+        change: &str,
+        detail: Option<String>,
+    ) -> Self {
+        EntryChange {
+            accession: accession.to_string(),
+            import_run: run,
+            change: change.to_string(),
+            detail,
+        }
+    }
+}
+
+// =========================================================
+// Database
+// =========================================================
+
+pub fn record_changes(
+    connection: &mut SqliteConnection,
+    changes: &[EntryChange],
+) -> QueryResult<()> {
+    for chunk in changes.chunks(batch_rows(4)) {
+        diesel::insert_into(uniprot_entry_history::table)
+            .values(chunk)
+            .execute(connection)?;
+    }
+    Ok(())
+}
+
+/// Changes of the entry `accession`, oldest first.
+pub fn entry_history(
+    connection: &mut SqliteConnection,
+    accession: &str,
+) -> QueryResult<Vec<HistoryEvent>> {
+    let rows: Vec<(EntryChange, ImportRun)> = uniprot_entry_history::table
+        .inner_join(import_runs::table)
+        .filter(uniprot_entry_history::accession.eq(accession))
+        .order(uniprot_entry_history::id)
+        .select((EntryChange::as_select(), ImportRun::as_select()))
+        .load(connection)?;
+
+    Ok(rows
+        .into_iter()
+        .map(|(change, run)| HistoryEvent {
+            run: run.id,
+            imported_at: run.imported_at,
+            source: run.source,
+            release_version: run.release_version,
+            change: change.change,
+            detail: change.detail,
+        })
+        .collect())
+}
+
+// =========================================================
+// Output
+// =========================================================
+
+impl Row for HistoryEvent {
+    const COLUMNS: &'static [&'static str] = &[
+        "imported_at",
+        "run",
+        "source",
+        "release_version",
+        "change",
+        "detail",
+    ];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.imported_at.clone(),
+            self.run.to_string(),
+            self.source.clone(),
+            self.release_version.clone().unwrap_or_default(),
+            self.change.clone(),
+            self.detail.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+pub fn write_history(
+    history: &[HistoryEvent],
+    format: OutputFormat,
+    out: impl Write,
+) -> Result<(), OutputError> {
+    write_rows(history, format, out)
+}
@@ -0,0 +1,407 @@
+/// Cross-references of the stored entries from the UniProt ID mapping
+/// service.
+///
+/// The service runs asynchronously: a job is submitted for a batch of
+/// accessions and one target database, its status polled until it
+/// finishes, then its results downloaded. Several jobs run at once, every
+/// batch is stored as soon as it is mapped and only entries without a
+/// mapping are submitted, so an interrupted run resumes where it stopped.
+use clap::ValueEnum;
+use diesel::dsl::{exists, not};
+use diesel::prelude::*;
+use log::info;
+use reqwest::redirect::Policy;
+use reqwest::Client;
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::io::Write;
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::schema::*;
+use crate::uniprot::db::batch_rows;
+use crate::uniprot::download::DownloadError;
+use crate::uniprot::fetch::{block_on, for_each_concurrent, Fetcher};
+use crate::uniprot::models::*;
+use crate::uniprot::output::{write_rows, OutputError, OutputFormat, Row};
+
+const ID_MAPPING_URL: &str = "https://rest.uniprot.org/idmapping";
+const FROM_DATABASE: &str = "UniProtKB_AC-ID";
+// Jobs of a few hundred accessions usually finish within seconds
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const MAX_POLLS: u32 = 150;
+
+#[derive(Error, Debug)]
+pub enum IdMapError {
+    #[error("Couldn't query the ID mapping service: {0}")]
+    Download(#[from] DownloadError),
+
+    #[error("Database error: {0}")]
+    Database(#[from] diesel::result::Error),
+
+    #[error("Mapping job {job_id} ended with status {status}")]
+    JobFailed { job_id: String, status: String },
+
+    #[error("Mapping job {0} didn't finish in time")]
+    JobTimeout(String),
+
+    #[error("Couldn't write the mappings: {0}")]
+    Output(#[from] OutputError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MappingTarget {
+    /// Gene symbols
+    GeneName,
+    /// Ensembl gene identifiers
+    Ensembl,
+    /// RefSeq protein identifiers
+    Refseq,
+}
+
+impl MappingTarget {
+    pub const ALL: [MappingTarget; 3] = [
+        MappingTarget::GeneName,
+        MappingTarget::Ensembl,
+        MappingTarget::Refseq,
+    ];
+
+    /// Name stored in the `database` column.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MappingTarget::GeneName => "gene_name",
+            MappingTarget::Ensembl => "ensembl",
+            MappingTarget::Refseq => "refseq",
+        }
+    }
+
+    // Database names of the ID mapping service
+    fn service_name(self) -> &'static str {
+        match self {
+            MappingTarget::GeneName => "Gene_Name",
+            MappingTarget::Ensembl => "Ensembl",
+            MappingTarget::Refseq => "RefSeq_Protein",
+        }
+    }
+}
+
+// ---------- Options ----------
+
+#[derive(Debug, Clone)]
+pub struct IdMapOptions {
+    pub targets: Vec<MappingTarget>,
+    pub batch_size: usize,
+    // Jobs running at once
+    pub concurrency: usize,
+    // Minimum delay between two submitted jobs
+    pub interval: Duration,
+    pub retries: u32,
+    // Entries mapped already are submitted again
+    pub all: bool,
+}
+
+impl Default for IdMapOptions {
+    fn default() -> Self {
+        IdMapOptions {
+            targets: MappingTarget::ALL.to_vec(),
+            batch_size: 100,
+            concurrency: 4,
+            interval: Duration::from_millis(500),
+            retries: 3,
+            all: false,
+        }
+    }
+}
+
+// ---------- REST API ----------
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiJob {
+    job_id: String,
+}
+
+// Finished jobs may answer with their results instead of a status
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiJobStatus {
+    job_status: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiMappings {
+    results: Vec<ApiMapping>,
+    #[serde(default)]
+    failed_ids: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiMapping {
+    from: String,
+    to: String,
+}
+
+async fn get_json<T: DeserializeOwned>(
+    client: &Client,
+    url: &str,
+) -> Result<Option<T>, DownloadError> {
+    let request_error = |source| DownloadError::from_reqwest(url, source);
+
+    let response = client.get(url).send().await.map_err(request_error)?;
+
+    match response.status() {
+        // The status of a finished job redirects to its results
+        StatusCode::SEE_OTHER => Ok(None),
+        status if status.is_success() => {
+            Ok(Some(response.json().await.map_err(request_error)?))
+        }
+        status => Err(DownloadError::Status {
+            url: url.to_string(),
+            status,
+        }),
+    }
+}
+
+async fn submit_job(
+    client: &Client,
+    target: MappingTarget,
+    accessions: &[String],
+) -> Result<String, DownloadError> {
+    let url = format!("{ID_MAPPING_URL}/run");
+    let request_error = |source| DownloadError::from_reqwest(&url, source);
+
+    let response = client
+        .post(&url)
+        .form(&[
+            ("from", FROM_DATABASE),
+            ("to", target.service_name()),
+            ("ids", accessions.join(",").as_str()),
+        ])
+        .send()
+        .await
+        .map_err(request_error)?;
+
+    let status = response.status();
+    if !status.is_success() {
+        return Err(DownloadError::Status {
+            url: url.clone(),
+            status,
+        });
+    }
+
+    let job: ApiJob = response.json().await.map_err(request_error)?;
+    Ok(job.job_id)
+}
+
+async fn get_mappings(
+    client: &Client,
+    url: &str,
+) -> Result<ApiMappings, DownloadError> {
+    get_json(client, url)
+        .await?
+        .ok_or_else(|| DownloadError::Status {
+            url: url.to_string(),
+            status: StatusCode::SEE_OTHER,
+        })
+}
+
+async fn wait_for_job(
+    fetcher: &Fetcher,
+    job_id: &str,
+) -> Result<(), IdMapError> {
+    let url = format!("{ID_MAPPING_URL}/status/{job_id}");
+
+    for _ in 0..MAX_POLLS {
+        let status: Option<ApiJobStatus> =
+            fetcher.run(|| get_json(fetcher.client(), &url)).await?;
+
+        match status.and_then(|s| s.job_status).as_deref() {
+            None | Some("FINISHED") => return Ok(()),
+            Some("NEW") | Some("RUNNING") => {
+                tokio::time::sleep(POLL_INTERVAL).await
+            }
+            Some(status) => {
+                return Err(IdMapError::JobFailed {
+                    job_id: job_id.to_string(),
+                    status: status.to_string(),
+                })
+            }
+        }
+    }
+
+    Err(IdMapError::JobTimeout(job_id.to_string()))
+}
+
+async fn map_batch(
+    fetcher: &Fetcher,
+    target: MappingTarget,
+    accessions: &[String],
+) -> Result<Vec<IdMapping>, IdMapError> {
+    let job_id = fetcher
+        .run(|| submit_job(fetcher.client(), target, accessions))
+        .await?;
+    wait_for_job(fetcher, &job_id).await?;
+
+    let url = format!("{ID_MAPPING_URL}/stream/{job_id}?format=json");
+    let mappings = fetcher.run(|| get_mappings(fetcher.client(), &url)).await?;
+
+    if !mappings.failed_ids.is_empty() {
+        info!(
+            "{} accessions have no {} identifier",
+            mappings.failed_ids.len(),
+            target.as_str()
+        );
+    }
+
+    Ok(mappings
+        .results
+        .into_iter()
+        .map(|mapping| IdMapping {
+            entry: mapping.from,
+            database: target.as_str().to_string(),
+            identifier: mapping.to,
+        })
+        .collect())
+}
+
+// =========================================================
+// Mapping
+// =========================================================
+
+fn pending_accessions(
+    connection: &mut SqliteConnection,
+    target: MappingTarget,
+    all: bool,
+) -> Result<Vec<String>, diesel::result::Error> {
+    let mut query = uniprot_entries::table
+        .select(uniprot_entries::accession_number)
+        .order(uniprot_entries::accession_number)
+        .into_boxed();
+
+    if !all {
+        query = query.filter(not(exists(
+            uniprot_id_mappings::table
+                .filter(
+                    uniprot_id_mappings::entry
+                        .eq(uniprot_entries::accession_number),
+                )
+                .filter(uniprot_id_mappings::database.eq(target.as_str())),
+        )));
+    }
+
+    query.load(connection)
+}
+
+// Replaces the mappings of the entries of the batch to `target`
+fn store_mappings(
+    connection: &mut SqliteConnection,
+    target: MappingTarget,
+    accessions: &[String],
+    mappings: &[IdMapping],
+) -> Result<(), diesel::result::Error> {
+    connection.transaction(|connection| {
+        diesel::delete(
+            uniprot_id_mappings::table
+                .filter(uniprot_id_mappings::database.eq(target.as_str()))
+                .filter(uniprot_id_mappings::entry.eq_any(accessions)),
+        )
+        .execute(connection)?;
+
+        for chunk in mappings.chunks(batch_rows(3)) {
+            diesel::insert_into(uniprot_id_mappings::table)
+                .values(chunk)
+                .on_conflict_do_nothing()
+                .execute(connection)?;
+        }
+        Ok(())
+    })
+}
+
+/// Map the stored entries to the target databases, returns the number of
+/// mappings stored.
+pub fn map_entries(
+    connection: &mut SqliteConnection,
+    opts: &IdMapOptions,
+) -> Result<usize, IdMapError> {
+    let client = Client::builder().redirect(Policy::none()).build().map_err(
+        |source| DownloadError::Request {
+            url: ID_MAPPING_URL.to_string(),
+            source,
+        },
+    )?;
+
+    let fetcher = &Fetcher::new(client, opts.interval, opts.retries);
+    let mut stored = 0;
+
+    for &target in &opts.targets {
+        let accessions = pending_accessions(connection, target, opts.all)?;
+        info!(
+            "Mapping {} entries to {}",
+            accessions.len(),
+            target.as_str()
+        );
+
+        let requests =
+            accessions
+                .chunks(opts.batch_size.max(1))
+                .map(|batch| async move {
+                    let mappings = map_batch(fetcher, target, batch).await?;
+                    Ok::<_, IdMapError>((batch, mappings))
+                });
+
+        block_on(for_each_concurrent(
+            opts.concurrency,
+            requests,
+            |(batch, mappings)| {
+                store_mappings(connection, target, batch, &mappings)?;
+                stored += mappings.len();
+
+                info!("Stored {stored} mappings");
+                Ok(())
+            },
+        ))??;
+    }
+
+    Ok(stored)
+}
+
+// =========================================================
+// Output
+// =========================================================
+
+impl Row for IdMapping {
+    const COLUMNS: &'static [&'static str] =
+        &["entry", "database", "identifier"];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.entry.clone(),
+            self.database.clone(),
+            self.identifier.clone(),
+        ]
+    }
+}
+
+/// Write the stored mappings to `targets`, returns their number.
+pub fn write_mappings(
+    connection: &mut SqliteConnection,
+    targets: &[MappingTarget],
+    format: OutputFormat,
+    out: impl Write,
+) -> Result<usize, IdMapError> {
+    let databases: Vec<&str> = targets.iter().map(|t| t.as_str()).collect();
+    let mappings: Vec<IdMapping> = uniprot_id_mappings::table
+        .filter(uniprot_id_mappings::database.eq_any(&databases))
+        .select(IdMapping::as_select())
+        .order((
+            uniprot_id_mappings::entry,
+            uniprot_id_mappings::database,
+            uniprot_id_mappings::identifier,
+        ))
+        .load(connection)?;
+
+    write_rows(&mappings, format, out)?;
+    Ok(mappings.len())
+}
@@ -0,0 +1,268 @@
+/// UniProt keywords, parsed from keywlist.txt, and the keywords of entries.
+///
+/// keywlist.txt only defines the controlled vocabulary, the keywords given
+/// to each stored entry come from the UniProtKB REST API.
+use diesel::dsl::{exists, not};
+use diesel::prelude::*;
+use diesel::upsert::excluded;
+use log::{info, warn};
+use reqwest::Client;
+use std::path::Path;
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::schema::*;
+use crate::uniprot::db::batch_rows;
+use crate::uniprot::download::{download_text, DownloadError, DownloadOptions};
+use crate::uniprot::enrich::{fetch_entries, UNIPROTKB_REST_URL};
+use crate::uniprot::fetch::{block_on, for_each_concurrent, Fetcher};
+use crate::uniprot::models::*;
+use crate::uniprot::similar::read_lines;
+
+#[derive(Error, Debug)]
+pub enum KeywordError {
+    #[error("Couldn't read the input: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("Couldn't download: {0}")]
+    DownloadError(#[from] DownloadError),
+
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] diesel::result::Error),
+
+    #[error("No keyword found in keywlist.txt")]
+    NoKeyword,
+}
+
+// ---------- Options ----------
+
+#[derive(Debug, Clone)]
+pub struct EntryKeywordsOptions {
+    pub batch_size: usize,
+    // Requests in flight at once
+    pub concurrency: usize,
+    // Minimum delay between two requests
+    pub interval: Duration,
+    pub retries: u32,
+    // Entries with keywords already are requested again
+    pub all: bool,
+}
+
+impl Default for EntryKeywordsOptions {
+    fn default() -> Self {
+        EntryKeywordsOptions {
+            batch_size: 100,
+            concurrency: 4,
+            interval: Duration::from_millis(500),
+            retries: 3,
+            all: false,
+        }
+    }
+}
+
+// =========================================================
+// Parsing
+// =========================================================
+
+// Records are blocks of `XX   value` lines ended by `//`. Keywords start
+// with an ID line, the categories they belong to with an IC line.
+fn parse_keywlist(
+    lines: &[String],
+) -> Result<Vec<UniprotKeyword>, KeywordError> {
+    let mut keywords: Vec<UniprotKeyword> = Vec::new();
+
+    let mut name: Option<String> = None;
+    let mut accession: Option<String> = None;
+    let mut category: Option<String> = None;
+    let mut definition: Vec<String> = Vec::new();
+
+    for line in lines {
+        let (code, value) = match line.split_at_checked(2) {
+            Some((code, value)) => (code, value.trim()),
+            None => continue,
+        };
+
+        match code {
+            "ID" => name = Some(value.trim_end_matches('.').to_string()),
+            "AC" => accession = Some(value.to_string()),
+            "CA" => category = Some(value.trim_end_matches('.').to_string()),
+            "DE" => definition.push(value.to_string()),
+            "//" => {
+                if let (Some(name), Some(accession)) =
+                    (name.take(), accession.take())
+                {
+                    keywords.push(UniprotKeyword {
+                        accession,
+                        name,
+                        category: category.take(),
+                        definition: Some(definition.join(" "))
+                            .filter(|d| !d.is_empty()),
+                    });
+                }
+                category = None;
+                definition.clear();
+            }
+            _ => {}
+        }
+    }
+
+    if keywords.is_empty() {
+        return Err(KeywordError::NoKeyword);
+    }
+
+    Ok(keywords)
+}
+
+pub fn get_keywords(
+    url: &str,
+    opts: &DownloadOptions,
+) -> Result<Vec<UniprotKeyword>, KeywordError> {
+    let text = download_text(url, opts)?;
+    let lines: Vec<String> = text.lines().map(|l| l.to_string()).collect();
+    parse_keywlist(&lines)
+}
+
+pub fn read_keywords(path: &Path) -> Result<Vec<UniprotKeyword>, KeywordError> {
+    parse_keywlist(&read_lines(path)?)
+}
+
+pub fn insert_keywords(
+    keywords: &[UniprotKeyword],
+    connection: &mut SqliteConnection,
+) -> Result<(), diesel::result::Error> {
+    info!("Inserting {} keywords", keywords.len());
+
+    connection.transaction(|connection| {
+        for chunk in keywords.chunks(batch_rows(4)) {
+            diesel::insert_into(uniprot_keywords::table)
+                .values(chunk)
+                .on_conflict(uniprot_keywords::accession)
+                .do_update()
+                .set((
+                    uniprot_keywords::name.eq(excluded(uniprot_keywords::name)),
+                    uniprot_keywords::category
+                        .eq(excluded(uniprot_keywords::category)),
+                    uniprot_keywords::definition
+                        .eq(excluded(uniprot_keywords::definition)),
+                ))
+                .execute(connection)?;
+        }
+        Ok(())
+    })
+}
+
+// =========================================================
+// Keywords of the entries
+// =========================================================
+
+fn pending_accessions(
+    connection: &mut SqliteConnection,
+    all: bool,
+) -> Result<Vec<String>, diesel::result::Error> {
+    let mut query = uniprot_entries::table
+        .select(uniprot_entries::accession_number)
+        .order(uniprot_entries::accession_number)
+        .into_boxed();
+
+    if !all {
+        query = query.filter(not(exists(
+            uniprot_entry_keywords::table.filter(
+                uniprot_entry_keywords::entry
+                    .eq(uniprot_entries::accession_number),
+            ),
+        )));
+    }
+
+    query.load(connection)
+}
+
+// Replaces the keywords of the entries of the batch
+fn store_entry_keywords(
+    connection: &mut SqliteConnection,
+    accessions: &[String],
+    links: &[EntryKeyword],
+) -> Result<(), diesel::result::Error> {
+    connection.transaction(|connection| {
+        diesel::delete(
+            uniprot_entry_keywords::table
+                .filter(uniprot_entry_keywords::entry.eq_any(accessions)),
+        )
+        .execute(connection)?;
+
+        for chunk in links.chunks(batch_rows(2)) {
+            diesel::insert_into(uniprot_entry_keywords::table)
+                .values(chunk)
+                .on_conflict_do_nothing()
+                .execute(connection)?;
+        }
+        Ok(())
+    })
+}
+
+/// Download the keywords of the stored entries, returns the number of
+/// entry keyword links stored.
+pub fn fetch_entry_keywords(
+    connection: &mut SqliteConnection,
+    opts: &EntryKeywordsOptions,
+) -> Result<usize, KeywordError> {
+    let accessions = pending_accessions(connection, opts.all)?;
+    info!("Fetching the keywords of {} entries", accessions.len());
+
+    let client =
+        Client::builder()
+            .build()
+            .map_err(|source| DownloadError::Request {
+                url: UNIPROTKB_REST_URL.to_string(),
+                source,
+            })?;
+
+    let fetcher = &Fetcher::new(client, opts.interval, opts.retries);
+    let requests =
+        accessions
+            .chunks(opts.batch_size.max(1))
+            .map(|batch| async move {
+                let entries = fetcher
+                    .run(|| {
+                        fetch_entries(
+                            fetcher.client(),
+                            batch,
+                            "accession,keyword",
+                        )
+                    })
+                    .await?;
+                Ok::<_, KeywordError>((batch, entries))
+            });
+
+    let mut stored = 0;
+    block_on(for_each_concurrent(
+        opts.concurrency,
+        requests,
+        |(batch, entries)| {
+            if entries.len() < batch.len() {
+                warn!(
+                    "UniProtKB returned {} of {} entries",
+                    entries.len(),
+                    batch.len()
+                );
+            }
+
+            let links: Vec<EntryKeyword> = entries
+                .iter()
+                .flat_map(|entry| {
+                    entry.keywords.iter().map(|keyword| EntryKeyword {
+                        entry: entry.primary_accession.clone(),
+                        keyword: keyword.id.clone(),
+                    })
+                })
+                .collect();
+
+            store_entry_keywords(connection, batch, &links)?;
+            stored += links.len();
+
+            info!("Stored {stored} entry keywords");
+            Ok(())
+        },
+    ))??;
+
+    Ok(stored)
+}
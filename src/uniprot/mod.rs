@@ -1,2 +1,31 @@
+pub mod cache;
+pub mod config;
+pub mod db;
+pub mod download;
+pub mod enrich;
+pub mod enzyme;
+#[cfg(feature = "parquet")]
+pub mod export;
+pub mod family;
+pub mod fetch;
+pub mod go;
+pub mod graph;
+pub mod history;
+pub mod idmap;
+pub mod keywords;
 pub mod models;
+pub mod orthologs;
+pub mod output;
+pub mod proteomes;
+pub mod query;
+pub mod representatives;
+pub mod retired;
+pub mod seqsearch;
+pub mod sequences;
+pub mod server;
 pub mod similar;
+#[cfg(feature = "parquet")]
+pub mod stats;
+pub mod sync;
+pub mod taxonomy;
+pub mod variants;
@@ -1,6 +1,7 @@
 use diesel::prelude::*;
+use serde::Serialize;
 
-#[derive(Queryable, Selectable, Insertable, Clone)]
+#[derive(Queryable, Selectable, Insertable, Clone, Debug, Serialize)]
 #[diesel(table_name = crate::schema::uniprot_entries)]
 #[diesel(check_for_backend(diesel::sqlite::Sqlite))]
 pub struct UniprotEntry {
@@ -8,6 +9,16 @@ pub struct UniprotEntry {
     pub accession_number: String,
     pub mass: Option<i32>,
     pub seq_length: Option<i32>,
+    pub protein_name: Option<String>,
+    pub gene_name: Option<String>,
+    pub taxon: Option<String>,
+    // Retired by UniProt, see `uniprot fetch-retired`
+    pub obsolete: bool,
+    pub replaced_by: Option<String>,
+    // Import run that last changed the entry
+    pub import_run: Option<i32>,
+    // Swiss-Prot or TrEMBL, known once enriched
+    pub reviewed: Option<bool>,
 }
 
 #[derive(Queryable, Selectable, Insertable, Clone)]
@@ -17,10 +28,182 @@ pub struct UniprotFamily {
     pub name: String,
 }
 
+#[derive(Queryable, Selectable, Insertable, Clone, Debug, Serialize)]
+#[diesel(table_name = crate::schema::family_aliases)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct FamilyAlias {
+    pub alias: String,
+    pub family: String,
+    // rename or merge
+    pub kind: String,
+    pub overlap: f32,
+    pub release_version: Option<String>,
+}
+
+#[derive(Queryable, Selectable, Insertable, Clone)]
+#[diesel(table_name = crate::schema::uniprot_taxa)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct UniprotTaxon {
+    pub code: String,
+    pub taxid: i32,
+    pub scientific_name: String,
+    pub common_name: Option<String>,
+    pub kingdom: String,
+}
+
+#[derive(Queryable, Selectable, Insertable, Clone)]
+#[diesel(table_name = crate::schema::uniprot_enzymes)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct UniprotEnzyme {
+    pub ec_number: String,
+    pub name: String,
+    pub alternative_names: Option<String>,
+}
+
+#[derive(Queryable, Selectable, Insertable, Clone)]
+#[diesel(table_name = crate::schema::uniprot_entry_enzymes)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct EntryEnzyme {
+    pub entry: String,
+    pub ec_number: String,
+}
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = crate::schema::go_terms)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct GoTerm {
+    pub id: String,
+    pub name: String,
+    pub namespace: String,
+}
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = crate::schema::go_term_parents)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct GoTermParent {
+    pub term: String,
+    pub parent: String,
+}
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = crate::schema::uniprot_entry_go_terms)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct EntryGoTerm {
+    pub entry: String,
+    pub term: String,
+}
+
+#[derive(Queryable, Selectable, Insertable, Clone)]
+#[diesel(table_name = crate::schema::uniprot_proteomes)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct UniprotProteome {
+    pub id: String,
+    pub taxid: Option<i32>,
+    pub organism: Option<String>,
+    pub proteome_type: String,
+    pub reference: bool,
+}
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = crate::schema::uniprot_entry_proteomes)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct EntryProteome {
+    pub entry: String,
+    pub proteome: String,
+}
+
+#[derive(Queryable, Selectable, Insertable, Serialize)]
+#[diesel(table_name = crate::schema::uniprot_id_mappings)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct IdMapping {
+    pub entry: String,
+    pub database: String,
+    pub identifier: String,
+}
+
+#[derive(Queryable, Selectable, Insertable, Clone)]
+#[diesel(table_name = crate::schema::uniprot_keywords)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct UniprotKeyword {
+    pub accession: String,
+    pub name: String,
+    pub category: Option<String>,
+    pub definition: Option<String>,
+}
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = crate::schema::uniprot_entry_keywords)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct EntryKeyword {
+    pub entry: String,
+    pub keyword: String,
+}
+
+#[derive(Queryable, Selectable, Insertable, Clone)]
+#[diesel(table_name = crate::schema::uniprot_sequences)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct UniprotSequence {
+    pub accession_number: String,
+    pub header: String,
+    pub sequence: String,
+}
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = crate::schema::uniprot_xrefs)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct UniprotXref {
+    pub accession: String,
+    pub database: String,
+    pub xref_id: String,
+}
+
+#[derive(Queryable, Selectable, Insertable, Clone)]
+#[diesel(table_name = crate::schema::uniprot_variants)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct UniprotVariant {
+    pub accession: String,
+    pub variant: String,
+    pub aa_change: String,
+    pub category: String,
+    pub dbsnp: Option<String>,
+    pub disease: Option<String>,
+}
+
 #[derive(Queryable, Selectable, Insertable)]
 #[diesel(table_name = crate::schema::belongs_to_uniprot_sequence_similarity_family)]
 #[diesel(check_for_backend(diesel::sqlite::Sqlite))]
 pub struct BelongsToFamily {
     pub entry: String,
     pub family: String,
+    pub obsolete: bool,
+    // Import run that last added or obsoleted the membership
+    pub import_run: Option<i32>,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = crate::schema::import_runs)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct ImportRun {
+    pub id: i32,
+    pub source: String,
+    pub release_version: Option<String>,
+    pub imported_at: String,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::import_runs)]
+pub struct NewImportRun {
+    pub source: String,
+    pub release_version: Option<String>,
+}
+
+#[derive(Queryable, Selectable, Insertable, Clone, Debug)]
+#[diesel(table_name = crate::schema::uniprot_entry_history)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct EntryChange {
+    pub accession: String,
+    pub import_run: i32,
+    // inserted, renamed, joined, left, annotated or retired
+    pub change: String,
+    pub detail: Option<String>,
 }
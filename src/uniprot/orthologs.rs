@@ -0,0 +1,169 @@
+/// Ortholog pairs between two species, from the family memberships.
+///
+/// Members of one family in two species are taken as orthologs. A family
+/// with a single member in each species gives an unambiguous 1:1 pair,
+/// one with paralogs on either side gives every pair between them, marked
+/// as such so that they can be resolved by hand or by sequence identity.
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use crate::uniprot::models::*;
+use crate::uniprot::output::{write_rows, OutputError, OutputFormat, Row};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Relationship {
+    #[serde(rename = "1:1")]
+    OneToOne,
+    #[serde(rename = "1:many")]
+    OneToMany,
+    #[serde(rename = "many:1")]
+    ManyToOne,
+    #[serde(rename = "many:many")]
+    ManyToMany,
+}
+
+impl Relationship {
+    fn new(a: usize, b: usize) -> Self {
+        match (a, b) {
+            (1, 1) => Relationship::OneToOne,
+            (1, _) => Relationship::OneToMany,
+            (_, 1) => Relationship::ManyToOne,
+            _ => Relationship::ManyToMany,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Relationship::OneToOne => "1:1",
+            Relationship::OneToMany => "1:many",
+            Relationship::ManyToOne => "many:1",
+            Relationship::ManyToMany => "many:many",
+        }
+    }
+}
+
+/// Members of one family in species A and B.
+#[derive(Debug, Clone)]
+pub struct OrthologPair<'a> {
+    pub family: &'a str,
+    pub a: &'a UniprotEntry,
+    pub b: &'a UniprotEntry,
+    pub relationship: Relationship,
+}
+
+// =========================================================
+// Pairing
+// =========================================================
+
+/// Pair the members of every family of `entries`, as returned by
+/// `query_entries`, between the species of `species_a` and `species_b`.
+/// Families lacking either species give no pair.
+pub fn ortholog_pairs<'a>(
+    entries: &'a [(String, UniprotEntry)],
+    species_a: &[String],
+    species_b: &[String],
+) -> Vec<OrthologPair<'a>> {
+    let in_species = |entry: &UniprotEntry, species: &[String]| {
+        entry
+            .taxon
+            .as_ref()
+            .is_some_and(|taxon| species.contains(taxon))
+    };
+
+    let mut families: BTreeMap<&str, (Vec<&UniprotEntry>, Vec<&UniprotEntry>)> =
+        BTreeMap::new();
+    for (family, entry) in entries {
+        let members = families.entry(family).or_default();
+        if in_species(entry, species_a) {
+            members.0.push(entry);
+        } else if in_species(entry, species_b) {
+            members.1.push(entry);
+        }
+    }
+
+    let mut pairs = Vec::new();
+    for (family, (a, b)) in &families {
+        if a.is_empty() || b.is_empty() {
+            continue;
+        }
+
+        let relationship = Relationship::new(a.len(), b.len());
+        for &a in a {
+            for &b in b {
+                pairs.push(OrthologPair {
+                    family,
+                    a,
+                    b,
+                    relationship,
+                });
+            }
+        }
+    }
+
+    pairs
+}
+
+// =========================================================
+// Output
+// =========================================================
+
+#[derive(Debug, Serialize)]
+struct OrthologRow<'a> {
+    family: &'a str,
+    accession_a: &'a str,
+    entry_name_a: &'a str,
+    gene_name_a: Option<&'a str>,
+    accession_b: &'a str,
+    entry_name_b: &'a str,
+    gene_name_b: Option<&'a str>,
+    relationship: Relationship,
+}
+
+impl Row for OrthologRow<'_> {
+    const COLUMNS: &'static [&'static str] = &[
+        "family",
+        "accession_a",
+        "entry_name_a",
+        "gene_name_a",
+        "accession_b",
+        "entry_name_b",
+        "gene_name_b",
+        "relationship",
+    ];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.family.to_string(),
+            self.accession_a.to_string(),
+            self.entry_name_a.to_string(),
+            self.gene_name_a.unwrap_or("").to_string(),
+            self.accession_b.to_string(),
+            self.entry_name_b.to_string(),
+            self.gene_name_b.unwrap_or("").to_string(),
+            self.relationship.as_str().to_string(),
+        ]
+    }
+}
+
+pub fn write_ortholog_pairs(
+    pairs: &[OrthologPair],
+    format: OutputFormat,
+    out: impl Write,
+) -> Result<(), OutputError> {
+    let rows: Vec<OrthologRow> = pairs
+        .iter()
+        .map(|pair| OrthologRow {
+            family: pair.family,
+            accession_a: &pair.a.accession_number,
+            entry_name_a: &pair.a.entry_name,
+            gene_name_a: pair.a.gene_name.as_deref(),
+            accession_b: &pair.b.accession_number,
+            entry_name_b: &pair.b.entry_name,
+            gene_name_b: pair.b.gene_name.as_deref(),
+            relationship: pair.relationship,
+        })
+        .collect();
+
+    write_rows(&rows, format, out)
+}
@@ -0,0 +1,124 @@
+/// Rendering of the rows listed by the UniProt commands.
+///
+/// Every command listing rows writes them through `write_rows`, so that
+/// they all offer the same formats: aligned columns to read, TSV or CSV for
+/// spreadsheets, and JSON or NDJSON, one object per line, for jq and
+/// notebooks.
+use clap::ValueEnum;
+use serde::Serialize;
+use std::io::{self, Write};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum OutputError {
+    #[error("Couldn't write the output: {0}")]
+    IoError(#[from] io::Error),
+
+    #[error("Couldn't write the output: {0}")]
+    CsvError(#[from] csv::Error),
+
+    #[error("Couldn't serialize the output: {0}")]
+    JsonError(#[from] serde_json::Error),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Aligned columns
+    #[default]
+    Table,
+    /// Tab separated values
+    Tsv,
+    Csv,
+    /// Array of objects
+    Json,
+    /// One object per line
+    Ndjson,
+}
+
+/// Row of a listing, an object of the JSON formats.
+pub trait Row: Serialize {
+    const COLUMNS: &'static [&'static str];
+
+    // Cells of the text formats, in the order of COLUMNS
+    fn fields(&self) -> Vec<String>;
+}
+
+/// Write the rows of `fields` as columns aligned on their widest cell.
+pub(crate) fn write_table<R: AsRef<[String]>>(
+    columns: &[&str],
+    fields: &[R],
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let mut widths: Vec<usize> = columns.iter().map(|c| c.len()).collect();
+    for row in fields {
+        let row = row.as_ref();
+        for (width, field) in widths.iter_mut().zip(row) {
+            *width = (*width).max(field.chars().count());
+        }
+    }
+
+    let line = |cells: &[&str]| -> String {
+        cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, &width)| format!("{cell:<width$}"))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+
+    writeln!(out, "{}", line(columns))?;
+    for row in fields {
+        let cells: Vec<&str> =
+            row.as_ref().iter().map(|s| s.as_str()).collect();
+        writeln!(out, "{}", line(&cells))?;
+    }
+
+    Ok(())
+}
+
+fn write_delimited<R: Row>(
+    rows: &[R],
+    delimiter: u8,
+    out: impl Write,
+) -> Result<(), csv::Error> {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(out);
+    writer.write_record(R::COLUMNS)?;
+    for row in rows {
+        writer.write_record(row.fields())?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn write_rows<R: Row>(
+    rows: &[R],
+    format: OutputFormat,
+    mut out: impl Write,
+) -> Result<(), OutputError> {
+    match format {
+        OutputFormat::Table => {
+            let fields: Vec<Vec<String>> =
+                rows.iter().map(|r| r.fields()).collect();
+            write_table(R::COLUMNS, &fields, &mut out)?
+        }
+        OutputFormat::Tsv => write_delimited(rows, b'\t', &mut out)?,
+        OutputFormat::Csv => write_delimited(rows, b',', &mut out)?,
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut out, rows)?;
+            writeln!(out)?;
+        }
+        OutputFormat::Ndjson => {
+            for row in rows {
+                serde_json::to_writer(&mut out, row)?;
+                writeln!(out)?;
+            }
+        }
+    }
+
+    out.flush()?;
+    Ok(())
+}
@@ -0,0 +1,306 @@
+/// Proteomes of the stored entries, from the UniProt REST API.
+///
+/// The proteomes of each entry come from its Proteomes cross-references,
+/// then every proteome linked is described by the proteomes endpoint,
+/// telling reference proteomes apart. Batches are stored as they come, so
+/// an interrupted run resumes where it stopped.
+use diesel::dsl::{exists, not};
+use diesel::prelude::*;
+use log::{info, warn};
+use reqwest::Client;
+use serde::Deserialize;
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::schema::*;
+use crate::uniprot::db::batch_rows;
+use crate::uniprot::download::DownloadError;
+use crate::uniprot::enrich::fetch_entries;
+use crate::uniprot::fetch::{block_on, for_each_concurrent, Fetcher};
+use crate::uniprot::models::*;
+
+const PROTEOMES_REST_URL: &str = "https://rest.uniprot.org/proteomes";
+
+#[derive(Error, Debug)]
+pub enum ProteomeError {
+    #[error("Couldn't download: {0}")]
+    DownloadError(#[from] DownloadError),
+
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] diesel::result::Error),
+}
+
+// ---------- Options ----------
+
+#[derive(Debug, Clone)]
+pub struct ProteomeOptions {
+    pub batch_size: usize,
+    // Requests in flight at once
+    pub concurrency: usize,
+    // Minimum delay between two requests
+    pub interval: Duration,
+    pub retries: u32,
+    // Entries linked to proteomes already are requested again
+    pub all: bool,
+}
+
+impl Default for ProteomeOptions {
+    fn default() -> Self {
+        ProteomeOptions {
+            batch_size: 100,
+            concurrency: 4,
+            interval: Duration::from_millis(500),
+            retries: 3,
+            all: false,
+        }
+    }
+}
+
+// ---------- REST API ----------
+
+#[derive(Debug, Deserialize)]
+struct ApiProteomes {
+    results: Vec<ApiProteome>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiProteome {
+    id: String,
+    taxonomy: Option<ApiTaxonomy>,
+    proteome_type: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiTaxonomy {
+    scientific_name: Option<String>,
+    taxon_id: Option<i32>,
+}
+
+impl From<ApiProteome> for UniprotProteome {
+    fn from(proteome: ApiProteome) -> Self {
+        let (organism, taxid) = match proteome.taxonomy {
+            Some(taxonomy) => (taxonomy.scientific_name, taxonomy.taxon_id),
+            None => (None, None),
+        };
+
+        UniprotProteome {
+            // Reference and representative proteome, Reference proteome...
+            reference: proteome.proteome_type.starts_with("Reference"),
+            id: proteome.id,
+            taxid,
+            organism,
+            proteome_type: proteome.proteome_type,
+        }
+    }
+}
+
+async fn fetch_proteome_batch(
+    client: &Client,
+    ids: &[String],
+) -> Result<Vec<UniprotProteome>, DownloadError> {
+    let url = format!("{PROTEOMES_REST_URL}/search");
+    let request_error = |source| DownloadError::from_reqwest(&url, source);
+
+    let query = ids
+        .iter()
+        .map(|id| format!("upid:{id}"))
+        .collect::<Vec<_>>()
+        .join(" OR ");
+    let response = client
+        .get(&url)
+        .query(&[
+            ("query", query.as_str()),
+            ("fields", "upid,organism,organism_id,proteome_type"),
+            ("size", ids.len().to_string().as_str()),
+            ("format", "json"),
+        ])
+        .send()
+        .await
+        .map_err(request_error)?;
+
+    let status = response.status();
+    if !status.is_success() {
+        return Err(DownloadError::Status {
+            url: url.clone(),
+            status,
+        });
+    }
+
+    let proteomes: ApiProteomes =
+        response.json().await.map_err(request_error)?;
+    Ok(proteomes.results.into_iter().map(Into::into).collect())
+}
+
+// =========================================================
+// Database
+// =========================================================
+
+fn pending_accessions(
+    connection: &mut SqliteConnection,
+    all: bool,
+) -> Result<Vec<String>, diesel::result::Error> {
+    let mut query = uniprot_entries::table
+        .select(uniprot_entries::accession_number)
+        .order(uniprot_entries::accession_number)
+        .into_boxed();
+
+    if !all {
+        query = query.filter(not(exists(
+            uniprot_entry_proteomes::table.filter(
+                uniprot_entry_proteomes::entry
+                    .eq(uniprot_entries::accession_number),
+            ),
+        )));
+    }
+
+    query.load(connection)
+}
+
+// Proteomes linked to entries but not described yet
+fn pending_proteomes(
+    connection: &mut SqliteConnection,
+) -> Result<Vec<String>, diesel::result::Error> {
+    uniprot_entry_proteomes::table
+        .filter(not(exists(uniprot_proteomes::table.filter(
+            uniprot_proteomes::id.eq(uniprot_entry_proteomes::proteome),
+        ))))
+        .select(uniprot_entry_proteomes::proteome)
+        .distinct()
+        .order(uniprot_entry_proteomes::proteome)
+        .load(connection)
+}
+
+// Replaces the links of the entries of the batch
+fn store_links(
+    connection: &mut SqliteConnection,
+    accessions: &[String],
+    links: &[EntryProteome],
+) -> Result<(), diesel::result::Error> {
+    connection.transaction(|connection| {
+        diesel::delete(
+            uniprot_entry_proteomes::table
+                .filter(uniprot_entry_proteomes::entry.eq_any(accessions)),
+        )
+        .execute(connection)?;
+
+        for chunk in links.chunks(batch_rows(2)) {
+            diesel::insert_into(uniprot_entry_proteomes::table)
+                .values(chunk)
+                .on_conflict_do_nothing()
+                .execute(connection)?;
+        }
+        Ok(())
+    })
+}
+
+fn store_proteomes(
+    connection: &mut SqliteConnection,
+    proteomes: &[UniprotProteome],
+) -> Result<(), diesel::result::Error> {
+    for chunk in proteomes.chunks(batch_rows(5)) {
+        diesel::insert_into(uniprot_proteomes::table)
+            .values(chunk)
+            .on_conflict_do_nothing()
+            .execute(connection)?;
+    }
+    Ok(())
+}
+
+// =========================================================
+// Fetching
+// =========================================================
+
+/// Link the stored entries to their proteomes and describe those,
+/// returns the number of links and proteomes stored.
+pub fn fetch_proteomes(
+    connection: &mut SqliteConnection,
+    opts: &ProteomeOptions,
+) -> Result<(usize, usize), ProteomeError> {
+    let client =
+        Client::builder()
+            .build()
+            .map_err(|source| DownloadError::Request {
+                url: PROTEOMES_REST_URL.to_string(),
+                source,
+            })?;
+    let fetcher = &Fetcher::new(client, opts.interval, opts.retries);
+
+    let accessions = pending_accessions(connection, opts.all)?;
+    info!("Fetching the proteomes of {} entries", accessions.len());
+
+    let requests =
+        accessions
+            .chunks(opts.batch_size.max(1))
+            .map(|batch| async move {
+                let entries = fetcher
+                    .run(|| {
+                        fetch_entries(
+                            fetcher.client(),
+                            batch,
+                            "accession,xref_proteomes",
+                        )
+                    })
+                    .await?;
+                Ok::<_, ProteomeError>((batch, entries))
+            });
+
+    let mut linked = 0;
+    block_on(for_each_concurrent(
+        opts.concurrency,
+        requests,
+        |(batch, entries)| {
+            let links: Vec<EntryProteome> = entries
+                .iter()
+                .flat_map(|entry| {
+                    entry
+                        .cross_references
+                        .iter()
+                        .filter(|reference| reference.database == "Proteomes")
+                        .map(|reference| EntryProteome {
+                            entry: entry.primary_accession.clone(),
+                            proteome: reference.id.clone(),
+                        })
+                })
+                .collect();
+
+            store_links(connection, batch, &links)?;
+            linked += links.len();
+
+            info!("Stored {linked} proteome links");
+            Ok(())
+        },
+    ))??;
+
+    let ids = pending_proteomes(connection)?;
+    info!("Describing {} proteomes", ids.len());
+
+    let requests = ids.chunks(opts.batch_size.max(1)).map(|batch| async move {
+        let proteomes = fetcher
+            .run(|| fetch_proteome_batch(fetcher.client(), batch))
+            .await?;
+        Ok::<_, ProteomeError>((batch, proteomes))
+    });
+
+    let mut described = 0;
+    block_on(for_each_concurrent(
+        opts.concurrency,
+        requests,
+        |(batch, proteomes)| {
+            if proteomes.len() < batch.len() {
+                warn!(
+                    "The proteomes endpoint returned {} of {} proteomes",
+                    proteomes.len(),
+                    batch.len()
+                );
+            }
+
+            store_proteomes(connection, &proteomes)?;
+            described += proteomes.len();
+            Ok(())
+        },
+    ))??;
+
+    Ok((linked, described))
+}
@@ -0,0 +1,221 @@
+/// Lookup of the stored entries and the families they belong to.
+use diesel::prelude::*;
+use serde::Serialize;
+use std::io::Write;
+
+use crate::schema::*;
+use crate::uniprot::enzyme::ec_class_pattern;
+use crate::uniprot::models::*;
+use crate::uniprot::output::{write_rows, OutputError, OutputFormat, Row};
+
+#[derive(Debug, Clone, Default)]
+pub struct EntryFilter {
+    // Substring of the family name
+    pub family: Option<String>,
+    // Accession numbers, any when empty
+    pub accessions: Vec<String>,
+    // Mnemonic codes of the species, any when empty
+    pub species: Vec<String>,
+    // Keyword names or accessions, entries with any of them when set
+    pub keywords: Vec<String>,
+    // EC number or class, such as 2.7.11.1 or 2.7.*
+    pub ec: Option<String>,
+    // GO identifiers, entries annotated with any of them when set
+    pub go_terms: Vec<String>,
+    // Cross-referenced databases, such as PDB, entries with a
+    // cross-reference to each of them when set
+    pub xref_databases: Vec<String>,
+    // Proteome identifiers, entries in any of them when set
+    pub proteomes: Vec<String>,
+    // Entries in a reference proteome only
+    pub reference_proteome: bool,
+    // Inclusive ranges, entries without the value are left out when set
+    pub mass: (Option<i32>, Option<i32>),
+    pub seq_length: (Option<i32>, Option<i32>),
+}
+
+/// Entries matching `filter`, once per family they belong to.
+pub fn query_entries(
+    connection: &mut SqliteConnection,
+    filter: &EntryFilter,
+) -> Result<Vec<(String, UniprotEntry)>, diesel::result::Error> {
+    let mut query = belongs_to_uniprot_sequence_similarity_family::table
+        .inner_join(uniprot_entries::table)
+        .filter(
+            belongs_to_uniprot_sequence_similarity_family::obsolete.eq(false),
+        )
+        .select((
+            belongs_to_uniprot_sequence_similarity_family::family,
+            UniprotEntry::as_select(),
+        ))
+        .order((
+            belongs_to_uniprot_sequence_similarity_family::family,
+            uniprot_entries::accession_number,
+        ))
+        .into_boxed();
+
+    // Former names of the families match too
+    if let Some(family) = &filter.family {
+        let pattern = format!("%{family}%");
+        query = query.filter(
+            belongs_to_uniprot_sequence_similarity_family::family
+                .like(pattern.clone())
+                .or(belongs_to_uniprot_sequence_similarity_family::family
+                    .eq_any(
+                        family_aliases::table
+                            .filter(family_aliases::alias.like(pattern))
+                            .select(family_aliases::family),
+                    )),
+        );
+    }
+    if !filter.accessions.is_empty() {
+        query = query.filter(
+            uniprot_entries::accession_number.eq_any(&filter.accessions),
+        );
+    }
+    if !filter.species.is_empty() {
+        query = query.filter(uniprot_entries::taxon.eq_any(&filter.species));
+    }
+    if !filter.keywords.is_empty() {
+        query = query.filter(
+            uniprot_entries::accession_number.eq_any(
+                uniprot_entry_keywords::table
+                    .inner_join(uniprot_keywords::table)
+                    .filter(uniprot_keywords::name.eq_any(&filter.keywords).or(
+                        uniprot_keywords::accession.eq_any(&filter.keywords),
+                    ))
+                    .select(uniprot_entry_keywords::entry),
+            ),
+        );
+    }
+    if let Some(ec) = &filter.ec {
+        let enzymes = uniprot_entry_enzymes::table
+            .select(uniprot_entry_enzymes::entry)
+            .into_boxed();
+        let enzymes = match ec_class_pattern(ec) {
+            Some(pattern) => {
+                enzymes.filter(uniprot_entry_enzymes::ec_number.like(pattern))
+            }
+            None => enzymes.filter(uniprot_entry_enzymes::ec_number.eq(ec)),
+        };
+        query = query.filter(uniprot_entries::accession_number.eq_any(enzymes));
+    }
+    if !filter.go_terms.is_empty() {
+        query = query.filter(
+            uniprot_entries::accession_number.eq_any(
+                uniprot_entry_go_terms::table
+                    .filter(
+                        uniprot_entry_go_terms::term.eq_any(&filter.go_terms),
+                    )
+                    .select(uniprot_entry_go_terms::entry),
+            ),
+        );
+    }
+    if !filter.proteomes.is_empty() {
+        query = query.filter(
+            uniprot_entries::accession_number.eq_any(
+                uniprot_entry_proteomes::table
+                    .filter(
+                        uniprot_entry_proteomes::proteome
+                            .eq_any(&filter.proteomes),
+                    )
+                    .select(uniprot_entry_proteomes::entry),
+            ),
+        );
+    }
+    if filter.reference_proteome {
+        query = query.filter(
+            uniprot_entries::accession_number.eq_any(
+                uniprot_entry_proteomes::table
+                    .inner_join(uniprot_proteomes::table)
+                    .filter(uniprot_proteomes::reference.eq(true))
+                    .select(uniprot_entry_proteomes::entry),
+            ),
+        );
+    }
+    for database in &filter.xref_databases {
+        query = query.filter(
+            uniprot_entries::accession_number.eq_any(
+                uniprot_xrefs::table
+                    .filter(uniprot_xrefs::database.eq(database.clone()))
+                    .select(uniprot_xrefs::accession),
+            ),
+        );
+    }
+
+    if let Some(min) = filter.mass.0 {
+        query = query.filter(uniprot_entries::mass.ge(min));
+    }
+    if let Some(max) = filter.mass.1 {
+        query = query.filter(uniprot_entries::mass.le(max));
+    }
+    if let Some(min) = filter.seq_length.0 {
+        query = query.filter(uniprot_entries::seq_length.ge(min));
+    }
+    if let Some(max) = filter.seq_length.1 {
+        query = query.filter(uniprot_entries::seq_length.le(max));
+    }
+
+    query.load(connection)
+}
+
+// =========================================================
+// Output
+// =========================================================
+
+#[derive(Debug, Serialize)]
+struct EntryRow<'a> {
+    family: &'a str,
+    accession_number: &'a str,
+    entry_name: &'a str,
+    mass: Option<i32>,
+    seq_length: Option<i32>,
+    protein_name: Option<&'a str>,
+    gene_name: Option<&'a str>,
+}
+
+impl Row for EntryRow<'_> {
+    const COLUMNS: &'static [&'static str] = &[
+        "family",
+        "accession_number",
+        "entry_name",
+        "mass",
+        "seq_length",
+        "protein_name",
+        "gene_name",
+    ];
+
+    fn fields(&self) -> Vec<String> {
+        let optional = |v: Option<i32>| v.map(|v| v.to_string());
+        vec![
+            self.family.to_string(),
+            self.accession_number.to_string(),
+            self.entry_name.to_string(),
+            optional(self.mass).unwrap_or_default(),
+            optional(self.seq_length).unwrap_or_default(),
+            self.protein_name.unwrap_or("").to_string(),
+            self.gene_name.unwrap_or("").to_string(),
+        ]
+    }
+}
+
+pub fn write_entries(
+    entries: &[(String, UniprotEntry)],
+    format: OutputFormat,
+    out: impl Write,
+) -> Result<(), OutputError> {
+    let rows: Vec<EntryRow> = entries
+        .iter()
+        .map(|(family, entry)| EntryRow {
+            family,
+            accession_number: &entry.accession_number,
+            entry_name: &entry.entry_name,
+            mass: entry.mass,
+            seq_length: entry.seq_length,
+            protein_name: entry.protein_name.as_deref(),
+            gene_name: entry.gene_name.as_deref(),
+        })
+        .collect();
+
+    write_rows(&rows, format, out)
+}
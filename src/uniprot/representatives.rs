@@ -0,0 +1,179 @@
+/// Selection of one representative entry per sequence similarity family.
+use clap::ValueEnum;
+use diesel::prelude::*;
+use log::info;
+use reqwest::blocking::get;
+use serde::Serialize;
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use crate::schema::*;
+use crate::uniprot::models::*;
+use crate::uniprot::output::{write_rows, OutputFormat, Row};
+
+const UNIPROTKB_REST_URL: &str = "https://rest.uniprot.org/uniprotkb";
+
+// ---------- Selection policies ----------
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Policy {
+    /// Longest sequence
+    Longest,
+    /// Most complete entry (mass and length known)
+    MostAnnotated,
+    /// Reviewed (Swiss-Prot) entries first, then longest
+    ReviewedFirst,
+}
+
+// =========================================================
+// Helper functions
+// =========================================================
+
+fn annotation_score(entry: &UniprotEntry) -> usize {
+    [entry.mass.is_some(), entry.seq_length.is_some()]
+        .iter()
+        .filter(|&&known| known)
+        .count()
+}
+
+fn pick<'a>(
+    members: &'a [UniprotEntry],
+    policy: Policy,
+) -> Option<&'a UniprotEntry> {
+    // Ties are broken on the accession number to keep the choice stable
+    let by_length = |e: &&UniprotEntry| {
+        (
+            e.seq_length.unwrap_or(0),
+            std::cmp::Reverse(e.accession_number.clone()),
+        )
+    };
+
+    match policy {
+        Policy::Longest => members.iter().max_by_key(by_length),
+        Policy::MostAnnotated => members.iter().max_by_key(|e| {
+            (
+                annotation_score(e),
+                std::cmp::Reverse(e.accession_number.clone()),
+            )
+        }),
+        // Entries never enriched rank with the unreviewed ones
+        Policy::ReviewedFirst => members
+            .iter()
+            .max_by_key(|e| (e.reviewed == Some(true), by_length(e))),
+    }
+}
+
+// =========================================================
+// Selection and export
+// =========================================================
+
+pub fn select_representatives(
+    connection: &mut SqliteConnection,
+    policy: Policy,
+) -> Result<Vec<(String, UniprotEntry)>, diesel::result::Error> {
+    let rows: Vec<(String, UniprotEntry)> =
+        belongs_to_uniprot_sequence_similarity_family::table
+            .inner_join(uniprot_entries::table)
+            .filter(
+                belongs_to_uniprot_sequence_similarity_family::obsolete
+                    .eq(false),
+            )
+            .select((
+                belongs_to_uniprot_sequence_similarity_family::family,
+                UniprotEntry::as_select(),
+            ))
+            .load(connection)?;
+
+    let mut families: BTreeMap<String, Vec<UniprotEntry>> = BTreeMap::new();
+    for (family, entry) in rows {
+        families.entry(family).or_default().push(entry);
+    }
+
+    let representatives: Vec<(String, UniprotEntry)> = families
+        .iter()
+        .filter_map(|(family, members)| {
+            pick(members, policy).map(|e| (family.clone(), e.clone()))
+        })
+        .collect();
+
+    info!(
+        "Selected {} representatives out of {} families",
+        representatives.len(),
+        families.len()
+    );
+
+    Ok(representatives)
+}
+
+#[derive(Debug, Serialize)]
+struct RepresentativeRow<'a> {
+    family: &'a str,
+    accession_number: &'a str,
+    entry_name: &'a str,
+    seq_length: Option<i32>,
+}
+
+impl Row for RepresentativeRow<'_> {
+    const COLUMNS: &'static [&'static str] =
+        &["family", "accession_number", "entry_name", "seq_length"];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.family.to_string(),
+            self.accession_number.to_string(),
+            self.entry_name.to_string(),
+            self.seq_length.map(|l| l.to_string()).unwrap_or_default(),
+        ]
+    }
+}
+
+pub fn write_representatives(
+    representatives: &[(String, UniprotEntry)],
+    format: OutputFormat,
+    path: impl AsRef<Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let rows: Vec<RepresentativeRow> = representatives
+        .iter()
+        .map(|(family, entry)| RepresentativeRow {
+            family,
+            accession_number: &entry.accession_number,
+            entry_name: &entry.entry_name,
+            seq_length: entry.seq_length,
+        })
+        .collect();
+
+    write_rows(&rows, format, BufWriter::new(File::create(path)?))?;
+    Ok(())
+}
+
+/// Download the canonical sequences of the representatives as FASTA.
+pub fn write_representatives_fasta(
+    representatives: &[(String, UniprotEntry)],
+    path: impl AsRef<Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    for (index, (_, entry)) in representatives.iter().enumerate() {
+        if index % 100 == 0 {
+            info!("Fetched {0}/{1}", index, representatives.len());
+        }
+
+        let url =
+            format!("{}/{}.fasta", UNIPROTKB_REST_URL, entry.accession_number);
+        let fasta = get(&url)
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.text())
+            .map_err(|e| {
+                format!("Couldn't fetch {}: {e}", entry.accession_number)
+            })?;
+        writer.write_all(fasta.as_bytes())?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
@@ -0,0 +1,341 @@
+/// Accessions retired by UniProt.
+///
+/// Deleted accessions are listed in delac_sp.txt for Swiss-Prot and
+/// delac_tr.txt for TrEMBL, accessions merged into another entry in
+/// sec_ac.txt as secondary accessions of their primary one. Stored entries
+/// found in either are flagged obsolete, with their replacement when
+/// merged, and can then be pruned with all the rows referring to them.
+use diesel::prelude::*;
+use log::info;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use thiserror::Error;
+
+use crate::schema::*;
+use crate::uaspire::reader::maybe_gunzip;
+use crate::uniprot::db::SQLITE_MAX_PARAMETERS;
+use crate::uniprot::download::DownloadError;
+use crate::uniprot::history::record_changes;
+use crate::uniprot::models::EntryChange;
+use crate::uniprot::output::{write_rows, OutputError, OutputFormat, Row};
+
+#[derive(Error, Debug)]
+pub enum RetiredError {
+    #[error("Couldn't read the input: {0}")]
+    IoError(#[from] io::Error),
+
+    #[error("Couldn't download: {0}")]
+    DownloadError(#[from] DownloadError),
+
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] diesel::result::Error),
+
+    #[error("Couldn't write the report: {0}")]
+    OutputError(#[from] OutputError),
+}
+
+/// Stored entry flagged obsolete, with the families it belongs to.
+#[derive(Debug, Clone, Serialize)]
+pub struct RetiredEntry {
+    pub accession: String,
+    pub entry_name: String,
+    pub replaced_by: Option<String>,
+    pub families: Vec<String>,
+}
+
+// =========================================================
+// Parsing
+// =========================================================
+
+/// Open a plain or gzipped list, `-` for stdin. delac_tr.txt is read
+/// this way, it is too large to download as text.
+pub fn open_list(path: &Path) -> io::Result<Box<dyn BufRead>> {
+    if path == Path::new("-") {
+        return Ok(Box::new(io::stdin().lock()));
+    }
+    Ok(Box::new(BufReader::new(maybe_gunzip(File::open(path)?)?)))
+}
+
+/// Accessions of a delac list found in `known`. Header and footer lines
+/// are skipped as they hold no stored accession.
+pub fn read_deleted(
+    reader: impl BufRead,
+    known: &HashSet<String>,
+) -> io::Result<Vec<String>> {
+    let mut deleted = Vec::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        let accession = line.trim();
+        if known.contains(accession) {
+            deleted.push(accession.to_string());
+        }
+    }
+
+    Ok(deleted)
+}
+
+/// Secondary accessions of sec_ac.txt found in `known`, with their primary
+/// accession. Header lines are skipped as above.
+pub fn read_merged(
+    reader: impl BufRead,
+    known: &HashSet<String>,
+) -> io::Result<Vec<(String, String)>> {
+    let mut merged = Vec::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        let mut fields = line.split_whitespace();
+        let (Some(secondary), Some(primary), None) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+
+        if known.contains(secondary) {
+            merged.push((secondary.to_string(), primary.to_string()));
+        }
+    }
+
+    Ok(merged)
+}
+
+// =========================================================
+// Database
+// =========================================================
+
+pub fn stored_accessions(
+    connection: &mut SqliteConnection,
+) -> Result<HashSet<String>, diesel::result::Error> {
+    Ok(uniprot_entries::table
+        .select(uniprot_entries::accession_number)
+        .load::<String>(connection)?
+        .into_iter()
+        .collect())
+}
+
+/// Flag the `deleted` and `merged` entries obsolete, returns their number.
+/// Those not retired yet, or merged into another entry since, are stamped
+/// with the import run `run`.
+pub fn mark_retired(
+    connection: &mut SqliteConnection,
+    deleted: &[String],
+    merged: &[(String, String)],
+    run: i32,
+) -> Result<usize, diesel::result::Error> {
+    info!(
+        "Flagging {} deleted and {} merged entries obsolete",
+        deleted.len(),
+        merged.len()
+    );
+
+    connection.transaction(|connection| {
+        // Replacement of the entries already retired
+        let retired: HashMap<String, Option<String>> = uniprot_entries::table
+            .filter(uniprot_entries::obsolete.eq(true))
+            .select((
+                uniprot_entries::accession_number,
+                uniprot_entries::replaced_by,
+            ))
+            .load::<(String, Option<String>)>(connection)?
+            .into_iter()
+            .collect();
+
+        let mut changes: Vec<EntryChange> = Vec::new();
+        for accession in deleted {
+            if !matches!(retired.get(accession), Some(None)) {
+                changes.push(EntryChange::new(
+                    accession,
+                    run,
+                    "retired",
+                    Some("deleted".to_string()),
+                ));
+            }
+        }
+        for (secondary, primary) in merged {
+            if retired.get(secondary).and_then(|r| r.as_ref()) != Some(primary)
+            {
+                changes.push(EntryChange::new(
+                    secondary,
+                    run,
+                    "retired",
+                    Some(format!("merged into {primary}")),
+                ));
+            }
+        }
+
+        let mut flagged = 0;
+
+        for chunk in deleted.chunks(SQLITE_MAX_PARAMETERS) {
+            flagged += diesel::update(
+                uniprot_entries::table
+                    .filter(uniprot_entries::accession_number.eq_any(chunk)),
+            )
+            .set((
+                uniprot_entries::obsolete.eq(true),
+                uniprot_entries::replaced_by.eq(None::<String>),
+            ))
+            .execute(connection)?;
+        }
+        for (secondary, primary) in merged {
+            flagged += diesel::update(uniprot_entries::table.find(secondary))
+                .set((
+                    uniprot_entries::obsolete.eq(true),
+                    uniprot_entries::replaced_by.eq(primary),
+                ))
+                .execute(connection)?;
+        }
+
+        let accessions: Vec<&str> =
+            changes.iter().map(|c| c.accession.as_str()).collect();
+        for chunk in accessions.chunks(SQLITE_MAX_PARAMETERS) {
+            diesel::update(
+                uniprot_entries::table
+                    .filter(uniprot_entries::accession_number.eq_any(chunk)),
+            )
+            .set(uniprot_entries::import_run.eq(run))
+            .execute(connection)?;
+        }
+        record_changes(connection, &changes)?;
+
+        Ok(flagged)
+    })
+}
+
+/// Entries flagged obsolete, by accession.
+pub fn retired_entries(
+    connection: &mut SqliteConnection,
+) -> Result<Vec<RetiredEntry>, diesel::result::Error> {
+    use crate::schema::belongs_to_uniprot_sequence_similarity_family as links;
+
+    let entries: Vec<(String, String, Option<String>)> = uniprot_entries::table
+        .filter(uniprot_entries::obsolete.eq(true))
+        .select((
+            uniprot_entries::accession_number,
+            uniprot_entries::entry_name,
+            uniprot_entries::replaced_by,
+        ))
+        .order(uniprot_entries::accession_number)
+        .load(connection)?;
+
+    let mut families: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let accessions: Vec<&str> = entries.iter().map(|e| e.0.as_str()).collect();
+    for chunk in accessions.chunks(SQLITE_MAX_PARAMETERS) {
+        let memberships: Vec<(String, String)> = links::table
+            .filter(links::entry.eq_any(chunk))
+            .select((links::entry, links::family))
+            .order((links::entry, links::family))
+            .load(connection)?;
+        for (entry, family) in memberships {
+            families.entry(entry).or_default().push(family);
+        }
+    }
+
+    Ok(entries
+        .into_iter()
+        .map(|(accession, entry_name, replaced_by)| RetiredEntry {
+            families: families.remove(&accession).unwrap_or_default(),
+            accession,
+            entry_name,
+            replaced_by,
+        })
+        .collect())
+}
+
+/// Delete the `retired` entries and every row referring to them.
+pub fn prune_entries(
+    connection: &mut SqliteConnection,
+    retired: &[RetiredEntry],
+) -> Result<usize, diesel::result::Error> {
+    use crate::schema::belongs_to_uniprot_sequence_similarity_family as links;
+
+    let accessions: Vec<&str> =
+        retired.iter().map(|e| e.accession.as_str()).collect();
+
+    connection.transaction(|connection| {
+        let mut pruned = 0;
+
+        for chunk in accessions.chunks(SQLITE_MAX_PARAMETERS) {
+            diesel::delete(links::table.filter(links::entry.eq_any(chunk)))
+                .execute(connection)?;
+            diesel::delete(
+                uniprot_entry_keywords::table
+                    .filter(uniprot_entry_keywords::entry.eq_any(chunk)),
+            )
+            .execute(connection)?;
+            diesel::delete(
+                uniprot_entry_enzymes::table
+                    .filter(uniprot_entry_enzymes::entry.eq_any(chunk)),
+            )
+            .execute(connection)?;
+            diesel::delete(
+                uniprot_entry_go_terms::table
+                    .filter(uniprot_entry_go_terms::entry.eq_any(chunk)),
+            )
+            .execute(connection)?;
+            diesel::delete(
+                uniprot_entry_proteomes::table
+                    .filter(uniprot_entry_proteomes::entry.eq_any(chunk)),
+            )
+            .execute(connection)?;
+            diesel::delete(
+                uniprot_id_mappings::table
+                    .filter(uniprot_id_mappings::entry.eq_any(chunk)),
+            )
+            .execute(connection)?;
+            diesel::delete(
+                uniprot_sequences::table
+                    .filter(uniprot_sequences::accession_number.eq_any(chunk)),
+            )
+            .execute(connection)?;
+            diesel::delete(
+                uniprot_xrefs::table
+                    .filter(uniprot_xrefs::accession.eq_any(chunk)),
+            )
+            .execute(connection)?;
+            diesel::delete(
+                uniprot_variants::table
+                    .filter(uniprot_variants::accession.eq_any(chunk)),
+            )
+            .execute(connection)?;
+
+            pruned += diesel::delete(
+                uniprot_entries::table
+                    .filter(uniprot_entries::accession_number.eq_any(chunk)),
+            )
+            .execute(connection)?;
+        }
+
+        Ok(pruned)
+    })
+}
+
+// =========================================================
+// Output
+// =========================================================
+
+// Families are separated by semicolons in the text formats
+impl Row for RetiredEntry {
+    const COLUMNS: &'static [&'static str] =
+        &["accession", "entry_name", "replaced_by", "families"];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.accession.clone(),
+            self.entry_name.clone(),
+            self.replaced_by.clone().unwrap_or_default(),
+            self.families.join(";"),
+        ]
+    }
+}
+
+pub fn write_report(
+    retired: &[RetiredEntry],
+    format: OutputFormat,
+    out: impl Write,
+) -> Result<(), OutputError> {
+    write_rows(retired, format, out)
+}
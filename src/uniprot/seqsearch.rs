@@ -0,0 +1,331 @@
+/// Search of the stored sequences closest to query sequences.
+///
+/// The stored sequences are indexed by their k-mers, exact words of k
+/// residues, and every stored sequence sharing enough k-mers with a query
+/// is a hit. The identity is then estimated on the diagonal holding most
+/// shared k-mers, without gaps, which is enough to tell the family a
+/// protein belongs to without running BLAST.
+use bio::io::fasta;
+use diesel::prelude::*;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::Path;
+use thiserror::Error;
+
+use crate::schema::*;
+use crate::uniprot::models::*;
+use crate::uniprot::output::{write_rows, OutputError, OutputFormat, Row};
+use crate::uniprot::retired::open_list;
+
+// Residues coded in k-mers, any other one breaks them
+const AMINO_ACIDS: &[u8; 20] = b"ACDEFGHIKLMNPQRSTVWY";
+// Bits per residue, 12 residues fill a u64
+const RESIDUE_BITS: usize = 5;
+pub const MAX_K: usize = 12;
+
+#[derive(Error, Debug)]
+pub enum SeqSearchError {
+    #[error("Couldn't read the queries: {0}")]
+    IoError(#[from] io::Error),
+
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] diesel::result::Error),
+
+    #[error("No sequence stored, run `uniprot fetch-sequences` first")]
+    NoSequence,
+}
+
+#[derive(Debug, Clone)]
+pub struct SeqSearchOptions {
+    // Distinct query k-mers a stored sequence must share to be a hit
+    pub min_shared: usize,
+    // Best hits kept per query
+    pub max_hits: usize,
+}
+
+impl Default for SeqSearchOptions {
+    fn default() -> Self {
+        SeqSearchOptions {
+            min_shared: 4,
+            max_hits: 10,
+        }
+    }
+}
+
+/// Stored sequence close to a query.
+#[derive(Debug, Clone, Serialize)]
+pub struct SeqHit {
+    pub query: String,
+    pub accession: String,
+    pub entry_name: String,
+    pub families: Vec<String>,
+    pub shared_kmers: usize,
+    // Identical residues over the extended diagonal
+    pub identity: f64,
+    // Share of the query covered by the extended diagonal
+    pub coverage: f64,
+}
+
+// =========================================================
+// Index
+// =========================================================
+
+fn residue_code(residue: u8) -> Option<u64> {
+    let residue = residue.to_ascii_uppercase();
+    AMINO_ACIDS
+        .iter()
+        .position(|&a| a == residue)
+        .map(|code| code as u64)
+}
+
+// K-mers of `sequence` with their start
+fn kmers(sequence: &[u8], k: usize) -> Vec<(usize, u64)> {
+    let mask = (1u64 << (RESIDUE_BITS * k)) - 1;
+    let mut kmers = Vec::with_capacity(sequence.len());
+    let mut code = 0;
+    let mut length = 0;
+
+    for (i, &residue) in sequence.iter().enumerate() {
+        match residue_code(residue) {
+            Some(residue) => {
+                code = ((code << RESIDUE_BITS) | residue) & mask;
+                length += 1;
+                if length >= k {
+                    kmers.push((i + 1 - k, code));
+                }
+            }
+            None => {
+                code = 0;
+                length = 0;
+            }
+        }
+    }
+
+    kmers
+}
+
+/// K-mers of the stored sequences, with the families of their entry.
+pub struct KmerIndex {
+    k: usize,
+    sequences: Vec<(UniprotEntry, UniprotSequence)>,
+    families: HashMap<String, Vec<String>>,
+    // Sequence index and start of every occurrence
+    postings: HashMap<u64, Vec<(u32, u32)>>,
+}
+
+impl KmerIndex {
+    /// Index `sequences` by their k-mers of length `k`, at most MAX_K.
+    pub fn build(
+        sequences: Vec<(UniprotEntry, UniprotSequence)>,
+        families: HashMap<String, Vec<String>>,
+        k: usize,
+    ) -> Self {
+        let k = k.clamp(1, MAX_K);
+        let mut postings: HashMap<u64, Vec<(u32, u32)>> = HashMap::new();
+
+        for (i, (_, sequence)) in sequences.iter().enumerate() {
+            for (start, kmer) in kmers(sequence.sequence.as_bytes(), k) {
+                postings
+                    .entry(kmer)
+                    .or_default()
+                    .push((i as u32, start as u32));
+            }
+        }
+
+        KmerIndex {
+            k,
+            sequences,
+            families,
+            postings,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.sequences.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sequences.is_empty()
+    }
+
+    // Identical residues and length of the segment of `diagonal` spanning
+    // the shared k-mers starting from `first` to `last` on the query
+    fn extend(
+        &self,
+        query: &[u8],
+        target: &[u8],
+        diagonal: i64,
+        (first, last): (usize, usize),
+    ) -> (usize, usize) {
+        let start = first;
+        let end = (last + self.k).min(query.len());
+        let identical = (start..end)
+            .filter(|&q| {
+                let t = q as i64 - diagonal;
+                t >= 0
+                    && (t as usize) < target.len()
+                    && query[q].eq_ignore_ascii_case(&target[t as usize])
+            })
+            .count();
+        (identical, end - start)
+    }
+
+    /// Stored sequences sharing at least `min_shared` k-mers with `query`,
+    /// most shared first.
+    pub fn search(
+        &self,
+        name: &str,
+        query: &[u8],
+        opts: &SeqSearchOptions,
+    ) -> Vec<SeqHit> {
+        // Diagonal and query start of every shared k-mer, by sequence
+        let mut shared: HashMap<u32, Vec<(i64, usize)>> = HashMap::new();
+        for (start, kmer) in kmers(query, self.k) {
+            let Some(occurrences) = self.postings.get(&kmer) else {
+                continue;
+            };
+            for &(i, target_start) in occurrences {
+                shared
+                    .entry(i)
+                    .or_default()
+                    .push((start as i64 - target_start as i64, start));
+            }
+        }
+
+        let mut hits: Vec<SeqHit> = shared
+            .into_iter()
+            .filter_map(|(i, mut matches)| {
+                let mut starts: Vec<usize> =
+                    matches.iter().map(|&(_, start)| start).collect();
+                starts.sort_unstable();
+                starts.dedup();
+                if starts.len() < opts.min_shared.max(1) {
+                    return None;
+                }
+
+                // Diagonal holding most shared k-mers
+                matches.sort_unstable();
+                let mut best = (0, 0, (0, 0));
+                for run in matches.chunk_by(|a, b| a.0 == b.0) {
+                    if run.len() > best.1 {
+                        let span = (run[0].1, run[run.len() - 1].1);
+                        best = (run[0].0, run.len(), span);
+                    }
+                }
+
+                let (entry, sequence) = &self.sequences[i as usize];
+                let (identical, length) = self.extend(
+                    query,
+                    sequence.sequence.as_bytes(),
+                    best.0,
+                    best.2,
+                );
+
+                Some(SeqHit {
+                    query: name.to_string(),
+                    accession: entry.accession_number.clone(),
+                    entry_name: entry.entry_name.clone(),
+                    families: self
+                        .families
+                        .get(&entry.accession_number)
+                        .cloned()
+                        .unwrap_or_default(),
+                    shared_kmers: starts.len(),
+                    identity: identical as f64 / length.max(1) as f64,
+                    coverage: length as f64 / query.len().max(1) as f64,
+                })
+            })
+            .collect();
+
+        hits.sort_by(|a, b| {
+            b.shared_kmers
+                .cmp(&a.shared_kmers)
+                .then(b.identity.total_cmp(&a.identity))
+                .then_with(|| a.accession.cmp(&b.accession))
+        });
+        hits.truncate(opts.max_hits);
+        hits
+    }
+}
+
+// =========================================================
+// Database
+// =========================================================
+
+/// Families of the stored entries, by accession.
+pub fn entry_families(
+    connection: &mut SqliteConnection,
+) -> Result<HashMap<String, Vec<String>>, diesel::result::Error> {
+    use crate::schema::belongs_to_uniprot_sequence_similarity_family as links;
+
+    let memberships: Vec<(String, String)> = links::table
+        .filter(links::obsolete.eq(false))
+        .select((links::entry, links::family))
+        .order((links::entry, links::family))
+        .load(connection)?;
+
+    let mut families: HashMap<String, Vec<String>> = HashMap::new();
+    for (entry, family) in memberships {
+        families.entry(entry).or_default().push(family);
+    }
+    Ok(families)
+}
+
+// =========================================================
+// Search
+// =========================================================
+
+/// Search every sequence of the FASTA file at `path`, `-` for stdin.
+pub fn search_fasta(
+    index: &KmerIndex,
+    path: &Path,
+    opts: &SeqSearchOptions,
+) -> Result<Vec<SeqHit>, SeqSearchError> {
+    if index.is_empty() {
+        return Err(SeqSearchError::NoSequence);
+    }
+
+    let mut hits = Vec::new();
+    for record in fasta::Reader::new(open_list(path)?).records() {
+        let record = record?;
+        hits.extend(index.search(record.id(), record.seq(), opts));
+    }
+    Ok(hits)
+}
+
+// =========================================================
+// Output
+// =========================================================
+
+impl Row for SeqHit {
+    const COLUMNS: &'static [&'static str] = &[
+        "query",
+        "accession",
+        "entry_name",
+        "families",
+        "shared_kmers",
+        "identity",
+        "coverage",
+    ];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.query.clone(),
+            self.accession.clone(),
+            self.entry_name.clone(),
+            self.families.join(";"),
+            self.shared_kmers.to_string(),
+            format!("{:.3}", self.identity),
+            format!("{:.3}", self.coverage),
+        ]
+    }
+}
+
+pub fn write_hits(
+    hits: &[SeqHit],
+    format: OutputFormat,
+    out: impl Write,
+) -> Result<(), OutputError> {
+    write_rows(hits, format, out)
+}
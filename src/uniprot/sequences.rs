@@ -0,0 +1,309 @@
+/// Canonical sequences of the stored entries and their FASTA export.
+///
+/// Sequences are downloaded in batches from the UniProtKB REST API and
+/// kept in `uniprot_sequences` with their FASTA header, so that selections
+/// of entries can be written out for alignment tools without another
+/// download.
+use bio::io::fasta;
+use diesel::dsl::{exists, not};
+use diesel::prelude::*;
+use log::{info, warn};
+use reqwest::Client;
+use std::{
+    fs::File,
+    io::{self, BufWriter},
+    path::Path,
+    time::Duration,
+};
+use thiserror::Error;
+
+use crate::schema::*;
+use crate::uniprot::download::DownloadError;
+use crate::uniprot::enrich::UNIPROTKB_REST_URL;
+use crate::uniprot::fetch::{block_on, for_each_concurrent, Fetcher};
+use crate::uniprot::models::*;
+
+#[derive(Error, Debug)]
+pub enum SequenceError {
+    #[error("Couldn't query UniProtKB: {0}")]
+    Download(#[from] DownloadError),
+
+    #[error("Database error: {0}")]
+    Database(#[from] diesel::result::Error),
+
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+}
+
+// ---------- Options ----------
+
+#[derive(Debug, Clone)]
+pub struct FetchSequencesOptions {
+    pub batch_size: usize,
+    // Requests in flight at once
+    pub concurrency: usize,
+    // Minimum delay between two requests
+    pub interval: Duration,
+    pub retries: u32,
+    // Sequences already stored are downloaded again
+    pub all: bool,
+    // Members of these families only, every entry when empty
+    pub families: Vec<String>,
+}
+
+impl Default for FetchSequencesOptions {
+    fn default() -> Self {
+        FetchSequencesOptions {
+            batch_size: 100,
+            concurrency: 4,
+            interval: Duration::from_millis(500),
+            retries: 3,
+            all: false,
+            families: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FastaSelection {
+    // Family names, every family when empty
+    pub families: Vec<String>,
+    // Species mnemonics such as HUMAN, every species when empty
+    pub species: Vec<String>,
+}
+
+// =========================================================
+// Download
+// =========================================================
+
+// UniProt headers read db|ACCESSION|ENTRY_NAME description
+fn parse_fasta(text: &str) -> io::Result<Vec<UniprotSequence>> {
+    let mut sequences = Vec::new();
+
+    for record in fasta::Reader::new(text.as_bytes()).records() {
+        let record = record?;
+        let accession = match record.id().split('|').nth(1) {
+            Some(accession) => accession.to_string(),
+            None => record.id().to_string(),
+        };
+        let header = match record.desc() {
+            Some(desc) => format!("{} {desc}", record.id()),
+            None => record.id().to_string(),
+        };
+
+        sequences.push(UniprotSequence {
+            accession_number: accession,
+            header,
+            sequence: String::from_utf8_lossy(record.seq()).into_owned(),
+        });
+    }
+
+    Ok(sequences)
+}
+
+async fn fetch_batch(
+    client: &Client,
+    accessions: &[String],
+) -> Result<String, DownloadError> {
+    let url = format!("{UNIPROTKB_REST_URL}/accessions");
+    let request_error = |source| DownloadError::from_reqwest(&url, source);
+
+    let response = client
+        .get(&url)
+        .query(&[
+            ("accessions", accessions.join(",").as_str()),
+            ("format", "fasta"),
+        ])
+        .send()
+        .await
+        .map_err(request_error)?;
+
+    let status = response.status();
+    if !status.is_success() {
+        return Err(DownloadError::Status {
+            url: url.clone(),
+            status,
+        });
+    }
+
+    response.text().await.map_err(request_error)
+}
+
+fn pending_accessions(
+    connection: &mut SqliteConnection,
+    opts: &FetchSequencesOptions,
+) -> Result<Vec<String>, diesel::result::Error> {
+    let mut query = uniprot_entries::table
+        .select(uniprot_entries::accession_number)
+        .order(uniprot_entries::accession_number)
+        .into_boxed();
+
+    if !opts.all {
+        query = query.filter(not(exists(
+            uniprot_sequences::table.filter(
+                uniprot_sequences::accession_number
+                    .eq(uniprot_entries::accession_number),
+            ),
+        )));
+    }
+    if !opts.families.is_empty() {
+        query = query.filter(
+            uniprot_entries::accession_number.eq_any(
+                belongs_to_uniprot_sequence_similarity_family::table
+                    .filter(
+                        belongs_to_uniprot_sequence_similarity_family::family
+                            .eq_any(&opts.families),
+                    )
+                    .select(
+                        belongs_to_uniprot_sequence_similarity_family::entry,
+                    ),
+            ),
+        );
+    }
+
+    query.load(connection)
+}
+
+fn store_sequences(
+    connection: &mut SqliteConnection,
+    sequences: &[UniprotSequence],
+) -> Result<(), diesel::result::Error> {
+    connection.transaction(|connection| {
+        for sequence in sequences {
+            diesel::insert_into(uniprot_sequences::table)
+                .values(sequence)
+                .on_conflict(uniprot_sequences::accession_number)
+                .do_update()
+                .set((
+                    uniprot_sequences::header.eq(&sequence.header),
+                    uniprot_sequences::sequence.eq(&sequence.sequence),
+                ))
+                .execute(connection)?;
+        }
+        Ok(())
+    })
+}
+
+/// Download the canonical sequences of the stored entries, returns the
+/// number of sequences stored.
+pub fn fetch_sequences(
+    connection: &mut SqliteConnection,
+    opts: &FetchSequencesOptions,
+) -> Result<usize, SequenceError> {
+    let accessions = pending_accessions(connection, opts)?;
+    info!("Fetching {} sequences", accessions.len());
+
+    let client =
+        Client::builder()
+            .build()
+            .map_err(|source| DownloadError::Request {
+                url: UNIPROTKB_REST_URL.to_string(),
+                source,
+            })?;
+
+    let fetcher = &Fetcher::new(client, opts.interval, opts.retries);
+    let requests =
+        accessions
+            .chunks(opts.batch_size.max(1))
+            .map(|batch| async move {
+                let text = fetcher
+                    .run(|| fetch_batch(fetcher.client(), batch))
+                    .await?;
+                Ok::<_, SequenceError>((batch, text))
+            });
+
+    let mut fetched = 0;
+    block_on(for_each_concurrent(
+        opts.concurrency,
+        requests,
+        |(batch, text)| {
+            let sequences: Vec<UniprotSequence> = parse_fasta(&text)?
+                .into_iter()
+                .filter(|s| batch.contains(&s.accession_number))
+                .collect();
+            if sequences.len() < batch.len() {
+                warn!(
+                    "UniProtKB returned {} of {} sequences",
+                    sequences.len(),
+                    batch.len()
+                );
+            }
+
+            store_sequences(connection, &sequences)?;
+            fetched += sequences.len();
+
+            info!("Fetched {fetched}/{}", accessions.len());
+            Ok(())
+        },
+    ))??;
+
+    Ok(fetched)
+}
+
+// =========================================================
+// Export
+// =========================================================
+
+fn species_of(entry_name: &str) -> Option<&str> {
+    entry_name.rsplit_once('_').map(|(_, species)| species)
+}
+
+/// Stored sequences of the selected entries, ordered by accession.
+pub fn select_sequences(
+    connection: &mut SqliteConnection,
+    selection: &FastaSelection,
+) -> Result<Vec<(UniprotEntry, UniprotSequence)>, diesel::result::Error> {
+    let mut query = uniprot_entries::table
+        .inner_join(uniprot_sequences::table)
+        .select((UniprotEntry::as_select(), UniprotSequence::as_select()))
+        .order(uniprot_entries::accession_number)
+        .into_boxed();
+
+    if !selection.families.is_empty() {
+        query = query.filter(
+            uniprot_entries::accession_number.eq_any(
+                belongs_to_uniprot_sequence_similarity_family::table
+                    .filter(
+                        belongs_to_uniprot_sequence_similarity_family::family
+                            .eq_any(&selection.families),
+                    )
+                    .filter(
+                        belongs_to_uniprot_sequence_similarity_family::obsolete
+                            .eq(false),
+                    )
+                    .select(
+                        belongs_to_uniprot_sequence_similarity_family::entry,
+                    ),
+            ),
+        );
+    }
+
+    let rows: Vec<(UniprotEntry, UniprotSequence)> = query.load(connection)?;
+
+    Ok(rows
+        .into_iter()
+        .filter(|(entry, _)| {
+            selection.species.is_empty()
+                || species_of(&entry.entry_name)
+                    .is_some_and(|s| selection.species.iter().any(|x| x == s))
+        })
+        .collect())
+}
+
+pub fn write_fasta(
+    sequences: &[(UniprotEntry, UniprotSequence)],
+    path: impl AsRef<Path>,
+) -> Result<(), SequenceError> {
+    let mut writer = fasta::Writer::new(BufWriter::new(File::create(path)?));
+
+    for (_, sequence) in sequences {
+        let (id, desc) = match sequence.header.split_once(' ') {
+            Some((id, desc)) => (id, Some(desc)),
+            None => (sequence.header.as_str(), None),
+        };
+        writer.write(id, desc, sequence.sequence.as_bytes())?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
@@ -0,0 +1,369 @@
+/// Read-only HTTP API over the local database, answering in JSON.
+///
+/// Lab web tools query the curated entries and families through it instead
+/// of reading SQLite themselves. Every request takes a connection of the
+/// pool on a blocking thread, so that slow queries don't hold up the
+/// others.
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use diesel::dsl::count_star;
+use diesel::prelude::*;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use thiserror::Error;
+
+use crate::schema::*;
+use crate::uniprot::db::{Database, DatabaseError};
+use crate::uniprot::go::expand_go_terms;
+use crate::uniprot::models::*;
+use crate::uniprot::query::{query_entries, EntryFilter};
+use crate::uniprot::sync::resolve_family;
+use crate::uniprot::taxonomy::{resolve_species, TaxonomyError};
+
+// Results of /search past this are cut
+const MAX_SEARCH_RESULTS: i64 = 500;
+const DEFAULT_SEARCH_RESULTS: i64 = 50;
+
+#[derive(Error, Debug)]
+pub enum ServerError {
+    #[error("Couldn't listen on {address}: {source}")]
+    Bind {
+        address: SocketAddr,
+        source: std::io::Error,
+    },
+
+    #[error("Server error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+// Errors answered to the clients
+#[derive(Error, Debug)]
+enum ApiError {
+    #[error("{0} not found")]
+    NotFound(String),
+
+    #[error("{0}")]
+    BadRequest(String),
+
+    #[error("Database error: {0}")]
+    Database(#[from] diesel::result::Error),
+
+    #[error("{0}")]
+    Connection(#[from] DatabaseError),
+
+    #[error("The request was interrupted")]
+    Interrupted,
+}
+
+impl From<TaxonomyError> for ApiError {
+    fn from(err: TaxonomyError) -> Self {
+        match err {
+            TaxonomyError::DatabaseError(err) => ApiError::Database(err),
+            err => ApiError::BadRequest(err.to_string()),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Database(_) | ApiError::Interrupted => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            ApiError::Connection(_) => StatusCode::SERVICE_UNAVAILABLE,
+        };
+        let body = serde_json::json!({ "error": self.to_string() });
+        (status, Json(body)).into_response()
+    }
+}
+
+// Diesel blocks, queries run off the runtime threads
+async fn with_connection<T, F>(database: &Database, f: F) -> Result<T, ApiError>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut SqliteConnection) -> Result<T, ApiError> + Send + 'static,
+{
+    let database = database.clone();
+    tokio::task::spawn_blocking(move || f(&mut database.get()?))
+        .await
+        .map_err(|_| ApiError::Interrupted)?
+}
+
+// ---------- Responses ----------
+
+#[derive(Debug, Serialize)]
+struct Member {
+    family: String,
+    #[serde(flatten)]
+    entry: UniprotEntry,
+}
+
+#[derive(Debug, Serialize)]
+struct EntryDetail {
+    #[serde(flatten)]
+    entry: UniprotEntry,
+    families: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct FamilySummary {
+    name: String,
+    members: i64,
+}
+
+#[derive(Debug, Serialize)]
+struct FamilyDetail {
+    name: String,
+    members: Vec<UniprotEntry>,
+}
+
+// ---------- Parameters ----------
+
+// Lists are comma separated, as in ?species=HUMAN,MOUSE
+#[derive(Debug, Default, Deserialize)]
+struct EntryParams {
+    family: Option<String>,
+    accession: Option<String>,
+    species: Option<String>,
+    keyword: Option<String>,
+    ec: Option<String>,
+    go: Option<String>,
+    xref: Option<String>,
+    proteome: Option<String>,
+    reference_proteome: Option<bool>,
+    min_mass: Option<i32>,
+    max_mass: Option<i32>,
+    min_length: Option<i32>,
+    max_length: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchParams {
+    q: String,
+    limit: Option<i64>,
+}
+
+fn list(value: &Option<String>) -> Vec<String> {
+    value
+        .iter()
+        .flat_map(|v| v.split(','))
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+impl EntryParams {
+    // Species are resolved and GO terms expanded to their descendants, as
+    // by `uniprot query`
+    fn filter(
+        &self,
+        connection: &mut SqliteConnection,
+    ) -> Result<EntryFilter, ApiError> {
+        let go_terms = list(&self.go);
+
+        Ok(EntryFilter {
+            family: self.family.clone(),
+            accessions: list(&self.accession),
+            species: resolve_species(&list(&self.species), connection)?,
+            keywords: list(&self.keyword),
+            ec: self.ec.clone(),
+            go_terms: if go_terms.is_empty() {
+                go_terms
+            } else {
+                expand_go_terms(&go_terms, connection)?
+            },
+            xref_databases: list(&self.xref),
+            proteomes: list(&self.proteome),
+            reference_proteome: self.reference_proteome.unwrap_or(false),
+            mass: (self.min_mass, self.max_mass),
+            seq_length: (self.min_length, self.max_length),
+        })
+    }
+}
+
+// =========================================================
+// Endpoints
+// =========================================================
+
+async fn entries(
+    State(database): State<Database>,
+    Query(params): Query<EntryParams>,
+) -> Result<Json<Vec<Member>>, ApiError> {
+    let entries = with_connection(&database, move |connection| {
+        let filter = params.filter(connection)?;
+        Ok(query_entries(connection, &filter)?)
+    })
+    .await?;
+
+    Ok(Json(
+        entries
+            .into_iter()
+            .map(|(family, entry)| Member { family, entry })
+            .collect(),
+    ))
+}
+
+async fn entry(
+    State(database): State<Database>,
+    Path(accession): Path<String>,
+) -> Result<Json<EntryDetail>, ApiError> {
+    with_connection(&database, move |connection| {
+        let entry = uniprot_entries::table
+            .find(&accession)
+            .select(UniprotEntry::as_select())
+            .first(connection)
+            .optional()?
+            .ok_or_else(|| ApiError::NotFound(format!("Entry {accession}")))?;
+
+        let families = belongs_to_uniprot_sequence_similarity_family::table
+            .filter(
+                belongs_to_uniprot_sequence_similarity_family::entry
+                    .eq(&accession),
+            )
+            .filter(
+                belongs_to_uniprot_sequence_similarity_family::obsolete
+                    .eq(false),
+            )
+            .select(belongs_to_uniprot_sequence_similarity_family::family)
+            .order(belongs_to_uniprot_sequence_similarity_family::family)
+            .load(connection)?;
+
+        Ok(Json(EntryDetail { entry, families }))
+    })
+    .await
+}
+
+async fn families(
+    State(database): State<Database>,
+) -> Result<Json<Vec<FamilySummary>>, ApiError> {
+    let counts: Vec<(String, i64)> = with_connection(&database, |connection| {
+        Ok(belongs_to_uniprot_sequence_similarity_family::table
+            .filter(
+                belongs_to_uniprot_sequence_similarity_family::obsolete
+                    .eq(false),
+            )
+            .group_by(belongs_to_uniprot_sequence_similarity_family::family)
+            .select((
+                belongs_to_uniprot_sequence_similarity_family::family,
+                count_star(),
+            ))
+            .order(belongs_to_uniprot_sequence_similarity_family::family)
+            .load(connection)?)
+    })
+    .await?;
+
+    Ok(Json(
+        counts
+            .into_iter()
+            .map(|(name, members)| FamilySummary { name, members })
+            .collect(),
+    ))
+}
+
+async fn family(
+    State(database): State<Database>,
+    Path(name): Path<String>,
+) -> Result<Json<FamilyDetail>, ApiError> {
+    with_connection(&database, move |connection| {
+        // Former names answer with the current family
+        let name = resolve_family(connection, &name)?;
+        let known: i64 = uniprot_sequence_similarity_families::table
+            .filter(uniprot_sequence_similarity_families::name.eq(&name))
+            .count()
+            .get_result(connection)?;
+        if known == 0 {
+            return Err(ApiError::NotFound(format!("Family {name}")));
+        }
+
+        let members = belongs_to_uniprot_sequence_similarity_family::table
+            .inner_join(uniprot_entries::table)
+            .filter(
+                belongs_to_uniprot_sequence_similarity_family::family.eq(&name),
+            )
+            .filter(
+                belongs_to_uniprot_sequence_similarity_family::obsolete
+                    .eq(false),
+            )
+            .select(UniprotEntry::as_select())
+            .order(uniprot_entries::accession_number)
+            .load(connection)?;
+
+        Ok(Json(FamilyDetail { name, members }))
+    })
+    .await
+}
+
+// Entries whose accession, entry name, protein name or gene name contain
+// the text, case insensitively
+async fn search(
+    State(database): State<Database>,
+    Query(params): Query<SearchParams>,
+) -> Result<Json<Vec<UniprotEntry>>, ApiError> {
+    let text = params.q.trim();
+    if text.is_empty() {
+        return Err(ApiError::BadRequest("Empty search".to_string()));
+    }
+    let pattern = format!("%{text}%");
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_SEARCH_RESULTS)
+        .clamp(1, MAX_SEARCH_RESULTS);
+
+    let found = with_connection(&database, move |connection| {
+        Ok(uniprot_entries::table
+            .filter(
+                uniprot_entries::accession_number
+                    .like(&pattern)
+                    .or(uniprot_entries::entry_name.like(&pattern))
+                    .or(uniprot_entries::protein_name.like(&pattern))
+                    .or(uniprot_entries::gene_name.like(&pattern)),
+            )
+            .select(UniprotEntry::as_select())
+            .order(uniprot_entries::accession_number)
+            .limit(limit)
+            .load(connection)?)
+    })
+    .await?;
+
+    Ok(Json(found))
+}
+
+// =========================================================
+// Server
+// =========================================================
+
+fn router(database: Database) -> Router {
+    Router::new()
+        .route("/entries", get(entries))
+        .route("/entries/:accession", get(entry))
+        .route("/families", get(families))
+        .route("/families/:name", get(family))
+        .route("/search", get(search))
+        .with_state(database)
+}
+
+/// Serve the database on `address` until the process is stopped.
+pub fn serve(
+    database: Database,
+    address: SocketAddr,
+) -> Result<(), ServerError> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+
+    runtime.block_on(async {
+        let listener = tokio::net::TcpListener::bind(address)
+            .await
+            .map_err(|source| ServerError::Bind { address, source })?;
+        info!("Serving the database on http://{address}");
+
+        axum::serve(listener, router(database)).await?;
+        Ok(())
+    })
+}
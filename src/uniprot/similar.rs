@@ -1,10 +1,19 @@
 use diesel::prelude::*;
+use diesel::upsert::excluded;
+use indicatif::{ProgressBar, ProgressStyle};
 use log::info;
 use regex::Regex;
-use reqwest::blocking::{get, Response};
+use reqwest::StatusCode;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
 use thiserror::Error;
 
 use crate::schema::*;
+use crate::uniprot::db::batch_rows;
+use crate::uniprot::download::{download_text, DownloadError, DownloadOptions};
+use crate::uniprot::history::record_changes;
 use crate::uniprot::models::*;
 
 #[derive(Error, Debug)]
@@ -12,54 +21,159 @@ pub enum EntryError {
     #[error("Regex pattern error: {0}")]
     RegexError(#[from] regex::Error),
 
-    #[error("Insufficient data: fewer than header")]
-    InsufficientData,
+    #[error("Couldn't read the input: {0}")]
+    IoError(#[from] io::Error),
 
-    #[error("Error parsing entry information")]
-    ParseError,
-}
+    #[error("similar.txt request to {url} answered HTTP {status}")]
+    HttpStatus { url: String, status: StatusCode },
 
-fn fetch_and_parse(url: &str) -> Vec<String> {
-    let lines: Vec<String> = Vec::new();
-    let response: Response;
-    let text: String;
+    #[error("Couldn't decode similar.txt from {url}: {source}")]
+    Decode {
+        url: String,
+        #[source]
+        source: reqwest::Error,
+    },
 
-    match get(url) {
-        Ok(resp) => response = resp,
-        Err(_) => return lines,
-    }
+    #[error("Couldn't download similar.txt: {0}")]
+    DownloadError(DownloadError),
+
+    #[error("similar.txt is empty")]
+    EmptyInput,
+
+    #[error("Malformed entry {entry:?} on line {line} of similar.txt")]
+    MalformedEntry { line: usize, entry: String },
 
-    match response.text() {
-        Ok(txt) => text = txt,
-        Err(_) => return lines,
+    #[error("No family block found between the header and the footer")]
+    MissingDataBlock,
+}
+
+impl From<DownloadError> for EntryError {
+    fn from(err: DownloadError) -> Self {
+        match err {
+            DownloadError::Status { url, status } => {
+                EntryError::HttpStatus { url, status }
+            }
+            DownloadError::Decode { url, source } => {
+                EntryError::Decode { url, source }
+            }
+            err => EntryError::DownloadError(err),
+        }
     }
+}
+
+fn fetch_and_parse(
+    url: &str,
+    opts: &DownloadOptions,
+) -> Result<Vec<String>, DownloadError> {
+    let text = download_text(url, opts)?;
+    Ok(text.lines().map(|line| line.to_string()).collect())
+}
+
+// A previously downloaded similar.txt, `-` meaning stdin
+pub(crate) fn read_lines(path: &Path) -> io::Result<Vec<String>> {
+    let reader: Box<dyn BufRead> = if path == Path::new("-") {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(BufReader::new(File::open(path)?))
+    };
+
+    reader.lines().collect()
+}
+
+// Rules made of dashes or equal signs delimit the header and the footer
+fn is_separator(line: &str) -> bool {
+    let line = line.trim();
+    line.len() >= 10 && line.chars().all(|c| c == '-' || c == '=')
+}
+
+// Entries are listed on indented lines below their family
+fn is_entry_line(line: &str) -> bool {
+    line.starts_with(char::is_whitespace)
+        && line.contains('(')
+        && line.trim_end().ends_with([')', ','])
+}
+
+fn is_footer(line: &str) -> bool {
+    is_separator(line) || line.trim_start().starts_with("Copyright")
+}
+
+/// First line of the family block: the line after the last separator
+/// preceding the first entry.
+fn find_first_line(lines: &[String]) -> Option<usize> {
+    let first_entry = lines.iter().position(|line| is_entry_line(line))?;
+
+    let start = lines[..first_entry]
+        .iter()
+        .rposition(|line| is_separator(line))
+        .map_or(0, |i| i + 1);
+
+    Some(start)
+}
+
+/// Line ending the family block: the first separator or copyright line
+/// following the last entry.
+fn find_last_line(lines: &[String]) -> Option<usize> {
+    let last_entry = lines.iter().rposition(|line| is_entry_line(line))?;
 
-    text.lines().map(|line| line.to_string()).collect()
+    let end = lines[last_entry..]
+        .iter()
+        .position(|line| is_footer(line))
+        .map_or(lines.len(), |i| last_entry + i);
+
+    Some(end)
 }
 
-fn find_first_line(_lines: &Vec<String>) -> usize {
-    16 as usize
+fn get_line_range(lines: &[String]) -> Result<(usize, usize), EntryError> {
+    match (find_first_line(lines), find_last_line(lines)) {
+        (Some(first), Some(last)) if first < last => Ok((first, last)),
+        _ => Err(EntryError::MissingDataBlock),
+    }
 }
 
-fn find_last_line(lines: &Vec<String>) -> usize {
-    lines.len() - 5
+/// Entries of one similar.txt and the UniProt release it comes from.
+pub struct SimilarEntries {
+    pub release: Option<String>,
+    pub entries: Vec<(UniprotFamily, UniprotEntry)>,
 }
 
-fn get_line_range(lines: &Vec<String>) -> (usize, usize) {
-    (find_first_line(lines), find_last_line(lines))
+impl SimilarEntries {
+    fn parse(lines: &[String]) -> Result<Self, EntryError> {
+        Ok(SimilarEntries {
+            release: parse_release(lines)?,
+            entries: parse_similar_entries(lines)?,
+        })
+    }
 }
 
 pub fn get_similar_entries(
     url: &str,
-) -> Result<Vec<(UniprotFamily, UniprotEntry)>, EntryError> {
-    let lines = fetch_and_parse(url);
+    opts: &DownloadOptions,
+) -> Result<SimilarEntries, EntryError> {
+    SimilarEntries::parse(&fetch_and_parse(url, opts)?)
+}
 
-    // Need to improve error handling
-    if lines.len() < 17 {
-        return Err(EntryError::InsufficientData);
+pub fn read_similar_entries(path: &Path) -> Result<SimilarEntries, EntryError> {
+    SimilarEntries::parse(&read_lines(path)?)
+}
+
+// The header reads `Release:     2024_01 of 24-Jan-2024`
+fn parse_release(lines: &[String]) -> Result<Option<String>, EntryError> {
+    let release_pattern = Regex::new(r"^Release:\s+(?P<release>\S+)")?;
+
+    Ok(lines
+        .iter()
+        .find_map(|line| release_pattern.captures(line))
+        .map(|caps| caps["release"].to_string()))
+}
+
+fn parse_similar_entries(
+    lines: &[String],
+) -> Result<Vec<(UniprotFamily, UniprotEntry)>, EntryError> {
+    if lines.iter().all(|line| line.trim().is_empty()) {
+        return Err(EntryError::EmptyInput);
     }
 
-    let (first_line, last_line) = get_line_range(&lines);
+    let (first_line, last_line) = get_line_range(lines)?;
 
     let family_pattern = Regex::new(r"(^\S.*)")?;
     let entry_pattern =
@@ -68,7 +182,7 @@ pub fn get_similar_entries(
     let mut entries: Vec<(UniprotFamily, UniprotEntry)> = Vec::new();
     let mut family = String::new();
 
-    for line in &lines[first_line..last_line] {
+    for (offset, line) in lines[first_line..last_line].iter().enumerate() {
         if line.is_empty() {
             continue;
         }
@@ -107,8 +221,22 @@ pub fn get_similar_entries(
                     accession_number: accession_number.clone(),
                     mass: None,
                     seq_length: None,
+                    protein_name: None,
+                    gene_name: None,
+                    taxon: entry_name
+                        .rsplit_once('_')
+                        .map(|(_, code)| code.to_string()),
+                    obsolete: false,
+                    replaced_by: None,
+                    import_run: None,
+                    reviewed: None,
                 };
                 entries.push((family, entry));
+            } else {
+                return Err(EntryError::MalformedEntry {
+                    line: first_line + offset + 1,
+                    entry: entry.to_string(),
+                });
             }
         }
     }
@@ -116,6 +244,8 @@ pub fn get_similar_entries(
     Ok(entries)
 }
 
+/// Entries of the species with mnemonics `species`, see `resolve_species`
+/// for taxids and scientific names.
 pub fn filter_by_species(
     entries: &[(UniprotFamily, UniprotEntry)],
     species: &[String],
@@ -135,63 +265,338 @@ pub fn filter_by_species(
     Ok(selected_entries)
 }
 
+// Entry name and taxon of the stored entries
+fn stored_entries(
+    connection: &mut SqliteConnection,
+) -> QueryResult<HashMap<String, (String, Option<String>)>> {
+    Ok(uniprot_entries::table
+        .select((
+            uniprot_entries::accession_number,
+            uniprot_entries::entry_name,
+            uniprot_entries::taxon,
+        ))
+        .load::<(String, String, Option<String>)>(connection)?
+        .into_iter()
+        .map(|(accession, name, taxon)| (accession, (name, taxon)))
+        .collect())
+}
+
+// Whether the stored memberships are obsolete
+fn stored_links(
+    connection: &mut SqliteConnection,
+) -> QueryResult<HashMap<(String, String), bool>> {
+    use crate::schema::belongs_to_uniprot_sequence_similarity_family as links;
+
+    Ok(links::table
+        .select((links::entry, links::family, links::obsolete))
+        .load::<(String, String, bool)>(connection)?
+        .into_iter()
+        .map(|(entry, family, obsolete)| ((entry, family), obsolete))
+        .collect())
+}
+
+/// Write `entries` and their memberships, only the new or changed ones
+/// stamped with the import run `run` and recorded in their history.
 pub fn insert_entries(
     entries: &[(UniprotFamily, UniprotEntry)],
+    run: i32,
     connection: &mut SqliteConnection,
 ) -> Result<(), Box<dyn std::error::Error>> {
     info!("Starting to insert {} entries", entries.len());
 
-    for (index, (family, entry)) in entries.iter().enumerate() {
-        if index % 1000 == 0 {
-            info!("Inserted {0}/{1}", index, entries.len());
+    let known_entries = stored_entries(connection)?;
+    let known_links = stored_links(connection)?;
+
+    // A row can only be upserted once per statement, duplicates are dropped
+    let mut families: BTreeMap<&str, UniprotFamily> = BTreeMap::new();
+    let mut unique_entries: BTreeMap<&str, UniprotEntry> = BTreeMap::new();
+    let mut links: BTreeSet<(&str, &str)> = BTreeSet::new();
+
+    for (family, entry) in entries {
+        families.insert(&family.name, family.clone());
+        unique_entries.insert(&entry.accession_number, entry.clone());
+        links.insert((&entry.accession_number, &family.name));
+    }
+
+    let mut changes: Vec<EntryChange> = Vec::new();
+
+    let families: Vec<UniprotFamily> = families.into_values().collect();
+    let unique_entries: Vec<UniprotEntry> = unique_entries
+        .into_values()
+        .filter_map(|entry| {
+            let accession = &entry.accession_number;
+            match known_entries.get(accession) {
+                None => {
+                    changes.push(EntryChange::new(
+                        accession, run, "inserted", None,
+                    ));
+                }
+                Some((name, taxon))
+                    if *name == entry.entry_name && *taxon == entry.taxon =>
+                {
+                    return None
+                }
+                Some((name, _)) => {
+                    changes.push(EntryChange::new(
+                        accession,
+                        run,
+                        "renamed",
+                        Some(name.clone()),
+                    ));
+                }
+            }
+            Some(UniprotEntry {
+                import_run: Some(run),
+                ..entry
+            })
+        })
+        .collect();
+    // Memberships already active are left as they are
+    let links: Vec<BelongsToFamily> = links
+        .into_iter()
+        .filter(|&(entry, family)| {
+            known_links.get(&(entry.to_string(), family.to_string()))
+                != Some(&false)
+        })
+        .map(|(entry, family)| {
+            changes.push(EntryChange::new(
+                entry,
+                run,
+                "joined",
+                Some(family.to_string()),
+            ));
+            BelongsToFamily {
+                entry: entry.to_string(),
+                family: family.to_string(),
+                obsolete: false,
+                import_run: Some(run),
+            }
+        })
+        .collect();
+
+    let bar = ProgressBar::new(
+        (families.len() + unique_entries.len() + links.len()) as u64,
+    );
+    bar.set_style(ProgressStyle::with_template(
+        "{msg:>8} [{bar:40}] {pos}/{len} ({eta})",
+    )?);
+
+    connection.transaction(|connection| {
+        bar.set_message("families");
+        for chunk in families.chunks(batch_rows(1)) {
+            diesel::insert_into(uniprot_sequence_similarity_families::table)
+                .values(chunk)
+                .on_conflict(uniprot_sequence_similarity_families::name)
+                .do_nothing()
+                .execute(connection)?;
+            bar.inc(chunk.len() as u64);
         }
 
-        diesel::insert_into(uniprot_sequence_similarity_families::table)
-            .values(family)
-            .on_conflict(uniprot_sequence_similarity_families::name)
-            .do_update()
-            .set(
-                uniprot_sequence_similarity_families::name
-                    .eq(family.name.clone()),
-            )
-            .execute(connection)?;
+        // Annotations filled by the enrichment are left untouched
+        bar.set_message("entries");
+        for chunk in unique_entries.chunks(batch_rows(11)) {
+            diesel::insert_into(uniprot_entries::table)
+                .values(chunk)
+                .on_conflict(uniprot_entries::accession_number)
+                .do_update()
+                .set((
+                    uniprot_entries::entry_name
+                        .eq(excluded(uniprot_entries::entry_name)),
+                    uniprot_entries::taxon.eq(excluded(uniprot_entries::taxon)),
+                    uniprot_entries::import_run
+                        .eq(excluded(uniprot_entries::import_run)),
+                ))
+                .execute(connection)?;
+            bar.inc(chunk.len() as u64);
+        }
 
-        diesel::insert_into(uniprot_entries::table)
-            .values(entry)
-            .on_conflict(uniprot_entries::accession_number)
+        bar.set_message("links");
+        // Memberships back in the release are no longer obsolete
+        for chunk in links.chunks(batch_rows(4)) {
+            diesel::insert_into(
+                belongs_to_uniprot_sequence_similarity_family::table,
+            )
+            .values(chunk)
+            .on_conflict((
+                belongs_to_uniprot_sequence_similarity_family::entry,
+                belongs_to_uniprot_sequence_similarity_family::family,
+            ))
             .do_update()
             .set((
-                uniprot_entries::accession_number
-                    .eq(entry.accession_number.clone()),
-                uniprot_entries::entry_name.eq(entry.entry_name.clone()),
-                uniprot_entries::mass.eq(entry.mass.clone()),
-                uniprot_entries::seq_length.eq(entry.seq_length.clone()),
+                belongs_to_uniprot_sequence_similarity_family::obsolete
+                    .eq(false),
+                belongs_to_uniprot_sequence_similarity_family::import_run
+                    .eq(run),
             ))
             .execute(connection)?;
+            bar.inc(chunk.len() as u64);
+        }
 
-        let belongs_to = BelongsToFamily {
-            entry: entry.accession_number.clone(),
-            family: family.name.clone(),
-        };
-
-        diesel::insert_into(
-            belongs_to_uniprot_sequence_similarity_family::table,
-        )
-        .values(&belongs_to)
-        .on_conflict((
-            belongs_to_uniprot_sequence_similarity_family::entry,
-            belongs_to_uniprot_sequence_similarity_family::family,
-        ))
-        .do_update()
-        .set((
-            belongs_to_uniprot_sequence_similarity_family::entry
-                .eq(belongs_to.entry.clone()),
-            belongs_to_uniprot_sequence_similarity_family::family
-                .eq(belongs_to.family.clone()),
-        ))
-        .execute(connection)?;
-    }
+        record_changes(connection, &changes)?;
+
+        Ok::<_, diesel::result::Error>(())
+    })?;
 
-    info!("Finishing inserting all entries");
+    bar.finish_and_clear();
+    info!(
+        "Inserted {} families, {} entries and {} links",
+        families.len(),
+        unique_entries.len(),
+        links.len()
+    );
     Ok(())
 }
+
+// ---------- Dry run ----------
+
+/// What `insert_entries` would write, compared with the stored rows.
+#[derive(Debug, Default)]
+pub struct ImportPreview {
+    pub families_inserted: usize,
+    pub families_unchanged: usize,
+    pub entries_inserted: usize,
+    // Entry name or species changed
+    pub entries_updated: usize,
+    pub entries_unchanged: usize,
+    pub links_inserted: usize,
+    // Obsolete memberships back in the release
+    pub links_updated: usize,
+    pub links_unchanged: usize,
+}
+
+impl ImportPreview {
+    pub fn print(&self) {
+        println!("Dry run, nothing written");
+        println!(
+            "Families: {} inserted, {} unchanged",
+            self.families_inserted, self.families_unchanged
+        );
+        println!(
+            "Entries: {} inserted, {} updated, {} unchanged",
+            self.entries_inserted, self.entries_updated, self.entries_unchanged
+        );
+        println!(
+            "Links: {} inserted, {} updated, {} unchanged",
+            self.links_inserted, self.links_updated, self.links_unchanged
+        );
+    }
+}
+
+/// Compare `entries` with the database without writing anything.
+pub fn preview_entries(
+    entries: &[(UniprotFamily, UniprotEntry)],
+    connection: &mut SqliteConnection,
+) -> Result<ImportPreview, diesel::result::Error> {
+    let known_families: HashSet<String> =
+        uniprot_sequence_similarity_families::table
+            .select(uniprot_sequence_similarity_families::name)
+            .load::<String>(connection)?
+            .into_iter()
+            .collect();
+    let known_entries = stored_entries(connection)?;
+    let known_links = stored_links(connection)?;
+
+    // Duplicates are counted once, as they are written once
+    let mut families: BTreeSet<&str> = BTreeSet::new();
+    let mut unique_entries: BTreeMap<&str, &UniprotEntry> = BTreeMap::new();
+    let mut memberships: BTreeSet<(&str, &str)> = BTreeSet::new();
+    for (family, entry) in entries {
+        families.insert(&family.name);
+        unique_entries.insert(&entry.accession_number, entry);
+        memberships.insert((&entry.accession_number, &family.name));
+    }
+
+    let mut preview = ImportPreview::default();
+    for family in families {
+        if known_families.contains(family) {
+            preview.families_unchanged += 1;
+        } else {
+            preview.families_inserted += 1;
+        }
+    }
+    for (accession, entry) in unique_entries {
+        match known_entries.get(accession) {
+            None => preview.entries_inserted += 1,
+            Some((name, taxon))
+                if *name == entry.entry_name && *taxon == entry.taxon =>
+            {
+                preview.entries_unchanged += 1
+            }
+            Some(_) => preview.entries_updated += 1,
+        }
+    }
+    for (entry, family) in memberships {
+        match known_links.get(&(entry.to_string(), family.to_string())) {
+            None => preview.links_inserted += 1,
+            Some(true) => preview.links_updated += 1,
+            Some(false) => preview.links_unchanged += 1,
+        }
+    }
+
+    Ok(preview)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Header and footer of two releases, with a few families in between
+    const RELEASE_2019: &str =
+        include_str!("../../test/data/uniprot/similar_2019_01.txt");
+    const RELEASE_2024: &str =
+        include_str!("../../test/data/uniprot/similar_2024_01.txt");
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn block_between_dashed_rules() {
+        let lines = lines(RELEASE_2019);
+        assert_eq!(get_line_range(&lines).unwrap(), (17, 25));
+        assert_eq!(lines[17], "");
+        assert_eq!(lines[18], "14-3-3 family");
+    }
+
+    #[test]
+    fn block_between_section_title_and_copyright() {
+        let lines = lines(RELEASE_2024);
+        assert_eq!(get_line_range(&lines).unwrap(), (13, 21));
+        assert!(lines[21].starts_with("Copyrighted"));
+    }
+
+    #[test]
+    fn entries_of_both_releases() {
+        for text in [RELEASE_2019, RELEASE_2024] {
+            let similar = SimilarEntries::parse(&lines(text)).unwrap();
+            assert_eq!(similar.entries.len(), 6);
+            let (family, entry) = &similar.entries[5];
+            assert!(family.name.starts_with("ABC transporter"));
+            assert_eq!(entry.accession_number, "P41233");
+            assert_eq!(entry.taxon.as_deref(), Some("MOUSE"));
+        }
+        let similar = SimilarEntries::parse(&lines(RELEASE_2024)).unwrap();
+        assert_eq!(similar.release.as_deref(), Some("2024_01"));
+    }
+
+    #[test]
+    fn header_without_families() {
+        let lines = lines(RELEASE_2019);
+        let header = &lines[..17];
+        assert!(find_first_line(header).is_none());
+        assert!(matches!(
+            parse_similar_entries(header),
+            Err(EntryError::MissingDataBlock)
+        ));
+    }
+
+    #[test]
+    fn malformed_entry() {
+        let mut lines = lines(RELEASE_2024);
+        lines[19] = "   ABCA1_HUMAN (O95477), ABCA1_MOUSE P41233)".to_string();
+        assert!(matches!(
+            parse_similar_entries(&lines),
+            Err(EntryError::MalformedEntry { line: 20, .. })
+        ));
+    }
+}
@@ -0,0 +1,213 @@
+/// Summary statistics of the sequence similarity families.
+///
+/// Member counts, species composition and the mean and median mass and
+/// length of the members help pick families of a tractable size for
+/// experiments. Masses and lengths come from the enrichment, families whose
+/// members were not enriched have none.
+use clap::ValueEnum;
+use polars::prelude::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use crate::uniprot::models::*;
+use crate::uniprot::output::{write_rows, OutputFormat, Row};
+
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+pub enum StatsFormat {
+    /// Aligned columns
+    #[default]
+    Table,
+    /// Tab separated values
+    Tsv,
+    Csv,
+    /// Array of objects
+    Json,
+    /// One object per line
+    Ndjson,
+    Parquet,
+}
+
+impl StatsFormat {
+    // Formats shared with the other listings
+    fn output(self) -> Option<OutputFormat> {
+        match self {
+            StatsFormat::Table => Some(OutputFormat::Table),
+            StatsFormat::Tsv => Some(OutputFormat::Tsv),
+            StatsFormat::Csv => Some(OutputFormat::Csv),
+            StatsFormat::Json => Some(OutputFormat::Json),
+            StatsFormat::Ndjson => Some(OutputFormat::Ndjson),
+            StatsFormat::Parquet => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FamilyStats {
+    pub family: String,
+    pub members: usize,
+    pub species: usize,
+    // Members per species, most frequent first, such as HUMAN:12;MOUSE:10
+    pub composition: String,
+    pub mean_mass: Option<f64>,
+    pub median_mass: Option<f64>,
+    pub mean_length: Option<f64>,
+    pub median_length: Option<f64>,
+}
+
+impl Row for FamilyStats {
+    const COLUMNS: &'static [&'static str] = &[
+        "family",
+        "members",
+        "species",
+        "composition",
+        "mean_mass",
+        "median_mass",
+        "mean_length",
+        "median_length",
+    ];
+
+    fn fields(&self) -> Vec<String> {
+        let optional =
+            |v: Option<f64>| v.map(|v| format!("{v:.1}")).unwrap_or_default();
+        vec![
+            self.family.clone(),
+            self.members.to_string(),
+            self.species.to_string(),
+            self.composition.clone(),
+            optional(self.mean_mass),
+            optional(self.median_mass),
+            optional(self.mean_length),
+            optional(self.median_length),
+        ]
+    }
+}
+
+// =========================================================
+// Statistics
+// =========================================================
+
+fn mean(values: &[i32]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    let sum: f64 = values.iter().map(|&v| f64::from(v)).sum();
+    Some(sum / values.len() as f64)
+}
+
+fn median(values: &mut [i32]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_unstable();
+
+    let middle = values.len() / 2;
+    if values.len() % 2 == 0 {
+        Some((f64::from(values[middle - 1]) + f64::from(values[middle])) / 2.0)
+    } else {
+        Some(f64::from(values[middle]))
+    }
+}
+
+fn summarize(family: &str, members: &[&UniprotEntry]) -> FamilyStats {
+    let mut species: BTreeMap<&str, usize> = BTreeMap::new();
+    for entry in members {
+        if let Some(taxon) = entry.taxon.as_deref() {
+            *species.entry(taxon).or_default() += 1;
+        }
+    }
+    let mut composition: Vec<(&str, usize)> = species.into_iter().collect();
+    composition.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+    let mut masses: Vec<i32> = members.iter().filter_map(|e| e.mass).collect();
+    let mut lengths: Vec<i32> =
+        members.iter().filter_map(|e| e.seq_length).collect();
+
+    FamilyStats {
+        family: family.to_string(),
+        members: members.len(),
+        species: composition.len(),
+        composition: composition
+            .iter()
+            .map(|(code, count)| format!("{code}:{count}"))
+            .collect::<Vec<_>>()
+            .join(";"),
+        mean_mass: mean(&masses),
+        median_mass: median(&mut masses),
+        mean_length: mean(&lengths),
+        median_length: median(&mut lengths),
+    }
+}
+
+/// Statistics of every family of `entries`, as returned by `query_entries`.
+pub fn family_stats(entries: &[(String, UniprotEntry)]) -> Vec<FamilyStats> {
+    let mut families: BTreeMap<&str, Vec<&UniprotEntry>> = BTreeMap::new();
+    for (family, entry) in entries {
+        families.entry(family).or_default().push(entry);
+    }
+
+    families
+        .iter()
+        .map(|(family, members)| summarize(family, members))
+        .collect()
+}
+
+// =========================================================
+// Output
+// =========================================================
+
+fn stats_frame(stats: &[FamilyStats]) -> PolarsResult<DataFrame> {
+    let column = |name: &str, values: Vec<Option<f64>>| -> Column {
+        Series::new(name.into(), values).into()
+    };
+
+    DataFrame::new(vec![
+        Series::new(
+            "family".into(),
+            stats.iter().map(|s| s.family.as_str()).collect::<Vec<_>>(),
+        )
+        .into(),
+        Series::new(
+            "members".into(),
+            stats.iter().map(|s| s.members as u64).collect::<Vec<_>>(),
+        )
+        .into(),
+        Series::new(
+            "species".into(),
+            stats.iter().map(|s| s.species as u64).collect::<Vec<_>>(),
+        )
+        .into(),
+        Series::new(
+            "composition".into(),
+            stats
+                .iter()
+                .map(|s| s.composition.as_str())
+                .collect::<Vec<_>>(),
+        )
+        .into(),
+        column("mean_mass", stats.iter().map(|s| s.mean_mass).collect()),
+        column("median_mass", stats.iter().map(|s| s.median_mass).collect()),
+        column("mean_length", stats.iter().map(|s| s.mean_length).collect()),
+        column(
+            "median_length",
+            stats.iter().map(|s| s.median_length).collect(),
+        ),
+    ])
+}
+
+pub fn write_stats(
+    stats: &[FamilyStats],
+    format: StatsFormat,
+    out: impl Write,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match format.output() {
+        Some(format) => write_rows(stats, format, out)?,
+        None => {
+            ParquetWriter::new(out)
+                .with_compression(ParquetCompression::Zstd(None))
+                .finish(&mut stats_frame(stats)?)?;
+        }
+    }
+
+    Ok(())
+}
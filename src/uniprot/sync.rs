@@ -0,0 +1,472 @@
+/// Incremental import of similar.txt, release after release.
+///
+/// The memberships of a new release are compared with those stored: new
+/// ones are inserted and those gone from the release are flagged obsolete
+/// rather than deleted. Every import is recorded with its release, so that
+/// loading the same release twice is noticed.
+///
+/// Families change names across releases. A family gone from the release
+/// whose members mostly belong to a single family of it is recorded as an
+/// alias of that family, a rename when the family is new and took in no
+/// other, a merge otherwise, so that the former name still finds them.
+use diesel::prelude::*;
+use diesel::upsert::excluded;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Write;
+
+use crate::schema::*;
+use crate::uniprot::history::record_changes;
+use crate::uniprot::models::*;
+use crate::uniprot::output::{write_rows, OutputError, OutputFormat, Row};
+use crate::uniprot::similar::insert_entries;
+
+// Share of the members of a former family that must have moved to the same
+// family for it to be taken as an alias
+const MIN_ALIAS_OVERLAP: f32 = 0.5;
+
+#[derive(Debug, Default)]
+pub struct SyncSummary {
+    pub previous_release: Option<String>,
+    pub release: Option<String>,
+    pub families_added: usize,
+    pub entries_added: usize,
+    pub memberships_added: usize,
+    pub memberships_obsoleted: usize,
+    pub memberships_unchanged: usize,
+    pub aliases: Vec<FamilyAlias>,
+}
+
+impl SyncSummary {
+    pub fn print(&self) {
+        let release = |r: &Option<String>| {
+            r.clone().unwrap_or_else(|| "unknown".to_string())
+        };
+
+        println!(
+            "Release: {} -> {}",
+            release(&self.previous_release),
+            release(&self.release)
+        );
+        println!("Families added: {}", self.families_added);
+        println!("Entries added: {}", self.entries_added);
+        println!("Memberships added: {}", self.memberships_added);
+        println!("Memberships obsoleted: {}", self.memberships_obsoleted);
+        println!("Memberships unchanged: {}", self.memberships_unchanged);
+        println!("Families renamed or merged: {}", self.aliases.len());
+        for alias in &self.aliases {
+            println!(
+                "  {} -> {} ({}, {:.0}% of the members)",
+                alias.alias,
+                alias.family,
+                alias.kind,
+                alias.overlap * 100.0
+            );
+        }
+    }
+}
+
+// ---------- Import runs ----------
+
+/// Release of the latest recorded import of similar.txt.
+pub fn last_release(
+    connection: &mut SqliteConnection,
+) -> QueryResult<Option<String>> {
+    // Enrichments and retirements are runs without a release
+    let release: Option<Option<String>> = import_runs::table
+        .filter(import_runs::release_version.is_not_null())
+        .select(import_runs::release_version)
+        .order(import_runs::id.desc())
+        .first(connection)
+        .optional()?;
+
+    Ok(release.flatten())
+}
+
+/// Record an import of `source`, returns the id of the run.
+pub fn record_import(
+    connection: &mut SqliteConnection,
+    source: &str,
+    release: Option<&str>,
+) -> QueryResult<i32> {
+    connection.transaction(|connection| {
+        diesel::insert_into(import_runs::table)
+            .values(NewImportRun {
+                source: source.to_string(),
+                release_version: release.map(|r| r.to_string()),
+            })
+            .execute(connection)?;
+
+        import_runs::table
+            .select(diesel::dsl::max(import_runs::id))
+            .first::<Option<i32>>(connection)?
+            .ok_or(diesel::result::Error::NotFound)
+    })
+}
+
+// ---------- Family aliases ----------
+
+fn members_by_family(
+    memberships: &HashSet<(String, String)>,
+) -> BTreeMap<&str, HashSet<&str>> {
+    let mut families: BTreeMap<&str, HashSet<&str>> = BTreeMap::new();
+    for (entry, family) in memberships {
+        families.entry(family).or_default().insert(entry);
+    }
+    families
+}
+
+// Families of the release taking in most members of those gone from it
+fn detect_aliases(
+    before: &BTreeMap<&str, HashSet<&str>>,
+    after: &BTreeMap<&str, HashSet<&str>>,
+    release: Option<&str>,
+) -> Vec<FamilyAlias> {
+    let mut families_of: HashMap<&str, Vec<&str>> = HashMap::new();
+    for (&family, members) in after {
+        for &entry in members {
+            families_of.entry(entry).or_default().push(family);
+        }
+    }
+
+    let mut moves: BTreeMap<&str, Vec<(&str, f32)>> = BTreeMap::new();
+    for (&former, members) in before {
+        if after.contains_key(former) {
+            continue;
+        }
+
+        let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+        for entry in members {
+            for &family in families_of.get(entry).into_iter().flatten() {
+                *counts.entry(family).or_default() += 1;
+            }
+        }
+        // Ties go to the first name
+        let Some((family, count)) = counts
+            .into_iter()
+            .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(a.0)))
+        else {
+            continue;
+        };
+
+        let overlap = count as f32 / members.len() as f32;
+        if overlap >= MIN_ALIAS_OVERLAP {
+            moves.entry(family).or_default().push((former, overlap));
+        }
+    }
+
+    let mut aliases = Vec::new();
+    for (family, formers) in moves {
+        let kind = if formers.len() == 1 && !before.contains_key(family) {
+            "rename"
+        } else {
+            "merge"
+        };
+        aliases.extend(formers.into_iter().map(|(former, overlap)| {
+            FamilyAlias {
+                alias: former.to_string(),
+                family: family.to_string(),
+                kind: kind.to_string(),
+                overlap,
+                release_version: release.map(|r| r.to_string()),
+            }
+        }));
+    }
+
+    aliases
+}
+
+// Aliases of a renamed or merged family move on to its new family, so that
+// every alias names a current family
+fn store_aliases(
+    connection: &mut SqliteConnection,
+    aliases: &[FamilyAlias],
+) -> QueryResult<()> {
+    connection.transaction(|connection| {
+        for alias in aliases {
+            let earlier: Vec<FamilyAlias> = family_aliases::table
+                .filter(family_aliases::family.eq(&alias.alias))
+                .select(FamilyAlias::as_select())
+                .load(connection)?;
+            diesel::delete(
+                family_aliases::table
+                    .filter(family_aliases::family.eq(&alias.alias)),
+            )
+            .execute(connection)?;
+
+            let moved: Vec<FamilyAlias> = earlier
+                .into_iter()
+                .map(|earlier| FamilyAlias {
+                    family: alias.family.clone(),
+                    ..earlier
+                })
+                .chain([alias.clone()])
+                // A family renamed back isn't its own alias
+                .filter(|moved| moved.alias != moved.family)
+                .collect();
+            diesel::insert_into(family_aliases::table)
+                .values(&moved)
+                .on_conflict((family_aliases::alias, family_aliases::family))
+                .do_update()
+                .set((
+                    family_aliases::kind.eq(excluded(family_aliases::kind)),
+                    family_aliases::overlap
+                        .eq(excluded(family_aliases::overlap)),
+                    family_aliases::release_version
+                        .eq(excluded(family_aliases::release_version)),
+                ))
+                .execute(connection)?;
+        }
+        Ok(())
+    })
+}
+
+/// Current name of the family once named `name`, `name` itself when it
+/// still has members or isn't an alias.
+pub fn resolve_family(
+    connection: &mut SqliteConnection,
+    name: &str,
+) -> QueryResult<String> {
+    use crate::schema::belongs_to_uniprot_sequence_similarity_family as links;
+
+    let current: bool = diesel::select(diesel::dsl::exists(
+        links::table
+            .filter(links::family.eq(name))
+            .filter(links::obsolete.eq(false)),
+    ))
+    .get_result(connection)?;
+    if current {
+        return Ok(name.to_string());
+    }
+
+    let family: Option<String> = family_aliases::table
+        .filter(family_aliases::alias.eq(name))
+        .select(family_aliases::family)
+        .first(connection)
+        .optional()?;
+
+    Ok(family.unwrap_or_else(|| name.to_string()))
+}
+
+/// Recorded aliases, those of families whose name contains `family` when
+/// set.
+pub fn family_aliases(
+    connection: &mut SqliteConnection,
+    family: Option<&str>,
+) -> QueryResult<Vec<FamilyAlias>> {
+    let mut query = family_aliases::table
+        .select(FamilyAlias::as_select())
+        .order((family_aliases::family, family_aliases::alias))
+        .into_boxed();
+
+    if let Some(family) = family {
+        let pattern = format!("%{family}%");
+        query = query.filter(
+            family_aliases::family
+                .like(pattern.clone())
+                .or(family_aliases::alias.like(pattern)),
+        );
+    }
+
+    query.load(connection)
+}
+
+impl Row for FamilyAlias {
+    const COLUMNS: &'static [&'static str] =
+        &["alias", "family", "kind", "overlap", "release_version"];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.alias.clone(),
+            self.family.clone(),
+            self.kind.clone(),
+            format!("{:.2}", self.overlap),
+            self.release_version.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+pub fn write_family_aliases(
+    aliases: &[FamilyAlias],
+    format: OutputFormat,
+    out: impl Write,
+) -> Result<(), OutputError> {
+    write_rows(aliases, format, out)
+}
+
+// =========================================================
+// Synchronization
+// =========================================================
+
+fn obsolete_memberships(
+    connection: &mut SqliteConnection,
+    memberships: &[(String, String)],
+    run: i32,
+) -> QueryResult<()> {
+    use crate::schema::belongs_to_uniprot_sequence_similarity_family as links;
+
+    connection.transaction(|connection| {
+        for (entry, family) in memberships {
+            diesel::update(links::table.find((entry, family)))
+                .set((links::obsolete.eq(true), links::import_run.eq(run)))
+                .execute(connection)?;
+        }
+
+        let changes: Vec<EntryChange> = memberships
+            .iter()
+            .map(|(entry, family)| {
+                EntryChange::new(entry, run, "left", Some(family.clone()))
+            })
+            .collect();
+        record_changes(connection, &changes)
+    })
+}
+
+/// Bring the memberships in line with `entries`, the content of
+/// `release`, recording the families renamed or merged and the changes of
+/// the entries under the import run `run`.
+pub fn sync_entries(
+    entries: &[(UniprotFamily, UniprotEntry)],
+    release: Option<&str>,
+    run: i32,
+    connection: &mut SqliteConnection,
+) -> Result<SyncSummary, Box<dyn std::error::Error>> {
+    use crate::schema::belongs_to_uniprot_sequence_similarity_family as links;
+
+    let active: HashSet<(String, String)> = links::table
+        .filter(links::obsolete.eq(false))
+        .select((links::entry, links::family))
+        .load::<(String, String)>(connection)?
+        .into_iter()
+        .collect();
+    let known_families: HashSet<String> =
+        uniprot_sequence_similarity_families::table
+            .select(uniprot_sequence_similarity_families::name)
+            .load::<String>(connection)?
+            .into_iter()
+            .collect();
+    let known_entries: HashSet<String> = uniprot_entries::table
+        .select(uniprot_entries::accession_number)
+        .load::<String>(connection)?
+        .into_iter()
+        .collect();
+
+    let current: HashSet<(String, String)> = entries
+        .iter()
+        .map(|(f, e)| (e.accession_number.clone(), f.name.clone()))
+        .collect();
+
+    let additions: Vec<(UniprotFamily, UniprotEntry)> = entries
+        .iter()
+        .filter(|(f, e)| {
+            !active.contains(&(e.accession_number.clone(), f.name.clone()))
+        })
+        .cloned()
+        .collect();
+    let mut removals: Vec<(String, String)> =
+        active.difference(&current).cloned().collect();
+    removals.sort();
+
+    let aliases = detect_aliases(
+        &members_by_family(&active),
+        &members_by_family(&current),
+        release,
+    );
+
+    let summary = SyncSummary {
+        families_added: additions
+            .iter()
+            .map(|(f, _)| &f.name)
+            .filter(|name| !known_families.contains(*name))
+            .collect::<HashSet<_>>()
+            .len(),
+        entries_added: additions
+            .iter()
+            .map(|(_, e)| &e.accession_number)
+            .filter(|acc| !known_entries.contains(*acc))
+            .collect::<HashSet<_>>()
+            .len(),
+        memberships_added: current.difference(&active).count(),
+        memberships_obsoleted: removals.len(),
+        memberships_unchanged: current.intersection(&active).count(),
+        aliases,
+        ..Default::default()
+    };
+
+    if !additions.is_empty() {
+        insert_entries(&additions, run, connection)?;
+    }
+    obsolete_memberships(connection, &removals, run)?;
+    store_aliases(connection, &summary.aliases)?;
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn families<'a>(
+        families: &[(&'a str, &[&'a str])],
+    ) -> BTreeMap<&'a str, HashSet<&'a str>> {
+        families
+            .iter()
+            .map(|(family, members)| {
+                (*family, members.iter().copied().collect())
+            })
+            .collect()
+    }
+
+    fn moves(aliases: &[FamilyAlias]) -> Vec<(&str, &str, &str)> {
+        aliases
+            .iter()
+            .map(|a| (a.alias.as_str(), a.family.as_str(), a.kind.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn renamed_family() {
+        let before = families(&[("Old", &["A", "B", "C"]), ("Kept", &["D"])]);
+        let after = families(&[("New", &["A", "B", "C"]), ("Kept", &["D"])]);
+        let aliases = detect_aliases(&before, &after, Some("2024_01"));
+        assert_eq!(moves(&aliases), [("Old", "New", "rename")]);
+        assert_eq!(aliases[0].overlap, 1.0);
+        assert_eq!(aliases[0].release_version.as_deref(), Some("2024_01"));
+    }
+
+    #[test]
+    fn merged_families() {
+        let before = families(&[("X", &["A", "B"]), ("Y", &["C", "D"])]);
+        let after = families(&[("Z", &["A", "B", "C", "D"])]);
+        assert_eq!(
+            moves(&detect_aliases(&before, &after, None)),
+            [("X", "Z", "merge"), ("Y", "Z", "merge")]
+        );
+
+        // Into a family of the earlier release
+        let before = families(&[("X", &["A", "B"]), ("Z", &["C"])]);
+        let after = families(&[("Z", &["A", "B", "C"])]);
+        assert_eq!(
+            moves(&detect_aliases(&before, &after, None)),
+            [("X", "Z", "merge")]
+        );
+    }
+
+    #[test]
+    fn scattered_members_are_no_alias() {
+        let before = families(&[("Old", &["A", "B", "C"])]);
+        let after = families(&[("P", &["A"]), ("Q", &["B"])]);
+        assert!(detect_aliases(&before, &after, None).is_empty());
+
+        let after = families(&[("P", &["D"])]);
+        assert!(detect_aliases(&before, &after, None).is_empty());
+    }
+
+    #[test]
+    fn ties_go_to_the_first_name() {
+        let before = families(&[("Old", &["A", "B", "C", "D"])]);
+        let after = families(&[("Q", &["A", "B"]), ("P", &["C", "D"])]);
+        let aliases = detect_aliases(&before, &after, None);
+        assert_eq!(moves(&aliases), [("Old", "P", "rename")]);
+        assert_eq!(aliases[0].overlap, 0.5);
+    }
+}
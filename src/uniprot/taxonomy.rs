@@ -0,0 +1,171 @@
+/// Organisms of UniProt, parsed from the controlled vocabulary speclist.txt.
+///
+/// Entry names end with the mnemonic code of their organism (`_HUMAN`), the
+/// taxonomy table maps these codes to NCBI taxids and scientific names, so
+/// that species can be selected by any of the three.
+use diesel::prelude::*;
+use diesel::upsert::excluded;
+use log::info;
+use regex::Regex;
+use std::path::Path;
+use thiserror::Error;
+
+use crate::schema::*;
+use crate::uniprot::db::batch_rows;
+use crate::uniprot::download::{download_text, DownloadError, DownloadOptions};
+use crate::uniprot::models::*;
+use crate::uniprot::similar::read_lines;
+
+#[derive(Error, Debug)]
+pub enum TaxonomyError {
+    #[error("Regex pattern error: {0}")]
+    RegexError(#[from] regex::Error),
+
+    #[error("Couldn't read the input: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("Couldn't download speclist.txt: {0}")]
+    DownloadError(#[from] DownloadError),
+
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] diesel::result::Error),
+
+    #[error("No organism found in speclist.txt")]
+    NoOrganism,
+
+    #[error("Unknown species {0}, is the taxonomy loaded?")]
+    UnknownSpecies(String),
+}
+
+// =========================================================
+// Parsing
+// =========================================================
+
+// Organisms read `CODE  K  TAXID: N=Scientific name`, followed by optional
+// indented C= (common name) and S= (synonym) lines
+fn parse_speclist(
+    lines: &[String],
+) -> Result<Vec<UniprotTaxon>, TaxonomyError> {
+    let organism_pattern = Regex::new(concat!(
+        r"^(?P<code>[A-Z0-9]{1,5})\s+(?P<kingdom>[ABEVO])",
+        r"\s+(?P<taxid>\d+):\s+N=(?P<name>.+)$",
+    ))?;
+    let common_pattern = Regex::new(r"^\s+C=(?P<name>.+)$")?;
+
+    let mut taxa: Vec<UniprotTaxon> = Vec::new();
+
+    for line in lines {
+        if let Some(caps) = organism_pattern.captures(line) {
+            let Ok(taxid) = caps["taxid"].parse() else {
+                continue;
+            };
+            taxa.push(UniprotTaxon {
+                code: caps["code"].to_string(),
+                taxid,
+                scientific_name: caps["name"].trim().to_string(),
+                common_name: None,
+                kingdom: caps["kingdom"].to_string(),
+            });
+            continue;
+        }
+
+        if let Some(caps) = common_pattern.captures(line) {
+            if let Some(taxon) = taxa.last_mut() {
+                taxon
+                    .common_name
+                    .get_or_insert(caps["name"].trim().to_string());
+            }
+        }
+    }
+
+    if taxa.is_empty() {
+        return Err(TaxonomyError::NoOrganism);
+    }
+
+    Ok(taxa)
+}
+
+pub fn get_taxa(
+    url: &str,
+    opts: &DownloadOptions,
+) -> Result<Vec<UniprotTaxon>, TaxonomyError> {
+    let text = download_text(url, opts)?;
+    let lines: Vec<String> = text.lines().map(|l| l.to_string()).collect();
+    parse_speclist(&lines)
+}
+
+pub fn read_taxa(path: &Path) -> Result<Vec<UniprotTaxon>, TaxonomyError> {
+    parse_speclist(&read_lines(path)?)
+}
+
+// =========================================================
+// Database
+// =========================================================
+
+pub fn insert_taxa(
+    taxa: &[UniprotTaxon],
+    connection: &mut SqliteConnection,
+) -> Result<(), diesel::result::Error> {
+    info!("Inserting {} organisms", taxa.len());
+
+    connection.transaction(|connection| {
+        for chunk in taxa.chunks(batch_rows(5)) {
+            diesel::insert_into(uniprot_taxa::table)
+                .values(chunk)
+                .on_conflict(uniprot_taxa::code)
+                .do_update()
+                .set((
+                    uniprot_taxa::taxid.eq(excluded(uniprot_taxa::taxid)),
+                    uniprot_taxa::scientific_name
+                        .eq(excluded(uniprot_taxa::scientific_name)),
+                    uniprot_taxa::common_name
+                        .eq(excluded(uniprot_taxa::common_name)),
+                    uniprot_taxa::kingdom.eq(excluded(uniprot_taxa::kingdom)),
+                ))
+                .execute(connection)?;
+        }
+        Ok(())
+    })
+}
+
+/// Mnemonic codes of `species`, given as mnemonics (HUMAN), NCBI taxids
+/// (9606) or scientific names (Homo sapiens).
+///
+/// Mnemonics are taken as they are, the other two are looked up in the
+/// taxonomy table.
+pub fn resolve_species(
+    species: &[String],
+    connection: &mut SqliteConnection,
+) -> Result<Vec<String>, TaxonomyError> {
+    let mut codes = Vec::new();
+
+    for name in species {
+        let name = name.trim();
+
+        let found: Vec<String> = if let Ok(taxid) = name.parse::<i32>() {
+            uniprot_taxa::table
+                .filter(uniprot_taxa::taxid.eq(taxid))
+                .select(uniprot_taxa::code)
+                .load(connection)?
+        } else if name.len() <= 5
+            && name
+                .chars()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+        {
+            vec![name.to_string()]
+        } else {
+            // LIKE compares ASCII case insensitively
+            uniprot_taxa::table
+                .filter(uniprot_taxa::scientific_name.like(name))
+                .select(uniprot_taxa::code)
+                .load(connection)?
+        };
+
+        if found.is_empty() {
+            return Err(TaxonomyError::UnknownSpecies(name.to_string()));
+        }
+        codes.extend(found);
+    }
+
+    Ok(codes)
+}
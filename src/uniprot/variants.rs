@@ -0,0 +1,286 @@
+/// Disease-associated variants of human entries from humsavar.txt.
+///
+/// The file lists every missense variant annotated in Swiss-Prot with its
+/// clinical significance, dbSNP identifier and disease. Only the variants
+/// of the stored entries are kept, and queried together with the families
+/// of their entry.
+use diesel::prelude::*;
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::Path;
+use thiserror::Error;
+
+use crate::schema::*;
+use crate::uniprot::db::batch_rows;
+use crate::uniprot::download::{download_text, DownloadError, DownloadOptions};
+use crate::uniprot::models::*;
+use crate::uniprot::output::{write_rows, OutputError, OutputFormat, Row};
+use crate::uniprot::similar::read_lines;
+
+#[derive(Error, Debug)]
+pub enum VariantError {
+    #[error("Couldn't read the input: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("Couldn't download humsavar.txt: {0}")]
+    DownloadError(#[from] DownloadError),
+
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] diesel::result::Error),
+
+    #[error("No variant found in humsavar.txt")]
+    NoVariant,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct VariantFilter {
+    // Substring of the family name
+    pub family: Option<String>,
+    // Substring of the disease, case insensitive
+    pub disease: Option<String>,
+    // Categories such as LP/P, any when empty
+    pub categories: Vec<String>,
+}
+
+// =========================================================
+// Parsing
+// =========================================================
+
+// Next whitespace separated field of `line` and the rest
+fn next_field(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim_start();
+    if line.is_empty() {
+        return None;
+    }
+    let (field, rest) =
+        line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    Some((field, rest))
+}
+
+// `-` stands for a missing value
+fn optional(value: &str) -> Option<String> {
+    Some(value.trim().to_string()).filter(|v| !v.is_empty() && v != "-")
+}
+
+// Gene name, accession, variant, change, category and dbSNP are single
+// words, the disease takes the rest of the line
+fn parse_variant(line: &str) -> Option<UniprotVariant> {
+    let (_gene, rest) = next_field(line)?;
+    let (accession, rest) = next_field(rest)?;
+    let (variant, rest) = next_field(rest)?;
+    let (aa_change, rest) = next_field(rest)?;
+    let (category, rest) = next_field(rest)?;
+    let (dbsnp, disease) = next_field(rest)?;
+
+    if !variant.starts_with("VAR_") || !aa_change.starts_with("p.") {
+        return None;
+    }
+
+    Some(UniprotVariant {
+        accession: accession.to_string(),
+        variant: variant.to_string(),
+        aa_change: aa_change.to_string(),
+        category: category.to_string(),
+        dbsnp: optional(dbsnp),
+        disease: optional(disease),
+    })
+}
+
+// The variants follow a header ended by an underline of `_` and precede a
+// footer starting with a rule of `-`
+fn parse_humsavar(
+    lines: &[String],
+) -> Result<Vec<UniprotVariant>, VariantError> {
+    let mut variants = Vec::new();
+    let mut skipped = 0;
+
+    let body = lines
+        .iter()
+        .skip_while(|line| !line.starts_with("___"))
+        .skip(1)
+        .take_while(|line| !line.starts_with('-'));
+
+    for line in body {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match parse_variant(line) {
+            Some(variant) => variants.push(variant),
+            None => skipped += 1,
+        }
+    }
+
+    if skipped > 0 {
+        warn!("Skipped {skipped} malformed lines of humsavar.txt");
+    }
+    if variants.is_empty() {
+        return Err(VariantError::NoVariant);
+    }
+
+    Ok(variants)
+}
+
+pub fn get_variants(
+    url: &str,
+    opts: &DownloadOptions,
+) -> Result<Vec<UniprotVariant>, VariantError> {
+    let text = download_text(url, opts)?;
+    let lines: Vec<String> = text.lines().map(|l| l.to_string()).collect();
+    parse_humsavar(&lines)
+}
+
+pub fn read_variants(path: &Path) -> Result<Vec<UniprotVariant>, VariantError> {
+    parse_humsavar(&read_lines(path)?)
+}
+
+// =========================================================
+// Database
+// =========================================================
+
+/// Replace the stored variants with those of `variants` on the stored
+/// entries, returns their number.
+pub fn insert_variants(
+    variants: &[UniprotVariant],
+    connection: &mut SqliteConnection,
+) -> Result<usize, diesel::result::Error> {
+    let known: HashSet<String> = uniprot_entries::table
+        .select(uniprot_entries::accession_number)
+        .load::<String>(connection)?
+        .into_iter()
+        .collect();
+    let variants: Vec<UniprotVariant> = variants
+        .iter()
+        .filter(|variant| known.contains(&variant.accession))
+        .cloned()
+        .collect();
+
+    info!("Inserting {} variants of stored entries", variants.len());
+
+    connection.transaction(|connection| {
+        // Variants follow the file, those gone from it are removed
+        diesel::delete(uniprot_variants::table).execute(connection)?;
+        for chunk in variants.chunks(batch_rows(6)) {
+            diesel::insert_into(uniprot_variants::table)
+                .values(chunk)
+                .on_conflict_do_nothing()
+                .execute(connection)?;
+        }
+
+        Ok(variants.len())
+    })
+}
+
+/// Variants of the members of the families matching `filter`, once per
+/// family their entry belongs to.
+pub fn family_variants(
+    connection: &mut SqliteConnection,
+    filter: &VariantFilter,
+) -> Result<Vec<(String, UniprotVariant)>, diesel::result::Error> {
+    let mut query = uniprot_variants::table
+        .inner_join(
+            uniprot_entries::table.inner_join(
+                belongs_to_uniprot_sequence_similarity_family::table,
+            ),
+        )
+        .filter(
+            belongs_to_uniprot_sequence_similarity_family::obsolete.eq(false),
+        )
+        .select((
+            belongs_to_uniprot_sequence_similarity_family::family,
+            UniprotVariant::as_select(),
+        ))
+        .order((
+            belongs_to_uniprot_sequence_similarity_family::family,
+            uniprot_variants::accession,
+            uniprot_variants::variant,
+        ))
+        .into_boxed();
+
+    // Former names of the families match too
+    if let Some(family) = &filter.family {
+        let pattern = format!("%{family}%");
+        query = query.filter(
+            belongs_to_uniprot_sequence_similarity_family::family
+                .like(pattern.clone())
+                .or(belongs_to_uniprot_sequence_similarity_family::family
+                    .eq_any(
+                        family_aliases::table
+                            .filter(family_aliases::alias.like(pattern))
+                            .select(family_aliases::family),
+                    )),
+        );
+    }
+    // LIKE compares ASCII case insensitively
+    if let Some(disease) = &filter.disease {
+        query = query
+            .filter(uniprot_variants::disease.like(format!("%{disease}%")));
+    }
+    if !filter.categories.is_empty() {
+        query =
+            query.filter(uniprot_variants::category.eq_any(&filter.categories));
+    }
+
+    query.load(connection)
+}
+
+// =========================================================
+// Output
+// =========================================================
+
+#[derive(Debug, Serialize)]
+struct VariantRow<'a> {
+    family: &'a str,
+    accession: &'a str,
+    variant: &'a str,
+    aa_change: &'a str,
+    category: &'a str,
+    dbsnp: Option<&'a str>,
+    disease: Option<&'a str>,
+}
+
+impl Row for VariantRow<'_> {
+    const COLUMNS: &'static [&'static str] = &[
+        "family",
+        "accession",
+        "variant",
+        "aa_change",
+        "category",
+        "dbsnp",
+        "disease",
+    ];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.family.to_string(),
+            self.accession.to_string(),
+            self.variant.to_string(),
+            self.aa_change.to_string(),
+            self.category.to_string(),
+            self.dbsnp.unwrap_or("").to_string(),
+            self.disease.unwrap_or("").to_string(),
+        ]
+    }
+}
+
+pub fn write_variants(
+    variants: &[(String, UniprotVariant)],
+    format: OutputFormat,
+    out: impl Write,
+) -> Result<(), OutputError> {
+    let rows: Vec<VariantRow> = variants
+        .iter()
+        .map(|(family, variant)| VariantRow {
+            family,
+            accession: &variant.accession,
+            variant: &variant.variant,
+            aa_change: &variant.aa_change,
+            category: &variant.category,
+            dbsnp: variant.dbsnp.as_deref(),
+            disease: variant.disease.as_deref(),
+        })
+        .collect();
+
+    write_rows(&rows, format, out)
+}